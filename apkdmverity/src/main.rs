@@ -28,7 +28,7 @@ use apkverify::{HashAlgorithm, V4Signature};
 use clap::{arg, Arg, ArgAction, Command};
 use dm::loopdevice;
 use dm::util;
-use dm::verity::{DmVerityHashAlgorithm, DmVerityTargetBuilder};
+use dm::verity::{DmVerityHashAlgorithm, DmVerityHashDescriptor, DmVerityTargetBuilder};
 use itertools::Itertools;
 use std::fmt::Debug;
 use std::fs;
@@ -132,18 +132,19 @@ fn enable_verity<P: AsRef<Path> + Debug>(
 
     // Build a dm-verity target spec from the information from the idsig file. The apk and the
     // idsig files are used as the data device and the hash device, respectively.
+    let descriptor = DmVerityHashDescriptor {
+        hash_algorithm: match sig.hashing_info.hash_algorithm {
+            HashAlgorithm::SHA256 => DmVerityHashAlgorithm::SHA256,
+        },
+        root_digest: roothash.unwrap_or(&sig.hashing_info.raw_root_hash),
+        salt: &sig.hashing_info.salt,
+        // The loop device of the hash device already starts at the merkle tree.
+        hash_offset: 0,
+    };
     let target = DmVerityTargetBuilder::default()
         .data_device(&data_device, apk_size)
         .hash_device(&hash_device)
-        .root_digest(if let Some(roothash) = roothash {
-            roothash
-        } else {
-            &sig.hashing_info.raw_root_hash
-        })
-        .hash_algorithm(match sig.hashing_info.hash_algorithm {
-            HashAlgorithm::SHA256 => DmVerityHashAlgorithm::SHA256,
-        })
-        .salt(&sig.hashing_info.salt)
+        .hash_descriptor(&descriptor)
         .build()
         .context(format!("Merkle tree in {:?} is not compatible with dm-verity", &idsig))?;
 
@@ -135,6 +135,7 @@ impl ComposClient {
                 taskProfiles: parameters.task_profiles.clone(),
                 ..Default::default()
             }),
+            tags: vec!["compos".to_owned()],
         });
 
         // Let logs go to logcat.
@@ -20,8 +20,10 @@
 
 use anyhow::{ensure, Context, Result};
 use clap::arg;
-use dm::{crypt::CipherType, util};
-use log::{error, info};
+use dm::{crypt::CipherType, util::DeviceInfo};
+use log::{error, info, warn};
+use nix::errno::Errno;
+use nix::mount::{umount2, MntFlags};
 use std::ffi::CString;
 use std::fs::{create_dir_all, OpenOptions};
 use std::io::{Error, Read, Write};
@@ -32,6 +34,10 @@ use std::process::Command;
 
 const MK2FS_BIN: &str = "/system/bin/mke2fs";
 const UNFORMATTED_STORAGE_MAGIC: &str = "UNFORMATTED-STORAGE";
+/// Size of the sectors encrypted by dm-crypt, in bytes.
+const CRYPT_SECTOR_SIZE: u32 = 4096;
+/// Name of the dm-crypt device mapping the backing device.
+const CRYPT_DEVICE_NAME: &str = "cryptdev";
 
 fn main() {
     android_logger::init_once(
@@ -52,8 +58,12 @@ fn try_main() -> Result<()> {
     let matches = clap_command().get_matches();
 
     let blkdevice = Path::new(matches.get_one::<String>("blkdevice").unwrap());
-    let key = matches.get_one::<String>("key").unwrap();
     let mountpoint = Path::new(matches.get_one::<String>("mountpoint").unwrap());
+    if matches.get_flag("wipe") {
+        return encryptedstore_wipe(blkdevice, mountpoint)
+            .with_context(|| format!("Unable to wipe encryptedstore on {:?}", blkdevice));
+    }
+    let key = matches.get_one::<String>("key").unwrap();
     // Note this error context is used in MicrodroidTests.
     encryptedstore_init(blkdevice, key, mountpoint).with_context(|| {
         format!(
@@ -67,8 +77,10 @@ fn try_main() -> Result<()> {
 fn clap_command() -> clap::Command {
     clap::Command::new("encryptedstore").args(&[
         arg!(--blkdevice <FILE> "the block device backing the encrypted storage").required(true),
-        arg!(--key <KEY> "key (in hex) equivalent to 32 bytes)").required(true),
+        arg!(--key <KEY> "key (in hex) equivalent to 32 bytes)").required_unless_present("wipe"),
         arg!(--mountpoint <MOUNTPOINT> "mount point for the storage").required(true),
+        arg!(--wipe "unmount the storage and destroy its content, e.g. when its key is revoked")
+            .conflicts_with("key"),
     ])
 }
 
@@ -85,7 +97,7 @@ fn encryptedstore_init(blkdevice: &Path, key: &str, mountpoint: &Path) -> Result
     let needs_formatting =
         needs_formatting(blkdevice).context("Unable to check if formatting is required")?;
     let crypt_device =
-        enable_crypt(blkdevice, key, "cryptdev").context("Unable to map crypt device")?;
+        enable_crypt(blkdevice, key, CRYPT_DEVICE_NAME).context("Unable to map crypt device")?;
 
     // We might need to format it with filesystem if this is a "seen-for-the-first-time" device.
     if needs_formatting {
@@ -100,6 +112,19 @@ fn encryptedstore_init(blkdevice: &Path, key: &str, mountpoint: &Path) -> Result
     Ok(())
 }
 
+/// Unmounts the storage and removes the crypt device, after zeroing the backing device so that no
+/// data remains once the key is gone.
+fn encryptedstore_wipe(blkdevice: &Path, mountpoint: &Path) -> Result<()> {
+    match umount2(mountpoint, MntFlags::MNT_DETACH) {
+        // The storage might not have been mounted, e.g. if formatting it failed.
+        Ok(()) | Err(Errno::EINVAL) | Err(Errno::ENOENT) => {}
+        Err(e) => warn!("Failed to unmount {:?}: {e}", mountpoint),
+    }
+    let dm = dm::DeviceMapper::new()?;
+    dm.delete_device_deferred_with_wipe(CRYPT_DEVICE_NAME, blkdevice)
+        .context("Failed to wipe the crypt device")
+}
+
 fn set_root_dir_permissions(mountpoint: &Path) -> Result<()> {
     // mke2fs hardwires the root dir permissions as 0o755 which doesn't match what we want.
     // We want to allow full access by both root and the payload group, and no access by anything
@@ -110,15 +135,28 @@ fn set_root_dir_permissions(mountpoint: &Path) -> Result<()> {
 }
 
 fn enable_crypt(data_device: &Path, key: &str, name: &str) -> Result<PathBuf> {
-    let dev_size = util::blkgetsize64(data_device)?;
+    let info = DeviceInfo::new(data_device)?;
+    ensure!(
+        info.logical_block_size <= CRYPT_SECTOR_SIZE,
+        "Logical block size of {:?} is larger than the crypt sector size: {}",
+        data_device,
+        info.logical_block_size
+    );
+    ensure!(
+        info.size % u64::from(CRYPT_SECTOR_SIZE) == 0,
+        "Size of {:?} isn't a multiple of the crypt sector size: {}",
+        data_device,
+        info.size
+    );
     let key = hex::decode(key).context("Unable to decode hex key")?;
 
     // Create the dm-crypt spec
+    let sector_size = format!("sector_size:{CRYPT_SECTOR_SIZE}");
     let target = dm::crypt::DmCryptTargetBuilder::default()
-        .data_device(data_device, dev_size)
+        .data_device(data_device, info.size)
         .cipher(CipherType::AES256HCTR2)
         .key(&key)
-        .opt_param("sector_size:4096")
+        .opt_param(&sector_size)
         .opt_param("iv_large_sectors")
         .build()
         .context("Couldn't build the DMCrypt target")?;
@@ -147,11 +185,17 @@ fn needs_formatting(data_device: &Path) -> Result<bool> {
 }
 
 fn format_ext4(device: &Path) -> Result<()> {
-    let root_dir_uid_gid = format!(
+    let info = DeviceInfo::new(device)?;
+    let mut extended_options = format!(
         "root_owner={}:{}",
         microdroid_uids::ROOT_UID,
         microdroid_uids::MICRODROID_PAYLOAD_GID
     );
+    if info.discard_granularity.is_none() {
+        // Don't try discarding the whole device before formatting it.
+        extended_options.push_str(",nodiscard");
+    }
+    let block_size = format!("-b {}", ext4_block_size(&info));
     let mkfs_options = [
         "-j", // Create appropriate sized journal
         /* metadata_csum: enabled for filesystem integrity
@@ -159,9 +203,9 @@ fn format_ext4(device: &Path) -> Result<()> {
          * 64bit: larger fields afforded by this feature enable full-strength checksumming.
          */
         "-O metadata_csum, extents, 64bit",
-        "-b 4096", // block size in the filesystem,
+        &block_size, // block size in the filesystem,
         "-E",
-        &root_dir_uid_gid,
+        &extended_options,
     ];
     let mut cmd = Command::new(MK2FS_BIN);
     let status = cmd
@@ -173,6 +217,15 @@ fn format_ext4(device: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Returns the block size of a filesystem on the crypt device, which shouldn't be smaller than
+/// what the device can write without read-modify-write cycles but can't be larger than a page.
+fn ext4_block_size(info: &DeviceInfo) -> u32 {
+    // SAFETY: sysconf has no preconditions.
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    let max_block_size = page_size.try_into().unwrap_or(CRYPT_SECTOR_SIZE).max(CRYPT_SECTOR_SIZE);
+    info.physical_block_size.clamp(CRYPT_SECTOR_SIZE, max_block_size)
+}
+
 fn mount(source: &Path, mountpoint: &Path) -> Result<()> {
     create_dir_all(mountpoint).with_context(|| format!("Failed to create {:?}", &mountpoint))?;
     let mount_options = CString::new(
@@ -69,6 +69,7 @@ import android.system.virtualizationservice.MemoryTrimLevel;
 import android.system.virtualizationservice.PartitionType;
 import android.system.virtualizationservice.VirtualMachineAppConfig;
 import android.system.virtualizationservice.VirtualMachineState;
+import android.system.virtualizationservice.VmCreationPolicyDecision;
 import android.util.JsonReader;
 import android.util.Log;
 
@@ -827,7 +828,9 @@ public class VirtualMachine implements AutoCloseable {
                 mVirtualMachine.registerCallback(new CallbackTranslator(service));
                 mContext.registerComponentCallbacks(mMemoryManagementCallbacks);
                 mVirtualMachine.start();
-            } catch (IllegalStateException | ServiceSpecificException e) {
+            } catch (ServiceSpecificException e) {
+                throw translateCreationError(service, e);
+            } catch (IllegalStateException e) {
                 throw new VirtualMachineException(e);
             } catch (RemoteException e) {
                 throw e.rethrowAsRuntimeException();
@@ -835,6 +838,39 @@ public class VirtualMachine implements AutoCloseable {
         }
     }
 
+    /**
+     * Explains why the VM couldn't be created, including when to retry if a device policy
+     * deferred the creation.
+     */
+    private static VirtualMachineException translateCreationError(
+            IVirtualizationService service, ServiceSpecificException e) {
+        if (e.errorCode != IVirtualizationService.ERROR_CREATION_DEFERRED
+                && e.errorCode != IVirtualizationService.ERROR_CREATION_DENIED) {
+            return new VirtualMachineException(e);
+        }
+        VmCreationPolicyDecision decision;
+        try {
+            decision = service.checkVmCreationPolicy();
+        } catch (RemoteException | ServiceSpecificException | SecurityException ignored) {
+            return new VirtualMachineException(e);
+        }
+        switch (decision.kind) {
+            case VmCreationPolicyDecision.Kind.DEFER:
+                return new VirtualMachineException(
+                        "VM creation deferred by a device policy: "
+                                + decision.reason
+                                + ". Retry in "
+                                + decision.retryAfterMillis
+                                + " ms.",
+                        e);
+            case VmCreationPolicyDecision.Kind.DENY:
+                return new VirtualMachineException(
+                        "VM creation denied by a device policy: " + decision.reason, e);
+            default:
+                return new VirtualMachineException(e);
+        }
+    }
+
     private void createIdSigs(IVirtualizationService service, VirtualMachineAppConfig appConfig)
             throws RemoteException, FileNotFoundException {
         // Fill the idsig file by hashing the apk
@@ -972,6 +1008,113 @@ public class VirtualMachine implements AutoCloseable {
         }
     }
 
+    /**
+     * Streams what the payload of this virtual machine writes to its stdout and stderr. At most
+     * {@code maxBytes} bytes of output are forwarded in total, after which the output is dropped.
+     * Output written before this is called is dropped too.
+     *
+     * <p>The payload blocks when the returned streams aren't read, so you must keep consuming both
+     * of them, or close the ones you aren't interested in. A new call replaces the streams
+     * returned by the previous one.
+     *
+     * <p>NOTE: This method may block and should not be called on the main thread.
+     *
+     * @throws VirtualMachineException if the virtual machine is not running or the streams could
+     *     not be created.
+     * @hide
+     */
+    @TestApi
+    @WorkerThread
+    @NonNull
+    public PayloadOutput streamPayloadOutput(@IntRange(from = 0) long maxBytes)
+            throws VirtualMachineException {
+        if (maxBytes < 0) {
+            throw new IllegalArgumentException("maxBytes must not be negative");
+        }
+        synchronized (mLock) {
+            IVirtualMachine vm = getRunningVm();
+            ParcelFileDescriptor[] stdout = null;
+            ParcelFileDescriptor[] stderr = null;
+            try {
+                stdout = ParcelFileDescriptor.createPipe();
+                stderr = ParcelFileDescriptor.createPipe();
+                vm.setPayloadOutput(stdout[1], stderr[1], maxBytes);
+                PayloadOutput output =
+                        new PayloadOutput(
+                                new AutoCloseInputStream(stdout[0]),
+                                new AutoCloseInputStream(stderr[0]));
+                stdout[0] = null;
+                stderr[0] = null;
+                return output;
+            } catch (IOException e) {
+                throw new VirtualMachineException("Failed to create payload output pipes", e);
+            } catch (RemoteException e) {
+                throw e.rethrowAsRuntimeException();
+            } catch (ServiceSpecificException e) {
+                throw new VirtualMachineException(e);
+            } finally {
+                // virtmgr keeps its own copies of the write ends, which must be the only ones for
+                // the streams to end once the payload closes its output.
+                closePipeQuietly(stdout);
+                closePipeQuietly(stderr);
+            }
+        }
+    }
+
+    private static void closePipeQuietly(@Nullable ParcelFileDescriptor[] pipe) {
+        if (pipe == null) {
+            return;
+        }
+        for (ParcelFileDescriptor pfd : pipe) {
+            if (pfd != null) {
+                try {
+                    pfd.close();
+                } catch (IOException e) {
+                    Log.w(TAG, "Failed to close pipe", e);
+                }
+            }
+        }
+    }
+
+    /**
+     * The streams carrying what the payload writes to its stdout and stderr, see {@link
+     * #streamPayloadOutput}.
+     *
+     * @hide
+     */
+    @TestApi
+    public static final class PayloadOutput implements AutoCloseable {
+        @NonNull private final InputStream mStdout;
+        @NonNull private final InputStream mStderr;
+
+        private PayloadOutput(@NonNull InputStream stdout, @NonNull InputStream stderr) {
+            mStdout = stdout;
+            mStderr = stderr;
+        }
+
+        /** Returns what the payload writes to its stdout. */
+        @NonNull
+        public InputStream getStdout() {
+            return mStdout;
+        }
+
+        /** Returns what the payload writes to its stderr. */
+        @NonNull
+        public InputStream getStderr() {
+            return mStderr;
+        }
+
+        /** Closes both streams, after which the output of the payload is dropped. */
+        @Override
+        public void close() throws IOException {
+            try {
+                mStdout.close();
+            } finally {
+                mStderr.close();
+            }
+        }
+    }
+
     /**
      * Stops this virtual machine. Stopping a virtual machine is like pulling the plug on a real
      * computer; the machine halts immediately. Software running on the virtual machine is not
@@ -49,6 +49,9 @@ import java.io.InputStream;
 import java.io.OutputStream;
 import java.lang.annotation.Retention;
 import java.lang.annotation.RetentionPolicy;
+import java.util.Arrays;
+import java.util.Collections;
+import java.util.List;
 import java.util.Objects;
 import java.util.zip.ZipFile;
 
@@ -80,6 +83,7 @@ public final class VirtualMachineConfig {
     private static final String KEY_VM_OUTPUT_CAPTURED = "vmOutputCaptured";
     private static final String KEY_VM_CONSOLE_INPUT_SUPPORTED = "vmConsoleInputSupported";
     private static final String KEY_VENDOR_DISK_IMAGE_PATH = "vendorDiskImagePath";
+    private static final String KEY_TAGS = "tags";
 
     /** @hide */
     @Retention(RetentionPolicy.SOURCE)
@@ -173,6 +177,9 @@ public final class VirtualMachineConfig {
 
     @Nullable private final File mVendorDiskImage;
 
+    /** Tags describing the workload running in the VM, used to segment it in metrics. */
+    @NonNull private final List<String> mTags;
+
     private VirtualMachineConfig(
             @Nullable String packageName,
             @Nullable String apkPath,
@@ -185,7 +192,8 @@ public final class VirtualMachineConfig {
             long encryptedStorageBytes,
             boolean vmOutputCaptured,
             boolean vmConsoleInputSupported,
-            @Nullable File vendorDiskImage) {
+            @Nullable File vendorDiskImage,
+            @NonNull List<String> tags) {
         // This is only called from Builder.build(); the builder handles parameter validation.
         mPackageName = packageName;
         mApkPath = apkPath;
@@ -199,6 +207,7 @@ public final class VirtualMachineConfig {
         mVmOutputCaptured = vmOutputCaptured;
         mVmConsoleInputSupported = vmConsoleInputSupported;
         mVendorDiskImage = vendorDiskImage;
+        mTags = tags;
     }
 
     /** Loads a config from a file. */
@@ -280,6 +289,11 @@ public final class VirtualMachineConfig {
             builder.setVendorDiskImage(new File(vendorDiskImagePath));
         }
 
+        String[] tags = b.getStringArray(KEY_TAGS);
+        if (tags != null) {
+            builder.setTags(Arrays.asList(tags));
+        }
+
         return builder.build();
     }
 
@@ -318,6 +332,9 @@ public final class VirtualMachineConfig {
         if (mVendorDiskImage != null) {
             b.putString(KEY_VENDOR_DISK_IMAGE_PATH, mVendorDiskImage.getAbsolutePath());
         }
+        if (!mTags.isEmpty()) {
+            b.putStringArray(KEY_TAGS, mTags.toArray(EMPTY_STRING_ARRAY));
+        }
         b.writeToStream(output);
     }
 
@@ -446,6 +463,18 @@ public final class VirtualMachineConfig {
         return mVmConsoleInputSupported;
     }
 
+    /**
+     * Returns the tags describing the workload running in the VM.
+     *
+     * @see Builder#setTags
+     * @hide
+     */
+    @TestApi
+    @NonNull
+    public List<String> getTags() {
+        return mTags;
+    }
+
     /**
      * Tests if this config is compatible with other config. Being compatible means that the configs
      * can be interchangeably used for the same virtual machine; they do not change the VM identity
@@ -508,6 +537,7 @@ public final class VirtualMachineConfig {
                 break;
         }
         vsConfig.protectedVm = mProtectedVm;
+        vsConfig.tags = mTags.toArray(EMPTY_STRING_ARRAY);
         vsConfig.memoryMib = bytesToMebiBytes(mMemoryBytes);
         switch (mCpuTopology) {
             case CPU_TOPOLOGY_MATCH_HOST:
@@ -604,6 +634,7 @@ public final class VirtualMachineConfig {
         private boolean mVmOutputCaptured = false;
         private boolean mVmConsoleInputSupported = false;
         @Nullable private File mVendorDiskImage;
+        @NonNull private List<String> mTags = Collections.emptyList();
 
         /**
          * Creates a builder for the given context.
@@ -678,7 +709,8 @@ public final class VirtualMachineConfig {
                     mEncryptedStorageBytes,
                     mVmOutputCaptured,
                     mVmConsoleInputSupported,
-                    mVendorDiskImage);
+                    mVendorDiskImage,
+                    mTags);
         }
 
         /**
@@ -910,5 +942,23 @@ public final class VirtualMachineConfig {
             mVendorDiskImage = vendorDiskImage;
             return this;
         }
+
+        /**
+         * Sets the tags describing the workload running in the VM (e.g. "compos"), which are used
+         * to segment the VM in dumpsys and metrics. Defaults to no tags.
+         *
+         * <p>At most 8 tags can be set, each of at most 32 characters from {@code [A-Za-z0-9._-]},
+         * otherwise the VM fails to run. Tags don't affect the {@linkplain #isCompatibleWith
+         * compatibility} of configs.
+         *
+         * @hide
+         */
+        @TestApi
+        @NonNull
+        public Builder setTags(@NonNull List<String> tags) {
+            requireNonNull(tags, "tags must not be null");
+            mTags = List.copyOf(tags);
+            return this;
+        }
     }
 }
@@ -107,7 +107,12 @@ public class VirtualMachineManager {
     @Retention(RetentionPolicy.SOURCE)
     @StringDef(
             prefix = "FEATURE_",
-            value = {FEATURE_DICE_CHANGES, FEATURE_MULTI_TENANT, FEATURE_VENDOR_MODULES})
+            value = {
+                FEATURE_DICE_CHANGES,
+                FEATURE_MULTI_TENANT,
+                FEATURE_VENDOR_MODULES,
+                FEATURE_VM_KIND_PERMISSIONS
+            })
     public @interface Features {}
 
     /**
@@ -134,6 +139,17 @@ public class VirtualMachineManager {
     public static final String FEATURE_VENDOR_MODULES =
             IVirtualizationService.FEATURE_VENDOR_MODULES;
 
+    /**
+     * Feature to require separate permissions for creating protected VMs, non-protected VMs and
+     * VMs with assigned devices, on top of {@link
+     * VirtualMachine#MANAGE_VIRTUAL_MACHINE_PERMISSION}.
+     *
+     * @hide
+     */
+    @TestApi
+    public static final String FEATURE_VM_KIND_PERMISSIONS =
+            IVirtualizationService.FEATURE_VM_KIND_PERMISSIONS;
+
     /**
      * Returns a set of flags indicating what this implementation of virtualization is capable of.
      *
@@ -28,6 +28,7 @@ mod err;
 mod evp;
 mod hkdf;
 mod hmac;
+mod mem;
 mod rand;
 mod sha;
 mod util;
@@ -43,5 +44,6 @@ pub use ec_key::{EcKey, ZVec};
 pub use evp::{PKey, PKeyType};
 pub use hkdf::hkdf;
 pub use hmac::hmac_sha256;
+pub use mem::constant_time_eq;
 pub use rand::rand_bytes;
 pub use sha::sha256;
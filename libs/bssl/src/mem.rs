@@ -0,0 +1,30 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Wrappers of the memory functions in BoringSSL mem.h.
+
+use bssl_ffi::CRYPTO_memcmp;
+
+/// Returns whether `a` and `b` are equal, in a time which only depends on their lengths.
+///
+/// This must be used instead of `==` to compare digests, MACs or any other secret-adjacent data.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    // SAFETY: This function only reads `a.len()` bytes from `a` and `b`, which are both valid for
+    // reads of that length.
+    let ret = unsafe { CRYPTO_memcmp(a.as_ptr().cast(), b.as_ptr().cast(), a.len()) };
+    ret == 0
+}
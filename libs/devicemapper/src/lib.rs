@@ -31,13 +31,15 @@
 #![allow(missing_docs)]
 #![cfg_attr(test, allow(unused))]
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use std::ffi::CStr;
 use std::fs::{File, OpenOptions};
 use std::io::Write;
 use std::mem::size_of;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 use zerocopy::AsBytes;
+use zerocopy::FromBytes;
 use zerocopy::FromZeroes;
 
 /// Exposes DmCryptTarget & related builder
@@ -53,12 +55,13 @@ mod sys;
 use crypt::DmCryptTarget;
 use sys::*;
 use util::*;
-use verity::DmVerityTarget;
+use verity::{DmVerityStatus, DmVerityTarget};
 
 nix::ioctl_readwrite!(_dm_dev_create, DM_IOCTL, Cmd::DM_DEV_CREATE, DmIoctl);
 nix::ioctl_readwrite!(_dm_dev_suspend, DM_IOCTL, Cmd::DM_DEV_SUSPEND, DmIoctl);
 nix::ioctl_readwrite!(_dm_table_load, DM_IOCTL, Cmd::DM_TABLE_LOAD, DmIoctl);
 nix::ioctl_readwrite!(_dm_dev_remove, DM_IOCTL, Cmd::DM_DEV_REMOVE, DmIoctl);
+nix::ioctl_readwrite!(_dm_table_status, DM_IOCTL, Cmd::DM_TABLE_STATUS, DmIoctl);
 
 /// Create a new (mapper) device
 fn dm_dev_create(dm: &DeviceMapper, ioctl: *mut DmIoctl) -> Result<i32> {
@@ -85,10 +88,26 @@ fn dm_dev_remove(dm: &DeviceMapper, ioctl: *mut DmIoctl) -> Result<i32> {
     Ok(unsafe { _dm_dev_remove(dm.0.as_raw_fd(), ioctl) }?)
 }
 
+/// Queries the status or the table of a (mapper) device
+///
+/// # Safety
+///
+/// `ioctl` must point to a buffer of at least `(*ioctl).data_size` bytes.
+unsafe fn dm_table_status(dm: &DeviceMapper, ioctl: *mut DmIoctl) -> Result<i32> {
+    // SAFETY: The kernel writes at most `data_size` bytes to `ioctl`, which the caller guarantees
+    // to be valid.
+    Ok(unsafe { _dm_table_status(dm.0.as_raw_fd(), ioctl) }?)
+}
+
+const SECTOR_SIZE: u64 = 512;
+
+/// Size of the buffer receiving the table of a device, which is large enough for a single target.
+const TABLE_STATUS_BUFFER_SIZE: usize = 16 * 1024;
+
 // `DmTargetSpec` is the header of the data structure for a device-mapper target. When doing the
 // ioctl, one of more `DmTargetSpec` (and its body) are appened to the `DmIoctl` struct.
 #[repr(C)]
-#[derive(Copy, Clone, AsBytes, FromZeroes)]
+#[derive(Copy, Clone, AsBytes, FromBytes, FromZeroes)]
 struct DmTargetSpec {
     sector_start: u64,
     length: u64, // number of 512 sectors
@@ -157,12 +176,44 @@ impl DeviceMapper {
         self.create_device(name, target.as_slice(), uuid("crypto".as_bytes())?, true)
     }
 
+    /// Creates a read-only (crypt) device and configure it according to the `target`
+    /// specification. The path to the generated device is "/dev/mapper/<name>".
+    pub fn create_read_only_crypt_device(
+        &self,
+        name: &str,
+        target: &DmCryptTarget,
+    ) -> Result<PathBuf> {
+        self.create_device(name, target.as_slice(), uuid("crypto".as_bytes())?, false)
+    }
+
     /// Creates a (verity) device and configure it according to the `target` specification.
     /// The path to the generated device is "/dev/mapper/<name>".
+    ///
+    /// The data and hash devices of the target must be read-only block devices (e.g. loop devices
+    /// attached with `writable` set to false), so that the verified content can't be modified
+    /// through another mapping.
     pub fn create_verity_device(&self, name: &str, target: &DmVerityTarget) -> Result<PathBuf> {
+        for device in target.devices() {
+            // A loop device attached without LO_FLAGS_READ_ONLY is reported as writable here.
+            if !blkroget(device).context(format!("failed to query {:?}", device))? {
+                bail!("{:?} is writable; verity devices require read-only inputs", device);
+            }
+        }
         self.create_device(name, target.as_slice(), uuid("apkver".as_bytes())?, false)
     }
 
+    /// Returns the parameters of the active table of the verity device `name`, as reported by the
+    /// kernel.
+    ///
+    /// This allows callers to check that the activated device uses the expected root digest.
+    pub fn verity_status(&self, name: &str) -> Result<DmVerityStatus> {
+        let (target_type, params) = self.active_table(name)?;
+        if target_type != "verity" {
+            bail!("{} is a {} device, not a verity device", name, target_type);
+        }
+        params.parse().context(format!("failed to parse the verity table of {}", name))
+    }
+
     /// Removes a mapper device.
     pub fn delete_device_deferred(&self, name: &str) -> Result<()> {
         let mut data = DmIoctl::new(name)?;
@@ -172,6 +223,29 @@ impl DeviceMapper {
         Ok(())
     }
 
+    /// Removes a mapper device, after destroying the data stored on its `data_device`.
+    ///
+    /// The table of the mapper device is first replaced with a "zero" target, so that the content
+    /// is no longer reachable through it. Then, the whole `data_device` is discarded and zeroed.
+    /// This is meant for sensitive devices (e.g. encryptedstore), for which no remnant should
+    /// outlive the revocation of the key.
+    pub fn delete_device_deferred_with_wipe(&self, name: &str, data_device: &Path) -> Result<()> {
+        let size = blkgetsize64(&Path::new(MAPPER_DEV_ROOT).join(name))?;
+        // The "zero" target has no parameters, but they still need their null terminator.
+        let spec_size = size_of::<DmTargetSpec>() + 1;
+        let aligned_size = (spec_size + 7) & !7; // align to 8 byte boundaries
+        let mut header = DmTargetSpec::new("zero")?;
+        header.length = size / SECTOR_SIZE;
+        header.next = aligned_size as u32;
+        let mut target = Vec::with_capacity(aligned_size);
+        target.extend_from_slice(header.as_bytes());
+        target.resize(aligned_size, 0);
+        self.replace_table(name, &target)
+            .context(format!("failed to load zero table onto device with name {}", &name))?;
+        wipe_block_device(data_device).context(format!("failed to wipe {:?}", data_device))?;
+        self.delete_device_deferred(name)
+    }
+
     fn create_device(
         &self,
         name: &str,
@@ -186,6 +260,20 @@ impl DeviceMapper {
             .context(format!("failed to create an empty device with name {}", &name))?;
 
         // Step 2: load table onto the device
+        self.load_table(name, target, writable)?;
+
+        // Step 3: activate the device (note: the term 'suspend' might be misleading, but it
+        // actually activates the table. See include/uapi/linux/dm-ioctl.h
+        let mut data = DmIoctl::new(name)?;
+        dm_dev_suspend(self, &mut data).context("failed to activate")?;
+
+        // Step 4: wait unti the device is created and return the device path
+        let path = Path::new(MAPPER_DEV_ROOT).join(name);
+        wait_for_path(&path)?;
+        Ok(path)
+    }
+
+    fn load_table(&self, name: &str, target: &[u8], writable: bool) -> Result<()> {
         let payload_size = size_of::<DmIoctl>() + target.len();
 
         let mut data = DmIoctl::new(name)?;
@@ -202,16 +290,56 @@ impl DeviceMapper {
         payload.extend_from_slice(target);
         dm_table_load(self, payload.as_mut_ptr() as *mut DmIoctl)
             .context("failed to load table")?;
+        Ok(())
+    }
+
+    /// Returns the type and the parameters of the single target of the active table of `name`.
+    fn active_table(&self, name: &str) -> Result<(String, String)> {
+        // Use a buffer of u64 so that the DmIoctl header is properly aligned.
+        let mut buf = vec![0u64; TABLE_STATUS_BUFFER_SIZE / size_of::<u64>()];
+        let buf = buf.as_bytes_mut();
 
-        // Step 3: activate the device (note: the term 'suspend' might be misleading, but it
-        // actually activates the table. See include/uapi/linux/dm-ioctl.h
         let mut data = DmIoctl::new(name)?;
-        dm_dev_suspend(self, &mut data).context("failed to activate")?;
+        data.data_size = buf.len() as u32;
+        data.data_start = size_of::<DmIoctl>() as u32;
+        data.flags |= Flag::DM_STATUS_TABLE_FLAG;
+        buf[..size_of::<DmIoctl>()].copy_from_slice(data.as_bytes());
+        // SAFETY: `buf` is `data_size` bytes long.
+        unsafe { dm_table_status(self, buf.as_mut_ptr() as *mut DmIoctl) }
+            .context(format!("failed to get the table of device with name {}", &name))?;
+
+        let data = DmIoctl::read_from_prefix(buf).context("truncated ioctl header")?;
+        if data.flags.contains(Flag::DM_BUFFER_FULL_FLAG) {
+            bail!("table of {} doesn't fit in {} bytes", name, buf.len());
+        }
+        if data.target_count != 1 {
+            bail!("expected a single target for {}, found {}", name, data.target_count);
+        }
+        let targets = buf
+            .get(data.data_start as usize..data.data_size as usize)
+            .context("invalid table location")?;
+        let spec = DmTargetSpec::read_from_prefix(targets).context("truncated target spec")?;
+        let target_type = CStr::from_bytes_until_nul(&spec.target_type)
+            .context("unterminated target type")?
+            .to_str()?;
+        let params = CStr::from_bytes_until_nul(&targets[size_of::<DmTargetSpec>()..])
+            .context("unterminated target parameters")?
+            .to_str()?;
+        Ok((target_type.to_owned(), params.to_owned()))
+    }
 
-        // Step 4: wait unti the device is created and return the device path
-        let path = Path::new(MAPPER_DEV_ROOT).join(name);
-        wait_for_path(&path)?;
-        Ok(path)
+    /// Swaps the table of an active device for `target`.
+    fn replace_table(&self, name: &str, target: &[u8]) -> Result<()> {
+        let mut data = DmIoctl::new(name)?;
+        data.flags |= Flag::DM_SUSPEND_FLAG;
+        dm_dev_suspend(self, &mut data).context("failed to suspend")?;
+
+        self.load_table(name, target, true)?;
+
+        // Resuming the device makes the newly loaded table the active one.
+        let mut data = DmIoctl::new(name)?;
+        dm_dev_suspend(self, &mut data).context("failed to resume")?;
+        Ok(())
     }
 }
 
@@ -238,7 +366,9 @@ mod tests {
     use rdroidtest::test;
     use rustutils::system_properties;
     use std::fs::{read, File, OpenOptions};
-    use std::io::Write;
+    use std::io::{Read, Write};
+    use std::os::unix::fs::OpenOptionsExt;
+    use verity::DmVerityTargetBuilder;
 
     // Just a logical set of keys to make testing easy. This has no real meaning.
     struct KeySet<'a> {
@@ -312,6 +442,202 @@ mod tests {
         data_inaccessible_with_diff_key(&KEY_SET_HCTR2, "name4");
     }
 
+    test!(delete_with_wipe_destroys_data);
+    fn delete_with_wipe_destroys_data() {
+        let dm = DeviceMapper::new().unwrap();
+        let inputimg = include_bytes!("../testdata/rand8k");
+        let sz = inputimg.len() as u64;
+        let device = "name5";
+
+        let test_dir = tempfile::TempDir::new().unwrap();
+        let backing_file = prepare_tmpfile(test_dir.path(), "storage", sz);
+        let data_device = loopdevice::attach(
+            &backing_file,
+            0,
+            sz,
+            /*direct_io*/ true,
+            /*writable*/ true,
+        )
+        .unwrap();
+        scopeguard::defer! {
+            loopdevice::detach(&data_device).unwrap();
+            let _ignored = delete_device(&dm, device);
+        }
+
+        let target = DmCryptTargetBuilder::default()
+            .data_device(&data_device, sz)
+            .cipher(KEY_SET_XTS.cipher)
+            .key(KEY_SET_XTS.key)
+            .build()
+            .unwrap();
+
+        let crypt_device = dm.create_crypt_device(device, &target).unwrap();
+        write_to_dev(&crypt_device, inputimg);
+
+        dm.delete_device_deferred_with_wipe(device, &data_device).unwrap();
+        assert!(read(&data_device).unwrap().iter().all(|&b| b == 0));
+    }
+
+    test!(delete_with_wipe_hides_data_from_open_handles);
+    fn delete_with_wipe_hides_data_from_open_handles() {
+        let dm = DeviceMapper::new().unwrap();
+        let inputimg = include_bytes!("../testdata/rand8k");
+        let sz = inputimg.len() as u64;
+        let device = "name9";
+
+        let test_dir = tempfile::TempDir::new().unwrap();
+        let backing_file = prepare_tmpfile(test_dir.path(), "storage", sz);
+        let data_device = loopdevice::attach(
+            &backing_file,
+            0,
+            sz,
+            /*direct_io*/ true,
+            /*writable*/ true,
+        )
+        .unwrap();
+        scopeguard::defer! {
+            loopdevice::detach(&data_device).unwrap();
+            let _ignored = delete_device(&dm, device);
+        }
+
+        let target = DmCryptTargetBuilder::default()
+            .data_device(&data_device, sz)
+            .cipher(KEY_SET_XTS.cipher)
+            .key(KEY_SET_XTS.key)
+            .build()
+            .unwrap();
+
+        let crypt_device = dm.create_crypt_device(device, &target).unwrap();
+        write_to_dev(&crypt_device, inputimg);
+
+        // Keeping the device open defers its removal, so reads go through the "zero" table. Bypass
+        // the page cache, which still holds what was written.
+        #[repr(align(4096))]
+        struct AlignedBuf([u8; 4096]);
+        let mut opened =
+            OpenOptions::new().read(true).custom_flags(libc::O_DIRECT).open(&crypt_device).unwrap();
+        dm.delete_device_deferred_with_wipe(device, &data_device).unwrap();
+        let mut buf = AlignedBuf([0xff; 4096]);
+        opened.read_exact(&mut buf.0).unwrap();
+        assert!(buf.0.iter().all(|&b| b == 0));
+    }
+
+    test!(read_only_crypt_device_rejects_writes);
+    fn read_only_crypt_device_rejects_writes() {
+        let dm = DeviceMapper::new().unwrap();
+        let sz = 8192;
+        let device = "name6";
+
+        let test_dir = tempfile::TempDir::new().unwrap();
+        let backing_file = prepare_tmpfile(test_dir.path(), "storage", sz);
+        let data_device = loopdevice::attach(
+            &backing_file,
+            0,
+            sz,
+            /*direct_io*/ true,
+            /*writable*/ true,
+        )
+        .unwrap();
+        scopeguard::defer! {
+            loopdevice::detach(&data_device).unwrap();
+            let _ignored = delete_device(&dm, device);
+        }
+
+        let target = DmCryptTargetBuilder::default()
+            .data_device(&data_device, sz)
+            .cipher(KEY_SET_XTS.cipher)
+            .key(KEY_SET_XTS.key)
+            .build()
+            .unwrap();
+
+        let crypt_device = dm.create_read_only_crypt_device(device, &target).unwrap();
+        assert!(blkroget(&crypt_device).unwrap());
+        assert!(OpenOptions::new().write(true).open(&crypt_device).is_err());
+    }
+
+    test!(verity_device_rejects_writable_inputs);
+    fn verity_device_rejects_writable_inputs() {
+        let dm = DeviceMapper::new().unwrap();
+        let sz = 8192;
+        let device = "name7";
+
+        let test_dir = tempfile::TempDir::new().unwrap();
+        let data_file = prepare_tmpfile(test_dir.path(), "data", sz);
+        let hash_file = prepare_tmpfile(test_dir.path(), "hash", 4096);
+        let data_device =
+            loopdevice::attach(&data_file, 0, sz, /*direct_io*/ false, /*writable*/ true).unwrap();
+        let hash_device = loopdevice::attach(
+            &hash_file, 0, 4096, /*direct_io*/ false, /*writable*/ false,
+        )
+        .unwrap();
+        scopeguard::defer! {
+            loopdevice::detach(&data_device).unwrap();
+            loopdevice::detach(&hash_device).unwrap();
+        }
+
+        let target = DmVerityTargetBuilder::default()
+            .data_device(&data_device, sz)
+            .hash_device(&hash_device)
+            .root_digest(&[0; 32])
+            .build()
+            .unwrap();
+
+        assert!(dm.create_verity_device(device, &target).is_err());
+        assert!(!Path::new(MAPPER_DEV_ROOT).join(device).exists());
+    }
+
+    test!(verity_table_format);
+    fn verity_table_format() {
+        let table = "1 /dev/block/loop0 /dev/block/loop1 4096 4096 2 1 sha256 5a5a 1234";
+        let params: verity::DmVerityStatus = table.parse().unwrap();
+        assert_eq!(params.hash_start_block, 1);
+        assert_eq!(params.to_string(), table);
+
+        let unsalted = verity::DmVerityStatus { salt: vec![], ..params };
+        assert!(unsalted.to_string().ends_with(" sha256 5a5a -"));
+    }
+
+    test!(verity_status_reports_root_digest);
+    fn verity_status_reports_root_digest() {
+        let dm = DeviceMapper::new().unwrap();
+        let sz = 8192;
+        let device = "name8";
+
+        let test_dir = tempfile::TempDir::new().unwrap();
+        let data_file = prepare_tmpfile(test_dir.path(), "data", sz);
+        let hash_file = prepare_tmpfile(test_dir.path(), "hash", 4096);
+        let data_device = loopdevice::attach(
+            &data_file, 0, sz, /*direct_io*/ false, /*writable*/ false,
+        )
+        .unwrap();
+        let hash_device = loopdevice::attach(
+            &hash_file, 0, 4096, /*direct_io*/ false, /*writable*/ false,
+        )
+        .unwrap();
+        scopeguard::defer! {
+            loopdevice::detach(&data_device).unwrap();
+            loopdevice::detach(&hash_device).unwrap();
+            let _ignored = delete_device(&dm, device);
+        }
+
+        let root_digest = [0x5a; 32];
+        let salt = [0x12, 0x34];
+        let target = DmVerityTargetBuilder::default()
+            .data_device(&data_device, sz)
+            .hash_device(&hash_device)
+            .root_digest(&root_digest)
+            .salt(&salt)
+            .build()
+            .unwrap();
+        dm.create_verity_device(device, &target).unwrap();
+
+        let status = dm.verity_status(device).unwrap();
+        assert_eq!(status.root_digest, root_digest);
+        assert_eq!(status.salt, salt);
+        assert_eq!(status.hash_algorithm, "sha256");
+        assert_eq!(status.num_data_blocks * status.data_block_size, sz);
+    }
+
     fn mapping_again_keeps_data(keyset: &KeySet, device: &str) {
         // This test creates 2 different crypt devices using same key backed by same data_device
         // -> Write data on dev1 -> Check the data is visible & same on dev2
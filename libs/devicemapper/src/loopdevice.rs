@@ -15,7 +15,8 @@
  */
 
 // `loopdevice` module provides `attach` and `detach` functions that are for attaching and
-// detaching a regular file to and from a loop device. Note that
+// detaching a regular file to and from a loop device, and `query` to read back how a loop device
+// is configured. Note that
 // `loopdev`(https://crates.io/crates/loopdev) is a public alternative to this. In-house
 // implementation was chosen to make Android-specific changes (like the use of the new
 // LOOP_CONFIGURE instead of the legacy LOOP_SET_FD + LOOP_SET_STATUS64 combo which is considerably
@@ -26,7 +27,9 @@ mod sys;
 use crate::util::*;
 use anyhow::{Context, Result};
 use libc::O_DIRECT;
+use std::ffi::OsStr;
 use std::fs::{File, OpenOptions};
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
@@ -40,6 +43,7 @@ use crate::loopdevice::sys::*;
 nix::ioctl_none_bad!(_loop_ctl_get_free, LOOP_CTL_GET_FREE);
 nix::ioctl_write_ptr_bad!(_loop_configure, LOOP_CONFIGURE, loop_config);
 nix::ioctl_none_bad!(_loop_clr_fd, LOOP_CLR_FD);
+nix::ioctl_read_bad!(_loop_get_status64, LOOP_GET_STATUS64, loop_info64);
 
 fn loop_ctl_get_free(ctrl_file: &File) -> Result<i32> {
     // SAFETY: this ioctl changes the state in kernel, but not the state in this process.
@@ -59,6 +63,14 @@ pub fn loop_clr_fd(device_file: &File) -> Result<i32> {
     Ok(unsafe { _loop_clr_fd(device_file.as_raw_fd()) }?)
 }
 
+fn loop_get_status64(device_file: &File) -> Result<loop_info64> {
+    let mut info = loop_info64::new_zeroed();
+    // SAFETY: this ioctl only writes to `info`, which is a valid loop_info64 owned by this
+    // function.
+    unsafe { _loop_get_status64(device_file.as_raw_fd(), &mut info) }?;
+    Ok(info)
+}
+
 /// Creates a loop device and attach the given file at `path` as the backing store.
 pub fn attach<P: AsRef<Path>>(
     path: P,
@@ -126,6 +138,10 @@ fn try_attach<P: AsRef<Path>>(
     config.block_size = 4096;
     config.info.lo_offset = offset;
     config.info.lo_sizelimit = size_limit;
+    // Recorded for `query`. The kernel keeps at most LO_NAME_SIZE - 1 bytes of it.
+    let name = path.as_ref().as_os_str().as_bytes();
+    let name_len = name.len().min(LO_NAME_SIZE - 1);
+    config.info.lo_file_name[..name_len].copy_from_slice(&name[..name_len]);
 
     if !writable {
         config.info.lo_flags = Flag::LO_FLAGS_READ_ONLY;
@@ -156,6 +172,39 @@ pub fn detach<P: AsRef<Path>>(path: P) -> Result<()> {
     Ok(())
 }
 
+/// Configuration of a loop device, as returned by `query`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoopInfo {
+    /// Path of the backing file when it was attached, truncated to 63 bytes.
+    pub backing_file: PathBuf,
+    /// Offset in the backing file where the loop device starts.
+    pub offset: u64,
+    /// Maximum size of the loop device, or 0 if it extends to the end of the backing file.
+    pub size_limit: u64,
+    /// Whether the loop device is read-only.
+    pub read_only: bool,
+    /// Whether the backing file is accessed with direct IO.
+    pub direct_io: bool,
+}
+
+/// Returns the configuration of the loop device `path`, which must be attached.
+pub fn query<P: AsRef<Path>>(path: P) -> Result<LoopInfo> {
+    let device_file = OpenOptions::new()
+        .read(true)
+        .open(&path)
+        .context(format!("failed to open {:?}", path.as_ref()))?;
+    let info =
+        loop_get_status64(&device_file).context(format!("Failed to query {:?}", path.as_ref()))?;
+    let name_len = info.lo_file_name.iter().position(|c| *c == 0).unwrap_or(LO_NAME_SIZE);
+    Ok(LoopInfo {
+        backing_file: PathBuf::from(OsStr::from_bytes(&info.lo_file_name[..name_len])),
+        offset: info.lo_offset,
+        size_limit: info.lo_sizelimit,
+        read_only: info.lo_flags.contains(Flag::LO_FLAGS_READ_ONLY),
+        direct_io: info.lo_flags.contains(Flag::LO_FLAGS_DIRECT_IO),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -217,4 +266,39 @@ mod tests {
         assert!(is_direct_io(&dev));
         assert!(is_direct_io_writable(&dev));
     }
+
+    #[test]
+    fn query_attached_loop_device() {
+        let a_dir = tempfile::TempDir::new().unwrap();
+        let a_file = a_dir.path().join("test");
+        create_empty_file(&a_file, 8192);
+        let dev = attach(&a_file, 4096, 4096, /*direct_io*/ false, /*writable*/ false).unwrap();
+        scopeguard::defer! {
+            detach(&dev).unwrap();
+        }
+        let info = query(&dev).unwrap();
+        assert_eq!(info.backing_file, a_file);
+        assert_eq!(info.offset, 4096);
+        assert_eq!(info.size_limit, 4096);
+        assert!(info.read_only);
+        assert!(!info.direct_io);
+    }
+
+    #[test]
+    fn device_info_of_loop_device() {
+        let a_dir = tempfile::TempDir::new().unwrap();
+        let a_file = a_dir.path().join("test");
+        create_empty_file(&a_file, 8192);
+        let dev = attach(&a_file, 0, 8192, /*direct_io*/ false, /*writable*/ true).unwrap();
+        scopeguard::defer! {
+            detach(&dev).unwrap();
+        }
+        let info = DeviceInfo::new(&dev).unwrap();
+        assert_eq!(info.size, 8192);
+        assert!(info.logical_block_size.is_power_of_two());
+        assert!(info.physical_block_size >= info.logical_block_size);
+        if let Some(granularity) = info.discard_granularity {
+            assert_ne!(granularity, 0);
+        }
+    }
 }
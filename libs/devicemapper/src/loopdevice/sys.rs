@@ -26,6 +26,7 @@ pub const LOOP_CONTROL: &str = "/dev/loop-control";
 pub const LOOP_CTL_GET_FREE: libc::c_ulong = 0x4C82;
 pub const LOOP_CONFIGURE: libc::c_ulong = 0x4C0A;
 pub const LOOP_CLR_FD: libc::c_ulong = 0x4C01;
+pub const LOOP_GET_STATUS64: libc::c_ulong = 0x4C05;
 
 #[repr(C)]
 #[derive(Copy, Clone, FromZeroes)]
@@ -14,10 +14,10 @@
  * limitations under the License.
  */
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use nix::sys::stat::FileStat;
-use std::fs::File;
-use std::os::unix::fs::FileTypeExt;
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
 use std::os::unix::io::AsRawFd;
 use std::path::Path;
 use std::thread;
@@ -60,18 +60,131 @@ pub fn fstat(p: &Path) -> Result<FileStat> {
 
 // From include/uapi/linux/fs.h
 const BLK: u8 = 0x12;
+const BLKROGET: u8 = 94;
+const BLKSSZGET: u8 = 104;
 const BLKGETSIZE64: u8 = 114;
+const BLKDISCARD: u8 = 119;
+const BLKPBSZGET: u8 = 123;
+const BLKROTATIONAL: u8 = 126;
+const BLKZEROOUT: u8 = 127;
+nix::ioctl_read_bad!(_blkroget, nix::request_code_none!(BLK, BLKROGET), libc::c_int);
+nix::ioctl_read_bad!(_blksszget, nix::request_code_none!(BLK, BLKSSZGET), libc::c_int);
 nix::ioctl_read!(_blkgetsize64, BLK, BLKGETSIZE64, libc::size_t);
+nix::ioctl_read_bad!(_blkpbszget, nix::request_code_none!(BLK, BLKPBSZGET), libc::c_uint);
+nix::ioctl_read_bad!(_blkrotational, nix::request_code_none!(BLK, BLKROTATIONAL), libc::c_ushort);
+nix::ioctl_write_ptr_bad!(_blkdiscard, nix::request_code_none!(BLK, BLKDISCARD), [u64; 2]);
+nix::ioctl_write_ptr_bad!(_blkzeroout, nix::request_code_none!(BLK, BLKZEROOUT), [u64; 2]);
 
 /// Gets the size of a block device
 pub fn blkgetsize64(p: &Path) -> Result<u64> {
-    let f = File::open(p)?;
-    if !f.metadata()?.file_type().is_block_device() {
-        bail!("{:?} is not a block device", p);
-    }
+    let f = open_block_device(p)?;
     let mut size: usize = 0;
     // SAFETY: kernel copies the return value out to `size`. The file is kept open until the end of
     // this function.
     unsafe { _blkgetsize64(f.as_raw_fd(), &mut size) }?;
     Ok(size as u64)
 }
+
+fn open_block_device(p: &Path) -> Result<File> {
+    let f = File::open(p)?;
+    if !f.metadata()?.file_type().is_block_device() {
+        bail!("{:?} is not a block device", p);
+    }
+    Ok(f)
+}
+
+/// Properties of a block device which matter when laying out data on it, e.g. a filesystem.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Size of the device, in bytes.
+    pub size: u64,
+    /// Smallest unit the device can address, in bytes.
+    pub logical_block_size: u32,
+    /// Smallest unit the device can write without a read-modify-write cycle, in bytes.
+    pub physical_block_size: u32,
+    /// Granularity of discards, in bytes, or `None` if the device doesn't support discarding or if
+    /// it couldn't be read from sysfs.
+    pub discard_granularity: Option<u32>,
+    /// Whether the device is backed by rotational storage.
+    pub rotational: bool,
+}
+
+impl DeviceInfo {
+    /// Queries the properties of the block device at `p`.
+    pub fn new(p: &Path) -> Result<Self> {
+        let f = open_block_device(p)?;
+        let fd = f.as_raw_fd();
+
+        let mut size: usize = 0;
+        // SAFETY: kernel copies the return value out to `size`. The file is kept open until the
+        // end of this function.
+        unsafe { _blkgetsize64(fd, &mut size) }.context("BLKGETSIZE64 failed")?;
+        let mut logical_block_size: libc::c_int = 0;
+        // SAFETY: kernel copies the return value out to `logical_block_size`. The file is kept
+        // open until the end of this function.
+        unsafe { _blksszget(fd, &mut logical_block_size) }.context("BLKSSZGET failed")?;
+        let mut physical_block_size: libc::c_uint = 0;
+        // SAFETY: kernel copies the return value out to `physical_block_size`. The file is kept
+        // open until the end of this function.
+        unsafe { _blkpbszget(fd, &mut physical_block_size) }.context("BLKPBSZGET failed")?;
+        let mut rotational: libc::c_ushort = 0;
+        // SAFETY: kernel copies the return value out to `rotational`. The file is kept open until
+        // the end of this function.
+        unsafe { _blkrotational(fd, &mut rotational) }.context("BLKROTATIONAL failed")?;
+
+        // Unlike the ioctls above, sysfs may not be readable by the caller (e.g. under SELinux) so
+        // this is best-effort.
+        let rdev = f.metadata()?.rdev();
+        let discard_granularity = read_discard_granularity(rdev).ok().filter(|g| *g != 0);
+
+        Ok(Self {
+            size: size as u64,
+            logical_block_size: logical_block_size.try_into()?,
+            physical_block_size,
+            discard_granularity,
+            rotational: rotational != 0,
+        })
+    }
+}
+
+/// Reads the discard granularity of a block device from sysfs, as it has no dedicated ioctl.
+fn read_discard_granularity(rdev: u64) -> Result<u32> {
+    let (major, minor) = (nix::sys::stat::major(rdev), nix::sys::stat::minor(rdev));
+    let dev = Path::new("/sys/dev/block").join(format!("{major}:{minor}"));
+    // Partitions share the request queue of their parent device.
+    let queue = [dev.join("queue"), dev.join("../queue")]
+        .into_iter()
+        .find(|q| q.exists())
+        .with_context(|| format!("No request queue found for {:?}", dev))?;
+    let granularity = fs::read_to_string(queue.join("discard_granularity"))?;
+    Ok(granularity.trim().parse()?)
+}
+
+/// Returns whether a block device is read-only
+pub fn blkroget(p: &Path) -> Result<bool> {
+    let f = File::open(p)?;
+    if !f.metadata()?.file_type().is_block_device() {
+        bail!("{:?} is not a block device", p);
+    }
+    let mut ro: libc::c_int = 0;
+    // SAFETY: kernel copies the return value out to `ro`. The file is kept open until the end of
+    // this function.
+    unsafe { _blkroget(f.as_raw_fd(), &mut ro) }?;
+    Ok(ro != 0)
+}
+
+/// Discards and zeroes the whole content of a block device.
+///
+/// Discarding is only a hint to the underlying storage, which may not support it, so the content
+/// is then explicitly zeroed.
+pub fn wipe_block_device(p: &Path) -> Result<()> {
+    let size = blkgetsize64(p)?;
+    let f = OpenOptions::new().write(true).open(p)?;
+    let range = [0, size];
+    // SAFETY: the kernel only reads `range`. The file is kept open until the end of this function.
+    // Failure is ignored as BLKZEROOUT below is what guarantees that the content is destroyed.
+    let _ = unsafe { _blkdiscard(f.as_raw_fd(), &range) };
+    // SAFETY: the kernel only reads `range`. The file is kept open until the end of this function.
+    unsafe { _blkzeroout(f.as_raw_fd(), &range) }.context("BLKZEROOUT failed")?;
+    Ok(())
+}
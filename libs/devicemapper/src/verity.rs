@@ -19,9 +19,11 @@
 // which is then given to `DeviceMapper` to create a mapper device.
 
 use anyhow::{bail, Context, Result};
+use std::fmt;
 use std::io::Write;
 use std::mem::size_of;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use zerocopy::AsBytes;
 
 use crate::util::*;
@@ -32,7 +34,10 @@ use crate::DmTargetSpec;
 
 /// Device-Mapper’s “verity” target provides transparent integrity checking of block devices using
 /// a cryptographic digest provided by the kernel crypto API
-pub struct DmVerityTarget(Box<[u8]>);
+pub struct DmVerityTarget {
+    table: Box<[u8]>,
+    devices: [PathBuf; 2],
+}
 
 /// Version of the verity target spec.
 pub enum DmVerityVersion {
@@ -42,6 +47,7 @@ pub enum DmVerityVersion {
 
 /// The hash algorithm to use. SHA256 and SHA512 are supported.
 #[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum DmVerityHashAlgorithm {
     /// sha with 256 bit hash
     SHA256,
@@ -49,6 +55,29 @@ pub enum DmVerityHashAlgorithm {
     SHA512,
 }
 
+impl DmVerityHashAlgorithm {
+    /// Name of the algorithm in the kernel crypto API.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::SHA256 => "sha256",
+            Self::SHA512 => "sha512",
+        }
+    }
+}
+
+/// Parameters of a merkle tree, e.g. as parsed from the hashing info of an idsig file.
+#[derive(Clone, Copy, Debug)]
+pub struct DmVerityHashDescriptor<'a> {
+    /// Hash algorithm used to build the tree.
+    pub hash_algorithm: DmVerityHashAlgorithm,
+    /// Root digest of the tree.
+    pub root_digest: &'a [u8],
+    /// Salt used to build the tree, empty if none.
+    pub salt: &'a [u8],
+    /// Offset of the tree in the hash device, in bytes. Must be a multiple of its block size.
+    pub hash_offset: u64,
+}
+
 /// A builder that constructs `DmVerityTarget` struct.
 pub struct DmVerityTargetBuilder<'a> {
     version: DmVerityVersion,
@@ -58,12 +87,18 @@ pub struct DmVerityTargetBuilder<'a> {
     hash_algorithm: DmVerityHashAlgorithm,
     root_digest: Option<&'a [u8]>,
     salt: Option<&'a [u8]>,
+    hash_offset: u64,
 }
 
 impl DmVerityTarget {
     /// flatten into slice
     pub fn as_slice(&self) -> &[u8] {
-        self.0.as_ref()
+        self.table.as_ref()
+    }
+
+    /// The data and hash devices the target reads from.
+    pub(crate) fn devices(&self) -> &[PathBuf] {
+        &self.devices
     }
 }
 
@@ -77,6 +112,7 @@ impl<'a> Default for DmVerityTargetBuilder<'a> {
             hash_algorithm: DmVerityHashAlgorithm::SHA256,
             root_digest: None,
             salt: None,
+            hash_offset: 0,
         }
     }
 }
@@ -114,6 +150,15 @@ impl<'a> DmVerityTargetBuilder<'a> {
         self
     }
 
+    /// Sets the hash algorithm, root digest, salt and hash offset of the merkle tree at once.
+    pub fn hash_descriptor(&mut self, descriptor: &DmVerityHashDescriptor<'a>) -> &mut Self {
+        self.hash_algorithm = descriptor.hash_algorithm;
+        self.root_digest = Some(descriptor.root_digest);
+        self.salt = Some(descriptor.salt);
+        self.hash_offset = descriptor.hash_offset;
+        self
+    }
+
     /// Constructs a `DmVerityTarget`.
     pub fn build(&self) -> Result<DmVerityTarget> {
         // The `DmVerityTarget` struct actually is a flattened data consisting of a header and
@@ -143,24 +188,12 @@ impl<'a> DmVerityTargetBuilder<'a> {
             .to_str()
             .context("hash device path is not encoded in utf8")?;
         let stat = fstat(self.data_device.unwrap())?; // safe; checked just above
-        let hash_block_size = stat.st_blksize;
-
-        let hash_algorithm = match self.hash_algorithm {
-            DmVerityHashAlgorithm::SHA256 => "sha256",
-            DmVerityHashAlgorithm::SHA512 => "sha512",
-        };
-
-        let root_digest = if let Some(root_digest) = self.root_digest {
-            hex::encode(root_digest)
-        } else {
-            bail!("root digest is not set")
-        };
+        let hash_block_size = stat.st_blksize as u64;
+        if self.hash_offset % hash_block_size != 0 {
+            bail!("hash offset {} is not a multiple of {hash_block_size}", self.hash_offset);
+        }
 
-        let salt = if self.salt.is_none() || self.salt.unwrap().is_empty() {
-            "-".to_string() // Note. It's not an empty string!
-        } else {
-            hex::encode(self.salt.unwrap())
-        };
+        let Some(root_digest) = self.root_digest else { bail!("root digest is not set") };
 
         // Step2: serialize the information according to the spec, which is ...
         // DmTargetSpec{...}
@@ -172,19 +205,19 @@ impl<'a> DmVerityTargetBuilder<'a> {
         // null terminator
 
         // TODO(jiyong): support the optional parameters... if needed.
-        let mut body = String::new();
-        use std::fmt::Write;
-        write!(&mut body, "{} ", version)?;
-        write!(&mut body, "{} ", data_device_path)?;
-        write!(&mut body, "{} ", hash_device_path)?;
-        write!(&mut body, "{} ", data_block_size)?;
-        write!(&mut body, "{} ", hash_block_size)?;
-        write!(&mut body, "{} ", num_data_blocks)?;
-        write!(&mut body, "{} ", 0)?; // hash_start_block
-        write!(&mut body, "{} ", hash_algorithm)?;
-        write!(&mut body, "{} ", root_digest)?;
-        write!(&mut body, "{}", salt)?;
-        write!(&mut body, "\0")?; // null terminator
+        let params = DmVerityStatus {
+            version,
+            data_device: data_device_path.to_owned(),
+            hash_device: hash_device_path.to_owned(),
+            data_block_size,
+            hash_block_size,
+            num_data_blocks,
+            hash_start_block: self.hash_offset / hash_block_size,
+            hash_algorithm: self.hash_algorithm.name().to_owned(),
+            root_digest: root_digest.to_vec(),
+            salt: self.salt.unwrap_or_default().to_vec(),
+        };
+        let body = format!("{params}\0"); // null terminator
 
         let size = size_of::<DmTargetSpec>() + body.len();
         let aligned_size = (size + 7) & !7; // align to 8 byte boundaries
@@ -198,6 +231,96 @@ impl<'a> DmVerityTargetBuilder<'a> {
         buf.write_all(header.as_bytes())?;
         buf.write_all(body.as_bytes())?;
         buf.write_all(vec![0; padding].as_slice())?;
-        Ok(DmVerityTarget(buf.into_boxed_slice()))
+        let devices = [self.data_device.unwrap().into(), self.hash_device.unwrap().into()];
+        Ok(DmVerityTarget { table: buf.into_boxed_slice(), devices })
+    }
+}
+
+/// Parameters of an active verity target, as reported by the kernel.
+///
+/// Its `Display` implementation formats the parameters as the table of a verity target.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DmVerityStatus {
+    /// Version of the verity target spec.
+    pub version: u32,
+    /// The data device, as `<major>:<minor>`.
+    pub data_device: String,
+    /// The hash device, as `<major>:<minor>`.
+    pub hash_device: String,
+    /// Block size of the data device, in bytes.
+    pub data_block_size: u64,
+    /// Block size of the hash device, in bytes.
+    pub hash_block_size: u64,
+    /// Number of blocks of the data device.
+    pub num_data_blocks: u64,
+    /// Offset of the hash tree in the hash device, in blocks.
+    pub hash_start_block: u64,
+    /// Name of the hash algorithm (e.g. "sha256").
+    pub hash_algorithm: String,
+    /// Root digest of the merkle tree.
+    pub root_digest: Vec<u8>,
+    /// Salt of the merkle tree, empty if none.
+    pub salt: Vec<u8>,
+}
+
+impl fmt::Display for DmVerityStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {} {} {} {} ",
+            self.version,
+            self.data_device,
+            self.hash_device,
+            self.data_block_size,
+            self.hash_block_size,
+            self.num_data_blocks,
+            self.hash_start_block,
+            self.hash_algorithm,
+            hex::encode(&self.root_digest),
+        )?;
+        if self.salt.is_empty() {
+            write!(f, "-") // Note. It's not an empty string!
+        } else {
+            write!(f, "{}", hex::encode(&self.salt))
+        }
+    }
+}
+
+impl FromStr for DmVerityStatus {
+    type Err = anyhow::Error;
+
+    /// Parses the table of a verity target, in the same format as the one built by
+    /// `DmVerityTargetBuilder`. Optional parameters are ignored.
+    fn from_str(table: &str) -> Result<Self> {
+        let mut fields = table.split_ascii_whitespace();
+        let mut next = |name| fields.next().context(format!("missing {}", name));
+        let version = next("version")?.parse().context("invalid version")?;
+        let data_device = next("data device")?.to_owned();
+        let hash_device = next("hash device")?.to_owned();
+        let data_block_size =
+            next("data block size")?.parse().context("invalid data block size")?;
+        let hash_block_size =
+            next("hash block size")?.parse().context("invalid hash block size")?;
+        let num_data_blocks =
+            next("number of data blocks")?.parse().context("invalid data size")?;
+        let hash_start_block = next("hash start block")?.parse().context("invalid hash start")?;
+        let hash_algorithm = next("hash algorithm")?.to_owned();
+        let root_digest = hex::decode(next("root digest")?).context("invalid root digest")?;
+        let salt = match next("salt")? {
+            "-" => Vec::new(),
+            salt => hex::decode(salt).context("invalid salt")?,
+        };
+        Ok(Self {
+            version,
+            data_device,
+            hash_device,
+            data_block_size,
+            hash_block_size,
+            num_data_blocks,
+            hash_start_block,
+            hash_algorithm,
+            root_digest,
+            salt,
+        })
     }
 }
@@ -0,0 +1,110 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! no_std helpers to format bytes (e.g. digests) as hex, or to hide them when they are secrets.
+
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt;
+
+const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Displays bytes as contiguous lowercase hex digits, without allocating.
+#[derive(Clone, Copy)]
+pub struct Hex<'a>(pub &'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut buf = [0; 64];
+        for chunk in self.0.chunks(buf.len() / 2) {
+            let s = encode_to_slice(chunk, &mut buf).ok_or(fmt::Error)?;
+            f.write_str(s)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::LowerHex for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Displays the size of secret bytes but never their value.
+#[derive(Clone, Copy)]
+pub struct Redacted<'a>(pub &'a [u8]);
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<redacted: {} bytes>", self.0.len())
+    }
+}
+
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Writes `bytes` as lowercase hex digits to the start of `out`.
+///
+/// Returns the written digits or None if `out` is smaller than twice the size of `bytes`.
+pub fn encode_to_slice<'a>(bytes: &[u8], out: &'a mut [u8]) -> Option<&'a str> {
+    let out = out.get_mut(..bytes.len().checked_mul(2)?)?;
+    for (b, digits) in bytes.iter().zip(out.chunks_exact_mut(2)) {
+        digits[0] = DIGITS[usize::from(b >> 4)];
+        digits[1] = DIGITS[usize::from(b & 0xf)];
+    }
+    // The buffer only holds ASCII hex digits.
+    Some(core::str::from_utf8(out).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_display() {
+        assert_eq!(Hex(&[]).to_string(), "");
+        assert_eq!(Hex(&[0x00, 0x1f, 0xa0, 0xff]).to_string(), "001fa0ff");
+        assert_eq!(format!("{:x}", Hex(&[0xde, 0xad])), "dead");
+        assert_eq!(format!("{:?}", Hex(&[0xbe, 0xef])), "beef");
+    }
+
+    #[test]
+    fn hex_display_longer_than_buffer() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let expected: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+        assert_eq!(Hex(&bytes).to_string(), expected);
+    }
+
+    #[test]
+    fn redacted_hides_value() {
+        assert_eq!(Redacted(&[1, 2, 3]).to_string(), "<redacted: 3 bytes>");
+        assert_eq!(format!("{:?}", Redacted(&[0; 32])), "<redacted: 32 bytes>");
+    }
+
+    #[test]
+    fn encode_into_fixed_size_buffer() {
+        let mut buf = [0; 8];
+        assert_eq!(encode_to_slice(&[0x12, 0x34], &mut buf), Some("1234"));
+        assert_eq!(encode_to_slice(&[0; 5], &mut buf), None);
+    }
+}
@@ -22,6 +22,7 @@ mod gunyah;
 mod kvm;
 
 use crate::error::{Error, Result};
+use crate::trace::hvc64;
 use alloc::boxed::Box;
 use common::Hypervisor;
 pub use common::{
@@ -33,7 +34,6 @@ use gunyah::GunyahHypervisor;
 pub use kvm::KvmError;
 use kvm::{ProtectedKvmHypervisor, RegularKvmHypervisor};
 use once_cell::race::OnceBox;
-use smccc::hvc64;
 use uuid::Uuid;
 
 enum HypervisorBackend {
@@ -16,12 +16,10 @@
 
 use super::common::{Hypervisor, MemSharingHypervisor, MmioGuardedHypervisor};
 use crate::error::{Error, Result};
+use crate::trace::hvc64;
 use crate::util::page_address;
 use core::fmt::{self, Display, Formatter};
-use smccc::{
-    error::{positive_or_error_64, success_or_error_64},
-    hvc64,
-};
+use smccc::error::{positive_or_error_64, success_or_error_64};
 use uuid::{uuid, Uuid};
 
 pub(super) struct GeniezoneHypervisor;
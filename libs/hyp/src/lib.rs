@@ -18,6 +18,7 @@
 
 mod error;
 mod hypervisor;
+pub mod trace;
 mod util;
 
 pub use crate::hypervisor::DeviceAssigningHypervisor;
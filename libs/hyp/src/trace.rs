@@ -0,0 +1,132 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracing of the calls made to the hypervisor and to the secure monitor.
+//!
+//! This is a debugging aid: once enabled, the most recent calls are kept in a small ring buffer,
+//! to be reported when a call fails unexpectedly or the client panics.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::mutex::SpinMutex;
+
+/// Number of calls kept in the trace. Older calls are overwritten.
+const TRACE_LEN: usize = 32;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TRACE: SpinMutex<CallTrace> = SpinMutex::new(CallTrace::new());
+
+/// Instruction used to make a call.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Conduit {
+    /// Call to the hypervisor.
+    Hvc,
+    /// Call to the secure monitor.
+    Smc,
+}
+
+/// A call recorded in the trace.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TracedCall {
+    /// Instruction used to make the call.
+    pub conduit: Conduit,
+    /// SMCCC function ID.
+    pub function: u32,
+    /// First arguments of the call, which identify the target of most calls (e.g. an IPA).
+    pub args: [u64; 2],
+    /// First return value, holding the status of the call.
+    pub ret: u64,
+}
+
+impl fmt::Display for TracedCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let conduit = match self.conduit {
+            Conduit::Hvc => "HVC",
+            Conduit::Smc => "SMC",
+        };
+        let [arg0, arg1] = self.args;
+        // Errors are small negative numbers, so display them as such.
+        let ret = self.ret as i64;
+        write!(f, "{conduit} {:#010x}({arg0:#x}, {arg1:#x}) -> {ret}", self.function)
+    }
+}
+
+struct CallTrace {
+    calls: [TracedCall; TRACE_LEN],
+    /// Index of the slot for the next call.
+    next: usize,
+    /// Number of calls recorded, up to `TRACE_LEN`.
+    len: usize,
+}
+
+impl CallTrace {
+    const fn new() -> Self {
+        const EMPTY: TracedCall =
+            TracedCall { conduit: Conduit::Hvc, function: 0, args: [0; 2], ret: 0 };
+        Self { calls: [EMPTY; TRACE_LEN], next: 0, len: 0 }
+    }
+
+    fn push(&mut self, call: TracedCall) {
+        self.calls[self.next] = call;
+        self.next = (self.next + 1) % TRACE_LEN;
+        self.len = (self.len + 1).min(TRACE_LEN);
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &TracedCall> {
+        let start = (self.next + TRACE_LEN - self.len) % TRACE_LEN;
+        self.calls.iter().cycle().skip(start).take(self.len)
+    }
+}
+
+/// Starts or stops recording the calls. The calls already recorded are kept.
+pub fn set_call_tracing(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Calls `f` with each recorded call, from the oldest to the most recent.
+///
+/// Returns `false` without calling `f` if the trace is being updated, which happens if this is
+/// called while a call is being recorded (e.g. from a panic handler).
+pub fn for_each_traced_call(mut f: impl FnMut(&TracedCall)) -> bool {
+    let Some(trace) = TRACE.try_lock() else {
+        return false;
+    };
+    trace.iter().for_each(&mut f);
+    true
+}
+
+fn record(conduit: Conduit, function: u32, args: &[u64; 17], ret: u64) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let call = TracedCall { conduit, function, args: [args[0], args[1]], ret };
+    // Don't risk a deadlock by waiting for the trace, in the unlikely case it is already locked.
+    if let Some(mut trace) = TRACE.try_lock() {
+        trace.push(call);
+    }
+}
+
+/// Makes an HVC64 call, recording it if tracing is enabled.
+pub fn hvc64(function: u32, args: [u64; 17]) -> [u64; 18] {
+    let ret = smccc::hvc64(function, args);
+    record(Conduit::Hvc, function, &args, ret[0]);
+    ret
+}
+
+/// Makes an SMC64 call, recording it if tracing is enabled.
+pub fn smc64(function: u32, args: [u64; 17]) -> [u64; 18] {
+    let ret = smccc::smc64(function, args);
+    record(Conduit::Smc, function, &args, ret[0]);
+    ret
+}
@@ -0,0 +1,216 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Declarative construction of DTs from scratch.
+
+use crate::{fdt_err_expect_zero, Fdt, FdtError, Phandle, Result};
+use core::ffi::{c_void, CStr};
+use core::{mem, ptr, slice};
+use cstr::cstr;
+
+/// First stage of building a DT, declaring the entries of its memory reservation block.
+///
+/// Once they have been declared, [`FdtReserveMapBuilder::root`] consumes the builder to move on
+/// to the nodes and properties, so that no more entries can follow them.
+///
+/// ```ignore
+/// let fdt = FdtReserveMapBuilder::new(&mut buf)?
+///     .mem_rsv(0x8000_0000, 0x1000)?
+///     .root()?
+///     .property_u32(cstr!("#address-cells"), 2)?
+///     .finish()?;
+/// ```
+pub struct FdtReserveMapBuilder<'a> {
+    buf: &'a mut [u8],
+}
+
+impl<'a> FdtReserveMapBuilder<'a> {
+    /// Starts building a DT in `buf`.
+    pub fn new(buf: &'a mut [u8]) -> Result<Self> {
+        let len = buf.len().try_into().map_err(|_| FdtError::NoSpace)?;
+        // SAFETY: fdt_create() only writes within the first `len` bytes of `buf`.
+        let ret = unsafe { libfdt_bindgen::fdt_create(buf.as_mut_ptr().cast::<c_void>(), len) };
+        fdt_err_expect_zero(ret)?;
+
+        Ok(Self { buf })
+    }
+
+    /// Adds an entry to the memory reservation block.
+    pub fn mem_rsv(mut self, addr: u64, size: u64) -> Result<Self> {
+        // SAFETY: Accesses are constrained to the buffer initialized by fdt_create().
+        let ret =
+            unsafe { libfdt_bindgen::fdt_add_reservemap_entry(self.as_mut_ptr(), addr, size) };
+        fdt_err_expect_zero(ret)?;
+        Ok(self)
+    }
+
+    /// Completes the memory reservation block and starts building the root node.
+    pub fn root(mut self) -> Result<FdtBuilder<'a>> {
+        // SAFETY: The buffer was initialized by fdt_create() and accesses are constrained to it.
+        let ret = unsafe { libfdt_bindgen::fdt_finish_reservemap(self.as_mut_ptr()) };
+        fdt_err_expect_zero(ret)?;
+        let mut builder = FdtBuilder { buf: self.buf, subnodes_started: false };
+        builder.begin_node(cstr!(""))?;
+        Ok(builder)
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut c_void {
+        self.buf.as_mut_ptr().cast::<_>()
+    }
+}
+
+/// Builds a DT into a caller-provided buffer, in a single pass.
+///
+/// Nodes and properties are serialized in the order in which they are declared, using the
+/// sequential-write functions of libfdt. As required by the DT specification, the properties of a
+/// node must all be declared before its first subnode.
+///
+/// ```ignore
+/// let fdt = FdtBuilder::new(&mut buf)?
+///     .property_u32(cstr!("#address-cells"), 2)?
+///     .node(cstr!("chosen"), |chosen| {
+///         chosen.property_str(cstr!("bootargs"), cstr!("console=hvc0"))?;
+///         Ok(())
+///     })?
+///     .finish()?;
+/// ```
+pub struct FdtBuilder<'a> {
+    buf: &'a mut [u8],
+    subnodes_started: bool,
+}
+
+impl<'a> FdtBuilder<'a> {
+    /// Starts building a DT in `buf`, with an empty memory reservation block and an empty root.
+    ///
+    /// Use [`FdtReserveMapBuilder`] to declare a non-empty memory reservation block.
+    pub fn new(buf: &'a mut [u8]) -> Result<Self> {
+        FdtReserveMapBuilder::new(buf)?.root()
+    }
+
+    /// Adds a property with a raw value to the current node.
+    pub fn property(&mut self, name: &CStr, value: &[u8]) -> Result<&mut Self> {
+        self.property_placeholder(name, value.len())?.copy_from_slice(value);
+        Ok(self)
+    }
+
+    /// Adds an empty property to the current node.
+    pub fn property_empty(&mut self, name: &CStr) -> Result<&mut Self> {
+        self.property(name, &[])
+    }
+
+    /// Adds a single-cell property to the current node.
+    pub fn property_u32(&mut self, name: &CStr, value: u32) -> Result<&mut Self> {
+        self.property(name, &value.to_be_bytes())
+    }
+
+    /// Adds a two-cell property to the current node.
+    pub fn property_u64(&mut self, name: &CStr, value: u64) -> Result<&mut Self> {
+        self.property(name, &value.to_be_bytes())
+    }
+
+    /// Adds a property holding an array of cells to the current node.
+    pub fn property_cells(&mut self, name: &CStr, cells: &[u32]) -> Result<&mut Self> {
+        let len = cells.len().checked_mul(mem::size_of::<u32>()).ok_or(FdtError::BadValue)?;
+        let value = self.property_placeholder(name, len)?;
+        for (dest, cell) in value.chunks_exact_mut(mem::size_of::<u32>()).zip(cells) {
+            dest.copy_from_slice(&cell.to_be_bytes());
+        }
+        Ok(self)
+    }
+
+    /// Adds a string property to the current node.
+    pub fn property_str(&mut self, name: &CStr, value: &CStr) -> Result<&mut Self> {
+        self.property(name, value.to_bytes_with_nul())
+    }
+
+    /// Sets the phandle of the current node.
+    pub fn phandle(&mut self, phandle: Phandle) -> Result<&mut Self> {
+        self.property_u32(cstr!("phandle"), phandle.into())
+    }
+
+    /// Adds a subnode to the current node, populated by `f`.
+    pub fn node<F>(&mut self, name: &CStr, f: F) -> Result<&mut Self>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        self.begin_node(name)?;
+        self.subnodes_started = false;
+        f(self)?;
+        self.end_node()?;
+        self.subnodes_started = true;
+        Ok(self)
+    }
+
+    /// Completes the DT and returns it, spanning the whole buffer so that it can still be edited.
+    ///
+    /// The builder can't be used anymore afterwards.
+    pub fn finish(&mut self) -> Result<&'a mut Fdt> {
+        self.end_node()?;
+        // SAFETY: Accesses are constrained to the buffer initialized by fdt_create().
+        let ret = unsafe { libfdt_bindgen::fdt_finish(self.as_mut_ptr()?) };
+        fdt_err_expect_zero(ret)?;
+
+        let buf = mem::take(&mut self.buf);
+        let len = buf.len().try_into().map_err(|_| FdtError::NoSpace)?;
+        let ptr = buf.as_mut_ptr().cast::<c_void>();
+        // SAFETY: fdt_open_into() reads the DT completed above and writes within `len` bytes of
+        // the same buffer, which it supports.
+        let ret = unsafe { libfdt_bindgen::fdt_open_into(ptr, ptr, len) };
+        fdt_err_expect_zero(ret)?;
+
+        Fdt::from_mut_slice(buf)
+    }
+
+    /// Adds a property of `len` bytes to the current node and returns its uninitialized value.
+    fn property_placeholder(&mut self, name: &CStr, len: usize) -> Result<&mut [u8]> {
+        if self.subnodes_started {
+            return Err(FdtError::BadLayout);
+        }
+        let c_len = len.try_into().map_err(|_| FdtError::BadValue)?;
+        let mut value = ptr::null_mut();
+        // SAFETY: Accesses are constrained to the buffer initialized by fdt_create().
+        let ret = unsafe {
+            libfdt_bindgen::fdt_property_placeholder(
+                self.as_mut_ptr()?,
+                name.as_ptr(),
+                c_len,
+                &mut value,
+            )
+        };
+        fdt_err_expect_zero(ret)?;
+        // SAFETY: On success, fdt_property_placeholder() reserved `len` bytes at `value`, within
+        // the buffer exclusively borrowed by self.
+        Ok(unsafe { slice::from_raw_parts_mut(value.cast::<u8>(), len) })
+    }
+
+    fn begin_node(&mut self, name: &CStr) -> Result<()> {
+        // SAFETY: Accesses are constrained to the buffer initialized by fdt_create().
+        let ret = unsafe { libfdt_bindgen::fdt_begin_node(self.as_mut_ptr()?, name.as_ptr()) };
+        fdt_err_expect_zero(ret)
+    }
+
+    fn end_node(&mut self) -> Result<()> {
+        // SAFETY: Accesses are constrained to the buffer initialized by fdt_create().
+        let ret = unsafe { libfdt_bindgen::fdt_end_node(self.as_mut_ptr()?) };
+        fdt_err_expect_zero(ret)
+    }
+
+    fn as_mut_ptr(&mut self) -> Result<*mut c_void> {
+        // The buffer is only empty once it has been handed over by finish().
+        if self.buf.is_empty() {
+            return Err(FdtError::BadState);
+        }
+        Ok(self.buf.as_mut_ptr().cast::<_>())
+    }
+}
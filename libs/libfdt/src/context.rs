@@ -0,0 +1,168 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Errors annotated with the location in the DT at which they occurred.
+
+use crate::{FdtError, FdtNode, FdtNodeMut};
+use core::ffi::{c_int, CStr};
+use core::fmt;
+use core::result;
+
+/// Maximum length of a property name, as recorded in [`FdtErrorContext`]. Longer names are
+/// truncated.
+const MAX_PROPERTY_NAME_LEN: usize = 31;
+
+/// Location in the DT at which an error occurred.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct FdtErrorContext {
+    node_offset: Option<c_int>,
+    property: [u8; MAX_PROPERTY_NAME_LEN],
+    property_len: usize,
+    property_truncated: bool,
+}
+
+impl FdtErrorContext {
+    /// Creates a context referring to the node at `node_offset` in the structure block.
+    pub fn node(node_offset: c_int) -> Self {
+        Self { node_offset: Some(node_offset), ..Default::default() }
+    }
+
+    /// Records the name of the property being accessed, truncated if too long.
+    pub fn with_property(mut self, name: &CStr) -> Self {
+        let name = name.to_bytes();
+        self.property_len = name.len().min(MAX_PROPERTY_NAME_LEN);
+        self.property_truncated = name.len() > MAX_PROPERTY_NAME_LEN;
+        self.property[..self.property_len].copy_from_slice(&name[..self.property_len]);
+        self
+    }
+
+    /// Returns the offset of the node in the structure block, if known.
+    pub fn node_offset(&self) -> Option<c_int> {
+        self.node_offset
+    }
+
+    /// Returns the (possibly truncated) name of the property, if known.
+    pub fn property(&self) -> Option<&[u8]> {
+        (self.property_len > 0).then_some(&self.property[..self.property_len])
+    }
+}
+
+impl fmt::Display for FdtErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.node_offset {
+            Some(offset) => write!(f, "node at offset {offset:#x}")?,
+            None => write!(f, "unknown node")?,
+        }
+        if let Some(property) = self.property() {
+            write!(f, ", property '")?;
+            for c in property {
+                match c {
+                    c if c.is_ascii_graphic() => write!(f, "{}", char::from(*c))?,
+                    c => write!(f, "\\x{c:02x}")?,
+                }
+            }
+            write!(f, "'")?;
+            if self.property_truncated {
+                write!(f, "...")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An [`FdtError`] with an optional [`FdtErrorContext`].
+///
+/// Without the `error_context` feature, it is displayed as the underlying error alone, so that
+/// the formatting code of the context doesn't get linked into size-constrained binaries. The
+/// context remains available through [`FdtContextError::context`] and the `Debug` output.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FdtContextError {
+    error: FdtError,
+    context: Option<FdtErrorContext>,
+}
+
+impl FdtContextError {
+    /// Creates an error which occurred at the location described by `context`.
+    pub fn new(error: FdtError, context: FdtErrorContext) -> Self {
+        Self { error, context: Some(context) }
+    }
+
+    /// Returns the underlying error.
+    pub fn error(&self) -> FdtError {
+        self.error
+    }
+
+    /// Returns the location at which the error occurred, if known.
+    pub fn context(&self) -> Option<&FdtErrorContext> {
+        self.context.as_ref()
+    }
+}
+
+impl From<FdtError> for FdtContextError {
+    fn from(error: FdtError) -> Self {
+        Self { error, context: None }
+    }
+}
+
+impl From<FdtContextError> for FdtError {
+    fn from(error: FdtContextError) -> Self {
+        error.error
+    }
+}
+
+impl fmt::Display for FdtContextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        #[cfg(feature = "error_context")]
+        if let Some(context) = &self.context {
+            write!(f, " ({context})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Extension trait attaching the location of a failure to [`crate::Result`].
+pub trait FdtResultExt<T> {
+    /// Annotates the error, if any, with the node on which the operation failed.
+    fn node_context(self, node: &FdtNode) -> result::Result<T, FdtContextError>;
+
+    /// Annotates the error, if any, with the node and property on which the operation failed.
+    fn property_context(self, node: &FdtNode, name: &CStr) -> result::Result<T, FdtContextError>;
+}
+
+impl<T> FdtResultExt<T> for result::Result<T, FdtError> {
+    fn node_context(self, node: &FdtNode) -> result::Result<T, FdtContextError> {
+        self.map_err(|e| FdtContextError::new(e, FdtErrorContext::node(node.offset)))
+    }
+
+    fn property_context(self, node: &FdtNode, name: &CStr) -> result::Result<T, FdtContextError> {
+        self.map_err(|e| {
+            FdtContextError::new(e, FdtErrorContext::node(node.offset).with_property(name))
+        })
+    }
+}
+
+impl<'a> FdtNodeMut<'a> {
+    /// Returns a context referring to this node, to annotate errors with.
+    pub fn error_context(&self) -> FdtErrorContext {
+        FdtErrorContext::node(self.offset)
+    }
+}
+
+impl<'a> FdtNode<'a> {
+    /// Returns a context referring to this node, to annotate errors with.
+    pub fn error_context(&self) -> FdtErrorContext {
+        FdtErrorContext::node(self.offset)
+    }
+}
@@ -0,0 +1,128 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Structural comparison of DTs.
+
+use crate::{Fdt, FdtNode, Result};
+use core::ffi::CStr;
+
+/// A difference between two DTs, as reported by [`Fdt::diff`].
+///
+/// `'a` is the lifetime of the original DT and `'b` the one of the DT it is compared to.
+#[derive(Clone, Copy, Debug)]
+pub enum DiffEvent<'a, 'b> {
+    /// A node only exists in the other DT. Its properties and subnodes aren't reported.
+    NodeAdded(FdtNode<'b>),
+    /// A node only exists in the original DT. Its properties and subnodes aren't reported.
+    NodeRemoved(FdtNode<'a>),
+    /// A property only exists in the other DT.
+    PropertyAdded {
+        /// Node of the other DT holding the property.
+        node: FdtNode<'b>,
+        /// Name of the property.
+        name: &'b CStr,
+        /// Value of the property.
+        value: &'b [u8],
+    },
+    /// A property only exists in the original DT.
+    PropertyRemoved {
+        /// Node of the original DT holding the property.
+        node: FdtNode<'a>,
+        /// Name of the property.
+        name: &'a CStr,
+        /// Value of the property.
+        value: &'a [u8],
+    },
+    /// A property has different values in the two DTs.
+    PropertyChanged {
+        /// Node of the original DT holding the property.
+        node: FdtNode<'a>,
+        /// Name of the property.
+        name: &'a CStr,
+        /// Value of the property in the original DT.
+        old: &'a [u8],
+        /// Value of the property in the other DT.
+        new: &'b [u8],
+    },
+}
+
+impl Fdt {
+    /// Compares this DT to `other`, reporting each difference to `visitor`.
+    ///
+    /// Nodes are matched by path and properties by name, so the order in which they are stored,
+    /// the layout of the blobs and the memory reservation blocks are ignored. The differences in
+    /// the properties of a node are reported before the ones in its subnodes.
+    pub fn diff<'a, 'b, F>(&'a self, other: &'b Fdt, mut visitor: F) -> Result<()>
+    where
+        F: FnMut(DiffEvent<'a, 'b>),
+    {
+        diff_nodes(self.root()?, other.root()?, &mut visitor)
+    }
+}
+
+fn diff_nodes<'a, 'b, F>(old: FdtNode<'a>, new: FdtNode<'b>, visitor: &mut F) -> Result<()>
+where
+    F: FnMut(DiffEvent<'a, 'b>),
+{
+    let mut property = old.first_property()?;
+    while let Some(prop) = property {
+        let name = prop.name()?;
+        let value = prop.value()?;
+        match new.getprop(name)? {
+            None => visitor(DiffEvent::PropertyRemoved { node: old, name, value }),
+            Some(new_value) if new_value != value => {
+                visitor(DiffEvent::PropertyChanged { node: old, name, old: value, new: new_value })
+            }
+            Some(_) => {}
+        }
+        property = prop.next_property()?;
+    }
+    let mut property = new.first_property()?;
+    while let Some(prop) = property {
+        let name = prop.name()?;
+        if old.getprop(name)?.is_none() {
+            visitor(DiffEvent::PropertyAdded { node: new, name, value: prop.value()? });
+        }
+        property = prop.next_property()?;
+    }
+
+    let mut subnode = old.first_subnode()?;
+    while let Some(node) = subnode {
+        match find_subnode(&new, node.name()?)? {
+            Some(other) => diff_nodes(node, other, visitor)?,
+            None => visitor(DiffEvent::NodeRemoved(node)),
+        }
+        subnode = node.next_subnode()?;
+    }
+    let mut subnode = new.first_subnode()?;
+    while let Some(node) = subnode {
+        if find_subnode(&old, node.name()?)?.is_none() {
+            visitor(DiffEvent::NodeAdded(node));
+        }
+        subnode = node.next_subnode()?;
+    }
+    Ok(())
+}
+
+fn find_subnode<'a>(node: &FdtNode<'a>, name: &CStr) -> Result<Option<FdtNode<'a>>> {
+    // fdt_subnode_offset() would match "cpu" to "cpu@0" so compare the full names instead.
+    let mut subnode = node.first_subnode()?;
+    while let Some(candidate) = subnode {
+        if candidate.name()? == name {
+            return Ok(Some(candidate));
+        }
+        subnode = candidate.next_subnode()?;
+    }
+    Ok(None)
+}
@@ -0,0 +1,142 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rendering of DTs in the DTS syntax.
+
+use crate::{Fdt, FdtNode, FdtProperty};
+use core::fmt;
+
+impl Fdt {
+    /// Writes the tree to `w` in the DTS syntax, for debugging purposes.
+    ///
+    /// Property values are printed as strings, cells or bytes, based on what they look like. Parts
+    /// of the tree which can't be read are replaced with comments describing the error.
+    pub fn write_dts<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        writeln!(w, "/dts-v1/;")?;
+        writeln!(w)?;
+        for range in self.mem_rsv_iter() {
+            match range {
+                Ok(range) => {
+                    writeln!(w, "/memreserve/ {:#x} {:#x};", range.start, range.end - range.start)?
+                }
+                Err(e) => writeln!(w, "/* memreserve: {e} */")?,
+            }
+        }
+        match self.root() {
+            Ok(root) => write_node(w, &root, 0),
+            Err(e) => writeln!(w, "/* root node: {e} */"),
+        }
+    }
+}
+
+fn write_node<W: fmt::Write>(w: &mut W, node: &FdtNode, depth: usize) -> fmt::Result {
+    write_indent(w, depth)?;
+    match node.name() {
+        Ok(name) if name.is_empty() => write!(w, "/")?,
+        Ok(name) => write_escaped(w, name.to_bytes())?,
+        Err(e) => write!(w, "/* name: {e} */")?,
+    }
+    writeln!(w, " {{")?;
+    match node.properties() {
+        Ok(properties) => {
+            for property in properties {
+                write_property(w, &property, depth + 1)?;
+            }
+        }
+        Err(e) => {
+            write_indent(w, depth + 1)?;
+            writeln!(w, "/* properties: {e} */")?;
+        }
+    }
+    match node.subnodes() {
+        Ok(subnodes) => {
+            for subnode in subnodes {
+                writeln!(w)?;
+                write_node(w, &subnode, depth + 1)?;
+            }
+        }
+        Err(e) => {
+            write_indent(w, depth + 1)?;
+            writeln!(w, "/* subnodes: {e} */")?;
+        }
+    }
+    write_indent(w, depth)?;
+    writeln!(w, "}};")
+}
+
+fn write_property<W: fmt::Write>(w: &mut W, property: &FdtProperty, depth: usize) -> fmt::Result {
+    write_indent(w, depth)?;
+    match property.name() {
+        Ok(name) => write_escaped(w, name.to_bytes())?,
+        Err(e) => write!(w, "/* name: {e} */")?,
+    }
+    match property.value() {
+        Ok([]) => {}
+        Ok(value) if is_string_list(value) => {
+            write!(w, " = ")?;
+            for (i, s) in value[..value.len() - 1].split(|c| *c == b'\0').enumerate() {
+                if i > 0 {
+                    write!(w, ", ")?;
+                }
+                write!(w, "\"")?;
+                write_escaped(w, s)?;
+                write!(w, "\"")?;
+            }
+        }
+        Ok(value) if value.len() % 4 == 0 => {
+            write!(w, " = <")?;
+            for (i, cell) in value.chunks_exact(4).enumerate() {
+                let separator = if i > 0 { " " } else { "" };
+                let cell = u32::from_be_bytes(cell.try_into().unwrap());
+                write!(w, "{separator}{cell:#x}")?;
+            }
+            write!(w, ">")?;
+        }
+        Ok(value) => {
+            write!(w, " = [")?;
+            for (i, byte) in value.iter().enumerate() {
+                let separator = if i > 0 { " " } else { "" };
+                write!(w, "{separator}{byte:02x}")?;
+            }
+            write!(w, "]")?;
+        }
+        Err(e) => write!(w, " /* value: {e} */")?,
+    }
+    writeln!(w, ";")
+}
+
+/// Returns whether `value` looks like a list of nul-terminated printable strings.
+fn is_string_list(value: &[u8]) -> bool {
+    let Some((b'\0', strings)) = value.split_last() else {
+        return false;
+    };
+    strings
+        .split(|c| *c == b'\0')
+        .all(|s| !s.is_empty() && s.iter().all(|c| c.is_ascii_graphic() || *c == b' '))
+}
+
+fn write_escaped<W: fmt::Write>(w: &mut W, s: &[u8]) -> fmt::Result {
+    for c in s {
+        match c {
+            b'"' | b'\\' => write!(w, "\\{}", char::from(*c))?,
+            c if c.is_ascii_graphic() || *c == b' ' => write!(w, "{}", char::from(*c))?,
+            c => write!(w, "\\x{c:02x}")?,
+        }
+    }
+    Ok(())
+}
+
+fn write_indent<W: fmt::Write>(w: &mut W, depth: usize) -> fmt::Result {
+    (0..depth).try_for_each(|_| w.write_char('\t'))
+}
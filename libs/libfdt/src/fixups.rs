@@ -0,0 +1,196 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parsing of the phandle fixups of unapplied DT overlays.
+
+use crate::{CellIterator, Fdt, FdtError, FdtNode, FdtProperty, Result, StringListIterator};
+use core::ffi::CStr;
+use cstr::cstr;
+
+const LOCAL_FIXUPS_PATH: &[u8] = b"/__local_fixups__";
+
+/// A reference to a phandle defined outside of the overlay, from `/__fixups__`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Fixup<'a> {
+    /// Label of the referenced node, to be resolved using the `/__symbols__` of the base DT.
+    pub label: &'a CStr,
+    /// Path of the node holding the reference, within the overlay.
+    pub path: &'a str,
+    /// Name of the property holding the reference.
+    pub property: &'a str,
+    /// Offset of the reference within the property value, in bytes.
+    pub offset: u32,
+}
+
+impl<'a> Fixup<'a> {
+    /// Parses a `<path>:<property>:<offset>` entry.
+    fn parse(label: &'a CStr, entry: &'a CStr) -> Result<Self> {
+        let entry = entry.to_str().map_err(|_| FdtError::BadValue)?;
+        let mut fields = entry.rsplitn(3, ':');
+        let offset = fields.next().ok_or(FdtError::BadValue)?;
+        let offset = offset.parse().map_err(|_| FdtError::BadValue)?;
+        let property = fields.next().ok_or(FdtError::BadValue)?;
+        let path = fields.next().ok_or(FdtError::BadValue)?;
+        Ok(Self { label, path, property, offset })
+    }
+}
+
+/// A reference to a phandle defined within the overlay, from `/__local_fixups__`.
+#[derive(Clone, Copy, Debug)]
+pub struct LocalFixup<'a> {
+    /// Node of `/__local_fixups__` mirroring the node holding the reference.
+    pub node: FdtNode<'a>,
+    /// Name of the property holding the reference.
+    pub property: &'a CStr,
+    /// Offset of the reference within the property value, in bytes.
+    pub offset: u32,
+}
+
+impl<'a> LocalFixup<'a> {
+    /// Writes the path of the node holding the reference, within the overlay, into `buf` and
+    /// returns it.
+    ///
+    /// Fails with [`FdtError::NoSpace`] if `buf` is too small to hold the path of the mirroring
+    /// node in `/__local_fixups__`.
+    pub fn path<'b>(&self, buf: &'b mut [u8]) -> Result<&'b CStr> {
+        let len = self.node.path_into(buf)?;
+        let relative = buf.get(LOCAL_FIXUPS_PATH.len()..=len).ok_or(FdtError::Internal)?;
+        let start = match relative {
+            // The node is /__local_fixups__ itself, which mirrors the root node.
+            [b'\0'] => LOCAL_FIXUPS_PATH.len() - 1,
+            [b'/', ..] => LOCAL_FIXUPS_PATH.len(),
+            _ => return Err(FdtError::Internal),
+        };
+        buf.copy_within(start..=len, 0);
+        if start == LOCAL_FIXUPS_PATH.len() - 1 {
+            buf[0] = b'/';
+        }
+        CStr::from_bytes_with_nul(&buf[..=(len - start)]).map_err(|_| FdtError::Internal)
+    }
+}
+
+impl Fdt {
+    /// Returns the references of the `/__fixups__` node, if any.
+    ///
+    /// Entries which can't be parsed are reported as errors, without ending the iteration.
+    pub fn fixups(&self) -> Result<Option<FixupIterator>> {
+        let Some(node) = self.node(cstr!("/__fixups__"))? else {
+            return Ok(None);
+        };
+        Ok(Some(FixupIterator { property: node.first_property()?, entries: None }))
+    }
+
+    /// Returns the references of the `/__local_fixups__` node, if any.
+    ///
+    /// Properties whose values aren't arrays of cells are reported as errors, without ending the
+    /// iteration.
+    pub fn local_fixups(&self) -> Result<Option<LocalFixupIterator>> {
+        let Some(node) = self.node(cstr!("/__local_fixups__"))? else {
+            return Ok(None);
+        };
+        Ok(Some(LocalFixupIterator {
+            node: Some((node, 0)),
+            property: node.first_property()?,
+            cells: None,
+        }))
+    }
+}
+
+/// Iterator over the references of a `/__fixups__` node.
+#[derive(Debug)]
+pub struct FixupIterator<'a> {
+    property: Option<FdtProperty<'a>>,
+    entries: Option<(&'a CStr, StringListIterator<'a>)>,
+}
+
+impl<'a> Iterator for FixupIterator<'a> {
+    type Item = Result<Fixup<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((label, ref mut entries)) = self.entries {
+                if let Some(entry) = entries.next() {
+                    return Some(Fixup::parse(label, entry));
+                }
+                self.entries = None;
+            }
+            let property = self.property.take()?;
+            match property.next_property() {
+                Ok(next) => self.property = next,
+                Err(e) => return Some(Err(e)),
+            }
+            let entries = property
+                .name()
+                .and_then(|label| Ok((label, StringListIterator::new(property.value()?)?)));
+            match entries {
+                Ok(entries) => self.entries = Some(entries),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Iterator over the references of a `/__local_fixups__` node.
+#[derive(Debug)]
+pub struct LocalFixupIterator<'a> {
+    /// Node being visited and its depth relative to `/__local_fixups__`.
+    node: Option<(FdtNode<'a>, usize)>,
+    property: Option<FdtProperty<'a>>,
+    cells: Option<(&'a CStr, CellIterator<'a>)>,
+}
+
+impl<'a> Iterator for LocalFixupIterator<'a> {
+    type Item = Result<LocalFixup<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, depth) = self.node?;
+            if let Some((property, ref mut cells)) = self.cells {
+                if let Some(offset) = cells.next() {
+                    return Some(Ok(LocalFixup { node, property, offset }));
+                }
+                self.cells = None;
+            }
+            let Some(property) = self.property.take() else {
+                self.node = None;
+                let (next, depth) = match node.next_node(depth) {
+                    Ok(Some((next, depth))) if depth > 0 => (next, depth),
+                    Ok(_) => return None,
+                    Err(e) => return Some(Err(e)),
+                };
+                self.node = Some((next, depth));
+                match next.first_property() {
+                    Ok(property) => self.property = property,
+                    Err(e) => return Some(Err(e)),
+                }
+                continue;
+            };
+            match property.next_property() {
+                Ok(next) => self.property = next,
+                Err(e) => return Some(Err(e)),
+            }
+            let cells = property.name().and_then(|name| {
+                let value = property.value()?;
+                if value.len() % 4 != 0 {
+                    return Err(FdtError::BadValue);
+                }
+                Ok((name, CellIterator::new(value)))
+            });
+            match cells {
+                Ok(cells) => self.cells = Some(cells),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
@@ -14,25 +14,36 @@
 
 //! Iterators over cells, and various layers on top of them.
 
+use crate::fdt_err;
 use crate::Fdt;
 use crate::FdtError;
 use crate::FdtNode;
 use crate::FdtProperty;
 use crate::{AddrCells, SizeCells};
-use core::ffi::CStr;
+use core::ffi::{c_int, CStr};
 use core::marker::PhantomData;
 use core::{mem::size_of, ops::Range, slice::ChunksExact};
+use cstr::cstr;
 
 /// Iterator over nodes sharing a same compatible string.
 pub struct CompatibleIterator<'a> {
     node: FdtNode<'a>,
     compatible: &'a CStr,
+    end: Option<c_int>,
 }
 
 impl<'a> CompatibleIterator<'a> {
     pub(crate) fn new(fdt: &'a Fdt, compatible: &'a CStr) -> Result<Self, FdtError> {
         let node = fdt.root()?;
-        Ok(Self { node, compatible })
+        Ok(Self { node, compatible, end: None })
+    }
+
+    pub(crate) fn new_in_subtree(
+        node: FdtNode<'a>,
+        compatible: &'a CStr,
+    ) -> Result<Self, FdtError> {
+        let end = node.subtree_end()?;
+        Ok(Self { node, compatible, end })
     }
 }
 
@@ -41,6 +52,8 @@ impl<'a> Iterator for CompatibleIterator<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         let next = self.node.next_compatible(self.compatible).ok()?;
+        // Nodes of a subtree are contiguous, so any match past its end is outside of it.
+        let next = next.filter(|node| self.end.map_or(true, |end| node.offset < end));
 
         if let Some(node) = next {
             self.node = node;
@@ -50,6 +63,33 @@ impl<'a> Iterator for CompatibleIterator<'a> {
     }
 }
 
+/// Iterator over nodes having a property with a given value.
+#[derive(Debug)]
+pub struct PropValueIterator<'a> {
+    fdt: &'a Fdt,
+    offset: c_int,
+    name: &'a CStr,
+    value: &'a [u8],
+}
+
+impl<'a> PropValueIterator<'a> {
+    pub(crate) fn new(fdt: &'a Fdt, name: &'a CStr, value: &'a [u8]) -> Self {
+        // Start before the root node, so that it is matched too.
+        Self { fdt, offset: -1, name, value }
+    }
+}
+
+impl<'a> Iterator for PropValueIterator<'a> {
+    type Item = FdtNode<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset =
+            self.fdt.node_offset_by_prop_value(self.offset, self.name, self.value).ok()??;
+        self.offset = offset;
+        Some(FdtNode { fdt: self.fdt, offset })
+    }
+}
+
 /// Iterator over cells of a DT property.
 #[derive(Debug)]
 pub struct CellIterator<'a> {
@@ -72,6 +112,121 @@ impl<'a> Iterator for CellIterator<'a> {
     }
 }
 
+/// Iterator over the <u32> values of a DT property.
+#[derive(Debug)]
+pub struct U32ArrayIterator<'a> {
+    chunks: ChunksExact<'a, u8>,
+}
+
+impl<'a> U32ArrayIterator<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Result<Self, FdtError> {
+        Ok(Self { chunks: exact_chunks(bytes, size_of::<u32>())? })
+    }
+}
+
+impl<'a> Iterator for U32ArrayIterator<'a> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(Self::Item::from_be_bytes(self.chunks.next()?.try_into().ok()?))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for U32ArrayIterator<'a> {}
+
+/// Iterator over the <u64> values of a DT property.
+#[derive(Debug)]
+pub struct U64ArrayIterator<'a> {
+    chunks: ChunksExact<'a, u8>,
+}
+
+impl<'a> U64ArrayIterator<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Result<Self, FdtError> {
+        Ok(Self { chunks: exact_chunks(bytes, size_of::<u64>())? })
+    }
+}
+
+impl<'a> Iterator for U64ArrayIterator<'a> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(Self::Item::from_be_bytes(self.chunks.next()?.try_into().ok()?))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for U64ArrayIterator<'a> {}
+
+/// Iterator over the cells of a DT property, taking `N` cells at a time (e.g. 'interrupt-map').
+#[derive(Debug)]
+pub struct CellChunkIterator<'a, const N: usize> {
+    chunks: ChunksExact<'a, u8>,
+}
+
+impl<'a, const N: usize> CellChunkIterator<'a, N> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Result<Self, FdtError> {
+        Ok(Self { chunks: exact_chunks(bytes, N * size_of::<u32>())? })
+    }
+}
+
+impl<'a, const N: usize> Iterator for CellChunkIterator<'a, N> {
+    type Item = [u32; N];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chunk = [0; N];
+        for (cell, value) in chunk.iter_mut().zip(CellIterator::new(self.chunks.next()?)) {
+            *cell = value;
+        }
+        Some(chunk)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+impl<'a, const N: usize> ExactSizeIterator for CellChunkIterator<'a, N> {}
+
+/// Splits `bytes` in chunks of `size`, failing if it doesn't hold a whole number of chunks.
+fn exact_chunks(bytes: &[u8], size: usize) -> Result<ChunksExact<u8>, FdtError> {
+    if size == 0 || bytes.len() % size != 0 {
+        return Err(FdtError::BadValue);
+    }
+    Ok(bytes.chunks_exact(size))
+}
+
+/// Iterator over the strings of a <stringlist> DT property, such as 'compatible'.
+#[derive(Debug)]
+pub struct StringListIterator<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StringListIterator<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Result<Self, FdtError> {
+        if bytes.last().is_some_and(|b| *b != 0) {
+            return Err(FdtError::BadValue);
+        }
+        Ok(Self { bytes })
+    }
+}
+
+impl<'a> Iterator for StringListIterator<'a> {
+    type Item = &'a CStr;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let s = CStr::from_bytes_until_nul(self.bytes).ok()?;
+        self.bytes = &self.bytes[s.to_bytes_with_nul().len()..];
+        Some(s)
+    }
+}
+
 /// Iterator over a 'reg' property of a DT node.
 #[derive(Debug)]
 pub struct RegIterator<'a> {
@@ -102,10 +257,61 @@ impl<T: TryInto<usize>> TryFrom<Reg<T>> for Range<usize> {
     }
 }
 
+impl<T: Copy + Into<u64>> Reg<T> {
+    /// Returns the end of the region, which fails if it has no size or if the end overflows.
+    pub fn end(&self) -> Result<u64, FdtError> {
+        let size = self.size.ok_or(FdtError::NotFound)?;
+        self.addr.into().checked_add(size.into()).ok_or(FdtError::BadValue)
+    }
+
+    /// Returns the region as a `Range<u64>`, which fails if it has no size or if its end overflows.
+    pub fn range(&self) -> Result<Range<u64>, FdtError> {
+        Ok(self.addr.into()..self.end()?)
+    }
+
+    /// Returns whether the region contains `addr`, which fails if the region can't be computed.
+    pub fn contains(&self, addr: u64) -> Result<bool, FdtError> {
+        Ok(self.range()?.contains(&addr))
+    }
+
+    /// Returns whether the region overlaps with `other`, which fails if the region can't be
+    /// computed.
+    pub fn overlaps(&self, other: &Range<u64>) -> Result<bool, FdtError> {
+        Ok(overlaps(&self.range()?, other))
+    }
+
+    /// Returns the region as a `Range<usize>`, which fails if it has no size, if its end
+    /// overflows, or if it extends beyond `max_end`.
+    pub fn try_into_range(&self, max_end: usize) -> Result<Range<usize>, FdtError> {
+        to_usize_range(self.addr.into(), self.end()?, max_end)
+    }
+}
+
+impl TryFrom<Range<usize>> for Reg<u64> {
+    type Error = FdtError;
+
+    fn try_from(range: Range<usize>) -> Result<Self, Self::Error> {
+        let to_u64 = |n: usize| u64::try_from(n).map_err(|_| FdtError::BadValue);
+        Ok(Self { addr: to_u64(range.start)?, size: Some(to_u64(range.len())?) })
+    }
+}
+
+fn overlaps(a: &Range<u64>, b: &Range<u64>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
 fn to_usize<T: TryInto<usize>>(num: T) -> Result<usize, FdtError> {
     num.try_into().map_err(|_| FdtError::BadValue)
 }
 
+fn to_usize_range(start: u64, end: u64, max_end: usize) -> Result<Range<usize>, FdtError> {
+    let end = to_usize(end)?;
+    if end > max_end {
+        return Err(FdtError::BadValue);
+    }
+    Ok(to_usize(start)?..end)
+}
+
 impl<'a> RegIterator<'a> {
     pub(crate) fn new(
         cells: CellIterator<'a>,
@@ -173,11 +379,73 @@ impl<'a> Iterator for MemRegIterator<'a> {
     type Item = Range<usize>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next = self.reg.next()?;
-        let addr = usize::try_from(next.addr).ok()?;
-        let size = usize::try_from(next.size?).ok()?;
+        self.reg.next()?.try_into_range(usize::MAX).ok()
+    }
+}
+
+/// Iterator over the address ranges of all the memory nodes of a DT.
+///
+/// Unlike [`MemRegIterator`], this reports the ranges which can't be represented as errors.
+#[derive(Debug)]
+pub struct MemoryBanksIterator<'a> {
+    nodes: PropValueIterator<'a>,
+    reg: Option<RegIterator<'a>>,
+}
+
+impl<'a> MemoryBanksIterator<'a> {
+    pub(crate) fn new(fdt: &'a Fdt) -> Self {
+        Self { nodes: fdt.nodes_with_device_type(cstr!("memory")), reg: None }
+    }
+}
+
+impl<'a> Iterator for MemoryBanksIterator<'a> {
+    type Item = Result<Range<usize>, FdtError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(reg) = self.reg.as_mut().and_then(Iterator::next) {
+                return Some(reg.try_into());
+            }
+            match self.nodes.next()?.reg() {
+                Ok(Some(reg)) => self.reg = Some(reg),
+                Ok(None) => return Some(Err(FdtError::BadValue)),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Iterator over the entries of the memory reservation block.
+///
+/// Stops after yielding the first error, e.g. for an entry whose range overflows.
+#[derive(Debug)]
+pub struct MemRsvIterator<'a> {
+    fdt: &'a Fdt,
+    index: Option<usize>,
+}
+
+impl<'a> MemRsvIterator<'a> {
+    pub(crate) fn new(fdt: &'a Fdt) -> Self {
+        Self { fdt, index: Some(0) }
+    }
+}
+
+impl<'a> Iterator for MemRsvIterator<'a> {
+    type Item = Result<Range<u64>, FdtError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.index.take()?;
+        let entry = match self.fdt.mem_rsv(index) {
+            Ok(entry) => entry?,
+            Err(e) => return Some(Err(e)),
+        };
+        let (addr, size) = entry;
+        let Some(end) = addr.checked_add(size) else {
+            return Some(Err(FdtError::BadValue));
+        };
+        self.index = Some(index + 1);
 
-        Some(addr..addr.checked_add(size)?)
+        Some(Ok(addr..end))
     }
 }
 
@@ -204,6 +472,72 @@ pub struct AddressRange<A, P, S> {
     pub size: S,
 }
 
+impl<A: AddrOffset, P: AddrOffset, S: Copy + Into<u64>> AddressRange<A, P, S> {
+    /// Returns the end of the range in the child's address space, which fails on overflow.
+    pub fn end(&self) -> Result<u64, FdtError> {
+        self.addr.offset().checked_add(self.size.into()).ok_or(FdtError::BadValue)
+    }
+
+    /// Returns the end of the range in the parent's address space, which fails on overflow.
+    pub fn parent_end(&self) -> Result<u64, FdtError> {
+        self.parent_addr.offset().checked_add(self.size.into()).ok_or(FdtError::BadValue)
+    }
+
+    /// Returns the range in the child's address space as a `Range<u64>`, which fails if its end
+    /// overflows.
+    pub fn range(&self) -> Result<Range<u64>, FdtError> {
+        Ok(self.addr.offset()..self.end()?)
+    }
+
+    /// Returns whether the range contains `addr`, in the child's address space. This fails if the
+    /// range can't be computed.
+    pub fn contains(&self, addr: u64) -> Result<bool, FdtError> {
+        Ok(self.range()?.contains(&addr))
+    }
+
+    /// Returns whether the range overlaps with `other`, in the child's address space. This fails
+    /// if the range can't be computed.
+    pub fn overlaps(&self, other: &Range<u64>) -> Result<bool, FdtError> {
+        Ok(overlaps(&self.range()?, other))
+    }
+
+    /// Translates `addr` from the child's address space into the parent's, returning `None` if it
+    /// isn't part of the range. This fails if the range can't be computed or if the translated
+    /// address overflows.
+    pub fn translate(&self, addr: u64) -> Result<Option<u64>, FdtError> {
+        if !self.contains(addr)? {
+            return Ok(None);
+        }
+        let offset = addr - self.addr.offset();
+        self.parent_addr.offset().checked_add(offset).map(Some).ok_or(FdtError::BadValue)
+    }
+
+    /// Returns the range in the child's address space as a `Range<usize>`, which fails if its end
+    /// overflows or if it extends beyond `max_end`.
+    pub fn try_into_range(&self, max_end: usize) -> Result<Range<usize>, FdtError> {
+        to_usize_range(self.addr.offset(), self.end()?, max_end)
+    }
+}
+
+/// Addresses from which an offset within their address space can be extracted, ignoring any flags
+/// they might encode, as PCI addresses do.
+pub trait AddrOffset: Copy {
+    /// Returns the offset within the address space.
+    fn offset(&self) -> u64;
+}
+
+impl AddrOffset for u64 {
+    fn offset(&self) -> u64 {
+        *self
+    }
+}
+
+impl AddrOffset for (u32, u64) {
+    fn offset(&self) -> u64 {
+        self.1
+    }
+}
+
 impl<'a, A, P, S> RangesIterator<'a, A, P, S> {
     pub(crate) fn new(
         cells: CellIterator<'a>,
@@ -275,6 +609,92 @@ impl FromSizeCells for u64 {
     }
 }
 
+/// Values which can be encoded in the cells of an address, as sized by #address-cells.
+pub trait ToAddrCells {
+    /// Encodes the value into `cell_count` cells, right-aligned in the returned array.
+    fn to_addr_cells(&self, cell_count: usize) -> Result<[u32; MAX_CELLS], FdtError>;
+}
+
+impl ToAddrCells for u64 {
+    fn to_addr_cells(&self, cell_count: usize) -> Result<[u32; MAX_CELLS], FdtError> {
+        u64_to_cells(*self, cell_count)
+    }
+}
+
+impl ToAddrCells for (u32, u64) {
+    fn to_addr_cells(&self, cell_count: usize) -> Result<[u32; MAX_CELLS], FdtError> {
+        if cell_count != AddrCells::Triple as usize {
+            return Err(FdtError::BadNCells);
+        }
+        let [_, hi, lo] = u64_to_cells(self.1, AddrCells::Double as usize)?;
+        Ok([self.0, hi, lo])
+    }
+}
+
+/// Values which can be encoded in the cells of a size, as sized by #size-cells.
+pub trait ToSizeCells {
+    /// Encodes the value into `cell_count` cells, right-aligned in the returned array.
+    fn to_size_cells(&self, cell_count: usize) -> Result<[u32; MAX_CELLS], FdtError>;
+}
+
+impl ToSizeCells for u64 {
+    fn to_size_cells(&self, cell_count: usize) -> Result<[u32; MAX_CELLS], FdtError> {
+        u64_to_cells(*self, cell_count)
+    }
+}
+
+/// Maximum number of cells in an address or size.
+const MAX_CELLS: usize = AddrCells::Triple as usize;
+
+fn u64_to_cells(value: u64, cell_count: usize) -> Result<[u32; MAX_CELLS], FdtError> {
+    let (hi, lo) = ((value >> 32) as u32, value as u32);
+    match cell_count {
+        1 if hi != 0 => Err(FdtError::BadValue),
+        1 => Ok([0, 0, lo]),
+        2 => Ok([0, hi, lo]),
+        _ => Err(FdtError::BadNCells),
+    }
+}
+
+fn last_cells(cells: [u32; MAX_CELLS], cell_count: usize) -> impl Iterator<Item = u32> {
+    cells.into_iter().skip(MAX_CELLS - cell_count)
+}
+
+/// Encodes `reg` as the cells of a 'reg' entry, the inverse of [`RegIterator`].
+pub(crate) fn reg_to_cells(
+    reg: &Reg<u64>,
+    addr_cells: AddrCells,
+    size_cells: SizeCells,
+) -> Result<impl Iterator<Item = u32>, FdtError> {
+    let (addr_cells, size_cells) = (addr_cells as usize, size_cells as usize);
+    let addr = reg.addr.to_addr_cells(addr_cells)?;
+    // 'size' must be omitted if and only if the parent node specifies 0 for #size-cells.
+    let size = match (reg.size, size_cells) {
+        (None, 0) => None,
+        (Some(size), 1..) => Some(size.to_size_cells(size_cells)?),
+        _ => return Err(FdtError::BadValue),
+    };
+    let size = size.into_iter().flat_map(move |size| last_cells(size, size_cells));
+    Ok(last_cells(addr, addr_cells).chain(size))
+}
+
+/// Encodes `range` as the cells of a 'ranges' entry, the inverse of [`RangesIterator`].
+pub(crate) fn range_to_cells<A: ToAddrCells, P: ToAddrCells, S: ToSizeCells>(
+    range: &AddressRange<A, P, S>,
+    addr_cells: AddrCells,
+    parent_addr_cells: AddrCells,
+    size_cells: SizeCells,
+) -> Result<impl Iterator<Item = u32>, FdtError> {
+    let (addr_cells, parent_addr_cells) = (addr_cells as usize, parent_addr_cells as usize);
+    let size_cells = size_cells as usize;
+    let addr = range.addr.to_addr_cells(addr_cells)?;
+    let parent_addr = range.parent_addr.to_addr_cells(parent_addr_cells)?;
+    let size = range.size.to_size_cells(size_cells)?;
+    Ok(last_cells(addr, addr_cells)
+        .chain(last_cells(parent_addr, parent_addr_cells))
+        .chain(last_cells(size, size_cells)))
+}
+
 impl AddressRange<(u32, u64), u64, u64> {
     const SIZE_CELLS: usize = 7;
     /// Converts to the format that is consumable by libfdt
@@ -346,6 +766,238 @@ impl<'a> Iterator for DescendantsIterator<'a> {
     }
 }
 
+/// Iterator over the nodes of a tree which reports and skips damaged nodes, instead of stopping.
+///
+/// A node is considered damaged if its name or one of its properties can't be read. When the
+/// structure block can't be parsed any further, it is scanned for the next token that looks like
+/// the start of a valid node, and the walk resumes from it.
+pub struct LenientDescendantsIterator<'a, F> {
+    fdt: &'a Fdt,
+    offset: Option<c_int>,
+    depth: usize,
+    node: FdtNode<'a>,
+    damaged: bool,
+    on_damaged: F,
+}
+
+impl<'a, F: FnMut(FdtNode<'a>, FdtError)> LenientDescendantsIterator<'a, F> {
+    pub(crate) fn new(fdt: &'a Fdt, on_damaged: F) -> Self {
+        let node = FdtNode { fdt, offset: 0 };
+        Self { fdt, offset: Some(0), depth: 0, node, damaged: false, on_damaged }
+    }
+
+    fn report_damage(&mut self, node: FdtNode<'a>, error: FdtError) {
+        self.node = node;
+        self.damaged = true;
+        (self.on_damaged)(node, error);
+    }
+}
+
+impl<'a, F: FnMut(FdtNode<'a>, FdtError)> Iterator for LenientDescendantsIterator<'a, F> {
+    type Item = (FdtNode<'a>, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let offset = self.offset?;
+            let mut next = 0;
+            // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
+            let tag = unsafe { libfdt_bindgen::fdt_next_tag(self.fdt.as_ptr(), offset, &mut next) };
+            if let Err(e) = fdt_err(next) {
+                if !self.damaged {
+                    self.report_damage(self.node, e);
+                }
+                // Nodes found this way are assumed to be descendants of the damaged one, which is
+                // corrected by the clamping of the depth below if they were actually not.
+                self.offset = resync(self.fdt, offset);
+                continue;
+            }
+            self.offset = Some(next);
+            match tag {
+                libfdt_bindgen::FDT_BEGIN_NODE => {
+                    let node = FdtNode { fdt: self.fdt, offset };
+                    let depth = self.depth;
+                    self.depth += 1;
+                    if let Err(e) = check_node(&node) {
+                        self.report_damage(node, e);
+                        continue;
+                    }
+                    self.node = node;
+                    self.damaged = false;
+                    if depth > 0 {
+                        return Some((node, depth));
+                    }
+                }
+                libfdt_bindgen::FDT_END_NODE => {
+                    // Only the root node may be at depth 0.
+                    self.depth = self.depth.saturating_sub(1).max(1);
+                    // Damage in the parent can't be attributed if it can't be found.
+                    match self.node.parent() {
+                        Ok(parent) => {
+                            self.node = parent;
+                            self.damaged = false;
+                        }
+                        Err(_) => self.damaged = true,
+                    }
+                }
+                libfdt_bindgen::FDT_END => {
+                    self.offset = None;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Checks that the name and the properties of `node` can be read.
+fn check_node(node: &FdtNode) -> Result<(), FdtError> {
+    node.name()?;
+    let mut prop = node.first_property()?;
+    while let Some(p) = prop {
+        p.name()?;
+        p.value()?;
+        prop = p.next_property()?;
+    }
+    Ok(())
+}
+
+/// Returns the offset of the first node after `offset` in the structure block that looks valid.
+fn resync(fdt: &Fdt, offset: c_int) -> Option<c_int> {
+    const TAG_SIZE: c_int = size_of::<u32>() as c_int;
+    let struct_size = u32::from_be(fdt.header().size_dt_struct).try_into().ok()?;
+    let start = offset.checked_add(TAG_SIZE)? & !(TAG_SIZE - 1);
+    (start..struct_size).step_by(TAG_SIZE as usize).find(|offset| looks_like_node(fdt, *offset))
+}
+
+fn looks_like_node(fdt: &Fdt, offset: c_int) -> bool {
+    let mut next = 0;
+    // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
+    let tag = unsafe { libfdt_bindgen::fdt_next_tag(fdt.as_ptr(), offset, &mut next) };
+    if tag != libfdt_bindgen::FDT_BEGIN_NODE || next < 0 {
+        return false;
+    }
+    let Ok(name) = (FdtNode { fdt, offset }).name() else {
+        return false;
+    };
+    let valid_char = |c: &u8| c.is_ascii_alphanumeric() || b",._+-@".contains(c);
+    if name.is_empty() || !name.to_bytes().iter().all(valid_char) {
+        return false;
+    }
+    // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
+    let tag = unsafe { libfdt_bindgen::fdt_next_tag(fdt.as_ptr(), next, &mut next) };
+    let valid_tags = [
+        libfdt_bindgen::FDT_PROP,
+        libfdt_bindgen::FDT_BEGIN_NODE,
+        libfdt_bindgen::FDT_END_NODE,
+        libfdt_bindgen::FDT_NOP,
+    ];
+    valid_tags.contains(&tag) && next >= 0
+}
+
+/// Maximum length of a node path, including the trailing nul, that `NodeWalker` can track.
+pub const MAX_PATH_LEN: usize = 256;
+
+/// Pre-order walk over a node and its descendants, yielding each node along with its full path.
+///
+/// The path is built incrementally in a buffer owned by the walker and reused between nodes, so
+/// obtaining it costs O(len(name)) per node instead of re-deriving it from the root. As the path
+/// borrows from the walker, this can't implement `Iterator`; use `next_node()` instead.
+#[derive(Debug)]
+pub struct NodeWalker<'a> {
+    node: Option<(FdtNode<'a>, usize)>,
+    started: bool,
+    path: NodePath,
+}
+
+impl<'a> NodeWalker<'a> {
+    pub(crate) fn new(node: &FdtNode<'a>) -> Result<Self, FdtError> {
+        Ok(Self { node: Some((*node, 0)), started: false, path: NodePath::new(node)? })
+    }
+
+    /// Returns the next node and its full path, or `None` once the walk is complete.
+    ///
+    /// Fails if the path of a node doesn't fit in `MAX_PATH_LEN`.
+    pub fn next_node(&mut self) -> Option<Result<(FdtNode<'a>, &CStr), FdtError>> {
+        let (node, depth) = self.node?;
+        if let Err(e) = self.enter(&node, depth) {
+            self.node = None;
+            return Some(Err(e));
+        }
+        self.node = node.next_node(depth).ok().flatten().filter(|(_, depth)| *depth > 0);
+
+        Some(self.path.as_cstr().map(|path| (node, path)))
+    }
+
+    /// Updates the path to point to `node`, which is at `depth` relative to the start.
+    fn enter(&mut self, node: &FdtNode, depth: usize) -> Result<(), FdtError> {
+        if !self.started {
+            // The path of the starting node was computed by the constructor.
+            self.started = true;
+            return Ok(());
+        }
+        self.path.enter(node.name()?.to_bytes(), depth)
+    }
+}
+
+/// Full path of the current node of a pre-order walk, updated as the walk moves from node to node.
+#[derive(Debug)]
+pub(crate) struct NodePath {
+    depth: usize,
+    path: [u8; MAX_PATH_LEN],
+    len: usize,
+}
+
+impl NodePath {
+    /// Starts with the path of `node`, the start of the walk at depth 0.
+    pub(crate) fn new(node: &FdtNode) -> Result<Self, FdtError> {
+        let mut path = [0; MAX_PATH_LEN];
+        let len = node.path_into(&mut path)?;
+        Ok(Self { depth: 0, path, len })
+    }
+
+    /// Moves to the next node of the walk, named `name` and at `depth` relative to the start.
+    pub(crate) fn enter(&mut self, name: &[u8], depth: usize) -> Result<(), FdtError> {
+        // Pre-order traversal: the node is either a child of the previous node, or a sibling of
+        // the previous node or of one of its ancestors.
+        for _ in depth..=self.depth {
+            self.pop_component();
+        }
+        self.push_component(name)?;
+        self.depth = depth;
+        Ok(())
+    }
+
+    pub(crate) fn to_bytes(&self) -> &[u8] {
+        &self.path[..self.len]
+    }
+
+    fn as_cstr(&self) -> Result<&CStr, FdtError> {
+        CStr::from_bytes_with_nul(&self.path[..=self.len]).map_err(|_| FdtError::Internal)
+    }
+
+    fn pop_component(&mut self) {
+        self.len = match self.to_bytes().iter().rposition(|c| *c == b'/') {
+            Some(0) | None => 1, // keep the leading '/' of the root
+            Some(pos) => pos,
+        };
+        self.path[self.len] = b'\0';
+    }
+
+    fn push_component(&mut self, name: &[u8]) -> Result<(), FdtError> {
+        let separator = if self.len > 1 { 1 } else { 0 };
+        let new_len = self.len + separator + name.len();
+        if new_len >= self.path.len() {
+            return Err(FdtError::NoSpace);
+        }
+        if separator != 0 {
+            self.path[self.len] = b'/';
+        }
+        self.path[self.len + separator..new_len].copy_from_slice(name);
+        self.path[new_len] = b'\0';
+        self.len = new_len;
+        Ok(())
+    }
+}
+
 /// Iterator over properties
 #[derive(Debug)]
 pub struct PropertyIterator<'a> {
@@ -17,12 +17,29 @@
 
 #![no_std]
 
+mod builder;
+mod context;
+mod diff;
+mod dts;
+mod fixups;
 mod iterators;
+mod overlay;
+mod subtree;
 
+pub use builder::{FdtBuilder, FdtReserveMapBuilder};
+pub use context::{FdtContextError, FdtErrorContext, FdtResultExt};
+pub use diff::DiffEvent;
+pub use fixups::{Fixup, FixupIterator, LocalFixup, LocalFixupIterator};
 pub use iterators::{
-    AddressRange, CellIterator, CompatibleIterator, DescendantsIterator, MemRegIterator,
-    PropertyIterator, RangesIterator, Reg, RegIterator, SubnodeIterator,
+    AddrOffset, AddressRange, CellChunkIterator, CellIterator, CompatibleIterator,
+    DescendantsIterator, LenientDescendantsIterator, MemRegIterator, MemRsvIterator,
+    MemoryBanksIterator, NodeWalker, PropValueIterator, PropertyIterator, RangesIterator, Reg,
+    RegIterator, StringListIterator, SubnodeIterator, ToAddrCells, ToSizeCells, U32ArrayIterator,
+    U64ArrayIterator, MAX_PATH_LEN,
 };
+pub use overlay::OverlayBuilder;
+
+use iterators::{range_to_cells, reg_to_cells};
 
 use core::cmp::max;
 use core::ffi::{c_int, c_void, CStr};
@@ -32,7 +49,9 @@ use core::ops::Range;
 use core::ptr;
 use core::result;
 use cstr::cstr;
+use zerocopy::byteorder::big_endian;
 use zerocopy::AsBytes as _;
+use zerocopy::{FromBytes, Unaligned};
 
 /// Error type corresponding to libfdt error codes.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -283,17 +302,31 @@ impl<'a> FdtNode<'a> {
 
     /// Returns supernode with depth. Note that root is at depth 0.
     pub fn supernode_at_depth(&self, depth: usize) -> Result<Self> {
+        let offset = self.supernode_offset_at_depth(depth)?;
+        Ok(Self { fdt: self.fdt, offset })
+    }
+
+    fn supernode_offset_at_depth(&self, depth: usize) -> Result<c_int> {
+        let depth = depth.try_into().map_err(|_| FdtError::NotFound)?;
         // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
         let ret = unsafe {
             libfdt_bindgen::fdt_supernode_atdepth_offset(
                 self.fdt.as_ptr(),
                 self.offset,
-                depth.try_into().unwrap(),
+                depth,
                 ptr::null_mut(),
             )
         };
 
-        Ok(Self { fdt: self.fdt, offset: fdt_err(ret)? })
+        fdt_err(ret)
+    }
+
+    /// Returns the depth of this node. Note that root is at depth 0.
+    pub fn depth(&self) -> Result<usize> {
+        // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
+        let ret = unsafe { libfdt_bindgen::fdt_node_depth(self.fdt.as_ptr(), self.offset) };
+
+        Ok(fdt_err(ret)?.try_into().unwrap())
     }
 
     /// Returns the standard (deprecated) device_type <string> property.
@@ -336,6 +369,35 @@ impl<'a> FdtNode<'a> {
         }
     }
 
+    /// Translates `addr`, as found in the `reg` property of this node, into the address space of
+    /// the root node (i.e. the CPU view) by walking the `ranges` properties of its ancestors.
+    ///
+    /// Returns `FdtError::NotFound` if an ancestor doesn't map `addr` to its parent bus.
+    pub fn translate_address(&self, addr: u64) -> Result<u64> {
+        let mut addr = addr;
+        let mut bus = self.parent()?;
+        while bus.offset != 0 {
+            let parent = bus.parent()?;
+            let ranges = bus.getprop(cstr!("ranges"))?.ok_or(FdtError::NotFound)?;
+            if !ranges.is_empty() {
+                // Buses with 3 address cells (e.g. PCI) encode more than an address.
+                if bus.address_cells()? == AddrCells::Triple
+                    || parent.address_cells()? == AddrCells::Triple
+                    || bus.size_cells()? == SizeCells::None
+                {
+                    return Err(FdtError::BadNCells);
+                }
+                addr = bus
+                    .ranges::<u64, u64, u64>()?
+                    .ok_or(FdtError::Internal)?
+                    .find_map(|range| range.translate(addr).transpose())
+                    .ok_or(FdtError::NotFound)??;
+            }
+            bus = parent;
+        }
+        Ok(addr)
+    }
+
     /// Returns the node name.
     pub fn name(&self) -> Result<&'a CStr> {
         let mut len: c_int = 0;
@@ -349,7 +411,7 @@ impl<'a> FdtNode<'a> {
     }
 
     /// Returns the value of a given <string> property.
-    pub fn getprop_str(&self, name: &CStr) -> Result<Option<&CStr>> {
+    pub fn getprop_str(&self, name: &CStr) -> Result<Option<&'a CStr>> {
         let value = if let Some(bytes) = self.getprop(name)? {
             Some(CStr::from_bytes_with_nul(bytes).map_err(|_| FdtError::BadValue)?)
         } else {
@@ -358,6 +420,19 @@ impl<'a> FdtNode<'a> {
         Ok(value)
     }
 
+    /// Returns the value of a given <stringlist> property, such as `compatible`.
+    pub fn getprop_str_list(&self, name: &CStr) -> Result<Option<StringListIterator<'a>>> {
+        self.getprop(name)?.map(StringListIterator::new).transpose()
+    }
+
+    /// Returns whether the standard compatible property of this node contains `compatible`.
+    pub fn contains_compatible(&self, compatible: &CStr) -> Result<bool> {
+        let Some(mut compatibles) = self.getprop_str_list(cstr!("compatible"))? else {
+            return Ok(false);
+        };
+        Ok(compatibles.any(|c| c == compatible))
+    }
+
     /// Returns the value of a given property as an array of cells.
     pub fn getprop_cells(&self, name: &CStr) -> Result<Option<CellIterator<'a>>> {
         if let Some(cells) = self.getprop(name)? {
@@ -387,8 +462,60 @@ impl<'a> FdtNode<'a> {
         Ok(value)
     }
 
+    /// Returns the value of a given property, reinterpreted as a `T`.
+    ///
+    /// As DT values are big-endian, integer fields of `T` should use the types of
+    /// `zerocopy::byteorder::big_endian`. Fails with [`FdtError::BadValue`] if the size of the
+    /// value doesn't match the size of `T`.
+    pub fn getprop_as<T: FromBytes + Unaligned>(&self, name: &CStr) -> Result<Option<&'a T>> {
+        self.getprop(name)?.map(|bytes| T::ref_from(bytes).ok_or(FdtError::BadValue)).transpose()
+    }
+
+    /// Returns the value of a given property holding exactly `N` <u32> values.
+    pub fn getprop_u32_array<const N: usize>(&self, name: &CStr) -> Result<Option<[u32; N]>> {
+        Ok(self.getprop_as::<[big_endian::U32; N]>(name)?.map(|values| values.map(|v| v.get())))
+    }
+
+    /// Returns the value of a given property holding exactly `N` <u64> values.
+    pub fn getprop_u64_array<const N: usize>(&self, name: &CStr) -> Result<Option<[u64; N]>> {
+        Ok(self.getprop_as::<[big_endian::U64; N]>(name)?.map(|values| values.map(|v| v.get())))
+    }
+
+    /// Returns an iterator over the <u32> values of a given property of any length.
+    ///
+    /// Fails with [`FdtError::BadValue`] if the size of the value isn't a multiple of 4 bytes.
+    pub fn getprop_u32_iter(&self, name: &CStr) -> Result<Option<U32ArrayIterator<'a>>> {
+        self.getprop(name)?.map(U32ArrayIterator::new).transpose()
+    }
+
+    /// Returns an iterator over the <u64> values of a given property of any length.
+    ///
+    /// Fails with [`FdtError::BadValue`] if the size of the value isn't a multiple of 8 bytes.
+    pub fn getprop_u64_iter(&self, name: &CStr) -> Result<Option<U64ArrayIterator<'a>>> {
+        self.getprop(name)?.map(U64ArrayIterator::new).transpose()
+    }
+
+    /// Returns an iterator over the cells of a given property, taken `N` at a time.
+    ///
+    /// Fails with [`FdtError::BadValue`] if the value doesn't hold a whole number of chunks.
+    pub fn getprop_cell_chunks<const N: usize>(
+        &self,
+        name: &CStr,
+    ) -> Result<Option<CellChunkIterator<'a, N>>> {
+        self.getprop(name)?.map(CellChunkIterator::new).transpose()
+    }
+
     /// Returns the value of a given property.
     pub fn getprop(&self, name: &CStr) -> Result<Option<&'a [u8]>> {
+        self.getprop_bytes(name.to_bytes())
+    }
+
+    /// Returns the value of a given property, without requiring a nul-terminated name.
+    pub fn getprop_by_str(&self, name: &str) -> Result<Option<&'a [u8]>> {
+        self.getprop_bytes(name.as_bytes())
+    }
+
+    fn getprop_bytes(&self, name: &[u8]) -> Result<Option<&'a [u8]>> {
         if let Some((prop, len)) = Self::getprop_internal(self.fdt, self.offset, name)? {
             Ok(Some(self.fdt.get_from_ptr(prop, len)?))
         } else {
@@ -401,7 +528,7 @@ impl<'a> FdtNode<'a> {
     fn getprop_internal(
         fdt: &'a Fdt,
         offset: c_int,
-        name: &CStr,
+        name: &[u8],
     ) -> Result<Option<(*const c_void, usize)>> {
         let mut len: i32 = 0;
         // SAFETY: Accesses are constrained to the DT totalsize (validated by ctor) and the
@@ -410,9 +537,9 @@ impl<'a> FdtNode<'a> {
             libfdt_bindgen::fdt_getprop_namelen(
                 fdt.as_ptr(),
                 offset,
-                name.as_ptr(),
+                name.as_ptr().cast::<_>(),
                 // *_namelen functions don't include the trailing nul terminator in 'len'.
-                name.to_bytes().len().try_into().map_err(|_| FdtError::BadPath)?,
+                name.len().try_into().map_err(|_| FdtError::BadPath)?,
                 &mut len as *mut i32,
             )
         } as *const u8;
@@ -448,6 +575,26 @@ impl<'a> FdtNode<'a> {
         Ok(fdt_err_or_option(ret)?.map(|offset| Self { fdt: self.fdt, offset }))
     }
 
+    /// Returns an iterator over the descendants of this node with the given compatible string.
+    pub fn compatible_descendants(&self, compatible: &'a CStr) -> Result<CompatibleIterator<'a>> {
+        CompatibleIterator::new_in_subtree(*self, compatible)
+    }
+
+    /// Returns the offset of the first node following the subtree of this node, if any.
+    fn subtree_end(&self) -> Result<Option<c_int>> {
+        let mut offset = self.offset;
+        let mut depth: c_int = 0;
+        loop {
+            // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
+            let ret =
+                unsafe { libfdt_bindgen::fdt_next_node(self.fdt.as_ptr(), offset, &mut depth) };
+            match fdt_err_or_option(ret)? {
+                Some(next) if depth > 0 => offset = next,
+                next => return Ok(next),
+            }
+        }
+    }
+
     /// Returns the first range of `reg` in this node.
     pub fn first_reg(&self) -> Result<Reg<u64>> {
         self.reg()?.ok_or(FdtError::NotFound)?.next().ok_or(FdtError::NotFound)
@@ -472,6 +619,26 @@ impl<'a> FdtNode<'a> {
         SubnodeIterator::new(self)
     }
 
+    /// Returns the subnode of the given name, without requiring a nul-terminated name.
+    pub fn subnode_by_str(&self, name: &str) -> Result<Option<Self>> {
+        let offset = self.subnode_offset(name.as_bytes())?;
+        Ok(offset.map(|offset| Self { fdt: self.fdt, offset }))
+    }
+
+    fn subnode_offset(&self, name: &[u8]) -> Result<Option<c_int>> {
+        let namelen = name.len().try_into().map_err(|_| FdtError::BadPath)?;
+        // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
+        let ret = unsafe {
+            libfdt_bindgen::fdt_subnode_offset_namelen(
+                self.fdt.as_ptr(),
+                self.offset,
+                name.as_ptr().cast::<_>(),
+                namelen,
+            )
+        };
+        fdt_err_or_option(ret)
+    }
+
     fn first_subnode(&self) -> Result<Option<Self>> {
         // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
         let ret = unsafe { libfdt_bindgen::fdt_first_subnode(self.fdt.as_ptr(), self.offset) };
@@ -491,6 +658,37 @@ impl<'a> FdtNode<'a> {
         DescendantsIterator::new(self)
     }
 
+    /// Returns a walker over this node and its descendants, yielding each node with its full path.
+    pub fn walk(&self) -> Result<NodeWalker<'a>> {
+        NodeWalker::new(self)
+    }
+
+    /// Writes the full path of this node into `buf` and returns it.
+    ///
+    /// Fails with [`FdtError::NoSpace`] if `buf` is too small to hold the path.
+    pub fn path<'b>(&self, buf: &'b mut [u8]) -> Result<&'b CStr> {
+        let len = self.path_into(buf)?;
+        CStr::from_bytes_with_nul(&buf[..=len]).map_err(|_| FdtError::Internal)
+    }
+
+    /// Writes the nul-terminated full path of this node into `buf` and returns its length, not
+    /// including the nul terminator.
+    fn path_into(&self, buf: &mut [u8]) -> Result<usize> {
+        let len = buf.len().try_into().map_err(|_| FdtError::NoSpace)?;
+        // SAFETY: Accesses (read-only) are constrained to the DT totalsize and fdt_get_path()
+        // writes at most `len` bytes into `buf`.
+        let ret = unsafe {
+            libfdt_bindgen::fdt_get_path(
+                self.fdt.as_ptr(),
+                self.offset,
+                buf.as_mut_ptr().cast::<_>(),
+                len,
+            )
+        };
+        fdt_err_expect_zero(ret)?;
+        buf.iter().position(|c| *c == b'\0').ok_or(FdtError::Internal)
+    }
+
     fn next_node(&self, depth: usize) -> Result<Option<(Self, usize)>> {
         let mut next_depth: c_int = depth.try_into().unwrap();
         // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
@@ -527,6 +725,18 @@ impl<'a> FdtNode<'a> {
             Ok(None)
         }
     }
+
+    /// Returns the node referenced by a given <phandle> property, such as `interrupt-parent`.
+    ///
+    /// Fails with [`FdtError::BadPhandle`] if the value isn't a valid phandle and with
+    /// [`FdtError::NotFound`] if no node has that phandle.
+    pub fn getprop_phandle(&self, name: &CStr) -> Result<Option<Self>> {
+        let Some(phandle) = self.getprop_u32(name)? else {
+            return Ok(None);
+        };
+        let node = self.fdt.node_with_phandle(phandle.try_into()?)?;
+        node.ok_or(FdtError::NotFound).map(Some)
+    }
 }
 
 impl<'a> PartialEq for FdtNode<'a> {
@@ -611,6 +821,66 @@ impl<'a> FdtNodeMut<'a> {
         fdt_err_expect_zero(ret)
     }
 
+    /// Appends `compatible` to the standard compatible <stringlist> property of this node.
+    ///
+    /// The property is created if missing and left unchanged if it already contains the string.
+    pub fn append_compatible(&mut self, compatible: &CStr) -> Result<()> {
+        if self.as_node().contains_compatible(compatible)? {
+            return Ok(());
+        }
+        self.appendprop(cstr!("compatible"), &compatible.to_bytes_with_nul())
+    }
+
+    /// Gives this node a phandle, unless it already has one, and adds a `name` entry pointing to it
+    /// in /__symbols__ so that overlays can reference the node as `&name`. Returns the phandle.
+    ///
+    /// Fails with [`FdtError::NotFound`] if the DT has no /__symbols__ node and with
+    /// [`FdtError::Exists`] if the symbol is already defined. The space needed by both properties
+    /// is checked beforehand, so that the DT is left unchanged if this fails.
+    pub fn set_phandle_and_symbol(&mut self, name: &CStr) -> Result<Phandle> {
+        let mut path = [0; MAX_PATH_LEN];
+        let path_len = self.as_node().path_into(&mut path)? + 1;
+        let symbols = self.fdt.symbols()?.ok_or(FdtError::NotFound)?;
+        if symbols.getprop(name)?.is_some() {
+            return Err(FdtError::Exists);
+        }
+
+        let existing = self.as_node().get_phandle()?;
+        let phandle = match existing {
+            Some(phandle) => phandle,
+            None => {
+                let mut phandle = 0;
+                // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
+                let ret = unsafe {
+                    libfdt_bindgen::fdt_generate_phandle(self.fdt.as_ptr(), &mut phandle)
+                };
+                fdt_err_expect_zero(ret)?;
+                phandle.try_into()?
+            }
+        };
+
+        // Conservatively assume that the property names aren't in the strings block yet.
+        let prop_size = mem::size_of::<libfdt_bindgen::fdt_property>();
+        let mut needed = prop_size
+            + path_len.checked_next_multiple_of(4).ok_or(FdtError::NoSpace)?
+            + name.to_bytes_with_nul().len();
+        if existing.is_none() {
+            needed += prop_size + mem::size_of::<u32>() + "phandle\0".len();
+        }
+        if needed > self.fdt.spare_capacity() {
+            return Err(FdtError::NoSpace);
+        }
+
+        if existing.is_none() {
+            self.setprop_u32(cstr!("phandle"), phandle.into())?;
+        }
+        let mut symbols = self.fdt.symbols_mut()?.ok_or(FdtError::Internal)?;
+        symbols.setprop(name, &path[..path_len])?;
+        // Growing /__symbols__ may have moved this node.
+        self.offset = self.fdt.node_offset_with_phandle(phandle)?.ok_or(FdtError::Internal)?;
+        Ok(phandle)
+    }
+
     /// Sets a property name-value pair to the given node.
     ///
     /// This may create a new prop or replace existing value.
@@ -630,6 +900,113 @@ impl<'a> FdtNodeMut<'a> {
         fdt_err_expect_zero(ret)
     }
 
+    /// Sets a <u32> property name-value pair to the given node.
+    ///
+    /// This may create a new prop or replace existing value.
+    pub fn setprop_u32(&mut self, name: &CStr, value: u32) -> Result<()> {
+        self.setprop(name, &value.to_be_bytes())
+    }
+
+    /// Sets a <u64> property name-value pair to the given node.
+    ///
+    /// This may create a new prop or replace existing value.
+    pub fn setprop_u64(&mut self, name: &CStr, value: u64) -> Result<()> {
+        self.setprop(name, &value.to_be_bytes())
+    }
+
+    /// Sets a <string> property name-value pair to the given node.
+    ///
+    /// This may create a new prop or replace existing value.
+    pub fn setprop_str(&mut self, name: &CStr, value: &CStr) -> Result<()> {
+        self.setprop(name, value.to_bytes_with_nul())
+    }
+
+    /// Sets a property made of an array of cells to the given node.
+    ///
+    /// This may create a new prop or replace existing value.
+    pub fn setprop_cells<I>(&mut self, name: &CStr, cells: I) -> Result<()>
+    where
+        I: IntoIterator<Item = u32>,
+        I::IntoIter: Clone,
+    {
+        let cells = cells.into_iter();
+        let len =
+            cells.clone().count().checked_mul(mem::size_of::<u32>()).ok_or(FdtError::BadValue)?;
+        let value = self.setprop_placeholder(name, len)?;
+        for (chunk, cell) in value.chunks_exact_mut(mem::size_of::<u32>()).zip(cells) {
+            chunk.copy_from_slice(&cell.to_be_bytes());
+        }
+        Ok(())
+    }
+
+    /// Sets the standard reg property, encoding each region with the #address-cells and #size-cells
+    /// of the parent node, as expected by [`FdtNode::reg`].
+    ///
+    /// Fails with [`FdtError::BadValue`] if a value doesn't fit in its cells or if the presence of
+    /// a size doesn't match #size-cells, in which case the property is removed.
+    pub fn setprop_reg(&mut self, regs: &[Reg<u64>]) -> Result<()> {
+        let parent = self.as_node().parent()?;
+        let (addr_cells, size_cells) = (parent.address_cells()?, parent.size_cells()?);
+        let entry_cells = addr_cells as usize + size_cells as usize;
+        self.setprop_entries(cstr!("reg"), regs, entry_cells, |reg| {
+            reg_to_cells(reg, addr_cells, size_cells)
+        })
+    }
+
+    /// Sets the standard ranges property, encoding each range with the #address-cells and
+    /// #size-cells of the node and the #address-cells of its parent, as expected by
+    /// [`FdtNode::ranges`].
+    ///
+    /// Fails with [`FdtError::BadValue`] if a value doesn't fit in its cells or with
+    /// [`FdtError::BadNCells`] if its type can't represent the cells, in which case the property
+    /// is removed.
+    pub fn setprop_ranges<A, P, S>(&mut self, ranges: &[AddressRange<A, P, S>]) -> Result<()>
+    where
+        A: ToAddrCells,
+        P: ToAddrCells,
+        S: ToSizeCells,
+    {
+        let node = self.as_node();
+        let (addr_cells, size_cells) = (node.address_cells()?, node.size_cells()?);
+        let parent_addr_cells = node.parent()?.address_cells()?;
+        let entry_cells = addr_cells as usize + parent_addr_cells as usize + size_cells as usize;
+        self.setprop_entries(cstr!("ranges"), ranges, entry_cells, |range| {
+            range_to_cells(range, addr_cells, parent_addr_cells, size_cells)
+        })
+    }
+
+    /// Sets a property made of `entries`, each encoded by `to_cells` into `entry_cells` cells.
+    ///
+    /// Each entry is encoded straight into the property so, if one of them fails, the property
+    /// is removed rather than left partially written.
+    fn setprop_entries<T, I>(
+        &mut self,
+        name: &CStr,
+        entries: &[T],
+        entry_cells: usize,
+        to_cells: impl Fn(&T) -> Result<I>,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = u32>,
+    {
+        let entry_len = entry_cells * mem::size_of::<u32>();
+        let len = entries.len().checked_mul(entry_len).ok_or(FdtError::BadValue)?;
+        let value = self.setprop_placeholder(name, len)?;
+        let encode = |(chunk, entry): (&mut [u8], &T)| {
+            let cells = to_cells(entry)?;
+            for (cell_bytes, cell) in chunk.chunks_exact_mut(mem::size_of::<u32>()).zip(cells) {
+                cell_bytes.copy_from_slice(&cell.to_be_bytes());
+            }
+            Ok(())
+        };
+        let filled = value.chunks_exact_mut(entry_len).zip(entries).try_for_each(encode);
+        if let Err(e) = filled {
+            self.delprop(name)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
     /// Sets the value of the given property with the given value, and ensure that the given
     /// value has the same length as the current value length.
     ///
@@ -665,6 +1042,31 @@ impl<'a> FdtNodeMut<'a> {
         self.setprop(name, &[])
     }
 
+    /// Sets the given property to `len` bytes, returned for the caller to fill.
+    ///
+    /// This may create a new prop or replace existing value.
+    fn setprop_placeholder(&mut self, name: &CStr, len: usize) -> Result<&mut [u8]> {
+        let mut data = ptr::null_mut();
+        // SAFETY: Accesses are constrained to the DT totalsize (validated by ctor) and the library
+        // only returns a pointer to the reserved value, within the DT, if it succeeds.
+        let ret = unsafe {
+            libfdt_bindgen::fdt_setprop_placeholder(
+                self.fdt.as_mut_ptr(),
+                self.offset,
+                name.as_ptr(),
+                len.try_into().map_err(|_| FdtError::BadValue)?,
+                &mut data,
+            )
+        };
+        fdt_err_expect_zero(ret)?;
+        if len == 0 {
+            return Ok(&mut []);
+        }
+        // SAFETY: On success, the library returned a pointer to `len` bytes within the DT, which
+        // stay valid while the DT is mutably borrowed.
+        Ok(unsafe { core::slice::from_raw_parts_mut(data.cast::<u8>(), len) })
+    }
+
     /// Deletes the given property.
     pub fn delprop(&mut self, name: &CStr) -> Result<()> {
         // SAFETY: Accesses are constrained to the DT totalsize (validated by ctor) when the
@@ -678,6 +1080,72 @@ impl<'a> FdtNodeMut<'a> {
         fdt_err_expect_zero(ret)
     }
 
+    /// Renames the given property, keeping its value.
+    ///
+    /// Fails without modifying the DT if the property doesn't exist, if a property named
+    /// `new_name` already exists, or if the DT doesn't have room for the renamed property.
+    pub fn renameprop(&mut self, old_name: &CStr, new_name: &CStr) -> Result<()> {
+        let (_, len) = FdtNode::getprop_internal(self.fdt, self.offset, old_name.to_bytes())?
+            .ok_or(FdtError::NotFound)?;
+        if FdtNode::getprop_internal(self.fdt, self.offset, new_name.to_bytes())?.is_some() {
+            return Err(FdtError::Exists);
+        }
+        // Conservatively assume that the new name isn't in the strings block yet.
+        let needed = mem::size_of::<libfdt_bindgen::fdt_property>()
+            + len.checked_next_multiple_of(4).ok_or(FdtError::NoSpace)?
+            + new_name.to_bytes_with_nul().len();
+        if needed > self.fdt.spare_capacity() {
+            return Err(FdtError::NoSpace);
+        }
+
+        let mut data = ptr::null_mut();
+        // SAFETY: Accesses are constrained to the DT totalsize (validated by ctor) and the library
+        // only returns a pointer to the reserved value, within the DT, if it succeeds.
+        let ret = unsafe {
+            libfdt_bindgen::fdt_setprop_placeholder(
+                self.fdt.as_mut_ptr(),
+                self.offset,
+                new_name.as_ptr(),
+                len.try_into().map_err(|_| FdtError::BadValue)?,
+                &mut data,
+            )
+        };
+        fdt_err_expect_zero(ret)?;
+
+        // Adding the property may have moved the old one, so look it up again.
+        let (old, _) = FdtNode::getprop_internal(self.fdt, self.offset, old_name.to_bytes())?
+            .ok_or(FdtError::Internal)?;
+        let base = self.fdt.as_ptr() as usize;
+        let src = (old as usize).checked_sub(base).ok_or(FdtError::Internal)?;
+        let dst = (data as usize).checked_sub(base).ok_or(FdtError::Internal)?;
+        if src.checked_add(len).map_or(true, |end| end > self.fdt.buffer.len()) {
+            return Err(FdtError::Internal);
+        }
+        self.fdt.buffer.copy_within(src..(src + len), dst);
+
+        self.delprop(old_name)
+    }
+
+    /// Renames this node to `name`, which must not be the name of one of its siblings.
+    pub fn rename(&mut self, name: &CStr) -> Result<()> {
+        // fdt_subnode_offset() would match "cpu" to "cpu@0" so compare the full names instead.
+        let parent = self.as_node().parent()?;
+        for sibling in parent.subnodes()? {
+            if sibling.offset != self.offset && sibling.name()? == name {
+                return Err(FdtError::Exists);
+            }
+        }
+
+        // SAFETY: Accesses are constrained to the DT totalsize (validated by ctor). Changing the
+        // name may shift the offsets of the following nodes and properties but the borrow checker
+        // should prevent this function from being called when FdtNode instances are in use.
+        let ret = unsafe {
+            libfdt_bindgen::fdt_set_name(self.fdt.as_mut_ptr(), self.offset, name.as_ptr())
+        };
+
+        fdt_err_expect_zero(ret)
+    }
+
     /// Deletes the given property effectively from DT, by setting it with FDT_NOP.
     pub fn nop_property(&mut self, name: &CStr) -> Result<()> {
         // SAFETY: Accesses are constrained to the DT totalsize (validated by ctor) when the
@@ -691,8 +1159,8 @@ impl<'a> FdtNodeMut<'a> {
 
     /// Trims the size of the given property to new_size.
     pub fn trimprop(&mut self, name: &CStr, new_size: usize) -> Result<()> {
-        let (prop, len) =
-            FdtNode::getprop_internal(self.fdt, self.offset, name)?.ok_or(FdtError::NotFound)?;
+        let (prop, len) = FdtNode::getprop_internal(self.fdt, self.offset, name.to_bytes())?
+            .ok_or(FdtError::NotFound)?;
         if len == new_size {
             return Ok(());
         }
@@ -724,12 +1192,101 @@ impl<'a> FdtNodeMut<'a> {
         FdtNode { fdt: self.fdt, offset: self.offset }
     }
 
+    /// Returns the standard reg <prop-encoded-array> property.
+    pub fn reg(&self) -> Result<Option<RegIterator>> {
+        self.as_node().reg()
+    }
+
+    /// Returns the first pair of values of the standard reg property.
+    pub fn first_reg(&self) -> Result<Reg<u64>> {
+        self.as_node().first_reg()
+    }
+
+    /// Returns the value of a given <string> property.
+    pub fn getprop_str(&self, name: &CStr) -> Result<Option<&CStr>> {
+        self.as_node().getprop_str(name)
+    }
+
+    /// Returns the value of a given <stringlist> property, such as `compatible`.
+    pub fn getprop_str_list(&self, name: &CStr) -> Result<Option<StringListIterator>> {
+        self.as_node().getprop_str_list(name)
+    }
+
+    /// Returns the value of a given property as an array of cells.
+    pub fn getprop_cells(&self, name: &CStr) -> Result<Option<CellIterator>> {
+        self.as_node().getprop_cells(name)
+    }
+
+    /// Returns the value of a given <u32> property.
+    pub fn getprop_u32(&self, name: &CStr) -> Result<Option<u32>> {
+        self.as_node().getprop_u32(name)
+    }
+
+    /// Returns the value of a given <u64> property.
+    pub fn getprop_u64(&self, name: &CStr) -> Result<Option<u64>> {
+        self.as_node().getprop_u64(name)
+    }
+
+    /// Returns an iterator over the <u32> values of a given property of any length.
+    pub fn getprop_u32_iter(&self, name: &CStr) -> Result<Option<U32ArrayIterator>> {
+        self.as_node().getprop_u32_iter(name)
+    }
+
+    /// Returns an iterator over the <u64> values of a given property of any length.
+    pub fn getprop_u64_iter(&self, name: &CStr) -> Result<Option<U64ArrayIterator>> {
+        self.as_node().getprop_u64_iter(name)
+    }
+
+    /// Returns the value of a given property.
+    pub fn getprop(&self, name: &CStr) -> Result<Option<&[u8]>> {
+        self.as_node().getprop(name)
+    }
+
+    /// Returns the value of a given property, without requiring a nul-terminated name.
+    pub fn getprop_by_str(&self, name: &str) -> Result<Option<&[u8]>> {
+        self.as_node().getprop_by_str(name)
+    }
+
+    /// Returns the value of a given property, to be modified in place.
+    ///
+    /// Unlike [`FdtNodeMut::setprop_inplace`], this doesn't copy a new value over the property but
+    /// its size can't be changed.
+    pub fn getprop_mut(&mut self, name: &CStr) -> Result<Option<&mut [u8]>> {
+        let Some((prop, len)) = FdtNode::getprop_internal(self.fdt, self.offset, name.to_bytes())?
+        else {
+            return Ok(None);
+        };
+        Ok(Some(self.fdt.get_mut_from_ptr(prop, len)?))
+    }
+
     /// Adds a new subnode to the given node and return it as a FdtNodeMut on success.
     pub fn add_subnode(&'a mut self, name: &CStr) -> Result<Self> {
         let offset = self.add_subnode_offset(name.to_bytes())?;
         Ok(Self { fdt: self.fdt, offset })
     }
 
+    /// Adds a deep copy of `src`, typically a node of another DT, as a subnode of this node and
+    /// returns it. The copy has the name, properties and descendants of `src`, in the same order.
+    ///
+    /// Phandles are copied as is, so the caller must make sure that they don't collide with the
+    /// ones of this DT. Fails with [`FdtError::Exists`] if this node already has a subnode of the
+    /// same name. The space needed is checked beforehand, so that the DT is left unchanged if this
+    /// fails.
+    pub fn copy_subtree_from(&'a mut self, src: &FdtNode) -> Result<Self> {
+        let name = src.name()?.to_bytes();
+        if self.subnode_offset(name)?.is_some() {
+            return Err(FdtError::Exists);
+        }
+        if copied_size(src)? > self.fdt.spare_capacity() {
+            return Err(FdtError::NoSpace);
+        }
+
+        let offset = self.add_subnode_offset(name)?;
+        // Filling the new node doesn't move it, as it only inserts data after its start.
+        copy_node(src, self.fdt, offset, CopyOrder::Source)?;
+        Ok(Self { fdt: self.fdt, offset })
+    }
+
     /// Adds a new subnode to the given node with name and namelen, and returns it as a FdtNodeMut
     /// on success.
     pub fn add_subnode_with_namelen(&'a mut self, name: &CStr, namelen: usize) -> Result<Self> {
@@ -757,18 +1314,14 @@ impl<'a> FdtNodeMut<'a> {
         Ok(offset.map(|offset| Self { fdt: self.fdt, offset }))
     }
 
+    /// Returns the subnode of the given name, without requiring a nul-terminated name.
+    pub fn subnode_mut_by_str(&'a mut self, name: &str) -> Result<Option<Self>> {
+        let offset = self.subnode_offset(name.as_bytes())?;
+        Ok(offset.map(|offset| Self { fdt: self.fdt, offset }))
+    }
+
     fn subnode_offset(&self, name: &[u8]) -> Result<Option<c_int>> {
-        let namelen = name.len().try_into().unwrap();
-        // SAFETY: Accesses are constrained to the DT totalsize (validated by ctor).
-        let ret = unsafe {
-            libfdt_bindgen::fdt_subnode_offset_namelen(
-                self.fdt.as_ptr(),
-                self.offset,
-                name.as_ptr().cast::<_>(),
-                namelen,
-            )
-        };
-        fdt_err_or_option(ret)
+        self.as_node().subnode_offset(name)
     }
 
     fn parent(&'a self) -> Result<FdtNode<'a>> {
@@ -778,6 +1331,26 @@ impl<'a> FdtNodeMut<'a> {
         Ok(FdtNode { fdt: &*self.fdt, offset: fdt_err(ret)? })
     }
 
+    /// Returns the depth of this node. Note that root is at depth 0.
+    pub fn depth(&self) -> Result<usize> {
+        self.as_node().depth()
+    }
+
+    /// Returns the supernode at `depth`, for modifications, consuming this node. Note that root is
+    /// at depth 0.
+    pub fn supernode_at_depth(self, depth: usize) -> Result<Self> {
+        let offset = self.as_node().supernode_offset_at_depth(depth)?;
+        Ok(Self { fdt: self.fdt, offset })
+    }
+
+    /// Returns the parent node, for modifications, consuming this node.
+    pub fn into_parent(self) -> Result<Self> {
+        // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
+        let ret = unsafe { libfdt_bindgen::fdt_parent_offset(self.fdt.as_ptr(), self.offset) };
+
+        Ok(Self { fdt: self.fdt, offset: fdt_err(ret)? })
+    }
+
     /// Returns the compatible node of the given name that is next after this node.
     pub fn next_compatible(self, compatible: &CStr) -> Result<Option<Self>> {
         // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
@@ -826,6 +1399,19 @@ impl<'a> FdtNodeMut<'a> {
         Ok(next_offset.map(|offset| Self { fdt: self.fdt, offset }))
     }
 
+    /// Deletes this node and its subtree from DT, compacting the DT immediately.
+    ///
+    /// Unlike [`FdtNodeMut::nop`], this doesn't leave FDT_NOP tags behind but shifts the following
+    /// nodes, which invalidates their offsets. This node is consumed, so that it can't be used any
+    /// further, and other nodes must be looked up again.
+    pub fn delete(self) -> Result<()> {
+        // SAFETY: Accesses are constrained to the DT totalsize (validated by ctor). This consumes
+        // self, so the invalidated node wouldn't be used any further.
+        let ret = unsafe { libfdt_bindgen::fdt_del_node(self.fdt.as_mut_ptr(), self.offset) };
+
+        fdt_err_expect_zero(ret)
+    }
+
     /// Deletes this node effectively from DT, by setting it with FDT_NOP
     pub fn nop(mut self) -> Result<()> {
         // SAFETY: This consumes self, so invalid node wouldn't be used any further
@@ -844,6 +1430,235 @@ impl<'a> FdtNodeMut<'a> {
     }
 }
 
+/// Order in which [`copy_node`] adds properties and subnodes.
+#[derive(Clone, Copy, Debug)]
+enum CopyOrder {
+    /// The order of the source node.
+    Source,
+    /// Sorted by name.
+    ByName,
+}
+
+/// Copies the properties and subnodes of `src`, in the given order, into the node of `dst` found
+/// at `dst_offset`.
+///
+/// As libfdt inserts new properties and subnodes before the existing ones, they are added from the
+/// last to the first.
+fn copy_node(src: &FdtNode, dst: &mut Fdt, dst_offset: c_int, order: CopyOrder) -> Result<()> {
+    let first = src.first_property()?;
+    for_each_rev(order, first, FdtProperty::next_property, FdtProperty::name, |prop| {
+        FdtNodeMut { fdt: dst, offset: dst_offset }.setprop(prop.name()?, prop.value()?)
+    })?;
+
+    let first = src.first_subnode()?;
+    for_each_rev(order, first, FdtNode::next_subnode, FdtNode::name, |subnode| {
+        let name = subnode.name()?.to_bytes();
+        let offset = FdtNodeMut { fdt: dst, offset: dst_offset }.add_subnode_offset(name)?;
+        copy_node(&subnode, dst, offset, order)
+    })
+}
+
+/// Returns the space that [`copy_node`] needs to copy `src` into a new node, i.e. an upper bound
+/// of the growth of the structure and strings blocks.
+fn copied_size(src: &FdtNode) -> Result<usize> {
+    let tag_size = mem::size_of::<u32>();
+    let name_len = src.name()?.to_bytes_with_nul().len();
+    // FDT_BEGIN_NODE and its name, then FDT_END_NODE.
+    let mut size = tag_size + name_len.checked_next_multiple_of(4).ok_or(FdtError::NoSpace)?;
+    size += tag_size;
+
+    for prop in src.properties()? {
+        let value_len = prop.value()?.len().checked_next_multiple_of(4).ok_or(FdtError::NoSpace)?;
+        // Conservatively assume that the property name isn't in the strings block yet.
+        size += mem::size_of::<libfdt_bindgen::fdt_property>()
+            + value_len
+            + prop.name()?.to_bytes_with_nul().len();
+    }
+
+    for subnode in src.subnodes()? {
+        size += copied_size(&subnode)?;
+    }
+
+    Ok(size)
+}
+
+/// Feeds the canonical serialization of `node` and its descendants to `hasher`, see
+/// [`Fdt::content_hash`].
+fn hash_node(node: &FdtNode, hasher: &mut dyn FnMut(&[u8])) -> Result<()> {
+    const BEGIN_NODE_TAG: u8 = 1;
+    const END_NODE_TAG: u8 = 2;
+    const PROP_TAG: u8 = 3;
+
+    hasher(&[BEGIN_NODE_TAG]);
+    hasher(node.name()?.to_bytes_with_nul());
+
+    let first = node.first_property()?;
+    let mut last = None;
+    while let Some(p) = next_by_name(first, FdtProperty::next_property, last, FdtProperty::name)? {
+        let value = p.value()?;
+        let len = u32::try_from(value.len()).map_err(|_| FdtError::Internal)?;
+        hasher(&[PROP_TAG]);
+        hasher(&len.to_be_bytes());
+        hasher(p.name()?.to_bytes_with_nul());
+        hasher(value);
+        last = Some(p.name()?);
+    }
+
+    let first = node.first_subnode()?;
+    let mut last = None;
+    while let Some(subnode) = next_by_name(first, FdtNode::next_subnode, last, FdtNode::name)? {
+        hash_node(&subnode, hasher)?;
+        last = Some(subnode.name()?);
+    }
+
+    hasher(&[END_NODE_TAG]);
+    Ok(())
+}
+
+/// Calls `f` on the items of the list starting at `first` and linked by `next`, from the last to
+/// the first in the given order.
+fn for_each_rev<'a, T: Copy>(
+    order: CopyOrder,
+    first: Option<T>,
+    next: impl Fn(&T) -> Result<Option<T>>,
+    name: impl Fn(&T) -> Result<&'a CStr>,
+    mut f: impl FnMut(T) -> Result<()>,
+) -> Result<()> {
+    match order {
+        CopyOrder::Source => {
+            let mut len = 0;
+            let mut item = first;
+            while let Some(i) = item {
+                len += 1;
+                item = next(&i)?;
+            }
+            match first {
+                Some(first) => for_each_rev_in(first, len, &next, &mut f),
+                None => Ok(()),
+            }
+        }
+        CopyOrder::ByName => {
+            let mut last = None;
+            while let Some(item) = prev_by_name(first, &next, last, &name)? {
+                last = Some(name(&item)?);
+                f(item)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Calls `f` on the `len` items starting at `first` from the last to the first.
+///
+/// The list can only be walked forwards so, without allocating, it is split in halves which are
+/// visited in reverse order, taking O(len * log(len)) steps.
+fn for_each_rev_in<T: Copy>(
+    first: T,
+    len: usize,
+    next: &impl Fn(&T) -> Result<Option<T>>,
+    f: &mut impl FnMut(T) -> Result<()>,
+) -> Result<()> {
+    if len <= 1 {
+        return if len == 1 { f(first) } else { Ok(()) };
+    }
+    let half = len / 2;
+    let mut second = first;
+    for _ in 0..half {
+        second = next(&second)?.ok_or(FdtError::Internal)?;
+    }
+    for_each_rev_in(second, len - half, next, f)?;
+    for_each_rev_in(first, half, next, f)
+}
+
+/// Returns the item of the list starting at `first` with the greatest name that is smaller than
+/// `before`, if any.
+fn prev_by_name<'a, T: Copy>(
+    first: Option<T>,
+    next: impl Fn(&T) -> Result<Option<T>>,
+    before: Option<&CStr>,
+    name: impl Fn(&T) -> Result<&'a CStr>,
+) -> Result<Option<T>> {
+    let mut prev: Option<(&CStr, T)> = None;
+    let mut item = first;
+    while let Some(i) = item {
+        let item_name = name(&i)?;
+        let is_before = before.map_or(true, |before| item_name < before);
+        if is_before && prev.map_or(true, |(prev_name, _)| item_name > prev_name) {
+            prev = Some((item_name, i));
+        }
+        item = next(&i)?;
+    }
+    Ok(prev.map(|(_, item)| item))
+}
+
+/// Returns the item of the list starting at `first` with the smallest name that is greater than
+/// `after`, if any.
+///
+/// Fails with [`FdtError::BadStructure`] if several items have that name.
+fn next_by_name<'a, T: Copy>(
+    first: Option<T>,
+    next: impl Fn(&T) -> Result<Option<T>>,
+    after: Option<&CStr>,
+    name: impl Fn(&T) -> Result<&'a CStr>,
+) -> Result<Option<T>> {
+    let mut found: Option<(&CStr, T)> = None;
+    let mut duplicated = false;
+    let mut item = first;
+    while let Some(i) = item {
+        let item_name = name(&i)?;
+        if after.map_or(true, |after| item_name > after) {
+            match found {
+                Some((found_name, _)) if item_name == found_name => duplicated = true,
+                Some((found_name, _)) if item_name > found_name => {}
+                _ => {
+                    found = Some((item_name, i));
+                    duplicated = false;
+                }
+            }
+        }
+        item = next(&i)?;
+    }
+    if duplicated {
+        return Err(FdtError::BadStructure);
+    }
+    Ok(found.map(|(_, item)| item))
+}
+
+/// Usage of the space of a DT, as returned by [`Fdt::stats`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct FdtStats {
+    /// Size of the structure block, in bytes.
+    pub struct_size: usize,
+    /// Size of the strings block, in bytes.
+    pub strings_size: usize,
+    /// Number of bytes by which the DT can grow in place before libfdt fails with `NoSpace`.
+    pub spare_capacity: usize,
+    /// Number of bytes of the structure block wasted by FDT_NOP tags.
+    pub nop_size: usize,
+}
+
+/// What [`Fdt::for_each_compatible_mut`] does with a node after visiting it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VisitAction {
+    /// Keeps the node and visits the next one.
+    Keep,
+    /// Deletes the node, as [`FdtNodeMut::nop`] does, and visits the next one.
+    Delete,
+    /// Keeps the node and stops visiting.
+    Stop,
+}
+
+/// How thoroughly a DT is validated when it gets wrapped, see [`Fdt::from_slice_with`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Validation {
+    /// Only validates the header, which is cheap. Meant for DTs which were already validated,
+    /// e.g. when wrapping them again: functions remain memory-safe but fail on a damaged tree.
+    HeaderOnly,
+    /// Validates the whole DT.
+    #[default]
+    Full,
+}
+
 /// Wrapper around low-level libfdt functions.
 #[derive(Debug)]
 #[repr(transparent)]
@@ -856,22 +1671,56 @@ impl Fdt {
     ///
     /// Fails if the FDT does not pass validation.
     pub fn from_slice(fdt: &[u8]) -> Result<&Self> {
+        Self::from_slice_with(fdt, Validation::Full)
+    }
+
+    /// Wraps a slice containing a Flattened Device Tree, validated as requested.
+    ///
+    /// Fails if the FDT does not pass validation.
+    pub fn from_slice_with(fdt: &[u8], validation: Validation) -> Result<&Self> {
         // SAFETY: The FDT will be validated before it is returned.
         let fdt = unsafe { Self::unchecked_from_slice(fdt) };
-        fdt.check_full()?;
+        fdt.validate(validation)?;
         Ok(fdt)
     }
 
+    /// Wraps a slice containing a possibly damaged Flattened Device Tree.
+    ///
+    /// Only the header is validated so, while all functions remain memory-safe, those accessing a
+    /// damaged part of the tree fail. Use [`Fdt::descendants_lenient`] to extract as much as
+    /// possible from such a tree.
+    pub fn from_slice_lenient(fdt: &[u8]) -> Result<&Self> {
+        Self::from_slice_with(fdt, Validation::HeaderOnly)
+    }
+
     /// Wraps a mutable slice containing a Flattened Device Tree.
     ///
     /// Fails if the FDT does not pass validation.
     pub fn from_mut_slice(fdt: &mut [u8]) -> Result<&mut Self> {
+        Self::from_mut_slice_with(fdt, Validation::Full)
+    }
+
+    /// Wraps a mutable slice containing a Flattened Device Tree, validated as requested.
+    ///
+    /// Fails if the FDT does not pass validation.
+    pub fn from_mut_slice_with(fdt: &mut [u8], validation: Validation) -> Result<&mut Self> {
         // SAFETY: The FDT will be validated before it is returned.
         let fdt = unsafe { Self::unchecked_from_mut_slice(fdt) };
-        fdt.check_full()?;
+        fdt.validate(validation)?;
         Ok(fdt)
     }
 
+    /// Copies a Flattened Device Tree from a slice of any alignment into `dst` and wraps it.
+    ///
+    /// The DT is placed at the first 8-byte aligned address of `dst`, whose remaining bytes are
+    /// available to grow it. Fails if `dst` is too small or if the FDT does not pass validation.
+    pub fn from_unaligned_slice<'a>(src: &[u8], dst: &'a mut [u8]) -> Result<&'a mut Self> {
+        let offset = dst.as_ptr().align_offset(8);
+        let dst = dst.get_mut(offset..).ok_or(FdtError::NoSpace)?;
+        dst.get_mut(..src.len()).ok_or(FdtError::NoSpace)?.copy_from_slice(src);
+        Self::from_mut_slice(dst)
+    }
+
     /// Creates an empty Flattened Device Tree with a mutable slice.
     pub fn create_empty_tree(fdt: &mut [u8]) -> Result<&mut Self> {
         // SAFETY: fdt_create_empty_tree() only write within the specified length,
@@ -944,6 +1793,25 @@ impl Fdt {
         fdt_err_expect_zero(ret)
     }
 
+    /// Copies the DT into `new_buf` and unpacks it to cover the whole of that buffer.
+    ///
+    /// This allows moving a DT to a larger buffer, e.g. when it ran out of space while being
+    /// edited, or to a smaller one, as long as it fits.
+    pub fn resize_into<'a>(&self, new_buf: &'a mut [u8]) -> Result<&'a mut Fdt> {
+        let len = new_buf.len().try_into().map_err(|_| FdtError::NoSpace)?;
+        // SAFETY: fdt_open_into() reads the valid DT in self and only writes within the `len`
+        // bytes of `new_buf`, which can't overlap with self as it is exclusively borrowed.
+        let ret = unsafe {
+            libfdt_bindgen::fdt_open_into(self.as_ptr(), new_buf.as_mut_ptr().cast::<c_void>(), len)
+        };
+        fdt_err_expect_zero(ret)?;
+
+        // SAFETY: The FDT will be validated before it is returned.
+        let fdt = unsafe { Self::unchecked_from_mut_slice(new_buf) };
+        fdt.check_full()?;
+        Ok(fdt)
+    }
+
     /// Packs the DT to take a minimum amount of memory.
     ///
     /// Doesn't shrink the underlying memory slice.
@@ -968,10 +1836,20 @@ impl Fdt {
         Ok(self)
     }
 
+    /// Copies the content of this tree into the (typically empty) tree `dst`, emitting properties
+    /// and subnodes sorted by name, so that the layout of the resulting DT doesn't depend on the
+    /// order in which nodes and properties were added to this tree.
+    ///
+    /// Properties and subnodes of the root node are added to the root node of `dst`.
+    pub fn copy_sorted_into(&self, dst: &mut Fdt) -> Result<()> {
+        let dst_root = dst.root_mut()?.offset;
+        copy_node(&self.root()?, dst, dst_root, CopyOrder::ByName)
+    }
+
     /// Returns an iterator of memory banks specified the "/memory" node.
     /// Throws an error when the "/memory" is not found in the device tree.
     ///
-    /// NOTE: This does not support individual "/memory@XXXX" banks.
+    /// NOTE: This does not support individual "/memory@XXXX" banks, see [`Fdt::memory_banks`].
     pub fn memory(&self) -> Result<MemRegIterator> {
         let memory_node_name = cstr!("/memory");
         let memory_device_type = cstr!("memory");
@@ -983,11 +1861,53 @@ impl Fdt {
         node.reg()?.ok_or(FdtError::BadValue).map(MemRegIterator::new)
     }
 
+    /// Returns an iterator over the ranges of all the memory banks, chaining the `reg` of every
+    /// node with `device_type = "memory"`, such as `/memory` and `/memory@XXXX`.
+    pub fn memory_banks(&self) -> MemoryBanksIterator {
+        MemoryBanksIterator::new(self)
+    }
+
     /// Returns the first memory range in the `/memory` node.
     pub fn first_memory_range(&self) -> Result<Range<usize>> {
         self.memory()?.next().ok_or(FdtError::NotFound)
     }
 
+    /// Returns an iterator over the address ranges of the memory reservation block.
+    pub fn mem_rsv_iter(&self) -> MemRsvIterator {
+        MemRsvIterator::new(self)
+    }
+
+    /// Adds an entry to the memory reservation block.
+    pub fn add_mem_rsv(&mut self, addr: u64, size: u64) -> Result<()> {
+        // SAFETY: Accesses are constrained to the DT totalsize (validated by ctor) and the library
+        // fails if there isn't enough room left to insert the entry.
+        let ret = unsafe { libfdt_bindgen::fdt_add_mem_rsv(self.as_mut_ptr(), addr, size) };
+
+        fdt_err_expect_zero(ret)
+    }
+
+    /// Returns the address and size of the n-th entry of the memory reservation block.
+    fn mem_rsv(&self, n: usize) -> Result<Option<(u64, u64)>> {
+        // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
+        let num = unsafe { libfdt_bindgen::fdt_num_mem_rsv(self.as_ptr()) };
+        if n >= usize::try_from(fdt_err(num)?).unwrap() {
+            return Ok(None);
+        }
+
+        let (mut addr, mut size) = (0, 0);
+        // SAFETY: Accesses (read-only) are constrained to the DT totalsize and `n` is in bounds.
+        let ret = unsafe {
+            libfdt_bindgen::fdt_get_mem_rsv(
+                self.as_ptr(),
+                n.try_into().map_err(|_| FdtError::BadOffset)?,
+                &mut addr,
+                &mut size,
+            )
+        };
+        fdt_err_expect_zero(ret)?;
+        Ok(Some((addr, size)))
+    }
+
     /// Returns the standard /chosen node.
     pub fn chosen(&self) -> Result<Option<FdtNode>> {
         self.node(cstr!("/chosen"))
@@ -1003,6 +1923,19 @@ impl Fdt {
         self.node(cstr!("/"))?.ok_or(FdtError::Internal)
     }
 
+    /// Returns an iterator over all the nodes of the tree but the root, with their depth, which
+    /// instead of stopping at the first structural error, passes damaged nodes to `on_damaged` and
+    /// skips past them.
+    ///
+    /// Recovering from damage is best effort: nodes following a damaged one may be missed or be
+    /// reported at the wrong depth.
+    pub fn descendants_lenient<'a, F>(&'a self, on_damaged: F) -> LenientDescendantsIterator<'a, F>
+    where
+        F: FnMut(FdtNode<'a>, FdtError),
+    {
+        LenientDescendantsIterator::new(self, on_damaged)
+    }
+
     /// Returns the standard /__symbols__ node.
     pub fn symbols(&self) -> Result<Option<FdtNode>> {
         self.node(cstr!("/__symbols__"))
@@ -1015,7 +1948,131 @@ impl Fdt {
 
     /// Returns a tree node by its full path.
     pub fn node(&self, path: &CStr) -> Result<Option<FdtNode>> {
-        Ok(self.path_offset(path.to_bytes())?.map(|offset| FdtNode { fdt: self, offset }))
+        self.node_by_bytes(path.to_bytes())
+    }
+
+    /// Returns a tree node by its full path, without requiring a nul-terminated path.
+    pub fn node_by_str(&self, path: &str) -> Result<Option<FdtNode>> {
+        self.node_by_bytes(path.as_bytes())
+    }
+
+    fn node_by_bytes(&self, path: &[u8]) -> Result<Option<FdtNode>> {
+        Ok(self.path_offset(path)?.map(|offset| FdtNode { fdt: self, offset }))
+    }
+
+    /// Returns the path that the alias `name` stands for, as defined in the /aliases node.
+    pub fn alias(&self, name: &CStr) -> Result<Option<&CStr>> {
+        let name = name.to_bytes();
+        let len = name.len().try_into().map_err(|_| FdtError::BadPath)?;
+        // SAFETY: Accesses (read-only) are constrained to the DT totalsize (validated by ctor) and
+        // the function respects the passed number of characters.
+        let ptr = unsafe {
+            // *_namelen functions don't include the trailing nul terminator in 'len'.
+            libfdt_bindgen::fdt_get_alias_namelen(self.as_ptr(), name.as_ptr().cast::<_>(), len)
+        };
+        if ptr.is_null() {
+            return Ok(None);
+        }
+        let offset =
+            (ptr as usize).checked_sub(self.as_ptr() as usize).ok_or(FdtError::Internal)?;
+        let value = self.as_slice().get(offset..).ok_or(FdtError::Internal)?;
+        Ok(Some(CStr::from_bytes_until_nul(value).map_err(|_| FdtError::BadValue)?))
+    }
+
+    /// Returns the node that the alias `name` stands for, e.g. `serial0`.
+    pub fn node_by_alias(&self, name: &CStr) -> Result<Option<FdtNode>> {
+        match self.alias(name)? {
+            Some(path) => self.node(path),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the first node having the property `name` with the given value.
+    ///
+    /// The value must match exactly, including the NUL terminator of strings, e.g. `b"memory\0"`.
+    pub fn node_by_prop_value(&self, name: &CStr, value: &[u8]) -> Result<Option<FdtNode>> {
+        let offset = self.node_offset_by_prop_value(-1, name, value)?;
+        Ok(offset.map(|offset| FdtNode { fdt: self, offset }))
+    }
+
+    /// Iterate over nodes having the property `name` with the given value, see
+    /// [`Fdt::node_by_prop_value`].
+    pub fn nodes_with_prop_value<'a>(
+        &'a self,
+        name: &'a CStr,
+        value: &'a [u8],
+    ) -> PropValueIterator<'a> {
+        PropValueIterator::new(self, name, value)
+    }
+
+    /// Iterate over nodes with the given `device_type`, such as "memory", "cpu" or "pci".
+    pub fn nodes_with_device_type<'a>(&'a self, device_type: &'a CStr) -> PropValueIterator<'a> {
+        self.nodes_with_prop_value(cstr!("device_type"), device_type.to_bytes_with_nul())
+    }
+
+    fn node_offset_by_prop_value(
+        &self,
+        start: c_int,
+        name: &CStr,
+        value: &[u8],
+    ) -> Result<Option<c_int>> {
+        let len = value.len().try_into().map_err(|_| FdtError::BadValue)?;
+        // SAFETY: Accesses (read-only) are constrained to the DT totalsize and to the `len` bytes
+        // of `value`.
+        let ret = unsafe {
+            libfdt_bindgen::fdt_node_offset_by_prop_value(
+                self.as_ptr(),
+                start,
+                name.as_ptr(),
+                value.as_ptr().cast::<c_void>(),
+                len,
+            )
+        };
+        fdt_err_or_option(ret)
+    }
+
+    /// Returns how the space of the DT is used, e.g. to report how close it is to overflowing.
+    pub fn stats(&self) -> Result<FdtStats> {
+        const TAG_SIZE: usize = mem::size_of::<u32>();
+        let header = self.header();
+        let mut nop_size = 0;
+        let mut offset = 0;
+        loop {
+            let mut next = 0;
+            // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
+            let tag = unsafe { libfdt_bindgen::fdt_next_tag(self.as_ptr(), offset, &mut next) };
+            fdt_err(next)?;
+            match tag {
+                libfdt_bindgen::FDT_NOP => nop_size += TAG_SIZE,
+                libfdt_bindgen::FDT_END => break,
+                _ => {}
+            }
+            offset = next;
+        }
+        Ok(FdtStats {
+            struct_size: u32::from_be(header.size_dt_struct) as usize,
+            strings_size: u32::from_be(header.size_dt_strings) as usize,
+            spare_capacity: self.spare_capacity(),
+            nop_size,
+        })
+    }
+
+    /// Returns a walker over all nodes of the tree, yielding each node with its full path.
+    pub fn walk(&self) -> Result<NodeWalker> {
+        self.root()?.walk()
+    }
+
+    /// Feeds a canonical serialization of the tree to `hasher`, e.g. to measure it.
+    ///
+    /// The properties and subnodes of each node are visited in the order of their names and each
+    /// is serialized with the length of its value, so that the result doesn't depend on the order
+    /// in which they were added, NOPs, free space, the layout of the strings block or the order of
+    /// the blocks in the blob.
+    ///
+    /// Fails with [`FdtError::BadStructure`] if a node has several properties or subnodes with the
+    /// same name, as their order would then be ambiguous.
+    pub fn content_hash(&self, mut hasher: impl FnMut(&[u8])) -> Result<()> {
+        hash_node(&self.root()?, &mut hasher)
     }
 
     /// Iterate over nodes with a given compatible string.
@@ -1023,6 +2080,74 @@ impl Fdt {
         CompatibleIterator::new(self, compatible)
     }
 
+    /// Visits the nodes with a given compatible string in order, letting `f` modify each of them
+    /// and decide whether to keep it, delete it or stop.
+    ///
+    /// As `f` may modify any part of the DT, which can move the visited node, nodes are looked up
+    /// again by their position among the compatible nodes after each call. Therefore, `f` must not
+    /// add or delete compatible nodes, including the visited one, but return
+    /// [`VisitAction::Delete`] instead.
+    pub fn for_each_compatible_mut(
+        &mut self,
+        compatible: &CStr,
+        f: impl FnMut(FdtNodeMut) -> Result<VisitAction>,
+    ) -> Result<()> {
+        self.for_each_nth_mut(|fdt, n| fdt.nth_compatible_offset(compatible, n), f)
+    }
+
+    /// Visits the nodes with the given `device_type` in order, like
+    /// [`Fdt::for_each_compatible_mut`] does for compatible nodes.
+    pub fn for_each_device_type_mut(
+        &mut self,
+        device_type: &CStr,
+        f: impl FnMut(FdtNodeMut) -> Result<VisitAction>,
+    ) -> Result<()> {
+        self.for_each_nth_mut(|fdt, n| fdt.nth_device_type_offset(device_type, n), f)
+    }
+
+    /// Visits the nodes that `nth` finds by their position among the visited nodes.
+    fn for_each_nth_mut(
+        &mut self,
+        nth: impl Fn(&Self, usize) -> Result<Option<c_int>>,
+        mut f: impl FnMut(FdtNodeMut) -> Result<VisitAction>,
+    ) -> Result<()> {
+        let mut kept = 0;
+        while let Some(offset) = nth(self, kept)? {
+            match f(FdtNodeMut { fdt: self, offset })? {
+                VisitAction::Keep => kept += 1,
+                VisitAction::Delete => {
+                    let offset = nth(self, kept)?.ok_or(FdtError::Internal)?;
+                    FdtNodeMut { fdt: self, offset }.nop()?;
+                }
+                VisitAction::Stop => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn nth_compatible_offset(&self, compatible: &CStr, n: usize) -> Result<Option<c_int>> {
+        let mut next = self.root()?.next_compatible(compatible)?;
+        for _ in 0..n {
+            let Some(node) = next else {
+                break;
+            };
+            next = node.next_compatible(compatible)?;
+        }
+        Ok(next.map(|node| node.offset))
+    }
+
+    fn nth_device_type_offset(&self, device_type: &CStr, n: usize) -> Result<Option<c_int>> {
+        let value = device_type.to_bytes_with_nul();
+        let mut offset = -1;
+        for _ in 0..=n {
+            match self.node_offset_by_prop_value(offset, cstr!("device_type"), value)? {
+                Some(next) => offset = next,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(offset))
+    }
+
     /// Returns max phandle in the tree.
     pub fn max_phandle(&self) -> Result<Phandle> {
         let mut phandle: u32 = 0;
@@ -1058,7 +2183,16 @@ impl Fdt {
 
     /// Returns a mutable tree node by its full path.
     pub fn node_mut(&mut self, path: &CStr) -> Result<Option<FdtNodeMut>> {
-        Ok(self.path_offset(path.to_bytes())?.map(|offset| FdtNodeMut { fdt: self, offset }))
+        self.node_mut_by_bytes(path.to_bytes())
+    }
+
+    /// Returns a mutable tree node by its full path, without requiring a nul-terminated path.
+    pub fn node_mut_by_str(&mut self, path: &str) -> Result<Option<FdtNodeMut>> {
+        self.node_mut_by_bytes(path.as_bytes())
+    }
+
+    fn node_mut_by_bytes(&mut self, path: &[u8]) -> Result<Option<FdtNodeMut>> {
+        Ok(self.path_offset(path)?.map(|offset| FdtNodeMut { fdt: self, offset }))
     }
 
     /// Returns the device tree as a slice (may be smaller than the containing buffer).
@@ -1078,6 +2212,27 @@ impl Fdt {
         fdt_err_or_option(ret)
     }
 
+    fn validate(&self, validation: Validation) -> Result<()> {
+        match validation {
+            Validation::HeaderOnly => self.check_header(),
+            Validation::Full => self.check_full(),
+        }
+    }
+
+    fn check_header(&self) -> Result<()> {
+        if self.capacity() < mem::size_of::<libfdt_bindgen::fdt_header>() {
+            return Err(FdtError::Truncated);
+        }
+        // SAFETY: Only performs read accesses within the header, which fits in the slice.
+        let ret = unsafe { libfdt_bindgen::fdt_check_header(self.as_ptr()) };
+        fdt_err_expect_zero(ret)?;
+        // Other functions rely on the header to bound their accesses, so it must fit in the slice.
+        if self.totalsize() > self.capacity() {
+            return Err(FdtError::Truncated);
+        }
+        Ok(())
+    }
+
     fn check_full(&self) -> Result<()> {
         // SAFETY: Only performs read accesses within the limits of the slice. If successful, this
         // call guarantees to other unsafe calls that the header contains a valid totalsize (w.r.t.
@@ -1094,6 +2249,13 @@ impl Fdt {
         self.buffer.get(offset..(offset + len)).ok_or(FdtError::Internal)
     }
 
+    fn get_mut_from_ptr(&mut self, ptr: *const c_void, len: usize) -> Result<&mut [u8]> {
+        let ptr = ptr as usize;
+        let offset = ptr.checked_sub(self.as_ptr() as usize).ok_or(FdtError::Internal)?;
+        let end = offset.checked_add(len).ok_or(FdtError::Internal)?;
+        self.buffer.get_mut(offset..end).ok_or(FdtError::Internal)
+    }
+
     fn string(&self, offset: c_int) -> Result<&CStr> {
         // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
         let res = unsafe { libfdt_bindgen::fdt_string(self.as_ptr(), offset) };
@@ -1124,7 +2286,46 @@ impl Fdt {
         unsafe { &*p }
     }
 
-    fn totalsize(&self) -> usize {
+    fn header_mut(&mut self) -> &mut libfdt_bindgen::fdt_header {
+        let p = self.as_mut_ptr().cast::<_>();
+        // SAFETY: A valid FDT (verified by constructor) must contain a valid fdt_header.
+        unsafe { &mut *p }
+    }
+
+    /// Returns the total size of the DT blob, as recorded in its header.
+    pub fn totalsize(&self) -> usize {
         u32::from_be(self.header().totalsize) as usize
     }
+
+    /// Returns the version of the DT format.
+    pub fn version(&self) -> u32 {
+        u32::from_be(self.header().version)
+    }
+
+    /// Returns the oldest version of the DT format with which this DT is backwards compatible.
+    pub fn last_comp_version(&self) -> u32 {
+        u32::from_be(self.header().last_comp_version)
+    }
+
+    /// Returns the physical ID of the boot CPU.
+    pub fn boot_cpuid_phys(&self) -> u32 {
+        u32::from_be(self.header().boot_cpuid_phys)
+    }
+
+    /// Sets the physical ID of the boot CPU.
+    pub fn set_boot_cpuid_phys(&mut self, cpuid: u32) {
+        self.header_mut().boot_cpuid_phys = cpuid.to_be();
+    }
+
+    /// Returns the number of bytes by which the DT can grow in place, as libfdt keeps the strings
+    /// block at the end of the DT when modifying it.
+    ///
+    /// This is bounded by [`Fdt::totalsize`]; use [`Fdt::unpack`] to make the rest of the
+    /// underlying slice available, or move the DT to a bigger buffer if that isn't enough.
+    pub fn spare_capacity(&self) -> usize {
+        let header = self.header();
+        let strings_end = u32::from_be(header.off_dt_strings) as usize
+            + u32::from_be(header.size_dt_strings) as usize;
+        self.totalsize().saturating_sub(strings_end)
+    }
 }
@@ -0,0 +1,87 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Construction of DT overlays (DTBO).
+
+use crate::{Fdt, FdtError, FdtNodeMut, Result};
+use core::ffi::CStr;
+use cstr::cstr;
+
+/// Length of "fragment@" followed by the decimal representation of `u32::MAX`.
+const FRAGMENT_NAME_MAX_LEN: usize = 9 + 10;
+
+/// Builds a DT overlay into a caller-provided buffer.
+///
+/// Each fragment is emitted as a `fragment@N` node, numbered in order of creation, with a
+/// `target-path` property and an `__overlay__` subnode holding the content to be merged into the
+/// target node.
+pub struct OverlayBuilder<'a> {
+    fdt: &'a mut Fdt,
+    next_fragment: u32,
+}
+
+impl<'a> OverlayBuilder<'a> {
+    /// Creates an empty overlay in `buf`.
+    pub fn new(buf: &'a mut [u8]) -> Result<Self> {
+        Ok(Self { fdt: Fdt::create_empty_tree(buf)?, next_fragment: 0 })
+    }
+
+    /// Adds a fragment targeting the node at `target_path` of the base DT, and returns its
+    /// `__overlay__` node, to be populated by the caller.
+    pub fn add_fragment(&mut self, target_path: &CStr) -> Result<FdtNodeMut> {
+        let mut name = [0; FRAGMENT_NAME_MAX_LEN];
+        let name = fragment_name(self.next_fragment, &mut name);
+
+        let root = FdtNodeMut { fdt: self.fdt, offset: 0 }.add_subnode_offset(name)?;
+        let mut fragment = FdtNodeMut { fdt: self.fdt, offset: root };
+        fragment.setprop(cstr!("target-path"), target_path.to_bytes_with_nul())?;
+        let overlay = fragment.add_subnode_offset(b"__overlay__")?;
+
+        self.next_fragment = self.next_fragment.checked_add(1).ok_or(FdtError::Internal)?;
+        Ok(FdtNodeMut { fdt: self.fdt, offset: overlay })
+    }
+
+    /// Returns the overlay built so far, for additional changes such as adding `__fixups__`.
+    pub fn fdt(&mut self) -> &mut Fdt {
+        self.fdt
+    }
+
+    /// Packs the overlay and returns it, ready to be applied with [`Fdt::apply_overlay`] or
+    /// written out as a DTBO.
+    pub fn finish(self) -> Result<&'a mut Fdt> {
+        self.fdt.pack()?;
+        Ok(self.fdt)
+    }
+}
+
+/// Formats the name of the fragment with the given index into `buf`.
+fn fragment_name(index: u32, buf: &mut [u8; FRAGMENT_NAME_MAX_LEN]) -> &[u8] {
+    const PREFIX: &[u8] = b"fragment@";
+    buf[..PREFIX.len()].copy_from_slice(PREFIX);
+
+    let mut digits = [0; 10];
+    let mut start = digits.len();
+    let mut index = index;
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (index % 10) as u8;
+        index /= 10;
+        if index == 0 {
+            break;
+        }
+    }
+    let len = PREFIX.len() + digits.len() - start;
+    buf[PREFIX.len()..len].copy_from_slice(&digits[start..]);
+    &buf[..len]
+}
@@ -0,0 +1,192 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Extraction of a subtree into a standalone DT.
+
+use crate::iterators::NodePath;
+use crate::MAX_PATH_LEN;
+use crate::{fdt_err_expect_zero, Fdt, FdtError, FdtNode, FdtNodeMut, FdtProperty, Result};
+use core::ffi::{c_int, CStr};
+use cstr::cstr;
+
+const SYMBOLS_PATH: &[u8] = b"/__symbols__";
+const FIXUPS_PATH: &[u8] = b"/__fixups__";
+const LOCAL_FIXUPS_PATH: &[u8] = b"/__local_fixups__";
+
+impl<'a> FdtNode<'a> {
+    /// Copies this node and its descendants into `buf`, as a standalone DT.
+    ///
+    /// The node keeps its path, so its ancestors are kept with their properties but all other
+    /// nodes are deleted. The entries of `__symbols__`, `__fixups__` and `__local_fixups__` that
+    /// refer to deleted nodes are deleted too, so that the phandle references of an overlay can
+    /// still be resolved, e.g. after splitting it into per-device fragments.
+    pub fn extract_to<'b>(&self, buf: &'b mut [u8]) -> Result<&'b mut Fdt> {
+        let mut path = [0; MAX_PATH_LEN];
+        let path_len = self.path_into(&mut path)?;
+        let path = &path[..path_len];
+
+        let len = buf.len().try_into().map_err(|_| FdtError::NoSpace)?;
+        // SAFETY: Reads are constrained to the DT totalsize (validated by ctor) and writes to the
+        // `len` bytes of `buf`, which doesn't overlap with the source DT as it is borrowed mutably.
+        let ret = unsafe {
+            libfdt_bindgen::fdt_open_into(self.fdt.as_ptr(), buf.as_mut_ptr().cast(), len)
+        };
+        fdt_err_expect_zero(ret)?;
+
+        let fdt = Fdt::from_mut_slice(buf)?;
+        fdt.retain_subtree(path)?;
+        fdt.pack()?;
+        Ok(fdt)
+    }
+}
+
+impl Fdt {
+    /// Deletes the nodes which aren't related to the node at `path`, see [`FdtNode::extract_to`].
+    fn retain_subtree(&mut self, path: &[u8]) -> Result<()> {
+        let root = self.root()?;
+        let mut node_path = NodePath::new(&root)?;
+        // Last node kept, with its depth. As deleting a node shifts the following ones, the walk
+        // continues from it, whose offset is unchanged.
+        let mut kept = (root.offset, 0);
+        loop {
+            let node = FdtNode { fdt: self, offset: kept.0 };
+            let Some((next, depth)) = node.next_node(kept.1)? else {
+                break;
+            };
+            node_path.enter(next.name()?.to_bytes(), depth)?;
+            if is_related(node_path.to_bytes(), path) {
+                kept = (next.offset, depth);
+                continue;
+            }
+            let offset = next.offset;
+            // SAFETY: Accesses are constrained to the DT totalsize (validated by ctor). The node
+            // was found above and no node of the DT is in use while the DT is modified.
+            let ret = unsafe { libfdt_bindgen::fdt_del_node(self.as_mut_ptr(), offset) };
+            fdt_err_expect_zero(ret)?;
+        }
+        self.retain_symbols(path)?;
+        self.retain_fixups(path)
+    }
+
+    /// Deletes the labels of the nodes outside of the subtree at `path`.
+    fn retain_symbols(&mut self, path: &[u8]) -> Result<()> {
+        let Some(symbols) = self.symbols()? else {
+            return Ok(());
+        };
+        let offset = symbols.offset;
+        self.retain_props(offset, |value| {
+            let target =
+                CStr::from_bytes_with_nul(value).map_err(|_| FdtError::BadValue)?.to_bytes();
+            Ok(if is_below(target, path) { value.len() } else { 0 })
+        })
+    }
+
+    /// Deletes the unresolved phandle references made by nodes outside of the subtree at `path`.
+    fn retain_fixups(&mut self, path: &[u8]) -> Result<()> {
+        let Some(fixups) = self.node(cstr!("/__fixups__"))? else {
+            return Ok(());
+        };
+        let offset = fixups.offset;
+        self.retain_props(offset, |value| {
+            retain_str_list(value, |entry| is_below(fixup_path(entry), path))
+        })
+    }
+
+    /// Passes the value of each property of the node at `offset` to `retain`, which may modify
+    /// it and returns the size to keep. The property is then trimmed to that size, or deleted if
+    /// it is 0.
+    fn retain_props(
+        &mut self,
+        offset: c_int,
+        mut retain: impl FnMut(&mut [u8]) -> Result<usize>,
+    ) -> Result<()> {
+        let mut name = [0; MAX_PATH_LEN];
+        // Last property kept. As deleting a property shifts the following ones, the scan continues
+        // from it, whose offset is unchanged.
+        let mut kept = None;
+        loop {
+            let prop = match kept {
+                None => FdtNode { fdt: self, offset }.first_property()?,
+                Some(kept) => FdtProperty::new(self, kept)?.next_property()?,
+            };
+            let Some(prop) = prop else {
+                return Ok(());
+            };
+            let prop_offset = prop.offset;
+            let (ptr, len) = (prop.property.data_ptr(), prop.property.data_len());
+            let prop_name = copy_name(prop.name()?, &mut name)?;
+
+            let new_len = retain(self.get_mut_from_ptr(ptr, len)?)?;
+            let mut node = FdtNodeMut { fdt: self, offset };
+            if new_len == 0 {
+                node.delprop(prop_name)?;
+            } else {
+                node.trimprop(prop_name, new_len)?;
+                kept = Some(prop_offset);
+            }
+        }
+    }
+}
+
+/// Returns whether the node at `node_path` is kept when extracting the subtree at `path`.
+fn is_related(node_path: &[u8], path: &[u8]) -> bool {
+    if node_path == SYMBOLS_PATH || node_path == FIXUPS_PATH {
+        return true;
+    }
+    // __local_fixups__ mirrors the structure of the tree.
+    let node_path = match node_path.strip_prefix(LOCAL_FIXUPS_PATH) {
+        Some(b"") => &b"/"[..],
+        Some(mirrored) => mirrored,
+        None => node_path,
+    };
+    is_below(node_path, path) || is_below(path, node_path)
+}
+
+/// Returns whether `path` is `ancestor` or one of its descendants.
+fn is_below(path: &[u8], ancestor: &[u8]) -> bool {
+    if ancestor == b"/" {
+        return path.starts_with(b"/");
+    }
+    path.strip_prefix(ancestor).is_some_and(|rest| rest.is_empty() || rest.starts_with(b"/"))
+}
+
+/// Returns the path of the node making a reference, from a `__fixups__` "path:property:offset"
+/// entry.
+fn fixup_path(entry: &CStr) -> &[u8] {
+    entry.to_bytes().split(|c| *c == b':').next().unwrap()
+}
+
+/// Moves the strings of `list` for which `keep` returns true to its start and returns their size.
+fn retain_str_list(list: &mut [u8], keep: impl Fn(&CStr) -> bool) -> Result<usize> {
+    let mut read = 0;
+    let mut write = 0;
+    while read < list.len() {
+        let entry = CStr::from_bytes_until_nul(&list[read..]).map_err(|_| FdtError::BadValue)?;
+        let len = entry.to_bytes_with_nul().len();
+        if keep(entry) {
+            list.copy_within(read..(read + len), write);
+            write += len;
+        }
+        read += len;
+    }
+    Ok(write)
+}
+
+/// Copies `name` into `buf`, so that it remains valid while the DT is modified.
+fn copy_name<'b>(name: &CStr, buf: &'b mut [u8]) -> Result<&'b CStr> {
+    let name = name.to_bytes_with_nul();
+    let buf = buf.get_mut(..name.len()).ok_or(FdtError::NoSpace)?;
+    buf.copy_from_slice(name);
+    CStr::from_bytes_with_nul(buf).map_err(|_| FdtError::Internal)
+}
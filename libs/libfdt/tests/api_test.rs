@@ -18,7 +18,11 @@
 
 use core::ffi::CStr;
 use cstr::cstr;
-use libfdt::{Fdt, FdtError, FdtNodeMut, Phandle};
+use libfdt::{
+    AddressRange, DiffEvent, Fdt, FdtBuilder, FdtContextError, FdtError, FdtNode, FdtNodeMut,
+    FdtReserveMapBuilder, FdtResultExt, Fixup, OverlayBuilder, Phandle, Reg, Validation,
+    VisitAction, MAX_PATH_LEN,
+};
 use std::ffi::CString;
 use std::fs;
 use std::ops::Range;
@@ -141,6 +145,25 @@ fn node_supernode_at_depth() {
     assert_eq!(supernode_names, expected);
 }
 
+#[test]
+fn node_mut_supernode_at_depth() {
+    let mut data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    let node = fdt.node(cstr!("/cpus/PowerPC,970@1")).unwrap().unwrap();
+    assert_eq!(node.depth(), Ok(2));
+    assert_eq!(fdt.root().unwrap().depth(), Ok(0));
+
+    let node = fdt.node_mut(cstr!("/cpus/PowerPC,970@1")).unwrap().unwrap();
+    assert_eq!(node.depth(), Ok(2));
+    let mut cpus = node.supernode_at_depth(1).unwrap();
+    assert_eq!(cpus.as_node().name(), Ok(cstr!("cpus")));
+    cpus.setprop_inplace(cstr!("#address-cells"), &2_u32.to_be_bytes()).unwrap();
+    assert_eq!(cpus.supernode_at_depth(2).err(), Some(FdtError::NotFound));
+
+    let cpus = fdt.node(cstr!("/cpus")).unwrap().unwrap();
+    assert_eq!(cpus.getprop_u32(cstr!("#address-cells")), Ok(Some(2)));
+}
+
 #[test]
 fn phandle_new() {
     let valid_phandles = [
@@ -250,6 +273,23 @@ fn node_nop() {
     assert_eq!(fdt.node(path), Ok(None));
 }
 
+#[test]
+fn node_delete() {
+    let mut data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    let phandle = Phandle::new(0xFF).unwrap();
+    let path = cstr!("/node_z/node_zz");
+
+    let node = fdt.node_mut(path).unwrap().unwrap();
+    node.delete().unwrap();
+
+    assert_eq!(fdt.node_with_phandle(phandle), Ok(None));
+    assert_eq!(fdt.node(path), Ok(None));
+    assert_eq!(fdt.node(cstr!("/node_z/node_zz/node_zzz")), Ok(None));
+    assert!(fdt.node(cstr!("/node_z/node_zb")).unwrap().is_some());
+    assert!(fdt.node(cstr!("/__symbols__")).unwrap().is_some());
+}
+
 #[test]
 fn node_add_subnode_with_namelen() {
     let mut data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
@@ -309,6 +349,146 @@ fn node_mut_as_node() {
     memory.setprop_inplace(cstr!("device_type"), b"MEMORY\0").unwrap();
 }
 
+#[test]
+fn node_mut_getprop() {
+    let mut data = fs::read(TEST_TREE_WITH_ONE_MEMORY_RANGE_PATH).unwrap();
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+
+    let mut memory = fdt.node_mut(cstr!("/memory")).unwrap().unwrap();
+    assert_eq!(memory.getprop_str(cstr!("device_type")), Ok(Some(cstr!("memory"))));
+    assert_eq!(memory.getprop(cstr!("nonexistent")), Ok(None));
+    let reg = memory.first_reg().unwrap();
+    assert_eq!(reg.size, Some(0x100));
+
+    // Read-modify-write without going through an immutable node.
+    let mut cells: Vec<u32> = memory.getprop_cells(cstr!("reg")).unwrap().unwrap().collect();
+    cells[1] /= 2;
+    let value: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+    memory.setprop_inplace(cstr!("reg"), &value).unwrap();
+    assert_eq!(memory.first_reg().unwrap().size, Some(0x80));
+}
+
+#[test]
+fn node_mut_getprop_mut() {
+    let mut data = fs::read(TEST_TREE_WITH_ONE_MEMORY_RANGE_PATH).unwrap();
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    let totalsize = fdt.totalsize();
+
+    let mut memory = fdt.node_mut(cstr!("/memory")).unwrap().unwrap();
+    assert_eq!(memory.getprop_mut(cstr!("nonexistent")), Ok(None));
+    let reg = memory.getprop_mut(cstr!("reg")).unwrap().unwrap();
+    // Only flip the lowest cell of the size.
+    let len = reg.len();
+    reg[len - 4..].copy_from_slice(&0x80_u32.to_be_bytes());
+    assert_eq!(memory.first_reg().unwrap().size, Some(0x80));
+    assert_eq!(fdt.totalsize(), totalsize);
+}
+
+#[test]
+fn node_mut_typed_setprop() {
+    let mut data = fs::read(TEST_TREE_WITH_ONE_MEMORY_RANGE_PATH).unwrap();
+    data.resize(data.len() * 2, 0_u8);
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    fdt.unpack().unwrap();
+
+    let mut memory = fdt.node_mut(cstr!("/memory")).unwrap().unwrap();
+    memory.setprop_u32(cstr!("u32"), 0x1234_5678).unwrap();
+    memory.setprop_u64(cstr!("u64"), 0x1234_5678_9abc_def0).unwrap();
+    memory.setprop_str(cstr!("str"), cstr!("value")).unwrap();
+    memory.setprop_cells(cstr!("cells"), [1, 2, 3]).unwrap();
+    memory.setprop_cells(cstr!("no_cells"), []).unwrap();
+
+    assert_eq!(memory.getprop(cstr!("u32")), Ok(Some(&[0x12, 0x34, 0x56, 0x78][..])));
+    assert_eq!(memory.getprop_u64(cstr!("u64")), Ok(Some(0x1234_5678_9abc_def0)));
+    assert_eq!(memory.getprop(cstr!("str")), Ok(Some(&b"value\0"[..])));
+    let cells: Vec<u32> = memory.getprop_cells(cstr!("cells")).unwrap().unwrap().collect();
+    assert_eq!(cells, [1, 2, 3]);
+    assert_eq!(memory.getprop(cstr!("no_cells")), Ok(Some(&[][..])));
+}
+
+#[test]
+fn node_getprop_str_list() {
+    let data = fs::read(TEST_TREE_WITH_ONE_MEMORY_RANGE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+    let root = fdt.root().unwrap();
+
+    let compatibles: Vec<_> =
+        root.getprop_str_list(cstr!("compatible")).unwrap().unwrap().collect();
+    assert_eq!(compatibles, [cstr!("MyBoardName"), cstr!("MyBoardFamilyName")]);
+    assert_eq!(root.getprop_str(cstr!("compatible")), Err(FdtError::BadValue));
+    assert_eq!(root.getprop_str_list(cstr!("nonexistent")).unwrap().map(|l| l.count()), None);
+
+    assert_eq!(root.contains_compatible(cstr!("MyBoardFamilyName")), Ok(true));
+    assert_eq!(root.contains_compatible(cstr!("MyBoard")), Ok(false));
+    let memory = fdt.node(cstr!("/memory")).unwrap().unwrap();
+    assert_eq!(memory.contains_compatible(cstr!("MyBoardName")), Ok(false));
+}
+
+#[test]
+fn node_path() {
+    let data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+    let mut buf = [0_u8; MAX_PATH_LEN];
+
+    let root = fdt.root().unwrap();
+    assert_eq!(root.path(&mut buf), Ok(cstr!("/")));
+    let node_zz = fdt.node(cstr!("/node_z/node_zz")).unwrap().unwrap();
+    assert_eq!(node_zz.path(&mut buf), Ok(cstr!("/node_z/node_zz")));
+    assert_eq!(node_zz.path(&mut buf[..8]), Err(FdtError::NoSpace));
+}
+
+#[test]
+fn node_extract_to() {
+    let data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+    let mut buf = vec![0_u8; data.len()];
+
+    let node_zz = fdt.node(cstr!("/node_z/node_zz")).unwrap().unwrap();
+    let subtree = node_zz.extract_to(&mut buf).unwrap();
+
+    let mut paths = vec![];
+    let mut walker = subtree.walk().unwrap();
+    while let Some(next) = walker.next_node() {
+        paths.push(next.unwrap().1.to_owned());
+    }
+    assert_eq!(
+        paths,
+        ["/", "/node_z", "/node_z/node_zz", "/node_z/node_zz/node_zzz", "/__symbols__"]
+            .map(|p| CString::new(p).unwrap())
+    );
+    let node = subtree.node_with_phandle(Phandle::new(0xff).unwrap()).unwrap().unwrap();
+    assert_eq!(node.name(), Ok(cstr!("node_zz")));
+    let symbols = subtree.symbols().unwrap().unwrap();
+    assert_eq!(symbols.getprop(cstr!("symbol_a")), Ok(None));
+
+    let node_a = fdt.node(cstr!("/node_a")).unwrap().unwrap();
+    let subtree = node_a.extract_to(&mut buf).unwrap();
+    let symbols = subtree.symbols().unwrap().unwrap();
+    assert_eq!(symbols.getprop_str(cstr!("symbol_a")), Ok(Some(cstr!("/node_a"))));
+    assert_eq!(subtree.node(cstr!("/node_z")), Ok(None));
+}
+
+#[test]
+fn mem_rsv() {
+    let mut data = fs::read(TEST_TREE_WITH_ONE_MEMORY_RANGE_PATH).unwrap();
+    data.resize(data.len() * 2, 0_u8);
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    fdt.unpack().unwrap();
+    assert_eq!(fdt.mem_rsv_iter().count(), 0);
+
+    fdt.add_mem_rsv(0x8000_0000, 0x1000).unwrap();
+    fdt.add_mem_rsv(0x9000_0000, 0x2000).unwrap();
+    fdt.pack().unwrap();
+
+    let reserved: Vec<_> = fdt.mem_rsv_iter().collect();
+    assert_eq!(reserved, [Ok(0x8000_0000..0x8000_1000), Ok(0x9000_0000..0x9000_2000)]);
+
+    fdt.unpack().unwrap();
+    fdt.add_mem_rsv(u64::MAX, 0x1000).unwrap();
+    let reserved: Vec<_> = fdt.mem_rsv_iter().skip(2).collect();
+    assert_eq!(reserved, [Err(FdtError::BadValue)]);
+}
+
 #[test]
 fn node_descendants() {
     let mut data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
@@ -328,3 +508,1355 @@ fn node_descendants() {
         ]
     );
 }
+
+#[test]
+fn node_walk() {
+    let data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let node_z = fdt.node(cstr!("/node_z")).unwrap().unwrap();
+    let mut walker = node_z.walk().unwrap();
+    let mut paths = vec![];
+    while let Some(next) = walker.next_node() {
+        let (node, path) = next.unwrap();
+        assert_eq!(fdt.node(path).unwrap(), Some(node));
+        paths.push(path.to_owned());
+    }
+
+    assert_eq!(
+        paths,
+        vec![
+            cstr!("/node_z").to_owned(),
+            cstr!("/node_z/node_za").to_owned(),
+            cstr!("/node_z/node_zb").to_owned(),
+            cstr!("/node_z/node_zz").to_owned(),
+            cstr!("/node_z/node_zz/node_zzz").to_owned(),
+        ]
+    );
+}
+
+#[test]
+fn fdt_walk() {
+    let data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let mut walker = fdt.walk().unwrap();
+    let mut paths = vec![];
+    while let Some(next) = walker.next_node() {
+        paths.push(next.unwrap().1.to_str().unwrap().to_owned());
+    }
+
+    assert_eq!(paths.first().map(String::as_str), Some("/"));
+    assert!(paths.contains(&"/node_a/node_ab/node_abc".to_owned()));
+    assert!(paths.contains(&"/node_b".to_owned()));
+    assert!(paths.contains(&"/__symbols__".to_owned()));
+    assert_eq!(paths.len(), fdt.root().unwrap().descendants().count() + 1);
+}
+
+#[test]
+fn fdt_alias() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.add_subnode(cstr!("uart@3f8")).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    let mut aliases = root.add_subnode(cstr!("aliases")).unwrap();
+    aliases.setprop_str(cstr!("serial0"), cstr!("/uart@3f8")).unwrap();
+    aliases.setprop_str(cstr!("serial1"), cstr!("/uart@2f8")).unwrap();
+
+    assert_eq!(fdt.alias(cstr!("serial0")), Ok(Some(cstr!("/uart@3f8"))));
+    assert_eq!(fdt.alias(cstr!("serial2")), Ok(None));
+    let uart = fdt.node_by_alias(cstr!("serial0")).unwrap().unwrap();
+    assert_eq!(uart.name(), Ok(cstr!("uart@3f8")));
+    assert_eq!(fdt.node_by_alias(cstr!("serial1")), Ok(None));
+}
+
+#[test]
+fn node_mut_set_phandle_and_symbol() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.add_subnode(cstr!("dev")).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.add_subnode(cstr!("other")).unwrap().setprop_u32(cstr!("phandle"), 5).unwrap();
+
+    let copy = fdt.as_slice().to_vec();
+    let mut dev = fdt.node_mut(cstr!("/dev")).unwrap().unwrap();
+    assert_eq!(dev.set_phandle_and_symbol(cstr!("dev")), Err(FdtError::NotFound));
+    assert_eq!(fdt.as_slice(), copy);
+
+    // Added last, so that it precedes the other nodes and growing it moves them.
+    fdt.root_mut().unwrap().add_subnode(cstr!("__symbols__")).unwrap();
+    let mut dev = fdt.node_mut(cstr!("/dev")).unwrap().unwrap();
+    let phandle = dev.set_phandle_and_symbol(cstr!("dev")).unwrap();
+    assert_eq!(phandle, Phandle::new(6).unwrap());
+    dev.setprop_empty(cstr!("still-valid")).unwrap();
+    assert_eq!(dev.set_phandle_and_symbol(cstr!("dev")), Err(FdtError::Exists));
+    assert_eq!(dev.set_phandle_and_symbol(cstr!("alias")), Ok(phandle));
+
+    let symbols = fdt.symbols().unwrap().unwrap();
+    assert_eq!(symbols.getprop_str(cstr!("dev")), Ok(Some(cstr!("/dev"))));
+    assert_eq!(symbols.getprop_str(cstr!("alias")), Ok(Some(cstr!("/dev"))));
+    let dev = fdt.node_with_phandle(phandle).unwrap().unwrap();
+    assert_eq!(dev.name(), Ok(cstr!("dev")));
+    assert!(dev.getprop(cstr!("still-valid")).unwrap().is_some());
+
+    let mut other = fdt.node_mut(cstr!("/other")).unwrap().unwrap();
+    assert_eq!(other.set_phandle_and_symbol(cstr!("other")), Ok(Phandle::new(5).unwrap()));
+
+    fdt.pack().unwrap();
+    let copy = fdt.as_slice().to_vec();
+    let mut root = fdt.root_mut().unwrap();
+    assert_eq!(root.set_phandle_and_symbol(cstr!("root")), Err(FdtError::NoSpace));
+    assert_eq!(fdt.as_slice(), copy);
+}
+
+#[test]
+fn node_by_prop_value() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.setprop(cstr!("device_type"), b"root\0").unwrap();
+    let nodes = [
+        (cstr!("memory@0"), &b"memory\0"[..]),
+        (cstr!("cpu@0"), &b"cpu\0"[..]),
+        (cstr!("memory@1000"), &b"memory\0"[..]),
+    ];
+    for (name, device_type) in nodes {
+        let mut root = fdt.root_mut().unwrap();
+        let mut node = root.add_subnode(name).unwrap();
+        node.setprop(cstr!("device_type"), device_type).unwrap();
+    }
+
+    let root = fdt.node_by_prop_value(cstr!("device_type"), b"root\0").unwrap().unwrap();
+    assert_eq!(root.name(), Ok(cstr!("")));
+    let cpu = fdt.node_by_prop_value(cstr!("device_type"), b"cpu\0").unwrap().unwrap();
+    assert_eq!(cpu.name(), Ok(cstr!("cpu@0")));
+    assert_eq!(fdt.node_by_prop_value(cstr!("device_type"), b"memory"), Ok(None));
+    assert_eq!(fdt.node_by_prop_value(cstr!("status"), b"okay\0"), Ok(None));
+
+    let mut memory: Vec<_> = fdt
+        .nodes_with_prop_value(cstr!("device_type"), b"memory\0")
+        .map(|n| n.name().unwrap())
+        .collect();
+    memory.sort();
+    assert_eq!(memory, vec![cstr!("memory@0"), cstr!("memory@1000")]);
+}
+
+#[test]
+fn nodes_with_device_type() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let nodes = [
+        (cstr!("cpus"), None),
+        (cstr!("memory@0"), Some(cstr!("memory"))),
+        (cstr!("cpu@0"), Some(cstr!("cpu"))),
+        (cstr!("pci"), Some(cstr!("pci"))),
+        (cstr!("cpu@1"), Some(cstr!("cpu"))),
+    ];
+    for (name, device_type) in nodes {
+        let mut root = fdt.root_mut().unwrap();
+        let mut node = root.add_subnode(name).unwrap();
+        if let Some(device_type) = device_type {
+            node.setprop_str(cstr!("device_type"), device_type).unwrap();
+        }
+    }
+
+    let mut cpus: Vec<_> =
+        fdt.nodes_with_device_type(cstr!("cpu")).map(|n| n.name().unwrap()).collect();
+    cpus.sort();
+    assert_eq!(cpus, vec![cstr!("cpu@0"), cstr!("cpu@1")]);
+    let pci: Vec<_> = fdt.nodes_with_device_type(cstr!("pci")).map(|n| n.name().unwrap()).collect();
+    assert_eq!(pci, vec![cstr!("pci")]);
+    // The whole value is compared, not a prefix.
+    assert_eq!(fdt.nodes_with_device_type(cstr!("mem")).count(), 0);
+    assert_eq!(fdt.nodes_with_device_type(cstr!("memory")).count(), 1);
+}
+
+#[test]
+fn node_mut_renameprop() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    let mut node = root.add_subnode(cstr!("node")).unwrap();
+    node.setprop_u32(cstr!("linux,phandle"), 0x1234).unwrap();
+    node.setprop_empty(cstr!("other")).unwrap();
+
+    node.renameprop(cstr!("linux,phandle"), cstr!("phandle")).unwrap();
+    assert_eq!(node.getprop_u32(cstr!("phandle")), Ok(Some(0x1234)));
+    assert_eq!(node.getprop(cstr!("linux,phandle")), Ok(None));
+
+    assert_eq!(node.renameprop(cstr!("phandle"), cstr!("other")), Err(FdtError::Exists));
+    assert_eq!(node.renameprop(cstr!("missing"), cstr!("new")), Err(FdtError::NotFound));
+
+    let fdt = node.fdt();
+    fdt.pack().unwrap();
+    let mut node = fdt.node_mut(cstr!("/node")).unwrap().unwrap();
+    assert_eq!(node.renameprop(cstr!("phandle"), cstr!("new")), Err(FdtError::NoSpace));
+    assert_eq!(node.getprop_u32(cstr!("phandle")), Ok(Some(0x1234)));
+}
+
+#[test]
+fn node_mut_into_parent() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = FdtBuilder::new(&mut data)
+        .unwrap()
+        .node(cstr!("bus"), |bus| {
+            bus.property_u32(cstr!("#address-cells"), 1)?;
+            bus.node(cstr!("dev"), |_| Ok(()))?;
+            Ok(())
+        })
+        .unwrap()
+        .finish()
+        .unwrap();
+
+    let dev = fdt.node_mut(cstr!("/bus/dev")).unwrap().unwrap();
+    let mut bus = dev.into_parent().unwrap();
+    bus.setprop_inplace(cstr!("#address-cells"), &2_u32.to_be_bytes()).unwrap();
+    let root = bus.into_parent().unwrap();
+    assert_eq!(root.as_node().name(), Ok(cstr!("")));
+    assert_eq!(root.into_parent().err(), Some(FdtError::NotFound));
+
+    let bus = fdt.node(cstr!("/bus")).unwrap().unwrap();
+    assert_eq!(bus.getprop_u32(cstr!("#address-cells")), Ok(Some(2)));
+}
+
+#[test]
+fn node_mut_append_compatible() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    let mut node = root.add_subnode(cstr!("node")).unwrap();
+
+    node.append_compatible(cstr!("vendor,dev")).unwrap();
+    node.append_compatible(cstr!("avf,dev")).unwrap();
+    node.append_compatible(cstr!("vendor,dev")).unwrap();
+
+    let node = fdt.node(cstr!("/node")).unwrap().unwrap();
+    let compatibles: Vec<_> =
+        node.getprop_str_list(cstr!("compatible")).unwrap().unwrap().collect();
+    assert_eq!(compatibles, [cstr!("vendor,dev"), cstr!("avf,dev")]);
+}
+
+#[test]
+fn fdt_for_each_compatible_mut() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    // Subnodes are added before existing ones, so add them in reverse order.
+    for name in [cstr!("n4"), cstr!("n3"), cstr!("n2"), cstr!("n1"), cstr!("n0")] {
+        let mut root = fdt.root_mut().unwrap();
+        root.add_subnode(name).unwrap().setprop_str(cstr!("compatible"), cstr!("test")).unwrap();
+    }
+
+    let mut visited = vec![];
+    fdt.for_each_compatible_mut(cstr!("test"), |mut node| {
+        let name = node.as_node().name()?.to_owned();
+        node.setprop_empty(cstr!("visited"))?;
+        let delete = name.as_c_str() == cstr!("n1") || name.as_c_str() == cstr!("n3");
+        visited.push(name);
+        Ok(if delete { VisitAction::Delete } else { VisitAction::Keep })
+    })
+    .unwrap();
+    assert_eq!(visited, ["n0", "n1", "n2", "n3", "n4"].map(|s| CString::new(s).unwrap()));
+
+    let remaining: Vec<_> = fdt.compatible_nodes(cstr!("test")).unwrap().collect();
+    let names: Vec<_> = remaining.iter().map(|node| node.name().unwrap()).collect();
+    assert_eq!(names, [cstr!("n0"), cstr!("n2"), cstr!("n4")]);
+    assert!(remaining.iter().all(|node| node.getprop(cstr!("visited")).unwrap().is_some()));
+
+    let mut visited = vec![];
+    fdt.for_each_compatible_mut(cstr!("test"), |mut node| {
+        visited.push(node.as_node().name()?.to_owned());
+        // Growing a property of the root moves the visited node.
+        node.fdt().root_mut()?.appendprop(cstr!("grown"), &[0; 4])?;
+        Ok(if visited.len() == 2 { VisitAction::Delete } else { VisitAction::Keep })
+    })
+    .unwrap();
+    assert_eq!(visited, ["n0", "n2", "n4"].map(|s| CString::new(s).unwrap()));
+    let names: Vec<_> = fdt.compatible_nodes(cstr!("test")).unwrap().map(|n| n.name()).collect();
+    assert_eq!(names, [Ok(cstr!("n0")), Ok(cstr!("n4"))]);
+
+    let mut count = 0;
+    fdt.for_each_compatible_mut(cstr!("test"), |_| {
+        count += 1;
+        Ok(VisitAction::Stop)
+    })
+    .unwrap();
+    assert_eq!(count, 1);
+
+    let result = fdt.for_each_compatible_mut(cstr!("test"), |_| Err(FdtError::BadValue));
+    assert_eq!(result, Err(FdtError::BadValue));
+}
+
+#[test]
+fn fdt_for_each_device_type_mut() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    // Subnodes are added before existing ones, so add them in reverse order.
+    let nodes = [
+        (cstr!("cpu@2"), cstr!("cpu")),
+        (cstr!("memory@0"), cstr!("memory")),
+        (cstr!("cpu@1"), cstr!("cpu")),
+        (cstr!("cpu@0"), cstr!("cpu")),
+    ];
+    for (name, device_type) in nodes {
+        let mut root = fdt.root_mut().unwrap();
+        root.add_subnode(name).unwrap().setprop_str(cstr!("device_type"), device_type).unwrap();
+    }
+
+    let mut visited = vec![];
+    fdt.for_each_device_type_mut(cstr!("cpu"), |mut node| {
+        visited.push(node.as_node().name()?.to_owned());
+        // Growing a property of the root moves the visited node.
+        node.fdt().root_mut()?.appendprop(cstr!("grown"), &[0; 4])?;
+        Ok(if visited.len() == 2 { VisitAction::Delete } else { VisitAction::Keep })
+    })
+    .unwrap();
+    assert_eq!(visited, ["cpu@0", "cpu@1", "cpu@2"].map(|s| CString::new(s).unwrap()));
+    let names: Vec<_> = fdt.nodes_with_device_type(cstr!("cpu")).map(|n| n.name()).collect();
+    assert_eq!(names, [Ok(cstr!("cpu@0")), Ok(cstr!("cpu@2"))]);
+    assert_eq!(fdt.nodes_with_device_type(cstr!("memory")).count(), 1);
+}
+
+#[test]
+fn node_mut_rename() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.add_subnode(cstr!("cpu@0")).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.add_subnode(cstr!("cpu@1")).unwrap().setprop_u32(cstr!("reg"), 1).unwrap();
+
+    let mut node = fdt.node_mut(cstr!("/cpu@1")).unwrap().unwrap();
+    assert_eq!(node.rename(cstr!("cpu@0")), Err(FdtError::Exists));
+    node.rename(cstr!("cpu@1")).unwrap();
+    node.rename(cstr!("cpu")).unwrap();
+    node.rename(cstr!("cpu@100")).unwrap();
+
+    assert!(fdt.node(cstr!("/cpu@1")).unwrap().is_none());
+    let node = fdt.node(cstr!("/cpu@100")).unwrap().unwrap();
+    assert_eq!(node.getprop_u32(cstr!("reg")), Ok(Some(1)));
+    assert!(fdt.node(cstr!("/cpu@0")).unwrap().is_some());
+}
+
+#[test]
+fn overlay_builder() {
+    let mut overlay_data = vec![0_u8; 1000];
+    let mut builder = OverlayBuilder::new(&mut overlay_data).unwrap();
+    let mut node = builder.add_fragment(cstr!("/a")).unwrap();
+    node.setprop_u32(cstr!("prop"), 0xcafe).unwrap();
+    let mut node = builder.add_fragment(cstr!("/")).unwrap();
+    node.add_subnode(cstr!("b")).unwrap();
+    let overlay = builder.finish().unwrap();
+
+    let root = overlay.root().unwrap();
+    let fragments: Vec<_> = root.subnodes().unwrap().map(|n| n.name().unwrap()).collect();
+    assert_eq!(fragments.len(), 2);
+    assert!(fragments.contains(&cstr!("fragment@0")));
+    assert!(fragments.contains(&cstr!("fragment@1")));
+    let fragment = overlay.node(cstr!("/fragment@0")).unwrap().unwrap();
+    assert_eq!(fragment.getprop_str(cstr!("target-path")), Ok(Some(cstr!("/a"))));
+
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.add_subnode(cstr!("a")).unwrap();
+    // SAFETY: Both trees are discarded if applying the overlay fails.
+    let fdt = unsafe { fdt.apply_overlay(overlay) }.unwrap();
+
+    let a = fdt.node(cstr!("/a")).unwrap().unwrap();
+    assert_eq!(a.getprop_u32(cstr!("prop")), Ok(Some(0xcafe)));
+    assert!(fdt.node(cstr!("/b")).unwrap().is_some());
+}
+
+#[test]
+fn overlay_builder_numbers_fragments_and_allows_extra_nodes() {
+    let mut overlay_data = vec![0_u8; 4000];
+    let mut builder = OverlayBuilder::new(&mut overlay_data).unwrap();
+    for _ in 0..11 {
+        builder.add_fragment(cstr!("/")).unwrap();
+    }
+    let mut node = builder.add_fragment(cstr!("/")).unwrap();
+    node.add_subnode(cstr!("rng")).unwrap();
+    let mut root = builder.fdt().root_mut().unwrap();
+    let mut symbols = root.add_subnode(cstr!("__symbols__")).unwrap();
+    symbols.setprop_str(cstr!("rng"), cstr!("/fragment@11/__overlay__/rng")).unwrap();
+    let overlay = builder.finish().unwrap();
+
+    assert!(overlay.node(cstr!("/fragment@10/__overlay__")).unwrap().is_some());
+    assert!(overlay.node(cstr!("/fragment@11/__overlay__/rng")).unwrap().is_some());
+    let symbols = overlay.symbols().unwrap().unwrap();
+    assert_eq!(symbols.getprop_str(cstr!("rng")), Ok(Some(cstr!("/fragment@11/__overlay__/rng"))));
+}
+
+#[test]
+fn copy_sorted_into() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    // libfdt inserts new properties and subnodes before the existing ones.
+    let mut root = fdt.root_mut().unwrap();
+    root.setprop(cstr!("prop_a"), b"a\0").unwrap();
+    root.setprop(cstr!("prop_b"), b"b\0").unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    let mut node_a = root.add_subnode(cstr!("node_a")).unwrap();
+    node_a.setprop_empty(cstr!("prop_y")).unwrap();
+    node_a.setprop_empty(cstr!("prop_z")).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.add_subnode(cstr!("node_b")).unwrap();
+
+    let root = fdt.root().unwrap();
+    let subnode_names: Vec<_> = root.subnodes().unwrap().map(|n| n.name().unwrap()).collect();
+    assert_eq!(subnode_names, vec![cstr!("node_b"), cstr!("node_a")]);
+
+    let mut sorted_data = vec![0_u8; 1000];
+    let sorted = Fdt::create_empty_tree(&mut sorted_data).unwrap();
+    fdt.copy_sorted_into(sorted).unwrap();
+
+    let root = sorted.root().unwrap();
+    let prop_names: Vec<_> = root.properties().unwrap().map(|p| p.name().unwrap()).collect();
+    assert_eq!(prop_names, vec![cstr!("prop_a"), cstr!("prop_b")]);
+    assert_eq!(root.getprop_str(cstr!("prop_a")).unwrap(), Some(cstr!("a")));
+    let subnode_names: Vec<_> = root.subnodes().unwrap().map(|n| n.name().unwrap()).collect();
+    assert_eq!(subnode_names, vec![cstr!("node_a"), cstr!("node_b")]);
+
+    let node_a = sorted.node(cstr!("/node_a")).unwrap().unwrap();
+    let prop_names: Vec<_> = node_a.properties().unwrap().map(|p| p.name().unwrap()).collect();
+    assert_eq!(prop_names, vec![cstr!("prop_y"), cstr!("prop_z")]);
+}
+
+#[test]
+fn node_mut_copy_subtree_from() {
+    let mut src_data = vec![0_u8; 1000];
+    let src = Fdt::create_empty_tree(&mut src_data).unwrap();
+    let mut root = src.root_mut().unwrap();
+    let mut device = root.add_subnode(cstr!("device@1000")).unwrap();
+    device.setprop_u32(cstr!("phandle"), 0x10).unwrap();
+    device.setprop(cstr!("compatible"), b"vendor,device\0").unwrap();
+    let mut child = device.add_subnode(cstr!("child_a")).unwrap();
+    child.setprop_empty(cstr!("flag")).unwrap();
+    let mut device = src.node_mut(cstr!("/device@1000")).unwrap().unwrap();
+    device.add_subnode(cstr!("child_b")).unwrap();
+    let src_node = src.node(cstr!("/device@1000")).unwrap().unwrap();
+
+    let mut dst_data = vec![0_u8; 1000];
+    let dst = Fdt::create_empty_tree(&mut dst_data).unwrap();
+    let mut root = dst.root_mut().unwrap();
+    let copy = root.copy_subtree_from(&src_node).unwrap();
+    assert_eq!(copy.as_node().name(), Ok(cstr!("device@1000")));
+
+    let copy = dst.node(cstr!("/device@1000")).unwrap().unwrap();
+    let names = |node: &FdtNode| -> Vec<_> {
+        node.properties().unwrap().map(|p| p.name().unwrap().to_owned()).collect()
+    };
+    assert_eq!(names(&copy), names(&src_node));
+    assert_eq!(copy.get_phandle(), Ok(Some(Phandle::new(0x10).unwrap())));
+    assert_eq!(copy.getprop_str(cstr!("compatible")), Ok(Some(cstr!("vendor,device"))));
+    let subnodes: Vec<_> = copy.subnodes().unwrap().map(|n| n.name().unwrap()).collect();
+    let src_subnodes: Vec<_> = src_node.subnodes().unwrap().map(|n| n.name().unwrap()).collect();
+    assert_eq!(subnodes, src_subnodes);
+    let child = dst.node(cstr!("/device@1000/child_a")).unwrap().unwrap();
+    assert_eq!(child.getprop(cstr!("flag")), Ok(Some(&[][..])));
+
+    let mut root = dst.root_mut().unwrap();
+    assert_eq!(root.copy_subtree_from(&src_node).unwrap_err(), FdtError::Exists);
+
+    let mut small_data = vec![0_u8; 100];
+    let small = Fdt::create_empty_tree(&mut small_data).unwrap();
+    let before = small.as_slice().to_vec();
+    let mut root = small.root_mut().unwrap();
+    assert_eq!(root.copy_subtree_from(&src_node).unwrap_err(), FdtError::NoSpace);
+    assert_eq!(small.as_slice(), &before[..]);
+}
+
+#[test]
+fn node_translate_address() {
+    fn cells(cells: &[u32]) -> Vec<u8> {
+        cells.iter().flat_map(|c| c.to_be_bytes()).collect()
+    }
+
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.setprop_u32(cstr!("#address-cells"), 2).unwrap();
+    root.setprop_u32(cstr!("#size-cells"), 2).unwrap();
+    let mut bus = root.add_subnode(cstr!("bus@40000000")).unwrap();
+    bus.setprop_u32(cstr!("#address-cells"), 1).unwrap();
+    bus.setprop_u32(cstr!("#size-cells"), 1).unwrap();
+    bus.setprop(cstr!("ranges"), &cells(&[0x0, 0x0, 0x4000_0000, 0x10_0000])).unwrap();
+    let mut sub = bus.add_subnode(cstr!("sub@1000")).unwrap();
+    sub.setprop_u32(cstr!("#address-cells"), 1).unwrap();
+    sub.setprop_u32(cstr!("#size-cells"), 1).unwrap();
+    sub.setprop(cstr!("ranges"), &cells(&[0x0, 0x1000, 0x100])).unwrap();
+    let mut transparent = sub.add_subnode(cstr!("transparent")).unwrap();
+    transparent.setprop_u32(cstr!("#address-cells"), 1).unwrap();
+    transparent.setprop_u32(cstr!("#size-cells"), 1).unwrap();
+    transparent.setprop_empty(cstr!("ranges")).unwrap();
+    transparent.add_subnode(cstr!("dev@10")).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.add_subnode(cstr!("opaque")).unwrap().add_subnode(cstr!("dev@0")).unwrap();
+
+    let bus = fdt.node(cstr!("/bus@40000000")).unwrap().unwrap();
+    assert_eq!(bus.translate_address(0x4000_0000), Ok(0x4000_0000));
+    let dev = fdt.node(cstr!("/bus@40000000/sub@1000/transparent/dev@10")).unwrap().unwrap();
+    assert_eq!(dev.translate_address(0x10), Ok(0x4000_1010));
+    assert_eq!(dev.translate_address(0x100), Err(FdtError::NotFound));
+    let dev = fdt.node(cstr!("/opaque/dev@0")).unwrap().unwrap();
+    assert_eq!(dev.translate_address(0x0), Err(FdtError::NotFound));
+}
+
+#[test]
+fn node_compatible_descendants() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.setprop(cstr!("compatible"), b"test\0").unwrap();
+    // libfdt inserts new subnodes before the existing ones.
+    for name in [cstr!("after"), cstr!("scope"), cstr!("before")] {
+        let mut root = fdt.root_mut().unwrap();
+        let mut node = root.add_subnode(name).unwrap();
+        node.setprop(cstr!("compatible"), b"test\0").unwrap();
+    }
+    let mut scope = fdt.node_mut(cstr!("/scope")).unwrap().unwrap();
+    let mut child = scope.add_subnode(cstr!("child")).unwrap();
+    child.setprop(cstr!("compatible"), b"test\0").unwrap();
+    let mut grandchild = child.add_subnode(cstr!("grandchild")).unwrap();
+    grandchild.setprop(cstr!("compatible"), b"test\0").unwrap();
+
+    let scope = fdt.node(cstr!("/scope")).unwrap().unwrap();
+    let names: Vec<_> =
+        scope.compatible_descendants(cstr!("test")).unwrap().map(|n| n.name().unwrap()).collect();
+    assert_eq!(names, vec![cstr!("child"), cstr!("grandchild")]);
+
+    let after = fdt.node(cstr!("/after")).unwrap().unwrap();
+    assert_eq!(after.compatible_descendants(cstr!("test")).unwrap().count(), 0);
+    let root = fdt.root().unwrap();
+    assert_eq!(root.compatible_descendants(cstr!("test")).unwrap().count(), 5);
+}
+
+#[test]
+fn fdt_builder() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = FdtBuilder::new(&mut data)
+        .unwrap()
+        .property_u32(cstr!("#address-cells"), 2)
+        .unwrap()
+        .property_u32(cstr!("#size-cells"), 2)
+        .unwrap()
+        .node(cstr!("memory@80000000"), |memory| {
+            memory.property_str(cstr!("device_type"), cstr!("memory"))?;
+            memory.property_cells(cstr!("reg"), &[0x0, 0x8000_0000, 0x0, 0x1000_0000])?;
+            Ok(())
+        })
+        .unwrap()
+        .node(cstr!("intc"), |intc| {
+            intc.phandle(Phandle::new(1).unwrap())?
+                .property_empty(cstr!("interrupt-controller"))?;
+            intc.node(cstr!("child"), |child| {
+                child.property_u64(cstr!("value"), 0x1234_5678_9abc_def0)?;
+                Ok(())
+            })?;
+            assert_eq!(intc.property_empty(cstr!("late")).err(), Some(FdtError::BadLayout));
+            Ok(())
+        })
+        .unwrap()
+        .finish()
+        .unwrap();
+
+    let root = fdt.root().unwrap();
+    let subnode_names: Vec<_> = root.subnodes().unwrap().map(|n| n.name().unwrap()).collect();
+    assert_eq!(subnode_names, vec![cstr!("memory@80000000"), cstr!("intc")]);
+    let memory = fdt.memory().unwrap().collect::<Vec<_>>();
+    assert_eq!(memory, vec![0x8000_0000..0x9000_0000]);
+    let intc = fdt.node_with_phandle(Phandle::new(1).unwrap()).unwrap().unwrap();
+    assert_eq!(intc.name(), Ok(cstr!("intc")));
+    let child = fdt.node(cstr!("/intc/child")).unwrap().unwrap();
+    assert_eq!(child.getprop_u64(cstr!("value")), Ok(Some(0x1234_5678_9abc_def0)));
+
+    // The tree spans the whole buffer, so it can still be modified.
+    fdt.root_mut().unwrap().add_subnode(cstr!("chosen")).unwrap();
+}
+
+#[test]
+fn fdt_stats() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let stats = fdt.stats().unwrap();
+    assert_eq!(stats.nop_size, 0);
+    assert_eq!(stats.strings_size, 0);
+    let initial_spare_capacity = stats.spare_capacity;
+
+    let mut root = fdt.root_mut().unwrap();
+    root.setprop_u32(cstr!("kept"), 1).unwrap();
+    root.setprop_empty(cstr!("dropped")).unwrap();
+    root.nop_property(cstr!("dropped")).unwrap();
+
+    let stats = fdt.stats().unwrap();
+    // FDT_PROP tag, length and name offset.
+    assert_eq!(stats.nop_size, 12);
+    assert_eq!(stats.strings_size, "kept\0dropped\0".len());
+    assert!(stats.struct_size > stats.nop_size);
+    assert_eq!(stats.spare_capacity, initial_spare_capacity - 12 - 16 - stats.strings_size);
+}
+
+#[test]
+fn fdt_spare_capacity() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let initial = fdt.spare_capacity();
+    assert!(initial < fdt.totalsize());
+
+    fdt.root_mut().unwrap().setprop_u32(cstr!("foo"), 1).unwrap();
+    // FDT_PROP tag, length, name offset and value, then the name in the strings block.
+    assert_eq!(fdt.spare_capacity(), initial - 16 - "foo\0".len());
+
+    fdt.pack().unwrap();
+    assert_eq!(fdt.spare_capacity(), 0);
+    let packed_size = fdt.totalsize();
+    assert_eq!(fdt.root_mut().unwrap().setprop_u32(cstr!("bar"), 2), Err(FdtError::NoSpace));
+
+    fdt.unpack().unwrap();
+    assert_eq!(fdt.totalsize(), 1000);
+    assert_eq!(fdt.spare_capacity(), 1000 - packed_size);
+}
+
+#[test]
+fn fdt_header_accessors() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    assert_eq!(fdt.totalsize(), 1000);
+    assert_eq!(fdt.version(), 17);
+    assert_eq!(fdt.last_comp_version(), 16);
+    assert_eq!(fdt.boot_cpuid_phys(), 0);
+
+    fdt.set_boot_cpuid_phys(3);
+    assert_eq!(fdt.boot_cpuid_phys(), 3);
+    assert_eq!(Fdt::from_slice(&data).unwrap().boot_cpuid_phys(), 3);
+}
+
+#[test]
+fn fdt_from_unaligned_slice() {
+    const EXPECTED_FIRST_MEMORY_RANGE: Range<usize> = 0..256;
+    let data = fs::read(TEST_TREE_WITH_ONE_MEMORY_RANGE_PATH).unwrap();
+    let mut src = vec![0_u8; data.len() + 1];
+    src[1..].copy_from_slice(&data);
+
+    let mut dst = vec![0_u8; data.len() + 16];
+    let fdt = Fdt::from_unaligned_slice(&src[1..], &mut dst[1..]).unwrap();
+    assert_eq!(fdt.as_ptr().align_offset(8), 0);
+    assert_eq!(fdt.as_slice()[..data.len()], data[..]);
+    assert_eq!(fdt.first_memory_range(), Ok(EXPECTED_FIRST_MEMORY_RANGE));
+
+    let mut too_small = vec![0_u8; data.len() - 1];
+    assert_eq!(Fdt::from_unaligned_slice(&data, &mut too_small).err(), Some(FdtError::NoSpace));
+    let mut dst = vec![0_u8; data.len() + 8];
+    assert!(Fdt::from_unaligned_slice(&data[1..], &mut dst).is_err());
+}
+
+#[test]
+fn fdt_resize_into() {
+    let mut data = vec![0_u8; 100];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    let value = [0xab_u8; 64];
+    assert_eq!(root.appendprop(cstr!("value"), &value), Ok(()));
+    assert_eq!(root.appendprop(cstr!("value"), &value), Err(FdtError::NoSpace));
+
+    let mut new_data = vec![0_u8; 1000];
+    let new_fdt = fdt.resize_into(&mut new_data).unwrap();
+    let mut root = new_fdt.root_mut().unwrap();
+    assert_eq!(root.appendprop(cstr!("value"), &value), Ok(()));
+    let expected = [value, value].concat();
+    assert_eq!(new_fdt.root().unwrap().getprop(cstr!("value")), Ok(Some(&expected[..])));
+
+    let mut too_small = vec![0_u8; 100];
+    assert_eq!(new_fdt.resize_into(&mut too_small).err(), Some(FdtError::NoSpace));
+}
+
+#[test]
+fn fdt_content_hash() {
+    fn content(fdt: &Fdt) -> Vec<u8> {
+        let mut out = vec![];
+        fdt.content_hash(|bytes| out.extend_from_slice(bytes)).unwrap();
+        out
+    }
+
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.setprop_u32(cstr!("#address-cells"), 2).unwrap();
+    root.setprop_u32(cstr!("#size-cells"), 1).unwrap();
+    let mut node = root.add_subnode(cstr!("node")).unwrap();
+    node.setprop_str(cstr!("status"), cstr!("okay")).unwrap();
+    node.setprop_empty(cstr!("removed")).unwrap();
+    node.nop_property(cstr!("removed")).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.add_subnode(cstr!("other")).unwrap();
+    let expected = content(fdt);
+
+    // Same content, added in a different order.
+    let mut other_data = vec![0_u8; 1000];
+    let other = Fdt::create_empty_tree(&mut other_data).unwrap();
+    let mut root = other.root_mut().unwrap();
+    root.add_subnode(cstr!("other")).unwrap();
+    let mut root = other.root_mut().unwrap();
+    root.add_subnode(cstr!("node")).unwrap().setprop_str(cstr!("status"), cstr!("okay")).unwrap();
+    let mut root = other.root_mut().unwrap();
+    root.setprop_u32(cstr!("#size-cells"), 1).unwrap();
+    root.setprop_u32(cstr!("#address-cells"), 2).unwrap();
+    other.pack().unwrap();
+    assert_eq!(content(other), expected);
+
+    let mut root = other.root_mut().unwrap();
+    root.setprop_inplace(cstr!("#address-cells"), &1_u32.to_be_bytes()).unwrap();
+    assert_ne!(content(other), expected);
+}
+
+#[test]
+fn fdt_descendants_lenient() {
+    let mut data = vec![0_u8; 1000];
+    FdtBuilder::new(&mut data)
+        .unwrap()
+        .node(cstr!("a"), |a| {
+            a.property_u32(cstr!("value"), 1)?;
+            Ok(())
+        })
+        .unwrap()
+        .node(cstr!("b"), |b| {
+            b.property_u32(cstr!("value"), 0xcafe_f00d)?;
+            b.node(cstr!("b-child"), |_| Ok(()))?;
+            Ok(())
+        })
+        .unwrap()
+        .node(cstr!("c"), |c| {
+            c.property_u32(cstr!("value"), 3)?;
+            Ok(())
+        })
+        .unwrap()
+        .finish()
+        .unwrap();
+
+    // Corrupt the tag of the property of /b, which precedes its length and name offset.
+    let value = data.windows(4).position(|w| w == 0xcafe_f00d_u32.to_be_bytes()).unwrap();
+    data[value - 12..value - 8].copy_from_slice(&0xffff_ffff_u32.to_be_bytes());
+
+    assert!(Fdt::from_slice(&data).is_err());
+    let fdt = Fdt::from_slice_lenient(&data).unwrap();
+    let mut damaged = vec![];
+    let nodes: Vec<_> = fdt
+        .descendants_lenient(|node, e| damaged.push((node.name().unwrap(), e)))
+        .map(|(node, depth)| (node.name().unwrap(), depth))
+        .collect();
+
+    assert_eq!(nodes, vec![(cstr!("a"), 1), (cstr!("b-child"), 2), (cstr!("c"), 1)]);
+    assert_eq!(damaged, vec![(cstr!("b"), FdtError::BadStructure)]);
+}
+
+#[test]
+fn fdt_from_slice_with_validation() {
+    const EXPECTED_FIRST_MEMORY_RANGE: Range<usize> = 0..256;
+    let mut data = fs::read(TEST_TREE_WITH_ONE_MEMORY_RANGE_PATH).unwrap();
+    let fdt = Fdt::from_slice_with(&data, Validation::HeaderOnly).unwrap();
+    assert_eq!(fdt.first_memory_range(), Ok(EXPECTED_FIRST_MEMORY_RANGE));
+    let fdt = Fdt::from_mut_slice_with(&mut data, Validation::HeaderOnly).unwrap();
+    assert_eq!(fdt.first_memory_range(), Ok(EXPECTED_FIRST_MEMORY_RANGE));
+
+    // Corrupt the FDT_END tag, which only a full validation checks.
+    let totalsize = Fdt::from_slice(&data).unwrap().totalsize();
+    let end = data[..totalsize].windows(4).rposition(|w| w == [0, 0, 0, 9]).unwrap();
+    data[end..end + 4].copy_from_slice(&0xffff_ffff_u32.to_be_bytes());
+    assert!(Fdt::from_slice_with(&data, Validation::Full).is_err());
+    assert!(Fdt::from_slice_with(&data, Validation::HeaderOnly).is_ok());
+    assert!(Fdt::from_mut_slice_with(&mut data, Validation::default()).is_err());
+
+    data[..4].fill(0);
+    assert!(Fdt::from_slice_with(&data, Validation::HeaderOnly).is_err());
+}
+
+#[test]
+fn fdt_write_dts() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = FdtBuilder::new(&mut data)
+        .unwrap()
+        .property_u32(cstr!("#address-cells"), 2)
+        .unwrap()
+        .property(cstr!("compatible"), b"foo,bar\0\"baz\"\0")
+        .unwrap()
+        .node(cstr!("node@1"), |node| {
+            node.property_empty(cstr!("empty"))?
+                .property_cells(cstr!("cells"), &[0x1, 0xcafe])?
+                .property(cstr!("bytes"), &[0x12, 0x00, 0xab])?;
+            Ok(())
+        })
+        .unwrap()
+        .finish()
+        .unwrap();
+    fdt.add_mem_rsv(0x1000, 0x200).unwrap();
+
+    let mut dts = String::new();
+    fdt.write_dts(&mut dts).unwrap();
+
+    let expected = "/dts-v1/;\n\n\
+        /memreserve/ 0x1000 0x200;\n\
+        / {\n\
+        \t#address-cells = <0x2>;\n\
+        \tcompatible = \"foo,bar\", \"\\\"baz\\\"\";\n\
+        \n\
+        \tnode@1 {\n\
+        \t\tempty;\n\
+        \t\tcells = <0x1 0xcafe>;\n\
+        \t\tbytes = [12 00 ab];\n\
+        \t};\n\
+        };\n";
+    assert_eq!(dts, expected);
+}
+
+#[test]
+fn node_getprop_as() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.setprop_cells(cstr!("cells"), [0x1, 0x2, 0x3, 0x4].into_iter()).unwrap();
+    root.setprop(cstr!("bytes"), &[0xab, 0xcd, 0xef]).unwrap();
+
+    let root = fdt.root().unwrap();
+    let expected_bytes = [0xab, 0xcd, 0xef];
+    assert_eq!(root.getprop_as::<[u8; 3]>(cstr!("bytes")), Ok(Some(&expected_bytes)));
+    assert_eq!(root.getprop_as::<[u8; 4]>(cstr!("bytes")), Err(FdtError::BadValue));
+    assert_eq!(root.getprop_as::<[u8; 3]>(cstr!("missing")), Ok(None));
+
+    assert_eq!(root.getprop_u32_array(cstr!("cells")), Ok(Some([0x1, 0x2, 0x3, 0x4])));
+    assert_eq!(root.getprop_u64_array(cstr!("cells")), Ok(Some([0x1_0000_0002, 0x3_0000_0004])));
+    assert_eq!(root.getprop_u32_array::<3>(cstr!("cells")), Err(FdtError::BadValue));
+    assert_eq!(root.getprop_u64_array::<1>(cstr!("bytes")), Err(FdtError::BadValue));
+}
+
+#[test]
+fn node_getprop_array_iterators() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.setprop_cells(cstr!("cells"), [0x1, 0x2, 0x3, 0x4, 0x5, 0x6].into_iter()).unwrap();
+    root.setprop(cstr!("bytes"), &[0xab, 0xcd, 0xef]).unwrap();
+    root.setprop_empty(cstr!("empty")).unwrap();
+
+    let root = fdt.root().unwrap();
+    let values: Vec<_> = root.getprop_u32_iter(cstr!("cells")).unwrap().unwrap().collect();
+    assert_eq!(values, [0x1, 0x2, 0x3, 0x4, 0x5, 0x6]);
+    let values: Vec<_> = root.getprop_u64_iter(cstr!("cells")).unwrap().unwrap().collect();
+    assert_eq!(values, [0x1_0000_0002, 0x3_0000_0004, 0x5_0000_0006]);
+    let chunks: Vec<_> = root.getprop_cell_chunks::<3>(cstr!("cells")).unwrap().unwrap().collect();
+    assert_eq!(chunks, [[0x1, 0x2, 0x3], [0x4, 0x5, 0x6]]);
+    assert_eq!(root.getprop_u32_iter(cstr!("empty")).unwrap().unwrap().len(), 0);
+
+    assert!(root.getprop_u32_iter(cstr!("missing")).unwrap().is_none());
+    assert_eq!(root.getprop_u32_iter(cstr!("bytes")).err(), Some(FdtError::BadValue));
+    assert_eq!(root.getprop_u64_iter(cstr!("bytes")).err(), Some(FdtError::BadValue));
+    assert_eq!(root.getprop_cell_chunks::<4>(cstr!("cells")).err(), Some(FdtError::BadValue));
+}
+
+#[test]
+fn node_mut_setprop_reg_and_ranges() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = FdtBuilder::new(&mut data)
+        .unwrap()
+        .property_u32(cstr!("#address-cells"), 2)
+        .unwrap()
+        .property_u32(cstr!("#size-cells"), 1)
+        .unwrap()
+        .node(cstr!("pci"), |pci| {
+            pci.property_u32(cstr!("#address-cells"), 3)?;
+            pci.property_u32(cstr!("#size-cells"), 2)?;
+            Ok(())
+        })
+        .unwrap()
+        .finish()
+        .unwrap();
+
+    let mut pci = fdt.node_mut(cstr!("/pci")).unwrap().unwrap();
+    let regs =
+        [Reg { addr: 0x1_0000_0000, size: Some(0x1000) }, Reg { addr: 0x0, size: Some(0x10) }];
+    pci.setprop_reg(&regs).unwrap();
+    let ranges = [AddressRange { addr: (0x200_0000, 0x4000), parent_addr: 0x8000, size: 0x1000 }];
+    pci.setprop_ranges(&ranges).unwrap();
+
+    let pci = fdt.node(cstr!("/pci")).unwrap().unwrap();
+    let expected_reg = [0x1, 0x0, 0x1000, 0x0, 0x0, 0x10];
+    assert_eq!(pci.getprop_u32_array(cstr!("reg")), Ok(Some(expected_reg)));
+    let regs: Vec<_> = pci.reg().unwrap().unwrap().map(|r| (r.addr, r.size)).collect();
+    assert_eq!(regs, [(0x1_0000_0000, Some(0x1000)), (0x0, Some(0x10))]);
+    let expected_ranges = [0x200_0000, 0x0, 0x4000, 0x0, 0x8000, 0x0, 0x1000];
+    assert_eq!(pci.getprop_u32_array(cstr!("ranges")), Ok(Some(expected_ranges)));
+    let range = pci.ranges::<(u32, u64), u64, u64>().unwrap().unwrap().next().unwrap();
+    assert_eq!((range.addr, range.parent_addr, range.size), ((0x200_0000, 0x4000), 0x8000, 0x1000));
+
+    let mut pci = fdt.node_mut(cstr!("/pci")).unwrap().unwrap();
+    let too_big = [Reg { addr: 0x0, size: Some(0x1_0000_0000) }];
+    assert_eq!(pci.setprop_reg(&too_big), Err(FdtError::BadValue));
+    let no_size = [Reg { addr: 0x0, size: None }];
+    assert_eq!(pci.setprop_reg(&no_size), Err(FdtError::BadValue));
+    let bad_addr = [AddressRange { addr: 0x0_u64, parent_addr: 0x0_u64, size: 0x0_u64 }];
+    assert_eq!(pci.setprop_ranges(&bad_addr), Err(FdtError::BadNCells));
+
+    let pci = fdt.node(cstr!("/pci")).unwrap().unwrap();
+    assert_eq!(pci.getprop(cstr!("reg")), Ok(None));
+    assert_eq!(pci.getprop(cstr!("ranges")), Ok(None));
+}
+
+#[test]
+fn fdt_fixups() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = FdtBuilder::new(&mut data)
+        .unwrap()
+        .node(cstr!("__fixups__"), |fixups| {
+            let entries = b"/fragment@0/__overlay__/dev:iommus:0\0/fragment@0/__overlay__:a:4\0";
+            fixups.property(cstr!("iommu"), entries)?;
+            fixups.property(cstr!("bad"), b"missing-offset\0")?;
+            Ok(())
+        })
+        .unwrap()
+        .node(cstr!("__local_fixups__"), |local_fixups| {
+            local_fixups.property_cells(cstr!("root-ref"), &[0])?;
+            local_fixups.node(cstr!("fragment@0"), |fragment| {
+                fragment.node(cstr!("__overlay__"), |overlay| {
+                    overlay.node(cstr!("dev"), |dev| {
+                        dev.property_cells(cstr!("clocks"), &[0, 8])?;
+                        Ok(())
+                    })?;
+                    Ok(())
+                })?;
+                Ok(())
+            })?;
+            Ok(())
+        })
+        .unwrap()
+        .finish()
+        .unwrap();
+
+    let fixups: Vec<_> = fdt.fixups().unwrap().unwrap().collect();
+    assert_eq!(
+        fixups,
+        [
+            Ok(Fixup {
+                label: cstr!("iommu"),
+                path: "/fragment@0/__overlay__/dev",
+                property: "iommus",
+                offset: 0
+            }),
+            Ok(Fixup {
+                label: cstr!("iommu"),
+                path: "/fragment@0/__overlay__",
+                property: "a",
+                offset: 4
+            }),
+            Err(FdtError::BadValue),
+        ]
+    );
+
+    let mut buf = [0; MAX_PATH_LEN];
+    let local_fixups: Vec<_> = fdt
+        .local_fixups()
+        .unwrap()
+        .unwrap()
+        .map(|f| {
+            let f = f.unwrap();
+            (f.path(&mut buf).unwrap().to_owned(), f.property.to_owned(), f.offset)
+        })
+        .collect();
+    let dev = CString::from(cstr!("/fragment@0/__overlay__/dev"));
+    let clocks = CString::from(cstr!("clocks"));
+    assert_eq!(
+        local_fixups,
+        [
+            (CString::from(cstr!("/")), CString::from(cstr!("root-ref")), 0),
+            (dev.clone(), clocks.clone(), 0),
+            (dev, clocks, 8),
+        ]
+    );
+
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    assert!(fdt.fixups().unwrap().is_none());
+    assert!(fdt.local_fixups().unwrap().is_none());
+}
+
+#[test]
+fn fdt_memory_banks() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = FdtBuilder::new(&mut data)
+        .unwrap()
+        .property_u32(cstr!("#address-cells"), 2)
+        .unwrap()
+        .property_u32(cstr!("#size-cells"), 2)
+        .unwrap()
+        .node(cstr!("memory@80000000"), |memory| {
+            memory.property_str(cstr!("device_type"), cstr!("memory"))?;
+            memory.property_cells(cstr!("reg"), &[0x0, 0x8000_0000, 0x0, 0x1000])?;
+            Ok(())
+        })
+        .unwrap()
+        .node(cstr!("chosen"), |_| Ok(()))
+        .unwrap()
+        .node(cstr!("memory@100000000"), |memory| {
+            memory.property_str(cstr!("device_type"), cstr!("memory"))?;
+            let reg = [0x1, 0x0, 0x0, 0x2000, 0x2, 0x0, 0x0, 0x3000];
+            memory.property_cells(cstr!("reg"), &reg)?;
+            Ok(())
+        })
+        .unwrap()
+        .finish()
+        .unwrap();
+
+    let banks: Vec<_> = fdt.memory_banks().collect();
+    assert_eq!(
+        banks,
+        [
+            Ok(0x8000_0000..0x8000_1000),
+            Ok(0x1_0000_0000..0x1_0000_2000),
+            Ok(0x2_0000_0000..0x2_0000_3000),
+        ]
+    );
+    // Fdt::memory() only looks for /memory.
+    assert_eq!(fdt.memory().unwrap_err(), FdtError::NotFound);
+
+    let mut data = vec![0_u8; 1000];
+    let fdt = FdtBuilder::new(&mut data)
+        .unwrap()
+        .node(cstr!("memory"), |memory| {
+            memory.property_str(cstr!("device_type"), cstr!("memory"))?;
+            Ok(())
+        })
+        .unwrap()
+        .finish()
+        .unwrap();
+    let banks: Vec<_> = fdt.memory_banks().collect();
+    assert_eq!(banks, [Err(FdtError::BadValue)]);
+}
+
+#[test]
+fn error_context() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.add_subnode(cstr!("node")).unwrap();
+    let node = fdt.node(cstr!("/node")).unwrap().unwrap();
+
+    let result = node.getprop_u32(cstr!("missing")).and_then(|p| p.ok_or(FdtError::NotFound));
+    let error = result.property_context(&node, cstr!("missing")).unwrap_err();
+    assert_eq!(error.error(), FdtError::NotFound);
+    let context = error.context().unwrap();
+    assert_eq!(context, &node.error_context().with_property(cstr!("missing")));
+    assert!(context.node_offset().is_some());
+    assert_eq!(context.property(), Some(&b"missing"[..]));
+
+    let long_name = cstr!("a-property-with-a-name-longer-than-the-context");
+    let context = node.error_context().with_property(long_name);
+    assert_eq!(context.property(), Some(&long_name.to_bytes()[..31]));
+    assert!(context.to_string().ends_with("-longer-t'..."));
+    let name = cstr!("a-property-name-of-31-bytes-max");
+    let context = node.error_context().with_property(name);
+    assert!(context.to_string().ends_with(", property 'a-property-name-of-31-bytes-max'"));
+
+    let error = FdtContextError::from(FdtError::BadValue);
+    assert_eq!(error.context(), None);
+    assert_eq!(FdtError::from(error), FdtError::BadValue);
+}
+
+#[test]
+fn error_context_display() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = Fdt::create_empty_tree(&mut data).unwrap();
+    let mut root = fdt.root_mut().unwrap();
+    root.add_subnode(cstr!("node")).unwrap();
+    let node = fdt.node(cstr!("/node")).unwrap().unwrap();
+
+    let context = node.error_context().with_property(cstr!("missing"));
+    let error = FdtContextError::new(FdtError::NotFound, context);
+    if cfg!(feature = "error_context") {
+        assert_eq!(error.to_string(), format!("{} ({context})", FdtError::NotFound));
+    } else {
+        assert_eq!(error.to_string(), FdtError::NotFound.to_string());
+    }
+    let error = FdtContextError::from(FdtError::BadValue);
+    assert_eq!(error.to_string(), FdtError::BadValue.to_string());
+}
+
+#[test]
+fn fdt_diff() {
+    let mut old_data = vec![0_u8; 1000];
+    let old = FdtBuilder::new(&mut old_data)
+        .unwrap()
+        .property_u32(cstr!("a"), 1)
+        .unwrap()
+        .property_u32(cstr!("b"), 2)
+        .unwrap()
+        .node(cstr!("kept"), |kept| {
+            kept.property_u32(cstr!("value"), 1)?;
+            Ok(())
+        })
+        .unwrap()
+        .node(cstr!("removed"), |_| Ok(()))
+        .unwrap()
+        .finish()
+        .unwrap();
+
+    let mut new_data = vec![0_u8; 1000];
+    let new = FdtBuilder::new(&mut new_data)
+        .unwrap()
+        .property_u32(cstr!("b"), 2)
+        .unwrap()
+        .property_u32(cstr!("a"), 1)
+        .unwrap()
+        .node(cstr!("kept"), |kept| {
+            kept.property_u32(cstr!("value"), 1)?;
+            Ok(())
+        })
+        .unwrap()
+        .node(cstr!("removed"), |_| Ok(()))
+        .unwrap()
+        .finish()
+        .unwrap();
+
+    let mut count = 0;
+    old.diff(new, |_| count += 1).unwrap();
+    assert_eq!(count, 0, "Reordering properties isn't a difference");
+
+    new.node_mut(cstr!("/removed")).unwrap().unwrap().nop().unwrap();
+    new.root_mut().unwrap().add_subnode(cstr!("added")).unwrap();
+    let mut kept = new.node_mut(cstr!("/kept")).unwrap().unwrap();
+    kept.setprop_u32(cstr!("value"), 2).unwrap();
+    kept.setprop_empty(cstr!("extra")).unwrap();
+    new.root_mut().unwrap().delprop(cstr!("a")).unwrap();
+
+    let mut events = vec![];
+    old.diff(new, |event| {
+        events.push(match event {
+            DiffEvent::NodeAdded(node) => format!("+{:?}", node.name().unwrap()),
+            DiffEvent::NodeRemoved(node) => format!("-{:?}", node.name().unwrap()),
+            DiffEvent::PropertyAdded { name, value, .. } => format!("+{name:?}={value:?}"),
+            DiffEvent::PropertyRemoved { name, value, .. } => format!("-{name:?}={value:?}"),
+            DiffEvent::PropertyChanged { name, old, new, .. } => {
+                format!("{name:?}:{old:?}->{new:?}")
+            }
+        })
+    })
+    .unwrap();
+    assert_eq!(
+        events,
+        [
+            r#"-"a"=[0, 0, 0, 1]"#,
+            r#""value":[0, 0, 0, 1]->[0, 0, 0, 2]"#,
+            r#"+"extra"=[]"#,
+            r#"-"removed""#,
+            r#"+"added""#,
+        ]
+    );
+}
+
+#[test]
+fn fdt_diff_compares_unit_addresses() {
+    let mut old_data = vec![0_u8; 1000];
+    let old = FdtBuilder::new(&mut old_data)
+        .unwrap()
+        .node(cstr!("uart@1"), |_| Ok(()))
+        .unwrap()
+        .finish()
+        .unwrap();
+    let mut new_data = vec![0_u8; 1000];
+    let new = FdtBuilder::new(&mut new_data)
+        .unwrap()
+        .node(cstr!("uart"), |_| Ok(()))
+        .unwrap()
+        .finish()
+        .unwrap();
+
+    let mut events = vec![];
+    old.diff(new, |event| {
+        events.push(match event {
+            DiffEvent::NodeAdded(node) => format!("+{:?}", node.name().unwrap()),
+            DiffEvent::NodeRemoved(node) => format!("-{:?}", node.name().unwrap()),
+            _ => panic!("Unexpected {event:?}"),
+        })
+    })
+    .unwrap();
+    assert_eq!(events, [r#"-"uart@1""#, r#"+"uart""#]);
+}
+
+#[test]
+fn reg_and_address_range_arithmetic() {
+    let reg = Reg { addr: 0x1000_u64, size: Some(0x1000_u64) };
+    assert_eq!(reg.end(), Ok(0x2000));
+    assert_eq!(reg.range(), Ok(0x1000..0x2000));
+    assert_eq!(reg.contains(0x1fff), Ok(true));
+    assert_eq!(reg.contains(0x2000), Ok(false));
+    assert_eq!(reg.contains(0xfff), Ok(false));
+    assert_eq!(reg.overlaps(&(0x1fff..0x2000)), Ok(true));
+    assert_eq!(reg.overlaps(&(0x2000..0x2001)), Ok(false));
+
+    let overflowing = Reg { addr: u64::MAX, size: Some(2_u64) };
+    assert_eq!(overflowing.end(), Err(FdtError::BadValue));
+    assert_eq!(overflowing.contains(u64::MAX), Err(FdtError::BadValue));
+    assert_eq!(overflowing.overlaps(&(0x1000..0x2000)), Err(FdtError::BadValue));
+    let no_size = Reg::<u64> { addr: 0, size: None };
+    assert_eq!(no_size.end(), Err(FdtError::NotFound));
+    assert_eq!(no_size.contains(0), Err(FdtError::NotFound));
+
+    let range = AddressRange {
+        addr: (0x0300_0000_u32, 0x1000_u64),
+        parent_addr: 0x8000_u64,
+        size: 0x100_u64,
+    };
+    assert_eq!(range.end(), Ok(0x1100));
+    assert_eq!(range.parent_end(), Ok(0x8100));
+    assert_eq!(range.range(), Ok(0x1000..0x1100));
+    assert_eq!(range.contains(0x10ff), Ok(true));
+    assert_eq!(range.contains(0x1100), Ok(false));
+    assert_eq!(range.overlaps(&(0x1080..0x2000)), Ok(true));
+    assert_eq!(range.overlaps(&(0x1100..0x2000)), Ok(false));
+    assert_eq!(range.translate(0x1010), Ok(Some(0x8010)));
+    assert_eq!(range.translate(0x2000), Ok(None));
+
+    let overflowing = AddressRange { addr: 0_u64, parent_addr: u64::MAX, size: 0x100_u64 };
+    assert_eq!(overflowing.parent_end(), Err(FdtError::BadValue));
+    assert_eq!(overflowing.translate(0x10), Err(FdtError::BadValue));
+    let overflowing = AddressRange { addr: u64::MAX, parent_addr: 0_u64, size: 0x100_u64 };
+    assert_eq!(overflowing.contains(u64::MAX), Err(FdtError::BadValue));
+    assert_eq!(overflowing.overlaps(&(0..0x1000)), Err(FdtError::BadValue));
+}
+
+#[test]
+fn reg_and_address_range_try_into_range() {
+    let reg = Reg { addr: 0x1000_u64, size: Some(0x1000_u64) };
+    assert_eq!(reg.try_into_range(usize::MAX), Ok(0x1000..0x2000));
+    assert_eq!(reg.try_into_range(0x2000), Ok(0x1000..0x2000));
+    assert_eq!(reg.try_into_range(0x1fff), Err(FdtError::BadValue));
+    let overflowing = Reg { addr: u64::MAX, size: Some(2_u64) };
+    assert_eq!(overflowing.try_into_range(usize::MAX), Err(FdtError::BadValue));
+    let no_size = Reg::<u64> { addr: 0, size: None };
+    assert_eq!(no_size.try_into_range(usize::MAX), Err(FdtError::NotFound));
+
+    let range = AddressRange {
+        addr: (0x0300_0000_u32, 0x1000_u64),
+        parent_addr: 0x8000_u64,
+        size: 0x100_u64,
+    };
+    assert_eq!(range.try_into_range(usize::MAX), Ok(0x1000..0x1100));
+    assert_eq!(range.try_into_range(0x10ff), Err(FdtError::BadValue));
+    let overflowing = AddressRange { addr: u64::MAX, parent_addr: 0_u64, size: 0x100_u64 };
+    assert_eq!(overflowing.try_into_range(usize::MAX), Err(FdtError::BadValue));
+}
+
+#[test]
+fn node_getprop_phandle() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = FdtBuilder::new(&mut data)
+        .unwrap()
+        .node(cstr!("intc"), |intc| {
+            intc.phandle(Phandle::new(0x1).unwrap())?;
+            Ok(())
+        })
+        .unwrap()
+        .node(cstr!("dev"), |dev| {
+            dev.property_u32(cstr!("interrupt-parent"), 0x1)?;
+            dev.property_u32(cstr!("dangling"), 0x2)?;
+            dev.property_u32(cstr!("invalid"), 0)?;
+            dev.property_u64(cstr!("too-long"), 0x1)?;
+            Ok(())
+        })
+        .unwrap()
+        .finish()
+        .unwrap();
+    let dev = fdt.node(cstr!("/dev")).unwrap().unwrap();
+
+    let intc = dev.getprop_phandle(cstr!("interrupt-parent")).unwrap().unwrap();
+    assert_eq!(intc.name(), Ok(cstr!("intc")));
+    assert_eq!(dev.getprop_phandle(cstr!("missing")), Ok(None));
+    assert_eq!(dev.getprop_phandle(cstr!("dangling")), Err(FdtError::NotFound));
+    assert_eq!(dev.getprop_phandle(cstr!("invalid")), Err(FdtError::BadPhandle));
+    assert_eq!(dev.getprop_phandle(cstr!("too-long")), Err(FdtError::BadValue));
+}
+
+#[test]
+fn str_lookups() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = FdtBuilder::new(&mut data)
+        .unwrap()
+        .node(cstr!("a"), |a| {
+            a.node(cstr!("b@10"), |b| {
+                b.property_u32(cstr!("value"), 0x1234)?;
+                Ok(())
+            })?;
+            Ok(())
+        })
+        .unwrap()
+        .finish()
+        .unwrap();
+
+    // Names are sliced from a longer string, so aren't nul-terminated.
+    let path = "/a/b@10/value";
+    let (node_path, prop_name) = path.rsplit_once('/').unwrap();
+    let node = fdt.node_by_str(node_path).unwrap().unwrap();
+    assert_eq!(node.getprop_by_str(prop_name), Ok(Some(0x1234_u32.to_be_bytes().as_slice())));
+    assert_eq!(node.getprop_by_str(&prop_name[..3]), Ok(None));
+
+    let a = fdt.node_by_str("/a").unwrap().unwrap();
+    assert_eq!(a.subnode_by_str("b@10").unwrap(), Some(node));
+    assert_eq!(a.subnode_by_str("b").unwrap(), Some(node));
+    assert_eq!(a.subnode_by_str("c").unwrap(), None);
+    assert_eq!(fdt.node_by_str("/a/c").unwrap(), None);
+
+    let mut a = fdt.node_mut_by_str("/a").unwrap().unwrap();
+    let b = a.subnode_mut_by_str("b@10").unwrap().unwrap();
+    assert_eq!(b.getprop_by_str("value"), Ok(Some(0x1234_u32.to_be_bytes().as_slice())));
+}
+
+#[test]
+fn str_lookups_match_cstr_lookups() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = FdtBuilder::new(&mut data)
+        .unwrap()
+        .node(cstr!("fragment@0"), |fragment| {
+            fragment.property_str(cstr!("target-path"), cstr!("/"))?;
+            fragment.node(cstr!("__overlay__"), |overlay| {
+                overlay.node(cstr!("avf"), |avf| {
+                    avf.property(cstr!("vendor_public_key"), b"key")?;
+                    Ok(())
+                })?;
+                Ok(())
+            })?;
+            Ok(())
+        })
+        .unwrap()
+        .finish()
+        .unwrap();
+
+    let fragment = fdt.node_by_str("/fragment@0").unwrap().unwrap();
+    assert_eq!(Some(fragment), fdt.node(cstr!("/fragment@0")).unwrap());
+    let target_path = fragment.getprop_by_str("target-path").unwrap();
+    assert_eq!(target_path, fragment.getprop(cstr!("target-path")).unwrap());
+    assert_eq!(target_path, Some(b"/\0".as_slice()));
+
+    let avf = fdt.node_by_str("/fragment@0/__overlay__/avf").unwrap().unwrap();
+    assert_eq!(Some(avf), fdt.node(cstr!("/fragment@0/__overlay__/avf")).unwrap());
+    let key = avf.getprop_by_str("vendor_public_key").unwrap();
+    assert_eq!(key, avf.getprop(cstr!("vendor_public_key")).unwrap());
+    assert_eq!(key, Some(b"key".as_slice()));
+}
+
+#[test]
+fn fdt_reserve_map_builder() {
+    let mut data = vec![0_u8; 1000];
+    let mut root = FdtReserveMapBuilder::new(&mut data)
+        .unwrap()
+        .mem_rsv(0x8000_0000, 0x1000)
+        .unwrap()
+        .mem_rsv(0x9000_0000, 0x2000)
+        .unwrap()
+        .root()
+        .unwrap();
+    let fdt = root.property_u32(cstr!("#address-cells"), 2).unwrap().finish().unwrap();
+
+    let reserved: Vec<_> = fdt.mem_rsv_iter().collect::<Result<_, _>>().unwrap();
+    assert_eq!(reserved, [0x8000_0000..0x8000_1000, 0x9000_0000..0x9000_2000]);
+    let root = fdt.root().unwrap();
+    assert_eq!(root.getprop_u32(cstr!("#address-cells")), Ok(Some(2)));
+}
@@ -0,0 +1,237 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Limits on the number of concurrent vsock connections that the host accepts from guests, so that
+//! a guest can't exhaust the file descriptors of the service.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::os::unix::raw::uid_t;
+use std::sync::{Arc, Mutex};
+
+/// Context identifier of a VM, i.e. its vsock address.
+pub type Cid = u32;
+
+/// Maximum number of concurrent connections accepted from a single VM.
+pub const MAX_CONNECTIONS_PER_VM: usize = 4;
+
+/// Maximum number of concurrent connections accepted from all the VMs of a single client UID.
+pub const MAX_CONNECTIONS_PER_UID: usize = 16;
+
+/// Reason for rejecting a vsock connection from a guest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionLimitError {
+    /// The connection comes from a CID which isn't allocated to a running VM.
+    UnknownVm { cid: Cid },
+    /// The VM already has the maximum number of open connections.
+    TooManyForVm { cid: Cid, limit: usize },
+    /// The VMs of the UID already have the maximum number of open connections.
+    TooManyForUid { uid: uid_t, limit: usize },
+}
+
+impl fmt::Display for ConnectionLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownVm { cid } => write!(f, "Connection from unknown VM with CID {cid}"),
+            Self::TooManyForVm { cid, limit } => {
+                write!(f, "VM with CID {cid} exceeded its limit of {limit} connections")
+            }
+            Self::TooManyForUid { uid, limit } => {
+                write!(f, "VMs of UID {uid} exceeded their limit of {limit} connections")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConnectionLimitError {}
+
+#[derive(Debug, Default)]
+struct ConnectionCounts {
+    per_vm: HashMap<Cid, usize>,
+    per_uid: HashMap<uid_t, usize>,
+    rejected: u64,
+}
+
+impl ConnectionCounts {
+    fn release(&mut self, cid: Cid, uid: uid_t) {
+        decrement(&mut self.per_vm, cid);
+        decrement(&mut self.per_uid, uid);
+    }
+}
+
+fn decrement<K: Eq + std::hash::Hash>(counts: &mut HashMap<K, usize>, key: K) {
+    if let Some(count) = counts.get_mut(&key) {
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(&key);
+        }
+    }
+}
+
+/// Counts the open connections accepted from guests, per VM and per client UID.
+#[derive(Clone, Debug)]
+pub struct ConnectionTracker {
+    counts: Arc<Mutex<ConnectionCounts>>,
+    max_per_vm: usize,
+    max_per_uid: usize,
+}
+
+impl Default for ConnectionTracker {
+    fn default() -> Self {
+        Self::new(MAX_CONNECTIONS_PER_VM, MAX_CONNECTIONS_PER_UID)
+    }
+}
+
+impl ConnectionTracker {
+    pub fn new(max_per_vm: usize, max_per_uid: usize) -> Self {
+        Self { counts: Default::default(), max_per_vm, max_per_uid }
+    }
+
+    /// Records a new connection from the VM with the given CID, started by the given UID, or
+    /// rejects it if either exceeds its limit. `uid` is None if the CID isn't allocated to a VM.
+    ///
+    /// The connection is counted until the returned permit is dropped.
+    pub fn acquire(
+        &self,
+        cid: Cid,
+        uid: Option<uid_t>,
+    ) -> Result<ConnectionPermit, ConnectionLimitError> {
+        let mut counts = self.counts.lock().unwrap();
+        let uid = self.check(&counts, cid, uid).inspect_err(|_| counts.rejected += 1)?;
+        *counts.per_vm.entry(cid).or_default() += 1;
+        *counts.per_uid.entry(uid).or_default() += 1;
+        Ok(ConnectionPermit { counts: self.counts.clone(), cid, uid })
+    }
+
+    fn check(
+        &self,
+        counts: &ConnectionCounts,
+        cid: Cid,
+        uid: Option<uid_t>,
+    ) -> Result<uid_t, ConnectionLimitError> {
+        let uid = uid.ok_or(ConnectionLimitError::UnknownVm { cid })?;
+        if counts.per_vm.get(&cid).copied().unwrap_or_default() >= self.max_per_vm {
+            return Err(ConnectionLimitError::TooManyForVm { cid, limit: self.max_per_vm });
+        }
+        if counts.per_uid.get(&uid).copied().unwrap_or_default() >= self.max_per_uid {
+            return Err(ConnectionLimitError::TooManyForUid { uid, limit: self.max_per_uid });
+        }
+        Ok(uid)
+    }
+
+    /// Returns whether any connection from the VM with the given CID is still open.
+    pub fn is_connected(&self, cid: Cid) -> bool {
+        self.counts.lock().unwrap().per_vm.contains_key(&cid)
+    }
+
+    /// Writes the connection counters, in the format used by dumpsys.
+    pub fn dump(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let counts = self.counts.lock().unwrap();
+        writeln!(
+            writer,
+            "Vsock connections (limits: {} per VM, {} per UID):",
+            self.max_per_vm, self.max_per_uid
+        )?;
+        for (cid, count) in &counts.per_vm {
+            writeln!(writer, "\tVM CID {cid}: {count}")?;
+        }
+        for (uid, count) in &counts.per_uid {
+            writeln!(writer, "\tUID {uid}: {count}")?;
+        }
+        writeln!(writer, "\tRejected: {}", counts.rejected)
+    }
+}
+
+/// An open connection accepted from a guest, counted against its limits until dropped.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    counts: Arc<Mutex<ConnectionCounts>>,
+    cid: Cid,
+    uid: uid_t,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.counts.lock().unwrap().release(self.cid, self.uid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_connections_over_vm_limit() {
+        let tracker = ConnectionTracker::new(2, 10);
+        let _a = tracker.acquire(2048, Some(1000)).unwrap();
+        let b = tracker.acquire(2048, Some(1000)).unwrap();
+        assert_eq!(
+            tracker.acquire(2048, Some(1000)).unwrap_err(),
+            ConnectionLimitError::TooManyForVm { cid: 2048, limit: 2 }
+        );
+        assert!(tracker.acquire(2049, Some(1000)).is_ok());
+
+        drop(b);
+        assert!(tracker.acquire(2048, Some(1000)).is_ok());
+    }
+
+    #[test]
+    fn rejects_connections_over_uid_limit() {
+        let tracker = ConnectionTracker::new(10, 2);
+        let _a = tracker.acquire(2048, Some(1000)).unwrap();
+        let _b = tracker.acquire(2049, Some(1000)).unwrap();
+        assert_eq!(
+            tracker.acquire(2050, Some(1000)).unwrap_err(),
+            ConnectionLimitError::TooManyForUid { uid: 1000, limit: 2 }
+        );
+        assert!(tracker.acquire(2050, Some(1001)).is_ok());
+    }
+
+    #[test]
+    fn vm_is_connected_until_last_permit_is_dropped() {
+        let tracker = ConnectionTracker::default();
+        assert!(!tracker.is_connected(2048));
+
+        let a = tracker.acquire(2048, Some(1000)).unwrap();
+        let b = tracker.acquire(2048, Some(1000)).unwrap();
+        drop(a);
+        assert!(tracker.is_connected(2048));
+        drop(b);
+        assert!(!tracker.is_connected(2048));
+    }
+
+    #[test]
+    fn rejects_connections_from_unknown_vm() {
+        let tracker = ConnectionTracker::default();
+        assert_eq!(
+            tracker.acquire(2048, None).unwrap_err(),
+            ConnectionLimitError::UnknownVm { cid: 2048 }
+        );
+    }
+
+    #[test]
+    fn dump_counts_rejected_connections() {
+        let tracker = ConnectionTracker::new(1, 1);
+        let _a = tracker.acquire(2048, Some(1000)).unwrap();
+        assert!(tracker.acquire(2048, Some(1000)).is_err());
+
+        let mut out = Vec::new();
+        tracker.dump(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("VM CID 2048: 1"));
+        assert!(out.contains("UID 1000: 1"));
+        assert!(out.contains("Rejected: 1"));
+    }
+}
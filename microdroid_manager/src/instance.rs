@@ -38,6 +38,7 @@ use crate::ioutil;
 
 use anyhow::{anyhow, bail, Context, Result};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use keystore2_crypto::ZVec;
 use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
 use serde::{Deserialize, Serialize};
 use std::fs::{File, OpenOptions};
@@ -157,21 +158,44 @@ impl InstanceDisk {
         microdroid_data: &MicrodroidData,
         dice: &DiceDriver,
     ) -> Result<()> {
-        let (header, offset) = self.locate_microdroid_header()?;
+        let data = serde_cbor::to_vec(microdroid_data)?;
+        let key = dice.get_sealing_key(INSTANCE_KEY_IDENTIFIER, Cipher::aes_256_gcm().key_len())?;
+        self.write_sealed_data(&data, &key)
+    }
 
+    /// Prepares the write of identity data to the partition for microdroid manager, so that it can
+    /// be committed once the DICE driver is no longer available.
+    pub fn prepare_write(
+        self,
+        microdroid_data: &MicrodroidData,
+        dice: &DiceDriver,
+    ) -> Result<PendingWrite> {
         let data = serde_cbor::to_vec(microdroid_data)?;
+        let key = dice.get_sealing_key(INSTANCE_KEY_IDENTIFIER, Cipher::aes_256_gcm().key_len())?;
+        Ok(PendingWrite { disk: self, data, key })
+    }
+
+    fn write_sealed_data(&mut self, data: &[u8], key: &[u8]) -> Result<()> {
+        let (header, offset) = self.locate_microdroid_header()?;
 
         // By encrypting and signing the data, tag will be appended. The tag also becomes part of
         // the encrypted payload which will be written. In addition, a nonce will be prepended
         // (non-encrypted).
         let payload_size = (AES_256_GCM_NONCE_LENGTH + data.len() + AES_256_GCM_TAG_LENGTH) as u64;
 
-        // If the partition exists, make sure we don't change the partition size. If not (i.e.
-        // partition is not found), write the header at the empty place.
+        // If the partition exists, make sure we don't change the space it takes, as it may be
+        // followed by other partitions. The payload size may still change within that space, e.g.
+        // when the data is migrated after an update of the payload. If the partition is not found,
+        // write the header at the empty place.
         if let Some(header) = header {
-            if header.payload_size != payload_size {
+            let old_size = round_to_multiple(header.payload_size, PARTITION_HEADER_SIZE)?;
+            let new_size = round_to_multiple(payload_size, PARTITION_HEADER_SIZE)?;
+            if old_size != new_size {
                 bail!("Can't change payload size from {} to {}", header.payload_size, payload_size);
             }
+            if header.payload_size != payload_size {
+                self.write_header_at(offset, &header.uuid, payload_size)?;
+            }
         } else {
             let uuid = Uuid::parse_str(MICRODROID_PARTITION_UUID)?;
             self.write_header_at(offset, &uuid, payload_size)?;
@@ -189,9 +213,8 @@ impl InstanceDisk {
 
         // Then encrypt and sign the data.
         let cipher = Cipher::aes_256_gcm();
-        let key = dice.get_sealing_key(INSTANCE_KEY_IDENTIFIER, cipher.key_len())?;
         let mut tag = [0; AES_256_GCM_TAG_LENGTH];
-        let ciphertext = encrypt_aead(cipher, &key, Some(&nonce), &header, &data, &mut tag)?;
+        let ciphertext = encrypt_aead(cipher, key, Some(&nonce), &header, data, &mut tag)?;
 
         // Persist the encrypted payload data and the tag.
         self.file.write_all(&ciphertext)?;
@@ -262,6 +285,20 @@ impl InstanceDisk {
 }
 
 /// Round `n` up to the nearest multiple of `unit`
+/// Identity data waiting to be written to the instance disk.
+pub struct PendingWrite {
+    disk: InstanceDisk,
+    data: Vec<u8>,
+    key: ZVec,
+}
+
+impl PendingWrite {
+    /// Writes the identity data to the partition for microdroid manager.
+    pub fn commit(mut self) -> Result<()> {
+        self.disk.write_sealed_data(&self.data, &self.key)
+    }
+}
+
 fn round_to_multiple(n: u64, unit: u64) -> Result<u64> {
     assert!((unit & (unit - 1)) == 0, "{} is not power of two", unit);
     let ret = (n + unit - 1) & !(unit - 1);
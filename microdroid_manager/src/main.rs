@@ -26,6 +26,7 @@ mod vm_secret;
 
 use android_system_virtualizationcommon::aidl::android::system::virtualizationcommon::ErrorCode::ErrorCode;
 use android_system_virtualmachineservice::aidl::android::system::virtualmachineservice::IVirtualMachineService::IVirtualMachineService;
+use android_system_virtualmachineservice::aidl::android::system::virtualmachineservice::PayloadOutputStream::PayloadOutputStream;
 use android_system_virtualization_payload::aidl::android::system::virtualization::payload::IVmPayloadService::{
     VM_APK_CONTENTS_PATH,
     VM_PAYLOAD_SERVICE_SOCKET_NAME,
@@ -36,12 +37,12 @@ use crate::dice::dice_derivation;
 use crate::dice_driver::DiceDriver;
 use crate::instance::{InstanceDisk, MicrodroidData};
 use crate::verify::verify_payload;
-use crate::vm_payload_service::register_vm_payload_service;
+use crate::vm_payload_service::{register_vm_payload_service, PayloadMigration};
 use anyhow::{anyhow, bail, ensure, Context, Error, Result};
 use binder::Strong;
 use keystore2_crypto::ZVec;
 use libc::VMADDR_CID_HOST;
-use log::{error, info};
+use log::{error, info, warn};
 use microdroid_metadata::PayloadMetadata;
 use microdroid_payload_config::{OsConfig, Task, TaskType, VmPayloadConfig};
 use nix::sys::signal::Signal;
@@ -52,21 +53,26 @@ use rustutils::system_properties;
 use rustutils::system_properties::PropertyWatcher;
 use std::borrow::Cow::{Borrowed, Owned};
 use std::env;
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 use std::fs::{self, create_dir, File, OpenOptions};
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+use std::iter;
 use std::os::unix::io::{FromRawFd, OwnedFd};
 use std::os::unix::process::CommandExt;
 use std::os::unix::process::ExitStatusExt;
 use std::path::Path;
 use std::process::{Child, Command, Stdio};
 use std::str;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
 use std::time::Duration;
 use vm_secret::VmSecret;
 
 const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
 const AVF_STRICT_BOOT: &str = "/sys/firmware/devicetree/base/chosen/avf,strict-boot";
 const AVF_NEW_INSTANCE: &str = "/sys/firmware/devicetree/base/chosen/avf,new-instance";
+const AVF_PVMFW_VERSION: &str = "/sys/firmware/devicetree/base/chosen/avf,pvmfw-version";
+const AVF_PVMFW_WARNINGS: &str = "/sys/firmware/devicetree/base/chosen/avf,pvmfw-warnings";
 const AVF_DEBUG_POLICY_RAMDUMP: &str = "/sys/firmware/devicetree/base/avf/guest/common/ramdump";
 const DEBUG_MICRODROID_NO_VERIFIED_BOOT: &str =
     "/sys/firmware/devicetree/base/virtualization/guest/debug-microdroid,no-verified-boot";
@@ -83,6 +89,14 @@ const FAILURE_SERIAL_DEVICE: &str = "/dev/ttyS1";
 const ENCRYPTEDSTORE_BACKING_DEVICE: &str = "/dev/block/by-name/encryptedstore";
 const ENCRYPTEDSTORE_KEYSIZE: usize = 32;
 
+/// Maximum size of the chunks of payload output sent to the host in a single binder call.
+const PAYLOAD_OUTPUT_CHUNK_SIZE: usize = 4096;
+/// How long to wait before retrying to forward payload output which the owner couldn't accept yet.
+const PAYLOAD_OUTPUT_RETRY_DELAY: Duration = Duration::from_millis(20);
+/// How long to keep forwarding the output of the payload after it exited, before reporting that it
+/// finished. Processes which the payload left behind may keep its stdout or stderr open forever.
+const PAYLOAD_OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(thiserror::Error, Debug)]
 enum MicrodroidError {
     #[error("Cannot connect to virtualization service: {0}")]
@@ -190,6 +204,12 @@ fn try_main() -> Result<()> {
         .context("cannot connect to VirtualMachineService")
         .map_err(|e| MicrodroidError::FailedToConnectToVirtualizationService(e.to_string()))?;
 
+    if let Err(e) = notify_component_versions(&service) {
+        // Not fatal: the versions are only used for debugging.
+        warn!("Failed to report component versions: {e:?}");
+    }
+    log_pvmfw_warnings();
+
     match try_run_payload(&service, vm_payload_service_fd) {
         Ok(code) => {
             if code == 0 {
@@ -252,21 +272,33 @@ fn try_run_payload(
 
     // In case identity is ignored (by debug policy), we should reuse existing payload data, even
     // when the payload is changed. This is to keep the derived secret same as before.
+    let mut migration = None;
     let instance_data = if let Some(saved_data) = saved_data {
         if !is_verified_boot() {
             if saved_data != extracted_data {
                 info!("Detected an update of the payload, but continue (regarding debug policy)")
             }
-        } else {
-            ensure!(
-                saved_data == extracted_data,
-                MicrodroidError::PayloadChanged(String::from(
-                    "Detected an update of the payload which isn't supported yet."
-                ))
+            saved_data
+        } else if saved_data != extracted_data {
+            check_payload_update(&saved_data, &extracted_data)?;
+            info!(
+                "Payload updated from version {} to {}, waiting for it to migrate its data.",
+                saved_data.apk_data.version_code, extracted_data.apk_data.version_code
             );
+            // The instance data is only updated once the payload has migrated its own state, so
+            // that the migration is attempted again if the VM stops before that.
+            let instance_data = instance
+                .prepare_write(&extracted_data, &dice)
+                .context("Failed to prepare identity data")?;
+            migration = Some(PayloadMigration {
+                previous_version_code: saved_data.apk_data.version_code,
+                instance_data,
+            });
+            extracted_data
+        } else {
             info!("Saved data is verified.");
+            saved_data
         }
-        saved_data
     } else {
         info!("Saving verified data.");
         instance
@@ -337,6 +369,7 @@ fn try_run_payload(
         allow_restricted_apis,
         service.clone(),
         vm_secret,
+        migration,
         vm_payload_service_fd,
     )?;
 
@@ -380,6 +413,49 @@ fn try_run_payload(
     exec_task(task, service).context("Failed to run payload")
 }
 
+/// Checks that the payload was updated in a way which allows the instance data to be migrated,
+/// rather than requiring the instance to be reset.
+///
+/// The APKs must still be signed by the same keys, as the instance secrets are bound to them, and
+/// their versions must not go backwards. The payload is told of the update through
+/// `IVmPayloadService`, so that it can migrate its own state.
+fn check_payload_update(saved: &MicrodroidData, updated: &MicrodroidData) -> Result<()> {
+    ensure!(
+        saved.salt == updated.salt && saved.apex_data == updated.apex_data,
+        MicrodroidError::PayloadChanged(String::from("Instance identity has changed."))
+    );
+    ensure!(
+        saved.extra_apks_data.len() == updated.extra_apks_data.len(),
+        MicrodroidError::PayloadChanged(String::from("Number of extra APKs has changed."))
+    );
+    let apks = iter::once((&saved.apk_data, &updated.apk_data))
+        .chain(saved.extra_apks_data.iter().zip(&updated.extra_apks_data));
+    for (saved, updated) in apks {
+        ensure!(
+            saved.package_name == updated.package_name,
+            MicrodroidError::PayloadChanged(format!(
+                "Package name has changed from {} to {}.",
+                saved.package_name, updated.package_name
+            ))
+        );
+        ensure!(
+            saved.cert_hash == updated.cert_hash,
+            MicrodroidError::PayloadChanged(format!(
+                "Signing certificate of {} has changed.",
+                updated.package_name
+            ))
+        );
+        ensure!(
+            saved.version_code <= updated.version_code,
+            MicrodroidError::PayloadChanged(format!(
+                "Version of {} has been rolled back from {} to {}.",
+                updated.package_name, saved.version_code, updated.version_code
+            ))
+        );
+    }
+    Ok(())
+}
+
 fn post_payload_work() -> Result<()> {
     // Sync the encrypted storage filesystem (flushes the filesystem caches).
     if Path::new(ENCRYPTEDSTORE_BACKING_DEVICE).exists() {
@@ -454,6 +530,30 @@ unsafe fn prepare_vm_payload_service_socket() -> Result<OwnedFd> {
     Ok(unsafe { OwnedFd::from_raw_fd(raw_fd) })
 }
 
+fn notify_component_versions(service: &Strong<dyn IVirtualMachineService>) -> Result<()> {
+    let uname = nix::sys::utsname::uname().context("Failed to get kernel release")?;
+    let kernel_release = uname.release().to_string_lossy();
+    let pvmfw_version = match fs::read(AVF_PVMFW_VERSION) {
+        Ok(v) => Some(CStr::from_bytes_until_nul(&v)?.to_string_lossy().into_owned()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).context("Failed to read pvmfw version"),
+    };
+    service.notifyComponentVersions(&kernel_release, pvmfw_version.as_deref())?;
+    Ok(())
+}
+
+fn log_pvmfw_warnings() {
+    match fs::read(AVF_PVMFW_WARNINGS) {
+        Ok(v) => {
+            for w in v.split(|&b| b == 0).filter(|w| !w.is_empty()) {
+                warn!("pvmfw reported: {}", String::from_utf8_lossy(w));
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => warn!("Failed to read pvmfw warnings: {e:?}"),
+    }
+}
+
 fn is_strict_boot() -> bool {
     Path::new(AVF_STRICT_BOOT).exists()
 }
@@ -605,6 +705,58 @@ fn load_crashkernel_if_supported() -> Result<()> {
     Ok(())
 }
 
+/// Forwards what the payload writes to `output` to the host, which passes it on to the owner of
+/// the VM, in a new thread which drops `done` once the payload closes `output`.
+///
+/// The next chunk is only read once the host has accepted the previous one, so that the payload
+/// blocks if its owner doesn't keep up.
+fn forward_payload_output(
+    mut output: impl Read + Send + 'static,
+    stream: PayloadOutputStream,
+    service: &Strong<dyn IVirtualMachineService>,
+    done: Sender<()>,
+) {
+    let service = service.clone();
+    thread::spawn(move || {
+        let _done = done;
+        let mut buf = [0; PAYLOAD_OUTPUT_CHUNK_SIZE];
+        loop {
+            let len = match output.read(&mut buf) {
+                Ok(0) => return,
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    warn!("Failed to read payload {stream:?}: {e:?}");
+                    return;
+                }
+            };
+            if let Err(e) = forward_payload_output_chunk(&*service, stream, &buf[..len]) {
+                warn!("Failed to forward payload {stream:?}, discarding it: {e:?}");
+                // Keep draining the pipe so that the payload doesn't block on it.
+                let _ = io::copy(&mut output, &mut io::sink());
+                return;
+            }
+        }
+    });
+}
+
+/// Sends `chunk` to the host, waiting for the owner of the VM to make room for it if needed.
+fn forward_payload_output_chunk(
+    service: &dyn IVirtualMachineService,
+    stream: PayloadOutputStream,
+    mut chunk: &[u8],
+) -> Result<()> {
+    while !chunk.is_empty() {
+        let consumed = service.writePayloadOutput(stream, chunk)?;
+        match usize::try_from(consumed) {
+            Ok(0) => thread::sleep(PAYLOAD_OUTPUT_RETRY_DELAY),
+            Ok(len) if len <= chunk.len() => chunk = &chunk[len..],
+            _ => bail!("Host consumed an invalid amount of output: {consumed}"),
+        }
+    }
+    Ok(())
+}
+
 /// Executes the given task.
 fn exec_task(task: &Task, service: &Strong<dyn IVirtualMachineService>) -> Result<i32> {
     info!("executing main task {:?}...", task);
@@ -635,12 +787,27 @@ fn exec_task(task: &Task, service: &Strong<dyn IVirtualMachineService>) -> Resul
         });
     }
 
-    command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+    command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
 
     info!("notifying payload started");
     service.notifyPayloadStarted()?;
 
-    let exit_status = command.spawn()?.wait()?;
+    let mut child = command.spawn()?;
+    let (done, forwarders_done) = mpsc::channel();
+    if let Some(out) = child.stdout.take() {
+        forward_payload_output(out, PayloadOutputStream::STDOUT, service, done.clone());
+    }
+    if let Some(err) = child.stderr.take() {
+        forward_payload_output(err, PayloadOutputStream::STDERR, service, done.clone());
+    }
+    drop(done);
+    let exit_status = child.wait()?;
+    // Try to make sure that the owner received all of the output before it is told that the
+    // payload finished. Nothing is ever sent: the channel disconnects once all forwarders are done.
+    if forwarders_done.recv_timeout(PAYLOAD_OUTPUT_DRAIN_TIMEOUT) == Err(RecvTimeoutError::Timeout)
+    {
+        warn!("Payload output is still open after it exited, not waiting for it any longer");
+    }
     match exit_status.code() {
         Some(exit_code) => Ok(exit_code),
         None => Err(match exit_status.signal() {
@@ -680,3 +847,106 @@ fn prepare_encryptedstore(vm_secret: &VmSecret) -> Result<Child> {
         .spawn()
         .context("encryptedstore failed")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance::{ApexData, ApkData};
+
+    fn apk_data(package_name: &str, cert_hash: &[u8], version_code: u64) -> ApkData {
+        ApkData {
+            root_hash: vec![version_code as u8; 32],
+            cert_hash: cert_hash.to_vec(),
+            package_name: package_name.to_string(),
+            version_code,
+        }
+    }
+
+    fn microdroid_data(apk_data: ApkData, extra_apks_data: Vec<ApkData>) -> MicrodroidData {
+        MicrodroidData {
+            salt: vec![0xaa; 64],
+            apk_data,
+            extra_apks_data,
+            apex_data: vec![ApexData {
+                name: "com.android.foo".to_string(),
+                manifest_name: None,
+                manifest_version: None,
+                public_key: vec![1; 32],
+                root_digest: vec![2; 32],
+                last_update_seconds: 0,
+                is_factory: true,
+            }],
+        }
+    }
+
+    fn assert_payload_changed(result: Result<()>) {
+        let err = result.unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<MicrodroidError>(),
+                Some(MicrodroidError::PayloadChanged(_))
+            ),
+            "Unexpected error: {err:?}"
+        );
+    }
+
+    #[test]
+    fn payload_update_with_same_key_is_accepted() {
+        let saved = microdroid_data(apk_data("com.foo", b"cert", 1), vec![]);
+        let updated = microdroid_data(apk_data("com.foo", b"cert", 2), vec![]);
+
+        check_payload_update(&saved, &updated).unwrap();
+    }
+
+    #[test]
+    fn payload_update_of_extra_apk_is_accepted() {
+        let saved =
+            microdroid_data(apk_data("com.foo", b"cert", 1), vec![apk_data("com.bar", b"b", 3)]);
+        let updated =
+            microdroid_data(apk_data("com.foo", b"cert", 1), vec![apk_data("com.bar", b"b", 4)]);
+
+        check_payload_update(&saved, &updated).unwrap();
+    }
+
+    #[test]
+    fn payload_update_with_different_key_is_rejected() {
+        let saved = microdroid_data(apk_data("com.foo", b"cert", 1), vec![]);
+        let updated = microdroid_data(apk_data("com.foo", b"other cert", 2), vec![]);
+
+        assert_payload_changed(check_payload_update(&saved, &updated));
+    }
+
+    #[test]
+    fn payload_update_with_different_package_is_rejected() {
+        let saved = microdroid_data(apk_data("com.foo", b"cert", 1), vec![]);
+        let updated = microdroid_data(apk_data("com.bar", b"cert", 2), vec![]);
+
+        assert_payload_changed(check_payload_update(&saved, &updated));
+    }
+
+    #[test]
+    fn payload_rollback_is_rejected() {
+        let saved = microdroid_data(apk_data("com.foo", b"cert", 2), vec![]);
+        let updated = microdroid_data(apk_data("com.foo", b"cert", 1), vec![]);
+
+        assert_payload_changed(check_payload_update(&saved, &updated));
+    }
+
+    #[test]
+    fn payload_update_changing_extra_apks_is_rejected() {
+        let saved = microdroid_data(apk_data("com.foo", b"cert", 1), vec![]);
+        let updated =
+            microdroid_data(apk_data("com.foo", b"cert", 2), vec![apk_data("com.bar", b"b", 1)]);
+
+        assert_payload_changed(check_payload_update(&saved, &updated));
+    }
+
+    #[test]
+    fn payload_update_changing_salt_is_rejected() {
+        let saved = microdroid_data(apk_data("com.foo", b"cert", 1), vec![]);
+        let mut updated = microdroid_data(apk_data("com.foo", b"cert", 2), vec![]);
+        updated.salt = vec![0xbb; 64];
+
+        assert_payload_changed(check_payload_update(&saved, &updated));
+    }
+}
@@ -16,7 +16,7 @@
 
 use android_system_virtualization_payload::aidl::android::system::virtualization::payload::IVmPayloadService::{
     BnVmPayloadService, IVmPayloadService, VM_PAYLOAD_SERVICE_SOCKET_NAME, AttestationResult::AttestationResult,
-    STATUS_FAILED_TO_PREPARE_CSR_AND_KEY
+    NO_PAYLOAD_UPDATE, STATUS_FAILED_TO_PREPARE_CSR_AND_KEY
 };
 use android_system_virtualmachineservice::aidl::android::system::virtualmachineservice::IVirtualMachineService::IVirtualMachineService;
 use anyhow::{anyhow, Context, Result};
@@ -26,14 +26,25 @@ use client_vm_csr::{generate_attestation_key_and_csr, ClientVmAttestationData};
 use diced_open_dice::DiceArtifacts;
 use log::info;
 use rpcbinder::RpcServer;
+use crate::instance::PendingWrite;
 use crate::vm_secret::VmSecret;
 use std::os::unix::io::OwnedFd;
+use std::sync::Mutex;
+
+/// Update of the payload which the payload hasn't migrated its data for yet.
+pub(crate) struct PayloadMigration {
+    /// Version code of the payload APK before the update.
+    pub previous_version_code: u64,
+    /// Instance data for the updated payload, written once the payload has migrated its data.
+    pub instance_data: PendingWrite,
+}
 
 /// Implementation of `IVmPayloadService`.
 struct VmPayloadService {
     allow_restricted_apis: bool,
     virtual_machine_service: Strong<dyn IVirtualMachineService>,
     secret: VmSecret,
+    migration: Mutex<Option<PayloadMigration>>,
 }
 
 impl IVmPayloadService for VmPayloadService {
@@ -69,6 +80,33 @@ impl IVmPayloadService for VmPayloadService {
         Ok(self.secret.dice().cdi_attest().to_vec())
     }
 
+    fn getPreviousPayloadVersionCode(&self) -> binder::Result<i64> {
+        match self.migration.lock().unwrap().as_ref() {
+            Some(migration) => migration
+                .previous_version_code
+                .try_into()
+                .context("Version code out of range")
+                .with_log()
+                .or_service_specific_exception(-1),
+            None => Ok(NO_PAYLOAD_UPDATE),
+        }
+    }
+
+    fn notifyPayloadMigrated(&self) -> binder::Result<()> {
+        let Some(migration) = self.migration.lock().unwrap().take() else {
+            return Err(anyhow!("No payload update to migrate"))
+                .with_log()
+                .or_binder_exception(ExceptionCode::ILLEGAL_STATE);
+        };
+        info!("Payload migrated its data, updating the instance data.");
+        migration
+            .instance_data
+            .commit()
+            .context("Failed to write identity data")
+            .with_log()
+            .or_service_specific_exception(-1)
+    }
+
     fn requestAttestation(&self, challenge: &[u8]) -> binder::Result<AttestationResult> {
         self.check_restricted_apis_allowed()?;
         let ClientVmAttestationData { private_key, csr } =
@@ -105,8 +143,14 @@ impl VmPayloadService {
         allow_restricted_apis: bool,
         vm_service: Strong<dyn IVirtualMachineService>,
         secret: VmSecret,
+        migration: Option<PayloadMigration>,
     ) -> VmPayloadService {
-        Self { allow_restricted_apis, virtual_machine_service: vm_service, secret }
+        Self {
+            allow_restricted_apis,
+            virtual_machine_service: vm_service,
+            secret,
+            migration: Mutex::new(migration),
+        }
     }
 
     fn check_restricted_apis_allowed(&self) -> binder::Result<()> {
@@ -125,10 +169,11 @@ pub(crate) fn register_vm_payload_service(
     allow_restricted_apis: bool,
     vm_service: Strong<dyn IVirtualMachineService>,
     secret: VmSecret,
+    migration: Option<PayloadMigration>,
     vm_payload_service_fd: OwnedFd,
 ) -> Result<()> {
     let vm_payload_binder = BnVmPayloadService::new_binder(
-        VmPayloadService::new(allow_restricted_apis, vm_service, secret),
+        VmPayloadService::new(allow_restricted_apis, vm_service, secret, migration),
         BinderFeatures::default(),
     );
 
@@ -17,6 +17,7 @@
 mod collection;
 mod common;
 mod hash;
+mod kernel_cmdline;
 mod property;
 
 pub(crate) use collection::Descriptors;
@@ -16,10 +16,12 @@
 
 use super::common::get_valid_descriptor;
 use super::hash::HashDescriptor;
+use super::kernel_cmdline::KernelCmdlineDescriptor;
 use super::property::PropertyDescriptor;
 use crate::partition::PartitionName;
 use crate::utils::{to_usize, usize_checked_add};
 use crate::PvmfwVerifyError;
+use alloc::vec::Vec;
 use avb::{IoError, IoResult, SlotVerifyError, SlotVerifyNoDataResult, VbmetaData};
 use avb_bindgen::{
     avb_descriptor_foreach, avb_descriptor_validate_and_byteswap, AvbDescriptor, AvbDescriptorTag,
@@ -27,17 +29,18 @@ use avb_bindgen::{
 use core::{ffi::c_void, mem::size_of, slice};
 use tinyvec::ArrayVec;
 
-/// `Descriptors` can have at most one `HashDescriptor` per known partition and at most one
-/// `PropertyDescriptor`.
+/// `Descriptors` can have at most one `HashDescriptor` per known partition, at most one
+/// `PropertyDescriptor` and any number of `KernelCmdlineDescriptor`.
 #[derive(Default)]
 pub(crate) struct Descriptors<'a> {
     hash_descriptors: ArrayVec<[HashDescriptor<'a>; PartitionName::NUM_OF_KNOWN_PARTITIONS]>,
     prop_descriptor: Option<PropertyDescriptor<'a>>,
+    kernel_cmdline_descriptors: Vec<KernelCmdlineDescriptor<'a>>,
 }
 
 impl<'a> Descriptors<'a> {
     /// Builds `Descriptors` from `VbmetaData`.
-    /// Returns an error if the given `VbmetaData` contains non-hash descriptor, hash
+    /// Returns an error if the given `VbmetaData` contains descriptors of unsupported types, hash
     /// descriptor of unknown `PartitionName` or duplicated hash descriptors.
     pub(crate) fn from_vbmeta(vbmeta: &'a VbmetaData) -> Result<Self, PvmfwVerifyError> {
         let mut res: IoResult<Self> = Ok(Self::default());
@@ -81,10 +84,33 @@ impl<'a> Descriptors<'a> {
         self.prop_descriptor.as_ref().filter(|desc| desc.key == key).map(|desc| desc.value)
     }
 
+    /// Returns the kernel command line built from the kernel command line descriptors, joined
+    /// with spaces in the order in which they appear in the vbmeta, if there is any.
+    ///
+    /// As pvmfw never disables dm-verity, descriptors only applicable when the hashtree is
+    /// disabled are ignored.
+    pub(crate) fn kernel_cmdline(&self) -> Option<Vec<u8>> {
+        let mut cmdline = Vec::new();
+        for d in &self.kernel_cmdline_descriptors {
+            if d.flags & KernelCmdlineDescriptor::FLAG_USE_ONLY_IF_HASHTREE_DISABLED != 0 {
+                continue;
+            }
+            if !cmdline.is_empty() {
+                cmdline.push(b' ');
+            }
+            cmdline.extend_from_slice(d.cmdline);
+        }
+        Some(cmdline).filter(|c| !c.is_empty())
+    }
+
     fn push(&mut self, descriptor: Descriptor<'a>) -> IoResult<()> {
         match descriptor {
             Descriptor::Hash(d) => self.push_hash_descriptor(d),
             Descriptor::Property(d) => self.push_property_descriptor(d),
+            Descriptor::KernelCmdline(d) => {
+                self.kernel_cmdline_descriptors.push(d);
+                Ok(())
+            }
         }
     }
 
@@ -151,6 +177,7 @@ unsafe fn try_check_and_save_descriptor(
 enum Descriptor<'a> {
     Hash(HashDescriptor<'a>),
     Property(PropertyDescriptor<'a>),
+    KernelCmdline(KernelCmdlineDescriptor<'a>),
 }
 
 impl<'a> Descriptor<'a> {
@@ -184,6 +211,13 @@ impl<'a> Descriptor<'a> {
                     unsafe { PropertyDescriptor::from_descriptor_ptr(descriptor, data)? };
                 Ok(Self::Property(descriptor))
             }
+            Ok(AvbDescriptorTag::AVB_DESCRIPTOR_TAG_KERNEL_CMDLINE) => {
+                let descriptor =
+                // SAFETY: It is safe because the caller ensures that `descriptor` is a non-null
+                // pointer pointing to a valid struct.
+                    unsafe { KernelCmdlineDescriptor::from_descriptor_ptr(descriptor, data)? };
+                Ok(Self::KernelCmdline(descriptor))
+            }
             _ => Err(IoError::NoSuchValue),
         }
     }
@@ -0,0 +1,79 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Structs and functions relating to the kernel command line descriptor.
+
+use super::common::get_valid_descriptor;
+use crate::utils::{to_usize, usize_checked_add};
+use avb::{IoError, IoResult};
+use avb_bindgen::{
+    avb_kernel_cmdline_descriptor_validate_and_byteswap, AvbDescriptor, AvbKernelCmdlineDescriptor,
+};
+use core::mem::size_of;
+
+pub(super) struct KernelCmdlineDescriptor<'a> {
+    pub(super) flags: u32,
+    pub(super) cmdline: &'a [u8],
+}
+
+impl<'a> KernelCmdlineDescriptor<'a> {
+    /// The command line only applies if dm-verity is disabled.
+    pub(super) const FLAG_USE_ONLY_IF_HASHTREE_DISABLED: u32 = 1 << 1;
+
+    /// # Safety
+    ///
+    /// Behavior is undefined if any of the following conditions are violated:
+    /// * The `descriptor` pointer must be non-null and point to a valid `AvbDescriptor`.
+    pub(super) unsafe fn from_descriptor_ptr(
+        descriptor: *const AvbDescriptor,
+        data: &'a [u8],
+    ) -> IoResult<Self> {
+        // SAFETY: It is safe as the raw pointer `descriptor` is non-null and points to
+        // a valid `AvbDescriptor`.
+        let h = unsafe { KernelCmdlineDescriptorHeader::from_descriptor_ptr(descriptor)? };
+        let cmdline =
+            data.get(h.cmdline_start()..h.cmdline_end()?).ok_or(IoError::RangeOutsidePartition)?;
+        if cmdline.contains(&b'\0') {
+            return Err(IoError::NoSuchValue);
+        }
+        Ok(Self { flags: h.0.flags, cmdline })
+    }
+}
+
+struct KernelCmdlineDescriptorHeader(AvbKernelCmdlineDescriptor);
+
+impl KernelCmdlineDescriptorHeader {
+    /// # Safety
+    ///
+    /// Behavior is undefined if any of the following conditions are violated:
+    /// * The `descriptor` pointer must be non-null and point to a valid `AvbDescriptor`.
+    unsafe fn from_descriptor_ptr(descriptor: *const AvbDescriptor) -> IoResult<Self> {
+        // SAFETY: It is safe as the raw pointer `descriptor` is non-null and points to
+        // a valid `AvbDescriptor`.
+        unsafe {
+            get_valid_descriptor(
+                descriptor as *const AvbKernelCmdlineDescriptor,
+                avb_kernel_cmdline_descriptor_validate_and_byteswap,
+            )
+            .map(Self)
+        }
+    }
+
+    fn cmdline_start(&self) -> usize {
+        size_of::<AvbKernelCmdlineDescriptor>()
+    }
+
+    fn cmdline_end(&self) -> IoResult<usize> {
+        usize_checked_add(self.cmdline_start(), to_usize(self.0.kernel_cmdline_length)?)
+    }
+}
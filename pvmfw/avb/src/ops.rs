@@ -19,6 +19,7 @@ use avb::{
     slot_verify, HashtreeErrorMode, IoError, IoResult, PublicKeyForPartitionInfo, SlotVerifyData,
     SlotVerifyFlags, SlotVerifyResult,
 };
+use bssl_avf::constant_time_eq;
 use core::ffi::CStr;
 
 pub(crate) struct Payload<'a> {
@@ -92,7 +93,7 @@ impl<'a> avb::Ops for Ops<'a> {
         _public_key_metadata: Option<&[u8]>,
     ) -> IoResult<bool> {
         // The public key metadata is not used when we build the VBMeta.
-        Ok(self.payload.trusted_public_key == public_key)
+        Ok(constant_time_eq(self.payload.trusted_public_key, public_key))
     }
 
     fn read_rollback_index(&mut self, _rollback_index_location: usize) -> IoResult<u64> {
@@ -40,6 +40,8 @@ pub struct VerifiedBootData<'a> {
     pub capabilities: Vec<Capability>,
     /// Rollback index of kernel.
     pub rollback_index: u64,
+    /// Kernel command line from the kernel command line descriptors, if any.
+    pub kernel_cmdline: Option<Vec<u8>>,
 }
 
 impl VerifiedBootData<'_> {
@@ -188,6 +190,7 @@ pub fn verify_payload<'a>(
     verify_vbmeta_is_from_kernel_partition(vbmeta_image)?;
     let descriptors = Descriptors::from_vbmeta(vbmeta_image)?;
     let capabilities = verify_property_and_get_capabilities(&descriptors)?;
+    let kernel_cmdline = descriptors.kernel_cmdline();
     let kernel_descriptor = descriptors.find_hash_descriptor(PartitionName::Kernel)?;
 
     if initrd.is_none() {
@@ -199,6 +202,7 @@ pub fn verify_payload<'a>(
             public_key: trusted_public_key,
             capabilities,
             rollback_index,
+            kernel_cmdline,
         });
     }
 
@@ -220,5 +224,6 @@ pub fn verify_payload<'a>(
         public_key: trusted_public_key,
         capabilities,
         rollback_index,
+        kernel_cmdline,
     })
 }
@@ -34,6 +34,7 @@ const TEST_IMG_WITH_NON_INITRD_HASHDESC_PATH: &str = "test_image_with_non_initrd
 const TEST_IMG_WITH_INITRD_AND_NON_INITRD_DESC_PATH: &str =
     "test_image_with_initrd_and_non_initrd_desc.img";
 const TEST_IMG_WITH_MULTIPLE_CAPABILITIES: &str = "test_image_with_multiple_capabilities.img";
+const TEST_IMG_WITH_KERNEL_CMDLINE_PATH: &str = "test_image_with_kernel_cmdline.img";
 const UNSIGNED_TEST_IMG_PATH: &str = "unsigned_test.img";
 
 const RANDOM_FOOTER_POS: usize = 30;
@@ -76,6 +77,7 @@ fn payload_expecting_no_initrd_passes_verification_with_no_initrd() -> Result<()
         public_key: &public_key,
         capabilities: vec![],
         rollback_index: 0,
+        kernel_cmdline: None,
     };
     assert_eq!(expected_boot_data, verified_boot_data);
 
@@ -120,6 +122,7 @@ fn payload_expecting_no_initrd_passes_verification_with_service_vm_prop() -> Res
         public_key: &public_key,
         capabilities: vec![Capability::RemoteAttest],
         rollback_index: 0,
+        kernel_cmdline: None,
     };
     assert_eq!(expected_boot_data, verified_boot_data);
 
@@ -407,6 +410,7 @@ fn payload_with_rollback_index() -> Result<()> {
         public_key: &public_key,
         capabilities: vec![],
         rollback_index: 5,
+        kernel_cmdline: None,
     };
     assert_eq!(expected_boot_data, verified_boot_data);
     Ok(())
@@ -426,3 +430,27 @@ fn payload_with_multiple_capabilities() -> Result<()> {
     assert!(verified_boot_data.has_capability(Capability::SecretkeeperProtection));
     Ok(())
 }
+
+#[test]
+fn payload_with_kernel_cmdline() -> Result<()> {
+    let public_key = load_trusted_public_key()?;
+    let verified_boot_data = verify_payload(
+        &fs::read(TEST_IMG_WITH_KERNEL_CMDLINE_PATH)?,
+        /* initrd= */ None,
+        &public_key,
+    )
+    .map_err(|e| anyhow!("Verification failed. Error: {}", e))?;
+
+    let kernel_digest = hash(&[&hex::decode("1311")?, &fs::read(UNSIGNED_TEST_IMG_PATH)?]);
+    let expected_boot_data = VerifiedBootData {
+        debug_level: DebugLevel::None,
+        kernel_digest,
+        initrd_digest: None,
+        public_key: &public_key,
+        capabilities: vec![],
+        rollback_index: 0,
+        kernel_cmdline: Some(b"androidboot.test=1 loglevel=4".to_vec()),
+    };
+    assert_eq!(expected_boot_data, verified_boot_data);
+    Ok(())
+}
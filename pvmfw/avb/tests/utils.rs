@@ -122,6 +122,7 @@ pub fn assert_latest_payload_verification_passes(
         public_key: &public_key,
         capabilities,
         rollback_index: if cfg!(llpvm_changes) { 1 } else { 0 },
+        kernel_cmdline: None,
     };
     assert_eq!(expected_boot_data, verified_boot_data);
 
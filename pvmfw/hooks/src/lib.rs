@@ -0,0 +1,61 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Interface of the product-specific hooks run by pvmfw while verifying the payload.
+//!
+//! A product implements [`VerificationHook`] in a crate named `pvmfw_product_hooks`, which exports
+//! the hooks to run as `pub static VERIFICATION_HOOKS: &[&dyn VerificationHook]`, and selects it
+//! at build time through the `pvmfw_product_hooks` Soong config variable.
+
+#![no_std]
+
+use core::fmt;
+use libfdt::Fdt;
+use pvmfw_avb::VerifiedBootData;
+
+/// Reason for which a hook rejected the payload.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HookError {
+    /// The payload doesn't comply with the product policy.
+    PolicyViolation(&'static str),
+    /// The hook failed to evaluate the product policy.
+    Internal(&'static str),
+}
+
+impl fmt::Display for HookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::PolicyViolation(reason) => write!(f, "Policy violation: {reason}"),
+            Self::Internal(reason) => write!(f, "Internal error: {reason}"),
+        }
+    }
+}
+
+/// Extra verification of the payload, run by pvmfw after AVB verification and before deriving
+/// the DICE secrets of the guest. Rejecting the payload prevents the guest from booting.
+pub trait VerificationHook: Sync {
+    /// Returns the name of the hook, used in logs.
+    fn name(&self) -> &str;
+
+    /// Checks the verified payload and the DT received from the host.
+    ///
+    /// The DT has been validated and sanitized but not yet modified for the guest: the debug
+    /// policy, the DICE node and the /chosen properties that pvmfw sets are still missing and the
+    /// bootargs haven't been filtered.
+    fn check_payload(
+        &self,
+        verified_boot_data: &VerifiedBootData,
+        fdt: &Fdt,
+    ) -> Result<(), HookError>;
+}
@@ -0,0 +1,161 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Policy for tracking the consecutive failed boot attempts of an instance.
+//!
+//! The count is stored in instance.img, which the host can freely modify: it is neither secret
+//! nor authenticated and must therefore only ever drive diagnostics, never security decisions.
+
+/// Number of consecutive failed boot attempts after which pvmfw reports repeated failures.
+pub const BOOT_FAILURE_THRESHOLD: u32 = 3;
+
+/// Diagnostics to enable for the current boot attempt.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Diagnostics {
+    /// The instance isn't failing repeatedly.
+    None,
+    /// Report the repeated failures to the guest.
+    Report,
+    /// Report the repeated failures to the guest and log verbosely.
+    Verbose,
+}
+
+/// Counter of the boot attempts that failed right before the current one.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BootAttempts {
+    previous_failures: u32,
+}
+
+impl BootAttempts {
+    /// Wraps the count of previous failures, as read from the instance.img.
+    pub fn new(previous_failures: u32) -> Self {
+        Self { previous_failures }
+    }
+
+    /// Returns the number of boot attempts which failed right before this one.
+    pub fn previous_failures(&self) -> u32 {
+        self.previous_failures
+    }
+
+    /// Returns the count to persist if the current attempt fails.
+    pub fn on_failure(&self) -> u32 {
+        self.previous_failures.saturating_add(1)
+    }
+
+    /// Returns the count to persist if the current attempt succeeds, if it needs updating.
+    ///
+    /// Successful boots of a healthy instance don't write to the instance.img.
+    pub fn on_success(&self) -> Option<u32> {
+        (self.previous_failures != 0).then_some(0)
+    }
+
+    /// Returns the diagnostics to enable for the current attempt.
+    ///
+    /// As the count is controlled by the host, it may only raise the log level of payloads that
+    /// are debuggable anyway.
+    pub fn diagnostics(&self, debuggable: bool) -> Diagnostics {
+        if self.previous_failures < BOOT_FAILURE_THRESHOLD {
+            Diagnostics::None
+        } else if debuggable {
+            Diagnostics::Verbose
+        } else {
+            Diagnostics::Report
+        }
+    }
+}
+
+/// Returns the text of a NUL-padded log excerpt, as recorded after a failed boot attempt.
+///
+/// Drops the partial UTF-8 characters which the excerpt may start or end with, as well as anything
+/// following invalid UTF-8, which could only have been written by the host.
+pub fn log_excerpt(bytes: &[u8]) -> &str {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    let start = bytes[..len].iter().position(|&b| !is_utf8_continuation(b)).unwrap_or(len);
+    let bytes = &bytes[start..len];
+    match core::str::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+    }
+}
+
+fn is_utf8_continuation(b: u8) -> bool {
+    b & 0xc0 == 0x80
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn healthy_instance_is_not_written_on_success() {
+        assert_eq!(BootAttempts::default().on_success(), None);
+    }
+
+    #[test]
+    fn failing_instance_is_reset_on_success() {
+        assert_eq!(BootAttempts::new(2).on_success(), Some(0));
+    }
+
+    #[test]
+    fn failure_increments_count() {
+        assert_eq!(BootAttempts::default().on_failure(), 1);
+        assert_eq!(BootAttempts::new(4).on_failure(), 5);
+    }
+
+    #[test]
+    fn failure_count_saturates() {
+        assert_eq!(BootAttempts::new(u32::MAX).on_failure(), u32::MAX);
+    }
+
+    #[test]
+    fn no_diagnostics_below_threshold() {
+        let attempts = BootAttempts::new(BOOT_FAILURE_THRESHOLD - 1);
+
+        assert_eq!(attempts.diagnostics(false), Diagnostics::None);
+        assert_eq!(attempts.diagnostics(true), Diagnostics::None);
+    }
+
+    #[test]
+    fn repeated_failures_only_reported_for_non_debuggable_payload() {
+        let attempts = BootAttempts::new(BOOT_FAILURE_THRESHOLD);
+
+        assert_eq!(attempts.diagnostics(false), Diagnostics::Report);
+    }
+
+    #[test]
+    fn repeated_failures_enable_verbose_logs_for_debuggable_payload() {
+        let attempts = BootAttempts::new(BOOT_FAILURE_THRESHOLD);
+
+        assert_eq!(attempts.diagnostics(true), Diagnostics::Verbose);
+    }
+
+    #[test]
+    fn log_excerpt_stops_at_padding() {
+        assert_eq!(log_excerpt(b"[ERROR] Failed\n\0\0\0"), "[ERROR] Failed\n");
+        assert_eq!(log_excerpt(&[0; 8]), "");
+    }
+
+    #[test]
+    fn log_excerpt_drops_partial_characters() {
+        let text = "é: ü".as_bytes();
+
+        assert_eq!(log_excerpt(&text[1..]), ": ü");
+        assert_eq!(log_excerpt(&text[..text.len() - 1]), "é: ");
+    }
+
+    #[test]
+    fn log_excerpt_stops_at_invalid_utf8() {
+        assert_eq!(log_excerpt(b"valid\xffinvalid"), "valid");
+    }
+}
@@ -105,6 +105,50 @@ impl<'a> Iterator for BootArgsIterator<'a> {
     }
 }
 
+/// Returns whether the raw boot args contain an argument called `name`, with or without a value.
+///
+/// Invalid boot args are considered not to contain any argument.
+pub fn has_bootarg(bootargs: &CStr, name: &str) -> bool {
+    BootArgsIterator::new(bootargs).is_ok_and(|mut args| args.any(|arg| arg.name() == name))
+}
+
+/// Boot arguments accepted on top of the ones built into pvmfw, as approved for the product.
+///
+/// The list is made of rules using the syntax of bootargs, where each rule accepts the boot
+/// arguments with its name and:
+///
+/// - `name`: no value;
+/// - `name=value`: exactly the given value;
+/// - `name=prefix*`: a value starting with the given prefix, so that `name=*` accepts any value.
+///
+/// An argument is accepted if any of the rules with its name accepts it.
+pub struct BootArgsAllowList<'a> {
+    rules: &'a str,
+}
+
+impl<'a> BootArgsAllowList<'a> {
+    /// Creates an allow-list from the raw rules. The input has to be encoded in ASCII.
+    pub fn new(rules: &'a CStr) -> Result<Self, String> {
+        let rules = BootArgsIterator::new(rules)?.arg;
+        Ok(Self { rules })
+    }
+
+    /// Returns whether `arg` is accepted by one of the rules.
+    pub fn accepts(&self, arg: &BootArg) -> bool {
+        BootArgsIterator { arg: self.rules }.any(|rule| {
+            rule.name() == arg.name()
+                && match (rule.value(), arg.value()) {
+                    (None, None) => true,
+                    (Some(pattern), Some(value)) => match pattern.strip_suffix('*') {
+                        Some(prefix) => value.starts_with(prefix),
+                        None => value == pattern,
+                    },
+                    _ => false,
+                }
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -192,6 +236,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn allow_list() {
+        let allow_list =
+            BootArgsAllowList::new(cstr!("nosmt  foo=bar foo=baz loglevel=* earlycon=uart*"))
+                .unwrap();
+        let accepts = |arg: &CStr| {
+            let mut args = BootArgsIterator::new(arg).unwrap();
+            allow_list.accepts(&args.next().unwrap())
+        };
+
+        assert!(accepts(cstr!("nosmt")));
+        assert!(!accepts(cstr!("nosmt=1")));
+        assert!(accepts(cstr!("foo=bar")));
+        assert!(accepts(cstr!("foo=baz")));
+        assert!(!accepts(cstr!("foo")));
+        assert!(!accepts(cstr!("foo=")));
+        assert!(!accepts(cstr!("foo=bar2")));
+        assert!(accepts(cstr!("loglevel=")));
+        assert!(accepts(cstr!("loglevel=7")));
+        assert!(!accepts(cstr!("loglevel")));
+        assert!(accepts(cstr!("earlycon=uart8250,mmio,0x3f8")));
+        assert!(!accepts(cstr!("earlycon=pl011")));
+        assert!(!accepts(cstr!("panic=-1")));
+        assert!(!accepts(cstr!("nosm")));
+    }
+
+    #[test]
+    fn has_bootarg_matches_names() {
+        let bootargs = cstr!("panic=-1 crashkernel=17M nokaslr");
+        assert!(has_bootarg(bootargs, "crashkernel"));
+        assert!(has_bootarg(bootargs, "nokaslr"));
+        assert!(!has_bootarg(bootargs, "crash"));
+        assert!(!has_bootarg(bootargs, "17M"));
+        assert!(!has_bootarg(cstr!(""), "crashkernel"));
+        assert!(!has_bootarg(CStr::from_bytes_with_nul(&[255, 0]).unwrap(), "crashkernel"));
+    }
+
+    #[test]
+    fn invalid_allow_list() {
+        assert!(BootArgsAllowList::new(CStr::from_bytes_with_nul(&[255, 0]).unwrap()).is_err());
+    }
+
     #[test]
     fn complex() {
         check(cstr!("  a  a1=  b=c d=e,f,g x=\"value with quote\" y=val\"ue with \"multiple\" quo\"te  "), Ok(&[
@@ -85,6 +85,8 @@ impl Header {
     const MAGIC: u32 = u32::from_ne_bytes(*b"pvmf");
     const VERSION_1_0: Version = Version { major: 1, minor: 0 };
     const VERSION_1_1: Version = Version { major: 1, minor: 1 };
+    const VERSION_1_2: Version = Version { major: 1, minor: 2 };
+    const VERSION_1_3: Version = Version { major: 1, minor: 3 };
 
     pub fn total_size(&self) -> usize {
         self.total_size as usize
@@ -106,8 +108,10 @@ impl Header {
         let last_entry = match self.version {
             Self::VERSION_1_0 => Entry::DebugPolicy,
             Self::VERSION_1_1 => Entry::VmDtbo,
+            Self::VERSION_1_2 => Entry::ServiceVmDtDigests,
+            Self::VERSION_1_3 => Entry::BootargsAllowList,
             v @ Version { major: 1, .. } => {
-                const LATEST: Version = Header::VERSION_1_1;
+                const LATEST: Version = Header::VERSION_1_3;
                 warn!("Parsing unknown config data version {v} as version {LATEST}");
                 return Ok(Entry::COUNT);
             }
@@ -123,6 +127,8 @@ pub enum Entry {
     Bcc,
     DebugPolicy,
     VmDtbo,
+    ServiceVmDtDigests,
+    BootargsAllowList,
     #[allow(non_camel_case_types)] // TODO: Use mem::variant_count once stable.
     _VARIANT_COUNT,
 }
@@ -136,6 +142,8 @@ pub struct Entries<'a> {
     pub bcc: &'a mut [u8],
     pub debug_policy: Option<&'a mut [u8]>,
     pub vm_dtbo: Option<&'a mut [u8]>,
+    pub service_vm_dt_digests: Option<&'a mut [u8]>,
+    pub bootargs_allow_list: Option<&'a mut [u8]>,
 }
 
 #[repr(packed)]
@@ -240,7 +248,13 @@ impl<'a> Config<'a> {
         let limits = header.body_lowest_bound()?..total_size;
         let mut ranges: [Option<NonEmptyRange>; Entry::COUNT] = [None; Entry::COUNT];
         let mut last_end = 0;
-        for entry in [Entry::Bcc, Entry::DebugPolicy, Entry::VmDtbo] {
+        for entry in [
+            Entry::Bcc,
+            Entry::DebugPolicy,
+            Entry::VmDtbo,
+            Entry::ServiceVmDtDigests,
+            Entry::BootargsAllowList,
+        ] {
             let Some(header_entry) = header_entries.get(entry as usize) else { continue };
             let entry_offset = header_entry.offset.try_into().unwrap();
             let entry_size = header_entry.size.try_into().unwrap();
@@ -272,6 +286,8 @@ impl<'a> Config<'a> {
         let bcc_range = self.get_entry_range(Entry::Bcc);
         let dp_range = self.get_entry_range(Entry::DebugPolicy);
         let vm_dtbo_range = self.get_entry_range(Entry::VmDtbo);
+        let digests_range = self.get_entry_range(Entry::ServiceVmDtDigests);
+        let allow_list_range = self.get_entry_range(Entry::BootargsAllowList);
         // TODO(b/291191157): Provision device assignment with this.
         if let Some(vm_dtbo_range) = vm_dtbo_range {
             info!("Found VM DTBO at {:?}", vm_dtbo_range);
@@ -279,15 +295,17 @@ impl<'a> Config<'a> {
 
         // SAFETY: When instantiated, ranges are validated to be in the body range without
         // overlapping.
-        let (bcc, debug_policy, vm_dtbo) = unsafe {
+        let (bcc, debug_policy, vm_dtbo, service_vm_dt_digests, bootargs_allow_list) = unsafe {
             let ptr = self.body.as_mut_ptr() as usize;
             (
                 Self::from_raw_range_mut(ptr, bcc_range.unwrap()),
                 dp_range.map(|dp_range| Self::from_raw_range_mut(ptr, dp_range)),
                 vm_dtbo_range.map(|vm_dtbo_range| Self::from_raw_range_mut(ptr, vm_dtbo_range)),
+                digests_range.map(|digests_range| Self::from_raw_range_mut(ptr, digests_range)),
+                allow_list_range.map(|range| Self::from_raw_range_mut(ptr, range)),
             )
         };
-        Entries { bcc, debug_policy, vm_dtbo }
+        Entries { bcc, debug_policy, vm_dtbo, service_vm_dt_digests, bootargs_allow_list }
     }
 
     fn get_entry_range(&self, entry: Entry) -> Option<NonEmptyRange> {
@@ -27,7 +27,9 @@ use alloc::vec::Vec;
 use core::ffi::CStr;
 use core::iter::Iterator;
 use core::mem;
-use libfdt::{Fdt, FdtError, FdtNode, Phandle};
+use core::ops::Range;
+use libfdt::{Fdt, FdtError, FdtNode, Phandle, MAX_PATH_LEN};
+use log::error;
 
 // TODO(b/308694211): Use cstr! from vmbase instead.
 macro_rules! cstr {
@@ -43,6 +45,9 @@ macro_rules! cstr {
 
 // TODO(b/277993056): Keep constants derived from platform.dts in one place.
 const CELLS_PER_INTERRUPT: usize = 3; // from /intc node in platform.dts
+/// Bounds the chains of interrupt controllers between assigned devices and the GIC, which also
+/// rejects chains with loops.
+const MAX_INTERRUPT_CONTROLLER_DEPTH: usize = 8;
 
 /// Errors in device assignment.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -53,6 +58,8 @@ pub enum DeviceAssignmentError {
     InvalidSymbols,
     /// Invalid <interrupts>
     InvalidInterrupts,
+    /// Invalid interrupt controller, or chain of interrupt controllers
+    InvalidInterruptController,
     /// Invalid <iommus>
     InvalidIommus,
     /// Invalid pvIOMMU node
@@ -84,6 +91,10 @@ impl fmt::Display for DeviceAssignmentError {
                 "Invalid property in /__symbols__. Must point to valid assignable device node."
             ),
             Self::InvalidInterrupts => write!(f, "Invalid <interrupts>"),
+            Self::InvalidInterruptController => write!(
+                f,
+                "Invalid interrupt controller. Must lead to the GIC through interrupt controllers"
+            ),
             Self::InvalidIommus => write!(f, "Invalid <iommus>"),
             Self::InvalidPvIommu => write!(f, "Invalid pvIOMMU node"),
             Self::TooManyPvIommu => write!(
@@ -214,6 +225,151 @@ impl PvIommu {
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
 struct Vsid(u32);
 
+/// Returns the phandle of the interrupt parent of the node, possibly inherited from its ancestors.
+fn interrupt_parent(node: &FdtNode) -> Result<Phandle> {
+    let mut node = *node;
+    loop {
+        if let Some(phandle) = node.getprop_u32(cstr!("interrupt-parent"))? {
+            return Phandle::try_from(phandle).or(Err(DeviceAssignmentError::InvalidInterrupts));
+        }
+        node = match node.parent() {
+            Err(FdtError::NotFound) => return Err(DeviceAssignmentError::InvalidInterrupts),
+            other => other?,
+        };
+    }
+}
+
+/// Returns the number of cells per interrupt of the interrupt controller.
+fn interrupt_cells(fdt: &Fdt, gic: Phandle, controller: Phandle) -> Result<usize> {
+    if controller == gic {
+        return Ok(CELLS_PER_INTERRUPT);
+    }
+    let node = fdt
+        .node_with_phandle(controller)?
+        .ok_or(DeviceAssignmentError::InvalidInterruptController)?;
+    let cells = node
+        .getprop_u32(cstr!("#interrupt-cells"))?
+        .ok_or(DeviceAssignmentError::InvalidInterruptController)?;
+    match cells.try_into() {
+        Ok(0) | Err(_) => Err(DeviceAssignmentError::InvalidInterruptController),
+        Ok(cells) => Ok(cells),
+    }
+}
+
+/// Validates that the size of <interrupts> is a multiple of the cells per interrupt, and returns
+/// the raw bytes so patch can be done with setprop().
+fn parse_interrupts(node: &FdtNode, cells_per_interrupt: usize) -> Result<Vec<u8>> {
+    // We can't know how many interrupts would exist.
+    let interrupts_cells = node
+        .getprop_cells(cstr!("interrupts"))?
+        .ok_or(DeviceAssignmentError::InvalidInterrupts)?
+        .count();
+    if interrupts_cells % cells_per_interrupt != 0 {
+        return Err(DeviceAssignmentError::InvalidInterrupts);
+    }
+
+    Ok(node.getprop(cstr!("interrupts")).unwrap().unwrap().into())
+}
+
+/// Interrupt controller between assigned devices and the GIC, parsed from crosvm DT.
+/// Keeps everything in the owned data, as the node is recreated in the platform DT.
+#[derive(Debug, Eq, PartialEq)]
+struct InterruptControllerInfo {
+    // Node name in the crosvm DT (e.g. "intc@8000000")
+    name: CString,
+    // Allowed properties from the crosvm DT, except phandles which are fixed up in the platform DT.
+    properties: Vec<(CString, Vec<u8>)>,
+    // MMIO regions of the controller, from its <reg>.
+    regions: Vec<Range<u64>>,
+    // Phandle of its interrupt parent in the crosvm DT, or None if it is the GIC.
+    parent: Option<Phandle>,
+}
+
+impl InterruptControllerInfo {
+    const PHANDLE_PROPS: [&CStr; 3] =
+        [cstr!("phandle"), cstr!("linux,phandle"), cstr!("interrupt-parent")];
+    /// Properties copied to the platform DT. Any other property is rejected, as the node isn't
+    /// otherwise validated.
+    const ALLOWED_PROPS: [&CStr; 5] = [
+        cstr!("compatible"),
+        cstr!("reg"),
+        cstr!("interrupts"),
+        cstr!("interrupt-controller"),
+        cstr!("#interrupt-cells"),
+    ];
+
+    fn parse(fdt: &Fdt, gic: Phandle, node: &FdtNode) -> Result<Self> {
+        if node.getprop(cstr!("interrupt-controller"))?.is_none() {
+            return Err(DeviceAssignmentError::InvalidInterruptController);
+        }
+        // Validates its own <#interrupt-cells>.
+        let phandle =
+            node.get_phandle()?.ok_or(DeviceAssignmentError::InvalidInterruptController)?;
+        interrupt_cells(fdt, gic, phandle)?;
+
+        let parent = interrupt_parent(node)?;
+        if node.getprop(cstr!("interrupts"))?.is_some() {
+            parse_interrupts(node, interrupt_cells(fdt, gic, parent)?)?;
+        }
+
+        let mut regions = vec![];
+        for reg in node.reg()?.ok_or(DeviceAssignmentError::InvalidInterruptController)? {
+            let end = reg.end().or(Err(DeviceAssignmentError::InvalidInterruptController))?;
+            regions.push(reg.addr..end);
+        }
+        if regions.is_empty() {
+            return Err(DeviceAssignmentError::InvalidInterruptController);
+        }
+
+        let mut properties = vec![];
+        for prop in node.properties()? {
+            let name = prop.name()?;
+            if Self::ALLOWED_PROPS.contains(&name) {
+                properties.push((name.into(), prop.value()?.into()));
+            } else if !Self::PHANDLE_PROPS.contains(&name) {
+                error!("Unexpected property {name:?} in interrupt controller");
+                return Err(DeviceAssignmentError::InvalidInterruptController);
+            }
+        }
+        let parent = if parent == gic { None } else { Some(parent) };
+        Ok(Self { name: node.name()?.into(), properties, regions, parent })
+    }
+
+    /// Checks that the controller fits in the platform DT, i.e. that it doesn't clash with any of
+    /// the devices that the platform template describes, including the GIC.
+    fn validate_against_platform(&self, fdt: &Fdt) -> Result<()> {
+        let root = fdt.root()?;
+        for node in root.subnodes()? {
+            let Some(regs) = node.reg()? else {
+                continue;
+            };
+            for reg in regs {
+                // Skips the placeholders of the template which haven't been patched (yet).
+                let Ok(end) = reg.end() else {
+                    continue;
+                };
+                if self.regions.iter().any(|region| region.start < end && reg.addr < region.end) {
+                    return Err(DeviceAssignmentError::InvalidInterruptController);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn patch(&self, fdt: &mut Fdt, phandle: Phandle, parent: Phandle) -> Result<()> {
+        self.validate_against_platform(fdt)?;
+        let mut root = fdt.root_mut()?;
+        let mut dst = root.add_subnode(&self.name)?;
+        for (name, value) in &self.properties {
+            dst.setprop(name, value)?;
+        }
+        dst.setprop_u32(cstr!("phandle"), phandle.into())?;
+        dst.setprop_u32(cstr!("interrupt-parent"), parent.into())?;
+
+        Ok(())
+    }
+}
+
 /// Assigned device information parsed from crosvm DT.
 /// Keeps everything in the owned data because underlying FDT will be reused for platform DT.
 #[derive(Debug, Eq, PartialEq)]
@@ -226,26 +382,13 @@ struct AssignedDeviceInfo {
     reg: Vec<u8>,
     // <interrupts> property from the crosvm DT
     interrupts: Vec<u8>,
+    // Phandle of the interrupt controller in the crosvm DT, or None if it is the GIC.
+    interrupt_parent: Option<Phandle>,
     // Parsed <iommus> property from the crosvm DT. Tuple of PvIommu and vSID.
     iommus: Vec<(PvIommu, Vsid)>,
 }
 
 impl AssignedDeviceInfo {
-    fn parse_interrupts(node: &FdtNode) -> Result<Vec<u8>> {
-        // Validation: Validate if interrupts cell numbers are multiple of #interrupt-cells.
-        // We can't know how many interrupts would exist.
-        let interrupts_cells = node
-            .getprop_cells(cstr!("interrupts"))?
-            .ok_or(DeviceAssignmentError::InvalidInterrupts)?
-            .count();
-        if interrupts_cells % CELLS_PER_INTERRUPT != 0 {
-            return Err(DeviceAssignmentError::InvalidInterrupts);
-        }
-
-        // Once validated, keep the raw bytes so patch can be done with setprop()
-        Ok(node.getprop(cstr!("interrupts")).unwrap().unwrap().into())
-    }
-
     // TODO(b/277993056): Also validate /__local_fixups__ to ensure that <iommus> has phandle.
     fn parse_iommus(
         node: &FdtNode,
@@ -276,6 +419,7 @@ impl AssignedDeviceInfo {
         vm_dtbo: &VmDtbo,
         dtbo_node_path: &CStr,
         pviommus: &BTreeMap<Phandle, PvIommu>,
+        gic: Phandle,
     ) -> Result<Option<Self>> {
         let node_path = vm_dtbo.locate_overlay_target_path(dtbo_node_path)?;
 
@@ -283,28 +427,38 @@ impl AssignedDeviceInfo {
 
         // TODO(b/277993056): Validate reg with HVC, and keep reg with FdtNode::reg()
         let reg = node.getprop(cstr!("reg")).unwrap().unwrap();
-        let interrupts = Self::parse_interrupts(&node)?;
+        let interrupt_parent = interrupt_parent(&node)?;
+        let interrupts = parse_interrupts(&node, interrupt_cells(fdt, gic, interrupt_parent)?)?;
+        let interrupt_parent = if interrupt_parent == gic { None } else { Some(interrupt_parent) };
         let iommus = Self::parse_iommus(&node, pviommus)?;
         Ok(Some(Self {
             node_path,
             dtbo_node_path: dtbo_node_path.into(),
             reg: reg.to_vec(),
             interrupts,
+            interrupt_parent,
             iommus,
         }))
     }
 
-    fn patch(&self, fdt: &mut Fdt, pviommu_phandles: &BTreeMap<PvIommu, Phandle>) -> Result<()> {
+    fn patch(
+        &self,
+        fdt: &mut Fdt,
+        pviommu_phandles: &BTreeMap<PvIommu, Phandle>,
+        interrupt_controller_phandles: &BTreeMap<Phandle, Phandle>,
+    ) -> Result<()> {
         let mut dst = fdt.node_mut(&self.node_path)?.unwrap();
         dst.setprop(cstr!("reg"), &self.reg)?;
         dst.setprop(cstr!("interrupts"), &self.interrupts)?;
-        let mut iommus = Vec::with_capacity(8 * self.iommus.len());
-        for (pviommu, vsid) in &self.iommus {
-            let phandle = pviommu_phandles.get(pviommu).unwrap();
-            iommus.extend_from_slice(&u32::from(*phandle).to_be_bytes());
-            iommus.extend_from_slice(&vsid.0.to_be_bytes());
+        if let Some(interrupt_parent) = &self.interrupt_parent {
+            let phandle = interrupt_controller_phandles.get(interrupt_parent).unwrap();
+            dst.setprop_u32(cstr!("interrupt-parent"), (*phandle).into())?;
         }
-        dst.setprop(cstr!("iommus"), &iommus)?;
+        let iommus = self.iommus.iter().flat_map(|(pviommu, vsid)| {
+            let phandle = pviommu_phandles.get(pviommu).unwrap();
+            [u32::from(*phandle), vsid.0]
+        });
+        dst.setprop_cells(cstr!("iommus"), iommus)?;
 
         Ok(())
     }
@@ -313,6 +467,8 @@ impl AssignedDeviceInfo {
 #[derive(Debug, Default, Eq, PartialEq)]
 pub struct DeviceAssignmentInfo {
     pviommus: BTreeSet<PvIommu>,
+    // Interrupt controllers used by assigned devices, keyed by phandle in the crosvm DT.
+    interrupt_controllers: BTreeMap<Phandle, InterruptControllerInfo>,
     assigned_devices: Vec<AssignedDeviceInfo>,
     filtered_dtbo_paths: Vec<CString>,
 }
@@ -328,7 +484,11 @@ impl DeviceAssignmentInfo {
             let Some(phandle) = compatible.get_phandle()? else {
                 continue; // Skips unreachable pvIOMMU node
             };
-            let pviommu = PvIommu::parse(&compatible)?;
+            let pviommu = PvIommu::parse(&compatible).inspect_err(|e| {
+                let mut buf = [0; MAX_PATH_LEN];
+                let path = compatible.path(&mut buf).unwrap_or(cstr!("<unknown>"));
+                error!("Invalid pvIOMMU node {path:?}: {e}");
+            })?;
             if pviommus.insert(phandle, pviommu).is_some() {
                 return Err(FdtError::BadPhandle.into());
             }
@@ -336,6 +496,39 @@ impl DeviceAssignmentInfo {
         Ok(pviommus)
     }
 
+    /// Parses the chain of interrupt controllers from `phandle` to the GIC, skipping the ones
+    /// already parsed for other assigned devices.
+    fn parse_interrupt_controllers(
+        fdt: &Fdt,
+        gic: Phandle,
+        phandle: Option<Phandle>,
+        interrupt_controllers: &mut BTreeMap<Phandle, InterruptControllerInfo>,
+    ) -> Result<()> {
+        let mut chain = vec![];
+        let mut next = phandle;
+        while let Some(phandle) = next {
+            if interrupt_controllers.contains_key(&phandle) {
+                break;
+            }
+            if chain.len() == MAX_INTERRUPT_CONTROLLER_DEPTH {
+                return Err(DeviceAssignmentError::InvalidInterruptController);
+            }
+            let node = fdt
+                .node_with_phandle(phandle)?
+                .ok_or(DeviceAssignmentError::InvalidInterruptController)?;
+            let controller = InterruptControllerInfo::parse(fdt, gic, &node).inspect_err(|e| {
+                let mut buf = [0; MAX_PATH_LEN];
+                let path = node.path(&mut buf).unwrap_or(cstr!("<unknown>"));
+                error!("Invalid interrupt controller node {path:?}: {e}");
+            })?;
+            next = controller.parent;
+            chain.push((phandle, controller));
+        }
+        // Only keeps controllers once the whole chain is known to lead to the GIC.
+        interrupt_controllers.extend(chain);
+        Ok(())
+    }
+
     /// Parses fdt and vm_dtbo, and creates new DeviceAssignmentInfo
     // TODO(b/277993056): Parse __local_fixups__
     // TODO(b/277993056): Parse __fixups__
@@ -352,6 +545,8 @@ impl DeviceAssignmentInfo {
             return Err(DeviceAssignmentError::DuplicatedPvIommuIds);
         }
 
+        let gic = interrupt_parent(&fdt.root()?)?;
+        let mut interrupt_controllers = BTreeMap::new();
         let mut assigned_devices = vec![];
         let mut filtered_dtbo_paths = vec![];
         for symbol_prop in symbols_node.properties()? {
@@ -359,8 +554,14 @@ impl DeviceAssignmentInfo {
             let dtbo_node_path = CStr::from_bytes_with_nul(symbol_prop_value)
                 .or(Err(DeviceAssignmentError::InvalidSymbols))?;
             let assigned_device =
-                AssignedDeviceInfo::parse(fdt, vm_dtbo, dtbo_node_path, &pviommus)?;
+                AssignedDeviceInfo::parse(fdt, vm_dtbo, dtbo_node_path, &pviommus, gic)?;
             if let Some(assigned_device) = assigned_device {
+                Self::parse_interrupt_controllers(
+                    fdt,
+                    gic,
+                    assigned_device.interrupt_parent,
+                    &mut interrupt_controllers,
+                )?;
                 assigned_devices.push(assigned_device);
             } else {
                 filtered_dtbo_paths.push(dtbo_node_path.into());
@@ -371,7 +572,12 @@ impl DeviceAssignmentInfo {
         }
         filtered_dtbo_paths.push(CString::new("/__symbols__").unwrap());
 
-        Ok(Some(Self { pviommus: unique_pviommus, assigned_devices, filtered_dtbo_paths }))
+        Ok(Some(Self {
+            pviommus: unique_pviommus,
+            interrupt_controllers,
+            assigned_devices,
+            filtered_dtbo_paths,
+        }))
     }
 
     /// Filters VM DTBO to only contain necessary information for booting pVM
@@ -431,12 +637,36 @@ impl DeviceAssignmentInfo {
         Ok(pviommu_phandles)
     }
 
+    /// Adds the interrupt controllers to the platform DT, with new phandles.
+    fn patch_interrupt_controllers(&self, fdt: &mut Fdt) -> Result<BTreeMap<Phandle, Phandle>> {
+        let mut phandles = BTreeMap::new();
+        if self.interrupt_controllers.is_empty() {
+            return Ok(phandles);
+        }
+
+        let gic = interrupt_parent(&fdt.root()?)?;
+        let mut phandle = u32::from(fdt.max_phandle()?);
+        for crosvm_phandle in self.interrupt_controllers.keys() {
+            phandle = phandle.checked_add(1).ok_or(FdtError::BadPhandle)?;
+            phandles.insert(*crosvm_phandle, Phandle::try_from(phandle)?);
+        }
+
+        for (crosvm_phandle, controller) in &self.interrupt_controllers {
+            let phandle = *phandles.get(crosvm_phandle).unwrap();
+            let parent = controller.parent.map_or(gic, |parent| *phandles.get(&parent).unwrap());
+            controller.patch(fdt, phandle, parent)?;
+        }
+
+        Ok(phandles)
+    }
+
     pub fn patch(&self, fdt: &mut Fdt) -> Result<()> {
         let pviommu_phandles = self.patch_pviommus(fdt)?;
+        let interrupt_controller_phandles = self.patch_interrupt_controllers(fdt)?;
 
         // Patches assigned devices
         for device in &self.assigned_devices {
-            device.patch(fdt, &pviommu_phandles)?;
+            device.patch(fdt, &pviommu_phandles, &interrupt_controller_phandles)?;
         }
 
         Ok(())
@@ -458,6 +688,11 @@ mod tests {
         "test_pvmfw_devices_with_multiple_devices_iommus.dtb";
     const FDT_WITH_IOMMU_SHARING: &str = "test_pvmfw_devices_with_iommu_sharing.dtb";
     const FDT_WITH_IOMMU_ID_CONFLICT: &str = "test_pvmfw_devices_with_iommu_id_conflict.dtb";
+    const FDT_WITH_INTERRUPT_CONTROLLER: &str = "test_pvmfw_devices_with_interrupt_controller.dtb";
+    const FDT_WITH_INVALID_INTERRUPT_CONTROLLER: &str =
+        "test_pvmfw_devices_with_invalid_interrupt_controller.dtb";
+    const FDT_WITH_INTERRUPT_CONTROLLER_OVERLAPPING_GIC: &str =
+        "test_pvmfw_devices_with_interrupt_controller_overlapping_gic.dtb";
 
     #[derive(Debug, Eq, PartialEq)]
     struct AssignedDeviceNode {
@@ -549,6 +784,7 @@ mod tests {
             dtbo_node_path: cstr!("/fragment@backlight/__overlay__/backlight").into(),
             reg: into_fdt_prop(vec![0x0, 0x9, 0x0, 0xFF]),
             interrupts: into_fdt_prop(vec![0x0, 0xF, 0x4]),
+            interrupt_parent: None,
             iommus: vec![],
         }];
 
@@ -569,6 +805,7 @@ mod tests {
             dtbo_node_path: cstr!("/fragment@rng/__overlay__/rng").into(),
             reg: into_fdt_prop(vec![0x0, 0x9, 0x0, 0xFF]),
             interrupts: into_fdt_prop(vec![0x0, 0xF, 0x4]),
+            interrupt_parent: None,
             iommus: vec![(PvIommu { id: 0x4 }, Vsid(0xFF0))],
         }];
 
@@ -790,4 +1027,80 @@ mod tests {
 
         assert_eq!(device_info, Err(DeviceAssignmentError::DuplicatedPvIommuIds));
     }
+
+    #[test]
+    fn device_info_shared_interrupt_controller() {
+        let mut fdt_data = fs::read(FDT_WITH_INTERRUPT_CONTROLLER).unwrap();
+        let mut vm_dtbo_data = fs::read(VM_DTBO_FILE_PATH).unwrap();
+        let fdt = Fdt::from_mut_slice(&mut fdt_data).unwrap();
+        let vm_dtbo = VmDtbo::from_mut_slice(&mut vm_dtbo_data).unwrap();
+        let mut platform_dt_data = pvmfw_fdt_template::RAW.to_vec();
+        platform_dt_data.resize(pvmfw_fdt_template::RAW.len() * 2, 0);
+        let platform_dt = Fdt::from_mut_slice(&mut platform_dt_data).unwrap();
+        platform_dt.unpack().unwrap();
+
+        let device_info = DeviceAssignmentInfo::parse(fdt, vm_dtbo).unwrap().unwrap();
+        assert_eq!(device_info.interrupt_controllers.len(), 1);
+        device_info.filter(vm_dtbo).unwrap();
+
+        // SAFETY: Damaged VM DTBO wouldn't be used after this unsafe block.
+        unsafe {
+            platform_dt.apply_overlay(vm_dtbo.as_mut()).unwrap();
+        }
+        device_info.patch(platform_dt).unwrap();
+
+        let intc = platform_dt.node(cstr!("/intc_sec@80000000")).unwrap().unwrap();
+        let intc_phandle = intc.get_phandle().unwrap().unwrap();
+        let gic_phandle = platform_dt.root().unwrap().getprop_u32(cstr!("interrupt-parent"));
+        assert_eq!(intc.getprop_u32(cstr!("interrupt-parent")), gic_phandle);
+        assert_eq!(intc.getprop_u32(cstr!("#interrupt-cells")), Ok(Some(2)));
+        assert_eq!(
+            intc.getprop(cstr!("interrupts")),
+            Ok(Some(into_fdt_prop(vec![0x0, 0x10, 0x4]).as_slice()))
+        );
+
+        for (path, interrupts) in [("/rng", vec![0x7, 0x4]), ("/led", vec![0x8, 0x1])] {
+            let path = CString::new(path).unwrap();
+            let node = platform_dt.node(&path).unwrap().unwrap();
+            let interrupt_parent = node.getprop_u32(cstr!("interrupt-parent"));
+            assert_eq!(interrupt_parent, Ok(Some(intc_phandle.into())));
+            let expected = into_fdt_prop(interrupts);
+            assert_eq!(node.getprop(cstr!("interrupts")), Ok(Some(expected.as_slice())));
+        }
+    }
+
+    #[test]
+    fn device_info_interrupt_controller_with_unexpected_property() {
+        let mut fdt_data = fs::read(FDT_WITH_INVALID_INTERRUPT_CONTROLLER).unwrap();
+        let mut vm_dtbo_data = fs::read(VM_DTBO_FILE_PATH).unwrap();
+        let fdt = Fdt::from_mut_slice(&mut fdt_data).unwrap();
+        let vm_dtbo = VmDtbo::from_mut_slice(&mut vm_dtbo_data).unwrap();
+
+        let device_info = DeviceAssignmentInfo::parse(fdt, vm_dtbo);
+
+        assert_eq!(device_info, Err(DeviceAssignmentError::InvalidInterruptController));
+    }
+
+    #[test]
+    fn device_info_interrupt_controller_overlapping_gic() {
+        let mut fdt_data = fs::read(FDT_WITH_INTERRUPT_CONTROLLER_OVERLAPPING_GIC).unwrap();
+        let mut vm_dtbo_data = fs::read(VM_DTBO_FILE_PATH).unwrap();
+        let fdt = Fdt::from_mut_slice(&mut fdt_data).unwrap();
+        let vm_dtbo = VmDtbo::from_mut_slice(&mut vm_dtbo_data).unwrap();
+        let mut platform_dt_data = pvmfw_fdt_template::RAW.to_vec();
+        platform_dt_data.resize(pvmfw_fdt_template::RAW.len() * 2, 0);
+        let platform_dt = Fdt::from_mut_slice(&mut platform_dt_data).unwrap();
+        platform_dt.unpack().unwrap();
+
+        let device_info = DeviceAssignmentInfo::parse(fdt, vm_dtbo).unwrap().unwrap();
+        device_info.filter(vm_dtbo).unwrap();
+
+        // SAFETY: Damaged VM DTBO wouldn't be used after this unsafe block.
+        unsafe {
+            platform_dt.apply_overlay(vm_dtbo.as_mut()).unwrap();
+        }
+        let result = device_info.patch(platform_dt);
+
+        assert_eq!(result, Err(DeviceAssignmentError::InvalidInterruptController));
+    }
 }
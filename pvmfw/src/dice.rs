@@ -14,14 +14,25 @@
 
 //! Support for DICE derivation and BCC generation.
 
+#[cfg(test)]
+extern crate alloc;
+
+use alloc::vec::Vec;
+use ciborium::Value;
+use core::fmt;
 use core::mem::size_of;
 use cstr::cstr;
 use diced_open_dice::{
-    bcc_format_config_descriptor, bcc_handover_main_flow, hash, Config, DiceConfigValues, DiceMode,
-    Hash, InputValues, HIDDEN_SIZE,
+    bcc_format_config_descriptor, bcc_handover_main_flow, hash, Config, DiceConfigValues,
+    DiceError, DiceMode, Hash, InputValues, HIDDEN_SIZE,
 };
+use hexfmt::Hex;
 use pvmfw_avb::{Capability, DebugLevel, Digest, VerifiedBootData};
 
+/// Key of the measurement of the DT in the configuration descriptor, outside of the range used by
+/// the Android Profile for DICE.
+const DT_HASH_KEY: i64 = -71100;
+
 fn to_dice_mode(debug_level: DebugLevel) -> DiceMode {
     match debug_level {
         DebugLevel::None => DiceMode::kDiceModeNormal,
@@ -44,10 +55,25 @@ pub struct PartialInputs {
     pub mode: DiceMode,
     pub security_version: u64,
     pub rkp_vm_marker: bool,
+    /// Measurement of the DT handed over to the guest, as recorded in the config descriptor.
+    pub dt_hash: Option<Hash>,
+}
+
+impl fmt::Debug for PartialInputs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PartialInputs")
+            .field("code_hash", &Hex(&self.code_hash))
+            .field("auth_hash", &Hex(&self.auth_hash))
+            .field("mode", &self.mode)
+            .field("security_version", &self.security_version)
+            .field("rkp_vm_marker", &self.rkp_vm_marker)
+            .field("dt_hash", &self.dt_hash.as_ref().map(|h| Hex(h)))
+            .finish()
+    }
 }
 
 impl PartialInputs {
-    pub fn new(data: &VerifiedBootData) -> diced_open_dice::Result<Self> {
+    pub fn new(data: &VerifiedBootData, dt_hash: Option<Hash>) -> diced_open_dice::Result<Self> {
         let code_hash = to_dice_hash(data)?;
         let auth_hash = hash(data.public_key)?;
         let mode = to_dice_mode(data.debug_level);
@@ -55,7 +81,7 @@ impl PartialInputs {
         let security_version = data.rollback_index;
         let rkp_vm_marker = data.has_capability(Capability::RemoteAttest);
 
-        Ok(Self { code_hash, auth_hash, mode, security_version, rkp_vm_marker })
+        Ok(Self { code_hash, auth_hash, mode, security_version, rkp_vm_marker, dt_hash })
     }
 
     pub fn write_next_bcc(
@@ -64,7 +90,7 @@ impl PartialInputs {
         salt: &[u8; HIDDEN_SIZE],
         next_bcc: &mut [u8],
     ) -> diced_open_dice::Result<()> {
-        let mut config_descriptor_buffer = [0; 128];
+        let mut config_descriptor_buffer = [0; 256];
         let config = self.generate_config_descriptor(&mut config_descriptor_buffer)?;
 
         let dice_inputs = InputValues::new(
@@ -88,13 +114,36 @@ impl PartialInputs {
             rkp_vm_marker: self.rkp_vm_marker,
             ..Default::default()
         };
-        let config_descriptor_size =
+        let mut config_descriptor_size =
             bcc_format_config_descriptor(&config_values, config_descriptor_buffer)?;
+        if let Some(dt_hash) = &self.dt_hash {
+            config_descriptor_size =
+                add_dt_hash(config_descriptor_buffer, config_descriptor_size, dt_hash)?;
+        }
         let config = &config_descriptor_buffer[..config_descriptor_size];
         Ok(config)
     }
 }
 
+/// Adds `dt_hash` to the config descriptor of `size` bytes at the start of `buffer` and returns
+/// the new size of the descriptor.
+///
+/// The descriptor is formatted by open-dice, which doesn't support extra fields.
+fn add_dt_hash(buffer: &mut [u8], size: usize, dt_hash: &Hash) -> diced_open_dice::Result<usize> {
+    let mut config = &buffer[..size];
+    let Ok(Value::Map(mut entries)) = ciborium::de::from_reader::<Value, _>(&mut config) else {
+        return Err(DiceError::InvalidInput);
+    };
+    entries.push((DT_HASH_KEY.into(), Value::Bytes(dt_hash.to_vec())));
+
+    let mut encoded = Vec::new();
+    ciborium::ser::into_writer(&Value::Map(entries), &mut encoded)
+        .map_err(|_| DiceError::PlatformError)?;
+    let dest = buffer.get_mut(..encoded.len()).ok_or(DiceError::BufferTooSmall(encoded.len()))?;
+    dest.copy_from_slice(&encoded);
+    Ok(encoded.len())
+}
+
 /// Flushes data caches over the provided address range.
 ///
 /// # Safety
@@ -121,7 +170,6 @@ unsafe extern "C" fn DiceClearMemory(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use ciborium::Value;
     use std::collections::HashMap;
     use std::vec;
 
@@ -138,12 +186,13 @@ mod tests {
         public_key: b"public key",
         capabilities: vec![],
         rollback_index: 42,
+        kernel_cmdline: None,
     };
 
     #[test]
     fn base_data_conversion() {
         let vb_data = BASE_VB_DATA;
-        let inputs = PartialInputs::new(&vb_data).unwrap();
+        let inputs = PartialInputs::new(&vb_data, None).unwrap();
 
         assert_eq!(inputs.mode, DiceMode::kDiceModeNormal);
         assert_eq!(inputs.security_version, 42);
@@ -155,7 +204,7 @@ mod tests {
     #[test]
     fn debuggable_conversion() {
         let vb_data = VerifiedBootData { debug_level: DebugLevel::Full, ..BASE_VB_DATA };
-        let inputs = PartialInputs::new(&vb_data).unwrap();
+        let inputs = PartialInputs::new(&vb_data, None).unwrap();
 
         assert_eq!(inputs.mode, DiceMode::kDiceModeDebug);
     }
@@ -164,7 +213,7 @@ mod tests {
     fn rkp_vm_conversion() {
         let vb_data =
             VerifiedBootData { capabilities: vec![Capability::RemoteAttest], ..BASE_VB_DATA };
-        let inputs = PartialInputs::new(&vb_data).unwrap();
+        let inputs = PartialInputs::new(&vb_data, None).unwrap();
 
         assert!(inputs.rkp_vm_marker);
     }
@@ -172,7 +221,7 @@ mod tests {
     #[test]
     fn base_config_descriptor() {
         let vb_data = BASE_VB_DATA;
-        let inputs = PartialInputs::new(&vb_data).unwrap();
+        let inputs = PartialInputs::new(&vb_data, None).unwrap();
         let config_map = decode_config_descriptor(&inputs);
 
         assert_eq!(config_map.get(&COMPONENT_NAME_KEY).unwrap().as_text().unwrap(), "vm_entry");
@@ -187,20 +236,31 @@ mod tests {
             assert_eq!(config_map.get(&SECURITY_VERSION_KEY), None);
         }
         assert_eq!(config_map.get(&RKP_VM_MARKER_KEY), None);
+        assert_eq!(config_map.get(&DT_HASH_KEY), None);
     }
 
     #[test]
     fn config_descriptor_with_rkp_vm() {
         let vb_data =
             VerifiedBootData { capabilities: vec![Capability::RemoteAttest], ..BASE_VB_DATA };
-        let inputs = PartialInputs::new(&vb_data).unwrap();
+        let inputs = PartialInputs::new(&vb_data, None).unwrap();
         let config_map = decode_config_descriptor(&inputs);
 
         assert!(config_map.get(&RKP_VM_MARKER_KEY).unwrap().is_null());
     }
 
+    #[test]
+    fn config_descriptor_with_dt_hash() {
+        let dt_hash = [3u8; size_of::<Hash>()];
+        let inputs = PartialInputs::new(&BASE_VB_DATA, Some(dt_hash)).unwrap();
+        let config_map = decode_config_descriptor(&inputs);
+
+        assert_eq!(config_map.get(&COMPONENT_NAME_KEY).unwrap().as_text().unwrap(), "vm_entry");
+        assert_eq!(config_map.get(&DT_HASH_KEY).unwrap().as_bytes().unwrap(), &dt_hash);
+    }
+
     fn decode_config_descriptor(inputs: &PartialInputs) -> HashMap<i64, Value> {
-        let mut buffer = [0; 128];
+        let mut buffer = [0; 256];
         let config_descriptor = inputs.generate_config_descriptor(&mut buffer).unwrap();
 
         let cbor_map =
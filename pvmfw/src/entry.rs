@@ -16,6 +16,7 @@
 
 use crate::config;
 use crate::crypto;
+use crate::exceptions;
 use crate::fdt;
 use crate::memory;
 use core::arch::asm;
@@ -29,12 +30,15 @@ use log::error;
 use log::info;
 use log::warn;
 use log::LevelFilter;
-use vmbase::util::RangeExt as _;
+use vmbase::util::{align_up, RangeExt as _};
 use vmbase::{
     configure_heap, console,
     layout::{self, crosvm},
     main,
-    memory::{min_dcache_line_size, MemoryTracker, MEMORY, SIZE_128KB, SIZE_4KB},
+    memory::{
+        claim_memory, min_dcache_line_size, MemoryOwner, MemoryTracker, MEMORY, SIZE_128KB,
+        SIZE_4KB,
+    },
     power::reboot,
 };
 use zeroize::Zeroize;
@@ -97,11 +101,19 @@ impl<'a> MemorySlices<'a> {
             error!("Failed to allocate the FDT range: {e}");
             RebootReason::InternalError
         })?;
+        claim_memory(range.clone(), MemoryOwner::DeviceTree).map_err(|e| {
+            error!("Failed to claim the FDT range: {e}");
+            RebootReason::InternalError
+        })?;
 
         // SAFETY: The tracker validated the range to be in main memory, mapped, and not overlap.
         let fdt = unsafe { slice::from_raw_parts_mut(range.start as *mut u8, range.len()) };
 
-        let info = fdt::sanitize_device_tree(fdt, vm_dtbo)?;
+        // The VM DTBO is part of the appended payload, which isn't accessible while sanitizing.
+        let mut vm_dtbo = vm_dtbo.map(|dtbo| dtbo.to_vec());
+        let info = memory::with_reduced_privileges(|| {
+            fdt::sanitize_device_tree(fdt, vm_dtbo.as_deref_mut())
+        })??;
         let fdt = libfdt::Fdt::from_mut_slice(fdt).map_err(|e| {
             error!("Failed to load sanitized FDT: {e}");
             RebootReason::InvalidFdt
@@ -158,6 +170,11 @@ impl<'a> MemorySlices<'a> {
             return Err(RebootReason::InvalidPayload);
         };
 
+        claim_memory(kernel_range.clone(), MemoryOwner::Payload).map_err(|e| {
+            error!("Failed to claim the kernel range: {e}");
+            RebootReason::InternalError
+        })?;
+
         let kernel = kernel_range.start as *const u8;
         // SAFETY: The tracker validated the range to be in main memory, mapped, and not overlap.
         let kernel = unsafe { slice::from_raw_parts(kernel, kernel_range.len()) };
@@ -168,6 +185,10 @@ impl<'a> MemorySlices<'a> {
                 error!("Failed to obtain the initrd range: {e}");
                 RebootReason::InvalidRamdisk
             })?;
+            claim_memory(r.clone(), MemoryOwner::Payload).map_err(|e| {
+                error!("Failed to claim the initrd range: {e}");
+                RebootReason::InvalidRamdisk
+            })?;
 
             // SAFETY: The region was validated by memory to be in main memory, mapped, and
             // not overlap.
@@ -196,7 +217,10 @@ fn main_wrapper(
     // - only access non-pvmfw memory once (and while) it has been mapped
 
     log::set_max_level(LevelFilter::Info);
+    // Keep track of the calls to the hypervisor, to be reported if pvmfw panics.
+    hyp::trace::set_call_tracing(true);
     crypto::init();
+    exceptions::init();
 
     let page_table = memory::init_page_table().map_err(|e| {
         error!("Failed to set up the dynamic page tables: {e}");
@@ -231,11 +255,27 @@ fn main_wrapper(
         slices.ramdisk,
         config_entries.bcc,
         config_entries.debug_policy,
+        config_entries.service_vm_dt_digests.as_deref(),
+        config_entries.bootargs_allow_list.as_deref(),
     )?;
 
     // Writable-dirty regions will be flushed when MemoryTracker is dropped.
     config_entries.bcc.zeroize();
 
+    // The final DT is packed so hand the rest of its range over to the heap. This happens after
+    // main() as nothing secret may be allocated from there, because the heap isn't zeroed.
+    let fdt_end = align_up(fdt + slices.fdt.as_slice().len(), SIZE_4KB).unwrap();
+    let fdt_tail = fdt_end..(fdt + crosvm::FDT_MAX_SIZE);
+    if !fdt_tail.is_empty() {
+        // SAFETY: The final DT doesn't extend into fdt_tail and slices.fdt isn't used anymore.
+        let res = unsafe {
+            MEMORY.lock().as_mut().unwrap().reclaim_range(&fdt_tail, MemoryOwner::DeviceTree)
+        };
+        if let Err(e) = res {
+            warn!("Failed to reclaim the end of the DT range {fdt_tail:#x?}: {e}");
+        }
+    }
+
     info!("Expecting a bug making MMIO_GUARD_UNMAP return NOT_SUPPORTED on success");
     MEMORY.lock().as_mut().unwrap().mmio_unmap_all().map_err(|e| {
         error!("Failed to unshare MMIO ranges: {e}");
@@ -16,13 +16,29 @@
 
 use vmbase::{
     eprintln,
-    exceptions::{ArmException, Esr, HandleExceptionError},
+    exceptions::{register_fault_handler, ArmException, Esr, HandleExceptionError},
     logger,
-    memory::{handle_permission_fault, handle_translation_fault},
+    memory::{handle_permission_fault, handle_translation_fault, memory_owner_of},
     power::reboot,
     read_sysreg,
 };
 
+/// Adds the pvmfw-specific context to the reports of fatal exceptions.
+pub fn init() {
+    register_fault_handler(report_fault);
+}
+
+fn report_fault(exception: &ArmException, _elr: u64) {
+    if !exception.syndrome.is_some_and(|s| s.far_valid) {
+        return;
+    }
+    // pvmfw identity-maps main memory so the FAR is also the physical address.
+    match memory_owner_of(exception.far.0) {
+        Some(owner) => eprintln!("far belongs to the {owner} memory"),
+        None => eprintln!("far doesn't belong to any claimed memory"),
+    }
+}
+
 fn handle_exception(exception: &ArmException) -> Result<(), HandleExceptionError> {
     // Handle all translation faults on both read and write, and MMIO guard map
     // flagged invalid pages or blocks that caused the exception.
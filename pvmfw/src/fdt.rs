@@ -14,67 +14,147 @@
 
 //! High-level FDT functions.
 
-use crate::bootargs::BootArgsIterator;
+use crate::bootargs::{has_bootarg, BootArgsAllowList, BootArgsIterator};
 use crate::device_assignment::DeviceAssignmentInfo;
 use crate::device_assignment::VmDtbo;
 use crate::helpers::GUEST_PAGE_SIZE;
+use crate::swiotlb;
 use crate::Box;
 use crate::RebootReason;
 use alloc::ffi::CString;
+use alloc::format;
+use alloc::vec;
 use alloc::vec::Vec;
+use bssl_avf::{constant_time_eq, hkdf, sha256, Digester};
 use core::cmp::max;
-use core::cmp::min;
 use core::ffi::CStr;
 use core::fmt;
 use core::mem::size_of;
 use core::ops::Range;
 use cstr::cstr;
+use diced_open_dice::{hash, Hash};
 use fdtpci::PciMemoryFlags;
 use fdtpci::PciRangeType;
+use hexfmt::Hex;
 use libfdt::AddressRange;
-use libfdt::CellIterator;
 use libfdt::Fdt;
+use libfdt::FdtContextError;
 use libfdt::FdtError;
-use libfdt::FdtNode;
 use libfdt::FdtNodeMut;
+use libfdt::FdtResultExt;
+use libfdt::Reg;
+use libfdt::VisitAction;
 use log::debug;
 use log::error;
 use log::info;
+use log::log;
 use log::warn;
+use log::Level;
 use tinyvec::ArrayVec;
-use vmbase::fdt::SwiotlbInfo;
+use vmbase::fdt::{DtWorkspace, SwiotlbInfo};
 use vmbase::layout::{crosvm::MEM_START, MAX_VIRT_ADDR};
 use vmbase::memory::SIZE_4KB;
 use vmbase::util::flatten;
-use vmbase::util::RangeExt as _;
+
+/// Maximum size of the version of pvmfw handed over to the guest.
+const PVMFW_VERSION_MAX_LEN: usize = 64;
 
 /// An enumeration of errors that can occur during the FDT validation.
 #[derive(Clone, Debug)]
 pub enum FdtValidationError {
     /// Invalid CPU count.
     InvalidCpuCount(usize),
+    /// Unsupported CPU enable-method for the n-th CPU.
+    InvalidCpuEnableMethod(usize),
+    /// Missing or invalid /psci node.
+    InvalidPsciNode,
 }
 
 impl fmt::Display for FdtValidationError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::InvalidCpuCount(num_cpus) => write!(f, "Invalid CPU count: {num_cpus}"),
+            Self::InvalidCpuEnableMethod(cpu) => write!(f, "Invalid enable-method for CPU #{cpu}"),
+            Self::InvalidPsciNode => write!(f, "Missing or invalid /psci node"),
+        }
+    }
+}
+
+/// Non-fatal issues found by pvmfw, reported to the guest as the `avf,pvmfw-warnings` <stringlist>
+/// of /chosen so that they can be logged where developers look, rather than only on the UART.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PvmfwWarning {
+    /// The DT has more than one memory range; only the first one is used.
+    ExtraMemoryRanges,
+    /// A debug policy was passed although the BCC isn't in debug mode.
+    DebugPolicyIgnored,
+    /// The debug policy couldn't be applied.
+    DebugPolicyInvalid,
+    /// Some bootargs were dropped, as they aren't allowed for a non-debuggable payload.
+    BootargsFiltered,
+    /// Previous boot attempts of the instance failed repeatedly, as reported by the host.
+    RepeatedBootFailures,
+    /// Some nodes of the DT were dropped, as the sanitized DT has no counterpart for them.
+    NodesDropped,
+    /// Some properties of /chosen were dropped, as the sanitized DT has no counterpart for them.
+    PropertiesDropped,
+    /// The DT has more serial devices than supported; only the first ones are kept.
+    SerialsDropped,
+}
+
+impl PvmfwWarning {
+    fn name(&self) -> &'static CStr {
+        match self {
+            Self::ExtraMemoryRanges => cstr!("extra-memory-ranges"),
+            Self::DebugPolicyIgnored => cstr!("debug-policy-ignored"),
+            Self::DebugPolicyInvalid => cstr!("debug-policy-invalid"),
+            Self::BootargsFiltered => cstr!("bootargs-filtered"),
+            Self::RepeatedBootFailures => cstr!("repeated-boot-failures"),
+            Self::NodesDropped => cstr!("nodes-dropped"),
+            Self::PropertiesDropped => cstr!("properties-dropped"),
+            Self::SerialsDropped => cstr!("serials-dropped"),
         }
     }
 }
 
+/// Adds `warning` to the /chosen/avf,pvmfw-warnings <stringlist>, unless it is already there.
+fn add_warning(fdt: &mut Fdt, warning: PvmfwWarning) -> libfdt::Result<()> {
+    let prop_name = cstr!("avf,pvmfw-warnings");
+    let name = warning.name();
+    let chosen = fdt.chosen()?.ok_or(FdtError::NotFound)?;
+    let mut value = Vec::new();
+    for w in chosen.getprop_str_list(prop_name)?.into_iter().flatten() {
+        if w == name {
+            return Ok(());
+        }
+        value.extend_from_slice(w.to_bytes_with_nul());
+    }
+    value.extend_from_slice(name.to_bytes_with_nul());
+    fdt.chosen_mut()?.ok_or(FdtError::NotFound)?.setprop(prop_name, &value)
+}
+
+/// Logs `e` with the node and property at fault, which liblibfdt only displays when built with
+/// its `error_context` feature.
+fn log_context_error(msg: &str, e: &FdtContextError) {
+    match e.context() {
+        Some(context) => error!("{msg}: {} ({context})", e.error()),
+        None => error!("{msg}: {}", e.error()),
+    }
+}
+
 /// Extract from /config the address range containing the pre-loaded kernel. Absence of /config is
 /// not an error.
-fn read_kernel_range_from(fdt: &Fdt) -> libfdt::Result<Option<Range<usize>>> {
+fn read_kernel_range_from(fdt: &Fdt) -> Result<Option<Range<usize>>, FdtContextError> {
     let addr = cstr!("kernel-address");
     let size = cstr!("kernel-size");
 
     if let Some(config) = fdt.node(cstr!("/config"))? {
-        if let (Some(addr), Some(size)) = (config.getprop_u32(addr)?, config.getprop_u32(size)?) {
-            let addr = addr as usize;
-            let size = size as usize;
-
-            return Ok(Some(addr..(addr + size)));
+        let (addr, size) = (
+            config.getprop_u32(addr).property_context(&config, addr)?,
+            config.getprop_u32(size).property_context(&config, size)?,
+        );
+        if let (Some(addr), Some(size)) = (addr, size) {
+            return Ok(Some(Reg { addr, size: Some(size) }.try_into_range(usize::MAX)?));
         }
     }
 
@@ -83,13 +163,18 @@ fn read_kernel_range_from(fdt: &Fdt) -> libfdt::Result<Option<Range<usize>>> {
 
 /// Extract from /chosen the address range containing the pre-loaded ramdisk. Absence is not an
 /// error as there can be initrd-less VM.
-fn read_initrd_range_from(fdt: &Fdt) -> libfdt::Result<Option<Range<usize>>> {
+fn read_initrd_range_from(fdt: &Fdt) -> Result<Option<Range<usize>>, FdtContextError> {
     let start = cstr!("linux,initrd-start");
     let end = cstr!("linux,initrd-end");
 
     if let Some(chosen) = fdt.chosen()? {
-        if let (Some(start), Some(end)) = (chosen.getprop_u32(start)?, chosen.getprop_u32(end)?) {
-            return Ok(Some((start as usize)..(end as usize)));
+        let (start, end) = (
+            chosen.getprop_u32(start).property_context(&chosen, start)?,
+            chosen.getprop_u32(end).property_context(&chosen, end)?,
+        );
+        if let (Some(start), Some(end)) = (start, end) {
+            let size = end.checked_sub(start).ok_or(FdtError::BadValue)?;
+            return Ok(Some(Reg { addr: start, size: Some(size) }.try_into_range(usize::MAX)?));
         }
     }
 
@@ -101,8 +186,8 @@ fn patch_initrd_range(fdt: &mut Fdt, initrd_range: &Range<usize>) -> libfdt::Res
     let end = u32::try_from(initrd_range.end).unwrap();
 
     let mut node = fdt.chosen_mut()?.ok_or(FdtError::NotFound)?;
-    node.setprop(cstr!("linux,initrd-start"), &start.to_be_bytes())?;
-    node.setprop(cstr!("linux,initrd-end"), &end.to_be_bytes())?;
+    node.setprop_u32(cstr!("linux,initrd-start"), start)?;
+    node.setprop_u32(cstr!("linux,initrd-end"), end)?;
     Ok(())
 }
 
@@ -123,13 +208,16 @@ fn patch_bootargs(fdt: &mut Fdt, bootargs: &CStr) -> libfdt::Result<()> {
     // This function is called before the verification is done. So, we just copy the bootargs to
     // the new FDT unmodified. This will be filtered again in the modify_for_next_stage function
     // if the VM is not debuggable.
-    node.setprop(cstr!("bootargs"), bootargs.to_bytes_with_nul())
+    node.setprop_str(cstr!("bootargs"), bootargs)
 }
 
 /// Reads and validates the memory range in the DT.
 ///
 /// Only one memory range is expected with the crosvm setup for now.
-fn read_and_validate_memory_range(fdt: &Fdt) -> Result<Range<usize>, RebootReason> {
+fn read_and_validate_memory_range(
+    fdt: &Fdt,
+    warnings: &mut Vec<PvmfwWarning>,
+) -> Result<Range<usize>, RebootReason> {
     let mut memory = fdt.memory().map_err(|e| {
         error!("Failed to read memory range from DT: {e}");
         RebootReason::InvalidFdt
@@ -143,6 +231,7 @@ fn read_and_validate_memory_range(fdt: &Fdt) -> Result<Range<usize>, RebootReaso
             "The /memory node in the DT contains more than one memory range, \
              while only one is expected."
         );
+        warnings.push(PvmfwWarning::ExtraMemoryRanges);
     }
     let base = range.start;
     if base != MEM_START {
@@ -172,7 +261,7 @@ fn patch_memory_range(fdt: &mut Fdt, memory_range: &Range<usize>) -> libfdt::Res
 
 /// Read the number of CPUs from DT
 fn read_num_cpus_from(fdt: &Fdt) -> libfdt::Result<usize> {
-    Ok(fdt.compatible_nodes(cstr!("arm,arm-v8"))?.count())
+    Ok(fdt.nodes_with_device_type(cstr!("cpu")).count())
 }
 
 /// Validate number of CPUs
@@ -184,23 +273,100 @@ fn validate_num_cpus(num_cpus: usize) -> Result<(), FdtValidationError> {
     }
 }
 
-/// Patch DT by keeping `num_cpus` number of arm,arm-v8 compatible nodes, and pruning the rest.
+/// Patch DT by keeping `num_cpus` number of CPU nodes, and pruning the rest.
 fn patch_num_cpus(fdt: &mut Fdt, num_cpus: usize) -> libfdt::Result<()> {
-    let cpu = cstr!("arm,arm-v8");
-    let mut next = fdt.root_mut()?.next_compatible(cpu)?;
-    for _ in 0..num_cpus {
-        next = if let Some(current) = next {
-            current.next_compatible(cpu)?
+    let mut kept = 0;
+    fdt.for_each_device_type_mut(cstr!("cpu"), |_| {
+        if kept < num_cpus {
+            kept += 1;
+            Ok(VisitAction::Keep)
         } else {
-            return Err(FdtError::NoSpace);
-        };
+            Ok(VisitAction::Delete)
+        }
+    })?;
+    if kept < num_cpus {
+        return Err(FdtError::NoSpace);
+    }
+    Ok(())
+}
+
+/// Conduit used to issue PSCI calls, as advertised by the /psci node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PsciMethod {
+    Hvc,
+    Smc,
+}
+
+impl PsciMethod {
+    fn from_cstr(method: &CStr) -> Option<Self> {
+        match method.to_bytes() {
+            b"hvc" => Some(Self::Hvc),
+            b"smc" => Some(Self::Smc),
+            _ => None,
+        }
+    }
+
+    fn as_cstr(&self) -> &'static CStr {
+        match self {
+            Self::Hvc => cstr!("hvc"),
+            Self::Smc => cstr!("smc"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PsciInfo {
+    method: Option<PsciMethod>,
+    /// Index of the first CPU whose enable-method isn't "psci", if any.
+    non_psci_cpu: Option<usize>,
+}
+
+/// Read the PSCI conduit from /psci and the enable-method of each CPU node.
+fn read_psci_info_from(fdt: &Fdt) -> libfdt::Result<PsciInfo> {
+    let method = if let Some(psci) = fdt.node(cstr!("/psci"))? {
+        match psci.getprop_str(cstr!("method"))? {
+            Some(method) => Some(PsciMethod::from_cstr(method).ok_or(FdtError::BadValue)?),
+            None => None,
+        }
+    } else {
+        None
+    };
+
+    let mut non_psci_cpu = None;
+    for (i, cpu) in fdt.nodes_with_device_type(cstr!("cpu")).enumerate() {
+        if cpu.getprop_str(cstr!("enable-method"))? != Some(cstr!("psci")) {
+            non_psci_cpu = Some(i);
+            break;
+        }
     }
-    while let Some(current) = next {
-        next = current.delete_and_next_compatible(cpu)?;
+
+    Ok(PsciInfo { method, non_psci_cpu })
+}
+
+/// Validate that the VMM boots CPUs through PSCI over a conduit supported by the guest.
+fn validate_psci_info(psci_info: &PsciInfo) -> Result<(), FdtValidationError> {
+    if psci_info.method.is_none() {
+        return Err(FdtValidationError::InvalidPsciNode);
+    }
+    if let Some(cpu) = psci_info.non_psci_cpu {
+        return Err(FdtValidationError::InvalidCpuEnableMethod(cpu));
     }
     Ok(())
 }
 
+/// Patch the /psci conduit and re-emit the enable-method of the remaining CPU nodes.
+fn patch_psci_info(fdt: &mut Fdt, psci_info: &PsciInfo) -> libfdt::Result<()> {
+    // `validate_psci_info()` checked that this wouldn't panic
+    let method = psci_info.method.unwrap();
+    let mut psci = fdt.node_mut(cstr!("/psci"))?.ok_or(FdtError::NotFound)?;
+    psci.setprop_str(cstr!("method"), method.as_cstr())?;
+
+    fdt.for_each_device_type_mut(cstr!("cpu"), |mut cpu| {
+        cpu.setprop_str(cstr!("enable-method"), cstr!("psci"))?;
+        Ok(VisitAction::Keep)
+    })
+}
+
 fn read_vendor_public_key_from(fdt: &Fdt) -> libfdt::Result<Option<Vec<u8>>> {
     if let Some(avf_node) = fdt.node(cstr!("/avf"))? {
         if let Some(vendor_public_key) = avf_node.getprop(cstr!("vendor_public_key"))? {
@@ -210,6 +376,61 @@ fn read_vendor_public_key_from(fdt: &Fdt) -> libfdt::Result<Option<Vec<u8>>> {
     Ok(None)
 }
 
+/// Top-level nodes which pvmfw reads from the DT although the template has no counterpart for them.
+const CONSUMED_NODES: [&CStr; 2] = [cstr!("config"), cstr!("avf")];
+
+/// Properties of /chosen which pvmfw carries over although the template has no counterpart for them.
+const CONSUMED_CHOSEN_PROPS: [&CStr; 3] =
+    [cstr!("bootargs"), cstr!("linux,initrd-start"), cstr!("linux,initrd-end")];
+
+/// Returns the name of `name` without its unit address, e.g. "uart" for "uart@3f8".
+fn node_base_name(name: &CStr) -> &[u8] {
+    let name = name.to_bytes();
+    name.split(|c| *c == b'@').next().unwrap_or(name)
+}
+
+/// Logs the top-level nodes and the properties of /chosen which sanitization drops, as the
+/// template DT has no counterpart for them and pvmfw doesn't carry them over, and reports them to
+/// the guest through `warnings`. Serial devices are matched by address instead, so aren't checked.
+fn read_dropped_content_from(fdt: &Fdt, warnings: &mut Vec<PvmfwWarning>) -> libfdt::Result<()> {
+    let template = Fdt::from_slice(pvmfw_fdt_template::RAW)?;
+    let template_root = template.root()?;
+    let root = fdt.root()?;
+    let serial = cstr!("ns16550a");
+
+    for node in root.subnodes()? {
+        let name = node.name()?;
+        if CONSUMED_NODES.contains(&name) || node.contains_compatible(serial)? {
+            continue;
+        }
+        let mut in_template = false;
+        for template_node in template_root.subnodes()? {
+            in_template |= node_base_name(template_node.name()?) == node_base_name(name);
+        }
+        if !in_template {
+            warn!("Dropping node /{name:?} which the sanitized DT doesn't support");
+            if !warnings.contains(&PvmfwWarning::NodesDropped) {
+                warnings.push(PvmfwWarning::NodesDropped);
+            }
+        }
+    }
+
+    let (Some(chosen), Some(template_chosen)) = (fdt.chosen()?, template.chosen()?) else {
+        return Ok(());
+    };
+    for prop in chosen.properties()? {
+        let name = prop.name()?;
+        if CONSUMED_CHOSEN_PROPS.contains(&name) || template_chosen.getprop(name)?.is_some() {
+            continue;
+        }
+        warn!("Dropping property /chosen/{name:?} which the sanitized DT doesn't support");
+        if !warnings.contains(&PvmfwWarning::PropertiesDropped) {
+            warnings.push(PvmfwWarning::PropertiesDropped);
+        }
+    }
+    Ok(())
+}
+
 fn patch_vendor_public_key(fdt: &mut Fdt, vendor_public_key: &[u8]) -> libfdt::Result<()> {
     let mut root_node = fdt.root_mut()?;
     let mut avf_node = root_node.add_subnode(cstr!("/avf"))?;
@@ -234,39 +455,17 @@ type PciAddrRange = AddressRange<(u32, u64), u64, u64>;
 type PciIrqMask = [u32; PciInfo::IRQ_MASK_CELLS];
 type PciIrqMap = [u32; PciInfo::IRQ_MAP_CELLS];
 
-/// Iterator that takes N cells as a chunk
-struct CellChunkIterator<'a, const N: usize> {
-    cells: CellIterator<'a>,
-}
-
-impl<'a, const N: usize> CellChunkIterator<'a, N> {
-    fn new(cells: CellIterator<'a>) -> Self {
-        Self { cells }
-    }
-}
-
-impl<'a, const N: usize> Iterator for CellChunkIterator<'a, N> {
-    type Item = [u32; N];
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut ret: Self::Item = [0; N];
-        for i in ret.iter_mut() {
-            *i = self.cells.next()?;
-        }
-        Some(ret)
-    }
-}
-
 /// Read pci host controller ranges, irq maps, and irq map masks from DT
 fn read_pci_info_from(fdt: &Fdt) -> libfdt::Result<PciInfo> {
-    let node =
-        fdt.compatible_nodes(cstr!("pci-host-cam-generic"))?.next().ok_or(FdtError::NotFound)?;
+    let node = fdt.nodes_with_device_type(cstr!("pci")).next().ok_or(FdtError::NotFound)?;
 
     let mut ranges = node.ranges::<(u32, u64), u64, u64>()?.ok_or(FdtError::NotFound)?;
     let range0 = ranges.next().ok_or(FdtError::NotFound)?;
     let range1 = ranges.next().ok_or(FdtError::NotFound)?;
 
-    let irq_masks = node.getprop_cells(cstr!("interrupt-map-mask"))?.ok_or(FdtError::NotFound)?;
-    let mut chunks = CellChunkIterator::<{ PciInfo::IRQ_MASK_CELLS }>::new(irq_masks);
+    let mut chunks = node
+        .getprop_cell_chunks::<{ PciInfo::IRQ_MASK_CELLS }>(cstr!("interrupt-map-mask"))?
+        .ok_or(FdtError::NotFound)?;
     let irq_masks = (&mut chunks).take(PciInfo::MAX_IRQS).collect();
 
     if chunks.next().is_some() {
@@ -274,8 +473,9 @@ fn read_pci_info_from(fdt: &Fdt) -> libfdt::Result<PciInfo> {
         return Err(FdtError::NoSpace);
     }
 
-    let irq_maps = node.getprop_cells(cstr!("interrupt-map"))?.ok_or(FdtError::NotFound)?;
-    let mut chunks = CellChunkIterator::<{ PciInfo::IRQ_MAP_CELLS }>::new(irq_maps);
+    let mut chunks = node
+        .getprop_cell_chunks::<{ PciInfo::IRQ_MAP_CELLS }>(cstr!("interrupt-map"))?
+        .ok_or(FdtError::NotFound)?;
     let irq_maps = (&mut chunks).take(PciInfo::MAX_IRQS).collect();
 
     if chunks.next().is_some() {
@@ -324,22 +524,19 @@ fn validate_pci_addr_range(
         return Err(RebootReason::InvalidFdt);
     }
 
-    let Some(bus_end) = bus_addr.checked_add(size) else {
-        error!("PCI address range size {:#x} overflows", size);
+    let Ok(bus_range) = range.try_into_range(MAX_VIRT_ADDR) else {
+        error!(
+            "PCI address range {:#x}+{:#x} overflows or is outside of translatable range",
+            bus_addr, size
+        );
         return Err(RebootReason::InvalidFdt);
     };
-    if bus_end > MAX_VIRT_ADDR.try_into().unwrap() {
-        error!("PCI address end {:#x} is outside of translatable range", bus_end);
-        return Err(RebootReason::InvalidFdt);
-    }
-
-    let memory_start = memory_range.start.try_into().unwrap();
-    let memory_end = memory_range.end.try_into().unwrap();
 
-    if max(bus_addr, memory_start) < min(bus_end, memory_end) {
+    let memory_range = (memory_range.start as u64)..(memory_range.end as u64);
+    if range.overlaps(&memory_range) != Ok(false) {
         error!(
-            "PCI address range {:#x}-{:#x} overlaps with main memory range {:#x}-{:#x}",
-            bus_addr, bus_end, memory_start, memory_end
+            "PCI address range {:#x?} overlaps with main memory range {:#x?}",
+            bus_range, memory_range
         );
         return Err(RebootReason::InvalidFdt);
     }
@@ -458,32 +655,33 @@ impl SerialInfo {
     const MAX_SERIALS: usize = 4;
 }
 
-fn read_serial_info_from(fdt: &Fdt) -> libfdt::Result<SerialInfo> {
+fn read_serial_info_from(
+    fdt: &Fdt,
+    warnings: &mut Vec<PvmfwWarning>,
+) -> libfdt::Result<SerialInfo> {
     let mut addrs: ArrayVec<[u64; SerialInfo::MAX_SERIALS]> = Default::default();
-    for node in fdt.compatible_nodes(cstr!("ns16550a"))?.take(SerialInfo::MAX_SERIALS) {
+    for node in fdt.compatible_nodes(cstr!("ns16550a"))? {
         let reg = node.first_reg()?;
-        addrs.push(reg.addr);
+        if addrs.try_push(reg.addr).is_some() {
+            warn!("Dropping serial device at {:#x}: only {} are supported", reg.addr, addrs.len());
+            if !warnings.contains(&PvmfwWarning::SerialsDropped) {
+                warnings.push(PvmfwWarning::SerialsDropped);
+            }
+        }
     }
     Ok(SerialInfo { addrs })
 }
 
 /// Patch the DT by deleting the ns16550a compatible nodes whose address are unknown
 fn patch_serial_info(fdt: &mut Fdt, serial_info: &SerialInfo) -> libfdt::Result<()> {
-    let name = cstr!("ns16550a");
-    let mut next = fdt.root_mut()?.next_compatible(name);
-    while let Some(current) = next? {
-        let reg = FdtNode::from_mut(&current)
-            .reg()?
-            .ok_or(FdtError::NotFound)?
-            .next()
-            .ok_or(FdtError::NotFound)?;
-        next = if !serial_info.addrs.contains(&reg.addr) {
-            current.delete_and_next_compatible(name)
+    fdt.for_each_compatible_mut(cstr!("ns16550a"), |node| {
+        let reg = node.first_reg()?;
+        if serial_info.addrs.contains(&reg.addr) {
+            Ok(VisitAction::Keep)
         } else {
-            current.next_compatible(name)
+            Ok(VisitAction::Delete)
         }
-    }
-    Ok(())
+    })
 }
 
 fn validate_swiotlb_info(
@@ -504,14 +702,10 @@ fn validate_swiotlb_info(
     }
 
     if let Some(addr) = swiotlb_info.addr {
-        if addr.checked_add(size).is_none() {
-            error!("Invalid swiotlb range: addr:{addr:#x} size:{size:#x}");
-            return Err(RebootReason::InvalidFdt);
-        }
-    }
-    if let Some(range) = swiotlb_info.fixed_range() {
-        if !range.is_within(memory) {
-            error!("swiotlb range {range:#x?} not part of memory range {memory:#x?}");
+        let reg = Reg { addr: addr as u64, size: Some(size as u64) };
+        let within_memory = reg.try_into_range(memory.end).is_ok_and(|r| r.start >= memory.start);
+        if !within_memory {
+            error!("swiotlb range {addr:#x}+{size:#x} not part of memory range {memory:#x?}");
             return Err(RebootReason::InvalidFdt);
         }
     }
@@ -524,11 +718,8 @@ fn patch_swiotlb_info(fdt: &mut Fdt, swiotlb_info: &SwiotlbInfo) -> libfdt::Resu
         fdt.root_mut()?.next_compatible(cstr!("restricted-dma-pool"))?.ok_or(FdtError::NotFound)?;
 
     if let Some(range) = swiotlb_info.fixed_range() {
-        node.setprop_addrrange_inplace(
-            cstr!("reg"),
-            range.start.try_into().unwrap(),
-            range.len().try_into().unwrap(),
-        )?;
+        let reg = Reg::try_from(range)?;
+        node.setprop_addrrange_inplace(cstr!("reg"), reg.addr, reg.size.unwrap())?;
         node.nop_property(cstr!("size"))?;
         node.nop_property(cstr!("alignment"))?;
     } else {
@@ -540,8 +731,60 @@ fn patch_swiotlb_info(fdt: &mut Fdt, swiotlb_info: &SwiotlbInfo) -> libfdt::Resu
     Ok(())
 }
 
+/// Chooses a fixed range for the swiotlb, if the VMM left its placement to the guest.
+///
+/// The range is picked within the top quarter of main memory, away from `reserved`, based on the
+/// instance `salt`. This varies the placement across instances while keeping it stable across
+/// boots of the same instance. Returns `None` if the VMM already fixed the range or if there is no
+/// room for the swiotlb, in which case the guest is left to place it.
+pub fn place_swiotlb(
+    fdt: &Fdt,
+    instance_salt: &[u8],
+    reserved: &[Range<usize>],
+) -> Result<Option<Range<usize>>, RebootReason> {
+    let swiotlb_info = SwiotlbInfo::new_from_fdt(fdt).map_err(|e| {
+        error!("Failed to read swiotlb info from DT: {e}");
+        RebootReason::InvalidFdt
+    })?;
+    if swiotlb_info.fixed_range().is_some() {
+        return Ok(None);
+    }
+    let memory = fdt.first_memory_range().map_err(|e| {
+        error!("Failed to read memory range from DT: {e}");
+        RebootReason::InvalidFdt
+    })?;
+    let seed =
+        hkdf::<8>(instance_salt, /* salt= */ &[], b"swiotlb", Digester::sha512()).map_err(|e| {
+            error!("Failed to derive swiotlb placement seed: {e}");
+            RebootReason::InternalError
+        })?;
+    let seed = u64::from_be_bytes(*seed);
+
+    let size = swiotlb_info.size;
+    let align = max(swiotlb_info.align.unwrap_or(GUEST_PAGE_SIZE), GUEST_PAGE_SIZE);
+    let window = (memory.end - memory.len() / 4)..memory.end;
+    let Some(range) = swiotlb::pick_slot(size, align, &window, reserved, seed) else {
+        warn!("No room for a swiotlb of {size:#x} bytes in {window:#x?}, leaving it to the guest");
+        return Ok(None);
+    };
+
+    let placed = SwiotlbInfo { addr: Some(range.start), size, align: None };
+    validate_swiotlb_info(&placed, &memory)?;
+    Ok(Some(range))
+}
+
+fn patch_swiotlb_placement(fdt: &mut Fdt, range: &Range<usize>) -> libfdt::Result<()> {
+    let mut node =
+        fdt.root_mut()?.next_compatible(cstr!("restricted-dma-pool"))?.ok_or(FdtError::NotFound)?;
+
+    node.setprop_reg(&[Reg::try_from(range.clone())?])?;
+    node.nop_property(cstr!("size"))?;
+    node.nop_property(cstr!("alignment"))
+}
+
 fn patch_gic(fdt: &mut Fdt, num_cpus: usize) -> libfdt::Result<()> {
-    let node = fdt.compatible_nodes(cstr!("arm,gic-v3"))?.next().ok_or(FdtError::NotFound)?;
+    let mut node =
+        fdt.root_mut()?.next_compatible(cstr!("arm,gic-v3"))?.ok_or(FdtError::NotFound)?;
     let mut ranges = node.reg()?.ok_or(FdtError::NotFound)?;
     let range0 = ranges.next().ok_or(FdtError::NotFound)?;
     let mut range1 = ranges.next().ok_or(FdtError::NotFound)?;
@@ -563,15 +806,14 @@ fn patch_gic(fdt: &mut Fdt, num_cpus: usize) -> libfdt::Result<()> {
         range1.1.unwrap(), //size
     ];
 
-    let mut node =
-        fdt.root_mut()?.next_compatible(cstr!("arm,gic-v3"))?.ok_or(FdtError::NotFound)?;
     node.setprop_inplace(cstr!("reg"), flatten(&value))
 }
 
 fn patch_timer(fdt: &mut Fdt, num_cpus: usize) -> libfdt::Result<()> {
     const NUM_INTERRUPTS: usize = 4;
     const CELLS_PER_INTERRUPT: usize = 3;
-    let node = fdt.compatible_nodes(cstr!("arm,armv8-timer"))?.next().ok_or(FdtError::NotFound)?;
+    let mut node =
+        fdt.root_mut()?.next_compatible(cstr!("arm,armv8-timer"))?.ok_or(FdtError::NotFound)?;
     let interrupts = node.getprop_cells(cstr!("interrupts"))?.ok_or(FdtError::NotFound)?;
     let mut value: ArrayVec<[u32; NUM_INTERRUPTS * CELLS_PER_INTERRUPT]> =
         interrupts.take(NUM_INTERRUPTS * CELLS_PER_INTERRUPT).collect();
@@ -593,8 +835,6 @@ fn patch_timer(fdt: &mut Fdt, num_cpus: usize) -> libfdt::Result<()> {
         >(value.into_inner())
     };
 
-    let mut node =
-        fdt.root_mut()?.next_compatible(cstr!("arm,armv8-timer"))?.ok_or(FdtError::NotFound)?;
     node.setprop_inplace(cstr!("interrupts"), value.as_slice())
 }
 
@@ -605,11 +845,13 @@ pub struct DeviceTreeInfo {
     pub memory_range: Range<usize>,
     bootargs: Option<CString>,
     num_cpus: usize,
+    psci_info: PsciInfo,
     pci_info: PciInfo,
     serial_info: SerialInfo,
     pub swiotlb_info: SwiotlbInfo,
     device_assignment: Option<DeviceAssignmentInfo>,
     vendor_public_key: Option<Vec<u8>>,
+    warnings: Vec<PvmfwWarning>,
 }
 
 impl DeviceTreeInfo {
@@ -666,7 +908,11 @@ pub fn sanitize_device_tree(
         }
     }
 
-    patch_device_tree(fdt, &info)?;
+    patch_device_tree(fdt, &info).map_err(|e| {
+        // Patching mostly fails for lack of space, so log what was using it.
+        log_dt_stats(fdt, Level::Error);
+        e
+    })?;
 
     fdt.pack().map_err(|e| {
         error!("Failed to unpack DT after patching: {e}");
@@ -678,16 +924,17 @@ pub fn sanitize_device_tree(
 
 fn parse_device_tree(fdt: &Fdt, vm_dtbo: Option<&VmDtbo>) -> Result<DeviceTreeInfo, RebootReason> {
     let kernel_range = read_kernel_range_from(fdt).map_err(|e| {
-        error!("Failed to read kernel range from DT: {e}");
+        log_context_error("Failed to read kernel range from DT", &e);
         RebootReason::InvalidFdt
     })?;
 
     let initrd_range = read_initrd_range_from(fdt).map_err(|e| {
-        error!("Failed to read initrd range from DT: {e}");
+        log_context_error("Failed to read initrd range from DT", &e);
         RebootReason::InvalidFdt
     })?;
 
-    let memory_range = read_and_validate_memory_range(fdt)?;
+    let mut warnings = Vec::new();
+    let memory_range = read_and_validate_memory_range(fdt, &mut warnings)?;
 
     let bootargs = read_bootargs_from(fdt).map_err(|e| {
         error!("Failed to read bootargs from DT: {e}");
@@ -703,13 +950,22 @@ fn parse_device_tree(fdt: &Fdt, vm_dtbo: Option<&VmDtbo>) -> Result<DeviceTreeIn
         RebootReason::InvalidFdt
     })?;
 
+    let psci_info = read_psci_info_from(fdt).map_err(|e| {
+        error!("Failed to read psci info from DT: {e}");
+        RebootReason::InvalidFdt
+    })?;
+    validate_psci_info(&psci_info).map_err(|e| {
+        error!("Failed to validate psci info from DT: {e}");
+        RebootReason::InvalidFdt
+    })?;
+
     let pci_info = read_pci_info_from(fdt).map_err(|e| {
         error!("Failed to read pci info from DT: {e}");
         RebootReason::InvalidFdt
     })?;
     validate_pci_info(&pci_info, &memory_range)?;
 
-    let serial_info = read_serial_info_from(fdt).map_err(|e| {
+    let serial_info = read_serial_info_from(fdt, &mut warnings).map_err(|e| {
         error!("Failed to read serial info from DT: {e}");
         RebootReason::InvalidFdt
     })?;
@@ -720,6 +976,11 @@ fn parse_device_tree(fdt: &Fdt, vm_dtbo: Option<&VmDtbo>) -> Result<DeviceTreeIn
     })?;
     validate_swiotlb_info(&swiotlb_info, &memory_range)?;
 
+    read_dropped_content_from(fdt, &mut warnings).map_err(|e| {
+        error!("Failed to compare DT with the template DT: {e}");
+        RebootReason::InvalidFdt
+    })?;
+
     let device_assignment = match vm_dtbo {
         Some(vm_dtbo) => DeviceAssignmentInfo::parse(fdt, vm_dtbo).map_err(|e| {
             error!("Failed to parse device assignment from DT and VM DTBO: {e}");
@@ -746,11 +1007,13 @@ fn parse_device_tree(fdt: &Fdt, vm_dtbo: Option<&VmDtbo>) -> Result<DeviceTreeIn
         memory_range,
         bootargs,
         num_cpus,
+        psci_info,
         pci_info,
         serial_info,
         swiotlb_info,
         device_assignment,
         vendor_public_key,
+        warnings,
     })
 }
 
@@ -775,6 +1038,10 @@ fn patch_device_tree(fdt: &mut Fdt, info: &DeviceTreeInfo) -> Result<(), RebootR
         error!("Failed to patch cpus to DT: {e}");
         RebootReason::InvalidFdt
     })?;
+    patch_psci_info(fdt, &info.psci_info).map_err(|e| {
+        error!("Failed to patch psci info to DT: {e}");
+        RebootReason::InvalidFdt
+    })?;
     patch_pci_info(fdt, &info.pci_info).map_err(|e| {
         error!("Failed to patch pci info to DT: {e}");
         RebootReason::InvalidFdt
@@ -802,6 +1069,10 @@ fn patch_device_tree(fdt: &mut Fdt, info: &DeviceTreeInfo) -> Result<(), RebootR
             error!("Failed to patch device assignment info to DT: {e}");
             RebootReason::InvalidFdt
         })?;
+        add_guest_feature(fdt, cstr!("device-assignment")).map_err(|e| {
+            error!("Failed to advertise device assignment to DT: {e}");
+            RebootReason::InvalidFdt
+        })?;
     }
     if let Some(vendor_public_key) = &info.vendor_public_key {
         patch_vendor_public_key(fdt, vendor_public_key).map_err(|e| {
@@ -809,10 +1080,30 @@ fn patch_device_tree(fdt: &mut Fdt, info: &DeviceTreeInfo) -> Result<(), RebootR
             RebootReason::InvalidFdt
         })?;
     }
+    for warning in &info.warnings {
+        add_warning(fdt, *warning).map_err(|e| {
+            error!("Failed to patch warnings to DT: {e}");
+            RebootReason::InvalidFdt
+        })?;
+    }
 
     Ok(())
 }
 
+/// Returns the version of pvmfw handed over to the guest, for it to report back to the host.
+///
+/// As the package version is rarely bumped, the build ID of the image is appended to identify the
+/// exact build which booted the guest.
+fn pvmfw_version() -> CString {
+    let version = env!("CARGO_PKG_VERSION");
+    let mut version = match vmbase::layout::build_id() {
+        Some(build_id) => format!("{version}+{}", Hex(build_id)),
+        None => version.into(),
+    };
+    version.truncate(PVMFW_VERSION_MAX_LEN);
+    CString::new(version).unwrap()
+}
+
 /// Modifies the input DT according to the fields of the configuration.
 pub fn modify_for_next_stage(
     fdt: &mut Fdt,
@@ -822,51 +1113,202 @@ pub fn modify_for_next_stage(
     debug_policy: Option<&mut [u8]>,
     debuggable: bool,
     kaslr_seed: u64,
+    kernel_cmdline: Option<&[u8]>,
+    secretkeeper_protection: bool,
+    swiotlb_range: Option<&Range<usize>>,
+    bootargs_allow_list: Option<&BootArgsAllowList>,
+    warnings: &[PvmfwWarning],
 ) -> libfdt::Result<()> {
     if let Some(debug_policy) = debug_policy {
-        let backup = Vec::from(fdt.as_slice());
-        fdt.unpack()?;
-        let backup_fdt = Fdt::from_slice(backup.as_slice()).unwrap();
-        if apply_debug_policy(fdt, backup_fdt, debug_policy)? {
-            info!("Debug policy applied.");
-        } else {
-            // apply_debug_policy restored fdt to backup_fdt so unpack it again.
+        let mut scratch = vec![0; fdt.as_slice().len()];
+        let result = DtWorkspace::new(fdt, &mut scratch).try_modify(|fdt| {
+            fdt.unpack()?;
+            apply_debug_policy(fdt, debug_policy)
+        })?;
+        if let Err(e) = result {
+            // An invalid debug policy shouldn't DOS the pvmfw, so carry on with the original DT.
+            warn!("Failed to apply debug policy: {e}. Not applying.");
             fdt.unpack()?;
+            add_warning(fdt, PvmfwWarning::DebugPolicyInvalid)?;
+        } else {
+            info!("Debug policy applied.");
         }
     } else {
         info!("No debug policy found.");
         fdt.unpack()?;
     }
 
-    patch_dice_node(fdt, bcc.as_ptr() as usize, bcc.len())?;
+    let bcc_range = bcc.as_ptr_range();
+    let bcc_reg = Reg::try_from((bcc_range.start as usize)..(bcc_range.end as usize))?;
+    patch_dice_node(fdt, &bcc_reg)?;
+    // The DICE chain records the measurements of each boot stage, including the payload.
+    add_guest_feature(fdt, cstr!("measurement-log"))?;
+    // Also reserve the BCC for stages which don't parse /reserved-memory before allocating.
+    fdt.add_mem_rsv(bcc_reg.addr, bcc_reg.size.unwrap())?;
 
     if let Some(mut chosen) = fdt.chosen_mut()? {
         empty_or_delete_prop(&mut chosen, cstr!("avf,strict-boot"), strict_boot)?;
         empty_or_delete_prop(&mut chosen, cstr!("avf,new-instance"), new_instance)?;
         chosen.setprop_inplace(cstr!("kaslr-seed"), &kaslr_seed.to_be_bytes())?;
+        chosen.setprop(cstr!("avf,pvmfw-version"), pvmfw_version().as_bytes_with_nul())?;
     };
+    if let Some(kernel_cmdline) = kernel_cmdline {
+        // The verified command line goes through the same filtering as the one from the host.
+        append_bootargs(fdt, kernel_cmdline)?;
+    }
+    if let Some(swiotlb_range) = swiotlb_range {
+        patch_swiotlb_placement(fdt, swiotlb_range)?;
+    }
+    if secretkeeper_protection {
+        add_guest_feature(fdt, cstr!("secretkeeper"))?;
+    }
     if !debuggable {
         if let Some(bootargs) = read_bootargs_from(fdt)? {
-            filter_out_dangerous_bootargs(fdt, &bootargs)?;
+            if filter_out_dangerous_bootargs(fdt, &bootargs, bootargs_allow_list)? {
+                add_warning(fdt, PvmfwWarning::BootargsFiltered)?;
+            }
+        }
+        if let Some(exposed) = read_exposed_serials_from_debug_policy(fdt)? {
+            hide_serials(fdt, exposed)?;
         }
     }
+    // Ramdumps are only taken if the crash kernel survived the filtering of the bootargs.
+    if read_bootargs_from(fdt)?.is_some_and(|bootargs| has_bootarg(&bootargs, "crashkernel")) {
+        add_guest_feature(fdt, cstr!("ramdump"))?;
+    }
+
+    for warning in warnings {
+        add_warning(fdt, *warning)?;
+    }
+
+    // Placeholders, filled by record_dt_size_and_digest() once the DT is final.
+    if let Some(mut chosen) = fdt.chosen_mut()? {
+        chosen.setprop_u32(cstr!("avf,dt-size"), 0)?;
+        chosen.setprop(cstr!("avf,dt-sha256"), &[0; DT_DIGEST_SIZE])?;
+    }
 
     fdt.pack()?;
 
     Ok(())
 }
 
+/// Size of the SHA-256 digest of the DT handed over to the next stage.
+const DT_DIGEST_SIZE: usize = 32;
+
+/// Logs how the space of `fdt` is used, e.g. to diagnose a failure with `FdtError::NoSpace`.
+pub fn log_dt_stats(fdt: &Fdt, level: Level) {
+    match fdt.stats() {
+        Ok(s) => log!(
+            level,
+            "DT has {} bytes of structure ({} of NOPs), {} bytes of strings, {} bytes spare",
+            s.struct_size,
+            s.nop_size,
+            s.strings_size,
+            s.spare_capacity
+        ),
+        Err(e) => warn!("Failed to compute DT statistics: {e}"),
+    }
+}
+
+/// Records the size and SHA-256 digest of the final DT in /chosen, so that the next stage can
+/// check that the DT wasn't modified after pvmfw. The digest covers the whole DT, with
+/// `avf,dt-sha256` zeroed.
+///
+/// This must be the last change to the DT, after [`modify_for_next_stage`].
+pub fn record_dt_size_and_digest(fdt: &mut Fdt) -> Result<(), RebootReason> {
+    let size = u32::try_from(fdt.as_slice().len()).unwrap();
+    set_chosen_prop_inplace(fdt, cstr!("avf,dt-size"), &size.to_be_bytes())?;
+    let digest = sha256(fdt.as_slice()).map_err(|e| {
+        error!("Failed to compute the digest of the DT: {e}");
+        RebootReason::InternalError
+    })?;
+    set_chosen_prop_inplace(fdt, cstr!("avf,dt-sha256"), &digest)?;
+    info!("Handing over a DT of {size} bytes with SHA-256 {}", Hex(&digest));
+    Ok(())
+}
+
+/// Checks that the SHA-256 digest of the sanitized DT is one of `golden_digests`, a concatenation
+/// of SHA-256 digests provided by the platform, so that the host can't alter the configuration of
+/// high-value VMs.
+///
+/// This must be called before [`modify_for_next_stage`], which makes non-reproducible changes.
+pub fn check_golden_dt_digest(fdt: &Fdt, golden_digests: &[u8]) -> Result<(), RebootReason> {
+    let digest = sha256(fdt.as_slice()).map_err(|e| {
+        error!("Failed to compute the digest of the DT: {e}");
+        RebootReason::InternalError
+    })?;
+    if golden_digests.is_empty() || golden_digests.len() % digest.len() != 0 {
+        error!("Invalid golden DT digests of {} bytes", golden_digests.len());
+        return Err(RebootReason::InvalidConfig);
+    }
+    if !golden_digests.chunks_exact(digest.len()).any(|golden| constant_time_eq(golden, &digest)) {
+        error!("DT with SHA-256 {} doesn't match any golden digest", Hex(&digest));
+        return Err(RebootReason::InvalidFdt);
+    }
+    info!("DT matches its golden digest");
+    Ok(())
+}
+
+/// Returns the measurement of the sanitized DT, which doesn't depend on how the DT is packed.
+///
+/// This must be called before [`modify_for_next_stage`], which makes non-reproducible changes.
+pub fn measure_dt(fdt: &Fdt) -> Result<Hash, RebootReason> {
+    let mut content = Vec::new();
+    fdt.content_hash(|bytes| content.extend_from_slice(bytes)).map_err(|e| {
+        error!("Failed to serialize the DT: {e}");
+        RebootReason::InvalidFdt
+    })?;
+    hash(&content).map_err(|e| {
+        error!("Failed to compute the measurement of the DT: {e}");
+        RebootReason::InternalError
+    })
+}
+
+fn set_chosen_prop_inplace(fdt: &mut Fdt, name: &CStr, value: &[u8]) -> Result<(), RebootReason> {
+    fdt.chosen_mut()
+        .and_then(|chosen| chosen.ok_or(FdtError::NotFound)?.setprop_inplace(name, value))
+        .map_err(|e| {
+            error!("Failed to set /chosen/{name:?}: {e}");
+            RebootReason::InternalError
+        })
+}
+
+/// Appends `args` to the bootargs in /chosen, which are created if missing.
+fn append_bootargs(fdt: &mut Fdt, args: &[u8]) -> libfdt::Result<()> {
+    let mut new_bootargs = Vec::new();
+    if let Some(bootargs) = read_bootargs_from(fdt)? {
+        new_bootargs.extend_from_slice(bootargs.to_bytes());
+    }
+    if !new_bootargs.is_empty() {
+        new_bootargs.push(b' '); // separator
+    }
+    new_bootargs.extend_from_slice(args);
+    new_bootargs.push(b'\0');
+
+    let mut node = fdt.chosen_mut()?.ok_or(FdtError::NotFound)?;
+    node.setprop(cstr!("bootargs"), new_bootargs.as_slice())
+}
+
 /// Patch the "google,open-dice"-compatible reserved-memory node to point to the bcc range
-fn patch_dice_node(fdt: &mut Fdt, addr: usize, size: usize) -> libfdt::Result<()> {
+fn patch_dice_node(fdt: &mut Fdt, bcc: &Reg<u64>) -> libfdt::Result<()> {
     // We reject DTs with missing reserved-memory node as validation should have checked that the
     // "swiotlb" subnode (compatible = "restricted-dma-pool") was present.
     let node = fdt.node_mut(cstr!("/reserved-memory"))?.ok_or(libfdt::FdtError::NotFound)?;
 
     let mut node = node.next_compatible(cstr!("google,open-dice"))?.ok_or(FdtError::NotFound)?;
 
-    let addr: u64 = addr.try_into().unwrap();
-    let size: u64 = size.try_into().unwrap();
-    node.setprop_inplace(cstr!("reg"), flatten(&[addr.to_be_bytes(), size.to_be_bytes()]))
+    node.setprop_addrrange_inplace(cstr!("reg"), bcc.addr, bcc.size.unwrap())
+}
+
+/// Advertises a feature provided by pvmfw to the guest, as an empty property of /avf/features.
+fn add_guest_feature(fdt: &mut Fdt, name: &CStr) -> libfdt::Result<()> {
+    if fdt.node(cstr!("/avf"))?.is_none() {
+        fdt.root_mut()?.add_subnode(cstr!("avf"))?;
+    }
+    if fdt.node(cstr!("/avf/features"))?.is_none() {
+        fdt.node_mut(cstr!("/avf"))?.ok_or(FdtError::Internal)?.add_subnode(cstr!("features"))?;
+    }
+    fdt.node_mut(cstr!("/avf/features"))?.ok_or(FdtError::Internal)?.setprop_empty(name)
 }
 
 fn empty_or_delete_prop(
@@ -885,31 +1327,17 @@ fn empty_or_delete_prop(
 
 /// Apply the debug policy overlay to the guest DT.
 ///
-/// Returns Ok(true) on success, Ok(false) on recovered failure and Err(_) on corruption of the DT.
-fn apply_debug_policy(
-    fdt: &mut Fdt,
-    backup_fdt: &Fdt,
-    debug_policy: &[u8],
-) -> libfdt::Result<bool> {
+/// On failure, the DT may be corrupted and must be restored by the caller.
+fn apply_debug_policy(fdt: &mut Fdt, debug_policy: &[u8]) -> libfdt::Result<()> {
     let mut debug_policy = Vec::from(debug_policy);
-    let overlay = match Fdt::from_mut_slice(debug_policy.as_mut_slice()) {
-        Ok(overlay) => overlay,
-        Err(e) => {
-            warn!("Corrupted debug policy found: {e}. Not applying.");
-            return Ok(false);
-        }
-    };
+    let overlay = Fdt::from_mut_slice(debug_policy.as_mut_slice()).map_err(|e| {
+        warn!("Corrupted debug policy found: {e}");
+        e
+    })?;
 
-    // SAFETY: on failure, the corrupted DT is restored using the backup.
-    if let Err(e) = unsafe { fdt.apply_overlay(overlay) } {
-        warn!("Failed to apply debug policy: {e}. Recovering...");
-        fdt.copy_from_slice(backup_fdt.as_slice())?;
-        // A successful restoration is considered success because an invalid debug policy
-        // shouldn't DOS the pvmfw
-        Ok(false)
-    } else {
-        Ok(true)
-    }
+    // SAFETY: on failure, the corrupted DT is restored by the caller.
+    unsafe { fdt.apply_overlay(overlay) }?;
+    Ok(())
 }
 
 fn has_common_debug_policy(fdt: &Fdt, debug_feature_name: &CStr) -> libfdt::Result<bool> {
@@ -921,7 +1349,49 @@ fn has_common_debug_policy(fdt: &Fdt, debug_feature_name: &CStr) -> libfdt::Resu
     Ok(false) // if the policy doesn't exist or not 1, don't enable the debug feature
 }
 
-fn filter_out_dangerous_bootargs(fdt: &mut Fdt, bootargs: &CStr) -> libfdt::Result<()> {
+/// Reads the bitmask of the serial devices that the debug policy exposes to the guest, where bit
+/// `i` stands for the `i`-th ns16550a compatible node of the DT.
+fn read_exposed_serials_from_debug_policy(fdt: &Fdt) -> libfdt::Result<Option<u32>> {
+    if let Some(node) = fdt.node(cstr!("/avf/guest/common"))? {
+        return node.getprop_u32(cstr!("serials"));
+    }
+    Ok(None) // if the policy doesn't exist, keep exposing all the serial devices
+}
+
+/// NOPs the ns16550a compatible nodes whose bit isn't set in `exposed`.
+fn hide_serials(fdt: &mut Fdt, exposed: u32) -> libfdt::Result<()> {
+    let name = cstr!("ns16550a");
+    let mut hidden: ArrayVec<[u64; SerialInfo::MAX_SERIALS]> = Default::default();
+    for (i, node) in fdt.compatible_nodes(name)?.take(SerialInfo::MAX_SERIALS).enumerate() {
+        if exposed & (1 << i) == 0 {
+            hidden.push(node.first_reg()?.addr);
+        }
+    }
+
+    for addr in hidden {
+        let mut next = fdt.root_mut()?.next_compatible(name)?;
+        while let Some(current) = next {
+            let reg = current.first_reg()?;
+            if reg.addr == addr {
+                debug!("Hiding serial device at {addr:#x} as per debug policy");
+                current.nop()?;
+                break;
+            }
+            next = current.next_compatible(name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether some bootargs were dropped.
+///
+/// Arguments not built into pvmfw are kept if accepted by `allow_list`, which can't change how
+/// the built-in ones are handled.
+fn filter_out_dangerous_bootargs(
+    fdt: &mut Fdt,
+    bootargs: &CStr,
+    allow_list: Option<&BootArgsAllowList>,
+) -> libfdt::Result<bool> {
     let has_crashkernel = has_common_debug_policy(fdt, cstr!("ramdump"))?;
     let has_console = has_common_debug_policy(fdt, cstr!("log"))?;
 
@@ -933,13 +1403,20 @@ fn filter_out_dangerous_bootargs(fdt: &mut Fdt, bootargs: &CStr) -> libfdt::Resu
 
     // parse and filter out unwanted
     let mut filtered = Vec::new();
+    let mut rejected = false;
     for arg in BootArgsIterator::new(bootargs).map_err(|e| {
         info!("Invalid bootarg: {e}");
         FdtError::BadValue
     })? {
-        match accepted.iter().find(|&t| t.0 == arg.name()) {
-            Some((_, pred)) if pred(arg.value()) => filtered.push(arg),
-            _ => debug!("Rejected bootarg {}", arg.as_ref()),
+        let is_accepted = match accepted.iter().find(|&t| t.0 == arg.name()) {
+            Some((_, pred)) => pred(arg.value()),
+            None => allow_list.map_or(false, |l| l.accepts(&arg)),
+        };
+        if is_accepted {
+            filtered.push(arg);
+        } else {
+            debug!("Rejected bootarg {}", arg.as_ref());
+            rejected = true;
         }
     }
 
@@ -954,5 +1431,6 @@ fn filter_out_dangerous_bootargs(fdt: &mut Fdt, bootargs: &CStr) -> libfdt::Resu
     new_bootargs.push(b'\0');
 
     let mut node = fdt.chosen_mut()?.ok_or(FdtError::NotFound)?;
-    node.setprop(cstr!("bootargs"), new_bootargs.as_slice())
+    node.setprop(cstr!("bootargs"), new_bootargs.as_slice())?;
+    Ok(rejected)
 }
@@ -24,17 +24,22 @@ use static_assertions::const_assert_eq;
 use uuid::Uuid;
 use virtio_drivers::device::blk::SECTOR_SIZE;
 use vmbase::util::ceiling_div;
-use vmbase::virtio::{pci, HalImpl};
+use vmbase::virtio::{pci, HalImpl, VirtIOError};
 use zerocopy::FromBytes;
 use zerocopy::FromZeroes;
 
+type BlkDevice = pci::BlkDevice<HalImpl>;
 type VirtIOBlk = pci::VirtIOBlk<HalImpl>;
 
 pub enum Error {
     /// VirtIO error during read operation.
-    FailedRead(virtio_drivers::Error),
+    FailedRead(VirtIOError),
     /// VirtIO error during write operation.
-    FailedWrite(virtio_drivers::Error),
+    FailedWrite(VirtIOError),
+    /// VirtIO error during flush operation.
+    FailedFlush(VirtIOError),
+    /// The device can't guarantee that completed writes are persisted.
+    FlushUnsupported,
     /// Invalid GPT header.
     InvalidHeader,
     /// Invalid partition block index.
@@ -46,6 +51,8 @@ impl fmt::Display for Error {
         match self {
             Self::FailedRead(e) => write!(f, "Failed to read from disk: {e}"),
             Self::FailedWrite(e) => write!(f, "Failed to write to disk: {e}"),
+            Self::FailedFlush(e) => write!(f, "Failed to flush disk: {e}"),
+            Self::FlushUnsupported => write!(f, "Disk doesn't support flushing its write cache"),
             Self::InvalidHeader => write!(f, "Found invalid GPT header"),
             Self::BlockOutsidePartition(i) => write!(f, "Accessed invalid block index {i}"),
         }
@@ -60,7 +67,7 @@ pub struct Partition {
 }
 
 impl Partition {
-    pub fn get_by_name(device: VirtIOBlk, name: &str) -> Result<Option<Self>> {
+    pub fn get_by_name(device: BlkDevice, name: &str) -> Result<Option<Self>> {
         Partitions::new(device)?.get_partition_by_name(name)
     }
 
@@ -85,6 +92,11 @@ impl Partition {
         self.partitions.write_block(index, blk)
     }
 
+    /// Ensures that all completed writes to the underlying device have reached persistent storage.
+    pub fn flush(&mut self) -> Result<()> {
+        self.partitions.flush()
+    }
+
     fn block_index(&self, index: usize) -> Option<usize> {
         if self.indices.contains(&index) {
             Some(index)
@@ -96,22 +108,24 @@ impl Partition {
 
 pub struct Partitions {
     device: VirtIOBlk,
+    supports_flush: bool,
     entries_count: usize,
 }
 
 impl Partitions {
     pub const LBA_SIZE: usize = SECTOR_SIZE;
 
-    fn new(mut device: VirtIOBlk) -> Result<Self> {
+    fn new(device: BlkDevice) -> Result<Self> {
+        let BlkDevice { driver: mut device, supports_flush } = device;
         let mut blk = [0; Self::LBA_SIZE];
-        device.read_blocks(Header::LBA, &mut blk).map_err(Error::FailedRead)?;
+        device.read_blocks(Header::LBA, &mut blk).map_err(|e| Error::FailedRead(e.into()))?;
         let header = Header::read_from_prefix(blk.as_slice()).unwrap();
         if !header.is_valid() {
             return Err(Error::InvalidHeader);
         }
         let entries_count = usize::try_from(header.entries_count()).unwrap();
 
-        Ok(Self { device, entries_count })
+        Ok(Self { device, supports_flush, entries_count })
     }
 
     fn get_partition_by_name(mut self, name: &str) -> Result<Option<Partition>> {
@@ -146,11 +160,18 @@ impl Partitions {
     }
 
     fn read_block(&mut self, index: usize, blk: &mut [u8]) -> Result<()> {
-        self.device.read_blocks(index, blk).map_err(Error::FailedRead)
+        self.device.read_blocks(index, blk).map_err(|e| Error::FailedRead(e.into()))
     }
 
     fn write_block(&mut self, index: usize, blk: &[u8]) -> Result<()> {
-        self.device.write_blocks(index, blk).map_err(Error::FailedWrite)
+        self.device.write_blocks(index, blk).map_err(|e| Error::FailedWrite(e.into()))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.supports_flush {
+            return Err(Error::FlushUnsupported);
+        }
+        self.device.flush().map_err(|e| Error::FailedFlush(e.into()))
     }
 }
 
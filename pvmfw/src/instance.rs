@@ -14,13 +14,15 @@
 
 //! Support for reading and writing to the instance.img.
 
+use crate::boot_attempts::{log_excerpt, BootAttempts};
 use crate::crypto;
 use crate::crypto::AeadCtx;
 use crate::dice::PartialInputs;
 use crate::gpt;
 use crate::gpt::Partition;
 use crate::gpt::Partitions;
-use bssl_avf::{self, hkdf, Digester};
+use alloc::vec::Vec;
+use bssl_avf::{self, constant_time_eq, hkdf, Digester};
 use core::fmt;
 use core::mem::size_of;
 use diced_open_dice::DiceMode;
@@ -28,10 +30,10 @@ use diced_open_dice::Hash;
 use diced_open_dice::Hidden;
 use log::trace;
 use uuid::Uuid;
-use virtio_drivers::transport::{pci::bus::PciRoot, DeviceType, Transport};
+use vmbase::logger;
 use vmbase::rand;
 use vmbase::util::ceiling_div;
-use vmbase::virtio::pci::{PciTransportIterator, VirtIOBlk};
+use vmbase::virtio::pci::VirtIODevice;
 use vmbase::virtio::HalImpl;
 use zerocopy::AsBytes;
 use zerocopy::FromBytes;
@@ -62,8 +64,8 @@ pub enum Error {
     RecordedDiceModeMismatch,
     /// Size of the instance.img entry being read or written is not supported.
     UnsupportedEntrySize(usize),
-    /// Failed to create VirtIO Block device.
-    VirtIOBlkCreationFailed(virtio_drivers::Error),
+    /// The disk can't guarantee that a new entry would persist.
+    UnsupportedFlush,
     /// An error happened during the interaction with BoringSSL.
     BoringSslFailed(bssl_avf::Error),
 }
@@ -95,9 +97,7 @@ impl fmt::Display for Error {
             Self::RecordedCodeHashMismatch => write!(f, "Recorded code hash doesn't match"),
             Self::RecordedDiceModeMismatch => write!(f, "Recorded DICE mode doesn't match"),
             Self::UnsupportedEntrySize(sz) => write!(f, "Invalid entry size: {sz}"),
-            Self::VirtIOBlkCreationFailed(e) => {
-                write!(f, "Failed to create VirtIO Block device: {e}")
-            }
+            Self::UnsupportedFlush => write!(f, "instance.img disk doesn't support flushing"),
             Self::BoringSslFailed(e) => {
                 write!(f, "An error happened during the interaction with BoringSSL: {e}")
             }
@@ -114,19 +114,17 @@ impl From<bssl_avf::Error> for Error {
 pub type Result<T> = core::result::Result<T, Error>;
 
 pub fn get_or_generate_instance_salt(
-    pci_root: &mut PciRoot,
+    instance_img: &mut Partition,
     dice_inputs: &PartialInputs,
     secret: &[u8],
 ) -> Result<(bool, Hidden)> {
-    let mut instance_img = find_instance_img(pci_root)?;
-
-    let entry = locate_entry(&mut instance_img)?;
+    let entry = locate_entry(instance_img, Entry::PVMFW_UUID)?;
     trace!("Found pvmfw instance.img entry: {entry:?}");
 
     let key = hkdf::<32>(secret, /* salt= */ &[], b"vm-instance", Digester::sha512())?;
     let mut blk = [0; BLK_SIZE];
     match entry {
-        PvmfwEntry::Existing { header_index, payload_size } => {
+        Entry::Existing { header_index, payload_size } => {
             if payload_size > blk.len() {
                 // We currently only support single-blk entries.
                 return Err(Error::UnsupportedEntrySize(payload_size));
@@ -152,17 +150,20 @@ pub fn get_or_generate_instance_salt(
                 // This ensures that the updated RKP VM will retain the same CDIs in the next stage.
                 return Ok((false, body.salt));
             }
-            if body.code_hash != dice_inputs.code_hash {
+            let expected = EntryBody::new(dice_inputs, &body.salt);
+            if !constant_time_eq(&body.code_hash, &expected.code_hash) {
                 Err(Error::RecordedCodeHashMismatch)
-            } else if body.auth_hash != dice_inputs.auth_hash {
+            } else if !constant_time_eq(&body.auth_hash, &expected.auth_hash) {
                 Err(Error::RecordedAuthHashMismatch)
-            } else if body.mode() != dice_inputs.mode {
+            } else if !constant_time_eq(&[encode_mode(body.mode())], &[expected.mode]) {
                 Err(Error::RecordedDiceModeMismatch)
             } else {
                 Ok((false, body.salt))
             }
         }
-        PvmfwEntry::New { header_index } => {
+        Entry::New { header_index } => {
+            // Fail before writing anything if the entry couldn't be reliably persisted.
+            flush(instance_img)?;
             let salt = rand::random_array().map_err(Error::FailedSaltGeneration)?;
             let body = EntryBody::new(dice_inputs, &salt);
 
@@ -175,17 +176,161 @@ pub fn get_or_generate_instance_salt(
             let payload_size = encrypted.len();
             let payload_index = header_index + 1;
             instance_img.write_block(payload_index, &blk).map_err(Error::FailedIo)?;
-
-            let header = EntryHeader::new(PvmfwEntry::UUID, payload_size);
-            header.write_to_prefix(blk.as_mut_slice()).unwrap();
-            blk[header.as_bytes().len()..].fill(0);
-            instance_img.write_block(header_index, &blk).map_err(Error::FailedIo)?;
+            // The payload must be persisted before the header referencing it.
+            flush(instance_img)?;
+            write_entry_header(instance_img, header_index, Entry::PVMFW_UUID, payload_size)?;
 
             Ok((true, salt))
         }
     }
 }
 
+/// Tracks the current boot attempt in instance.img, recording it as failed unless
+/// [`BootAttemptTracker::succeed`] is called before the tracker is dropped.
+///
+/// A failed attempt is recorded along with the last error and the tail of the log, which are
+/// available to the next attempt through [`BootAttemptTracker::last_failure`].
+///
+/// A successful boot of a healthy instance leaves instance.img untouched and any other attempt
+/// writes to it at most once.
+pub struct BootAttemptTracker<'a> {
+    instance_img: &'a mut Partition,
+    record: Option<(Entry, BootAttempts)>,
+    last_failure: Option<BootFailure>,
+}
+
+impl<'a> BootAttemptTracker<'a> {
+    /// Starts tracking the current attempt, without tracking it if the record can't be read.
+    ///
+    /// Failing to do so doesn't prevent the boot, as tracking attempts only serves diagnostics.
+    pub fn start(instance_img: &'a mut Partition) -> Self {
+        match Self::read(instance_img) {
+            Ok((entry, body)) => {
+                let attempts = BootAttempts::new(u32::from_le(body.failures));
+                let last_failure = (attempts.previous_failures() != 0)
+                    .then(|| BootFailure { last_error: body.last_error, log_tail: body.log_tail });
+                Self { instance_img, record: Some((entry, attempts)), last_failure }
+            }
+            Err(e) => {
+                log::warn!("Failed to read the previous boot attempts: {e}");
+                Self { instance_img, record: None, last_failure: None }
+            }
+        }
+    }
+
+    /// Reads the record of the previous attempts; a missing record counts as no failures.
+    fn read(instance_img: &mut Partition) -> Result<(Entry, BootAttemptsBody)> {
+        let entry = locate_entry(instance_img, Entry::BOOT_ATTEMPTS_UUID)?;
+        let body = match entry {
+            Entry::Existing { header_index, payload_size } => {
+                if payload_size != size_of::<BootAttemptsBody>() {
+                    return Err(Error::UnsupportedEntrySize(payload_size));
+                }
+                let mut blk = [0; BLK_SIZE];
+                instance_img.read_block(header_index + 1, &mut blk).map_err(Error::FailedIo)?;
+                BootAttemptsBody::read_from_prefix(blk.as_slice()).unwrap()
+            }
+            Entry::New { .. } => BootAttemptsBody::new_zeroed(),
+        };
+        Ok((entry, body))
+    }
+
+    /// Returns the boot attempts which failed right before this one.
+    pub fn attempts(&self) -> BootAttempts {
+        self.record.as_ref().map(|(_, attempts)| *attempts).unwrap_or_default()
+    }
+
+    /// Returns the diagnostics recorded by the previous attempt, if it failed.
+    pub fn last_failure(&self) -> Option<&BootFailure> {
+        self.last_failure.as_ref()
+    }
+
+    /// Gives access to the tracked instance.img.
+    pub fn instance_img(&mut self) -> &mut Partition {
+        self.instance_img
+    }
+
+    /// Records that this boot attempt succeeded.
+    pub fn succeed(mut self) -> Result<()> {
+        match self.record.take() {
+            Some((entry, attempts)) => match attempts.on_success() {
+                Some(failures) => {
+                    let mut body = BootAttemptsBody::new_zeroed();
+                    body.failures = failures.to_le();
+                    Self::write(self.instance_img, entry, &body)
+                }
+                None => Ok(()),
+            },
+            None => Ok(()),
+        }
+    }
+
+    fn write(instance_img: &mut Partition, entry: Entry, body: &BootAttemptsBody) -> Result<()> {
+        let mut blk = [0; BLK_SIZE];
+        body.write_to_prefix(blk.as_mut_slice()).unwrap();
+        match entry {
+            Entry::Existing { header_index, .. } => {
+                instance_img.write_block(header_index + 1, &blk).map_err(Error::FailedIo)?;
+                flush(instance_img)
+            }
+            Entry::New { header_index } => {
+                // Fail before writing anything if the entry couldn't be reliably persisted.
+                flush(instance_img)?;
+                instance_img.write_block(header_index + 1, &blk).map_err(Error::FailedIo)?;
+                // The payload must be persisted before the header referencing it.
+                flush(instance_img)?;
+                let uuid = Entry::BOOT_ATTEMPTS_UUID;
+                write_entry_header(instance_img, header_index, uuid, size_of::<BootAttemptsBody>())
+            }
+        }
+    }
+}
+
+impl Drop for BootAttemptTracker<'_> {
+    fn drop(&mut self) {
+        if let Some((entry, attempts)) = self.record.take() {
+            let mut body = BootAttemptsBody::new_zeroed();
+            body.failures = attempts.on_failure().to_le();
+            logger::last_error(&mut body.last_error);
+            logger::tail(&mut body.log_tail);
+            if let Err(e) = Self::write(self.instance_img, entry, &body) {
+                log::warn!("Failed to record the failed boot attempt: {e}");
+            }
+        }
+    }
+}
+
+/// Diagnostics recorded by a failed boot attempt.
+///
+/// They come from the instance.img, so the host may have modified them; as the host also receives
+/// the log of pvmfw, they don't disclose anything to it.
+pub struct BootFailure {
+    last_error: [u8; logger::LAST_ERROR_SIZE],
+    log_tail: [u8; logger::TAIL_SIZE],
+}
+
+impl BootFailure {
+    /// Returns the last error logged by the failed attempt, which states why it failed.
+    pub fn last_error(&self) -> &str {
+        log_excerpt(&self.last_error)
+    }
+
+    /// Returns the end of the log of the failed attempt.
+    pub fn log_tail(&self) -> &str {
+        log_excerpt(&self.log_tail)
+    }
+}
+
+#[derive(AsBytes, FromZeroes, FromBytes)]
+#[repr(C, packed)]
+struct BootAttemptsBody {
+    failures: u32,
+    /// The last error logged by the last failed attempt, NUL-padded.
+    last_error: [u8; logger::LAST_ERROR_SIZE],
+    /// The end of the log of the last failed attempt, NUL-padded.
+    log_tail: [u8; logger::TAIL_SIZE],
+}
+
 #[derive(FromZeroes, FromBytes)]
 #[repr(C, packed)]
 struct Header {
@@ -206,12 +351,15 @@ impl Header {
     }
 }
 
-fn find_instance_img(pci_root: &mut PciRoot) -> Result<Partition> {
-    for transport in PciTransportIterator::<HalImpl>::new(pci_root)
-        .filter(|t| DeviceType::Block == t.device_type())
-    {
-        let device =
-            VirtIOBlk::<HalImpl>::new(transport).map_err(Error::VirtIOBlkCreationFailed)?;
+fn flush(instance_img: &mut Partition) -> Result<()> {
+    instance_img.flush().map_err(|e| match e {
+        gpt::Error::FlushUnsupported => Error::UnsupportedFlush,
+        e => Error::FailedIo(e),
+    })
+}
+
+pub fn find_instance_img(virtio_devices: Vec<VirtIODevice<HalImpl>>) -> Result<Partition> {
+    for device in virtio_devices.into_iter().filter_map(VirtIODevice::into_blk) {
         match Partition::get_by_name(device, "vm-instance") {
             Ok(Some(p)) => return Ok(p),
             Ok(None) => {}
@@ -223,18 +371,20 @@ fn find_instance_img(pci_root: &mut PciRoot) -> Result<Partition> {
 }
 
 #[derive(Debug)]
-enum PvmfwEntry {
+enum Entry {
     Existing { header_index: usize, payload_size: usize },
     New { header_index: usize },
 }
 
 const BLK_SIZE: usize = Partitions::LBA_SIZE;
 
-impl PvmfwEntry {
-    const UUID: Uuid = Uuid::from_u128(0x90d2174a038a4bc6adf3824848fc5825);
+impl Entry {
+    const PVMFW_UUID: Uuid = Uuid::from_u128(0x90d2174a038a4bc6adf3824848fc5825);
+    /// Randomly generated (version 4) to identify the entry; not shared with any other client.
+    const BOOT_ATTEMPTS_UUID: Uuid = Uuid::from_u128(0x8f321fd34a384f8d980332aebba4f71f);
 }
 
-fn locate_entry(partition: &mut Partition) -> Result<PvmfwEntry> {
+fn locate_entry(partition: &mut Partition, entry_uuid: Uuid) -> Result<Entry> {
     let mut blk = [0; BLK_SIZE];
     let mut indices = partition.indices();
     let header_index = indices.next().ok_or(Error::MissingInstanceImageHeader)?;
@@ -250,9 +400,9 @@ fn locate_entry(partition: &mut Partition) -> Result<PvmfwEntry> {
 
         let header = EntryHeader::read_from_prefix(blk.as_slice()).unwrap();
         match (header.uuid(), header.payload_size()) {
-            (uuid, _) if uuid.is_nil() => return Ok(PvmfwEntry::New { header_index }),
-            (PvmfwEntry::UUID, payload_size) => {
-                return Ok(PvmfwEntry::Existing { header_index, payload_size })
+            (uuid, _) if uuid.is_nil() => return Ok(Entry::New { header_index }),
+            (uuid, payload_size) if uuid == entry_uuid => {
+                return Ok(Entry::Existing { header_index, payload_size })
             }
             (uuid, payload_size) => {
                 trace!("Skipping instance.img entry {uuid}: {payload_size:?} bytes");
@@ -267,6 +417,19 @@ fn locate_entry(partition: &mut Partition) -> Result<PvmfwEntry> {
     Err(Error::InstanceImageFull)
 }
 
+/// Writes the header of an entry, once its payload has been persisted.
+fn write_entry_header(
+    partition: &mut Partition,
+    header_index: usize,
+    uuid: Uuid,
+    payload_size: usize,
+) -> Result<()> {
+    let mut blk = [0; BLK_SIZE];
+    EntryHeader::new(uuid, payload_size).write_to_prefix(blk.as_mut_slice()).unwrap();
+    partition.write_block(header_index, &blk).map_err(Error::FailedIo)?;
+    flush(partition)
+}
+
 /// Marks the start of an instance.img entry.
 ///
 /// Note: Virtualization/microdroid_manager/src/instance.rs uses the name "partition".
@@ -302,18 +465,11 @@ struct EntryBody {
 
 impl EntryBody {
     fn new(dice_inputs: &PartialInputs, salt: &Hidden) -> Self {
-        let mode = match dice_inputs.mode {
-            DiceMode::kDiceModeNotInitialized => 0,
-            DiceMode::kDiceModeNormal => 1,
-            DiceMode::kDiceModeDebug => 2,
-            DiceMode::kDiceModeMaintenance => 3,
-        };
-
         Self {
             code_hash: dice_inputs.code_hash,
             auth_hash: dice_inputs.auth_hash,
             salt: *salt,
-            mode,
+            mode: encode_mode(dice_inputs.mode),
         }
     }
 
@@ -326,3 +482,12 @@ impl EntryBody {
         }
     }
 }
+
+fn encode_mode(mode: DiceMode) -> u8 {
+    match mode {
+        DiceMode::kDiceModeNotInitialized => 0,
+        DiceMode::kDiceModeNormal => 1,
+        DiceMode::kDiceModeDebug => 2,
+        DiceMode::kDiceModeMaintenance => 3,
+    }
+}
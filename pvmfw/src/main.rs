@@ -20,6 +20,7 @@
 extern crate alloc;
 
 mod bcc;
+mod boot_attempts;
 mod bootargs;
 mod config;
 mod crypto;
@@ -32,29 +33,46 @@ mod gpt;
 mod helpers;
 mod instance;
 mod memory;
+mod swiotlb;
 
 use crate::bcc::Bcc;
+use crate::boot_attempts::Diagnostics;
+use crate::bootargs::BootArgsAllowList;
 use crate::dice::PartialInputs;
 use crate::entry::RebootReason;
-use crate::fdt::modify_for_next_stage;
+use crate::fdt::{
+    check_golden_dt_digest, log_dt_stats, measure_dt, modify_for_next_stage, place_swiotlb,
+    record_dt_size_and_digest, PvmfwWarning,
+};
 use crate::helpers::GUEST_PAGE_SIZE;
-use crate::instance::get_or_generate_instance_salt;
+use crate::instance::{find_instance_img, get_or_generate_instance_salt, BootAttemptTracker};
+use crate::memory::with_reduced_privileges;
 use alloc::borrow::Cow;
 use alloc::boxed::Box;
+use alloc::vec;
+use core::ffi::CStr;
 use core::ops::Range;
 use diced_open_dice::{bcc_handover_parse, DiceArtifacts};
 use fdtpci::{PciError, PciInfo};
+use hexfmt::Redacted;
 use libfdt::Fdt;
-use log::{debug, error, info, trace, warn};
+use libfdt::FdtError;
+use log::{debug, error, info, trace, warn, Level, LevelFilter};
 use pvmfw_avb::verify_payload;
 use pvmfw_avb::Capability;
 use pvmfw_avb::DebugLevel;
 use pvmfw_embedded_key::PUBLIC_KEY;
+use pvmfw_hooks::HookError;
+use pvmfw_product_hooks::VERIFICATION_HOOKS;
+use virtio_drivers::transport::DeviceType;
 use vmbase::heap;
+use vmbase::layout::crosvm::FDT_MAX_SIZE;
+use vmbase::logger::hexdump;
 use vmbase::memory::flush;
-use vmbase::memory::MEMORY;
+use vmbase::memory::{claim_memory, MemoryOwner, MEMORY};
 use vmbase::rand;
-use vmbase::virtio::pci;
+use vmbase::virtio::pci::{self, VirtIODeviceRegistry};
+use vmbase::virtio::HalImpl;
 
 const NEXT_BCC_SIZE: usize = GUEST_PAGE_SIZE;
 
@@ -64,6 +82,8 @@ fn main(
     ramdisk: Option<&[u8]>,
     current_bcc_handover: &[u8],
     mut debug_policy: Option<&mut [u8]>,
+    service_vm_dt_digests: Option<&[u8]>,
+    bootargs_allow_list: Option<&[u8]>,
 ) -> Result<Range<usize>, RebootReason> {
     info!("pVM firmware");
     debug!("FDT: {:?}", fdt.as_ptr());
@@ -75,11 +95,16 @@ fn main(
         debug!("Ramdisk: None");
     }
 
+    let bootargs_allow_list = bootargs_allow_list.map(parse_bootargs_allow_list).transpose()?;
+
     let bcc_handover = bcc_handover_parse(current_bcc_handover).map_err(|e| {
         error!("Invalid BCC Handover: {e:?}");
         RebootReason::InvalidBcc
     })?;
-    trace!("BCC: {bcc_handover:x?}");
+    trace!("BCC handover: {}", Redacted(current_bcc_handover));
+    if let Some(bcc) = bcc_handover.bcc() {
+        hexdump(Level::Trace, "BCC", bcc);
+    }
 
     let cdi_seal = bcc_handover.cdi_seal();
 
@@ -88,11 +113,13 @@ fn main(
         RebootReason::InvalidBcc
     })?;
 
+    let mut warnings = vec![];
     // The bootloader should never pass us a debug policy when the boot is secure (the bootloader
     // is locked). If it gets it wrong, disregard it & log it, to avoid it causing problems.
     if debug_policy.is_some() && !bcc.is_debug_mode() {
         warn!("Ignoring debug policy, BCC does not indicate Debug mode");
         debug_policy = None;
+        warnings.push(PvmfwWarning::DebugPolicyIgnored);
     }
 
     // Set up PCI bus for VirtIO devices.
@@ -102,17 +129,53 @@ fn main(
         error!("Failed to initialize PCI: {e}");
         RebootReason::InternalError
     })?;
-
-    let verified_boot_data = verify_payload(signed_kernel, ramdisk, PUBLIC_KEY).map_err(|e| {
-        error!("Failed to verify the payload: {e}");
-        RebootReason::PayloadVerificationError
+    let virtio_devices = VirtIODeviceRegistry::<HalImpl>::new()
+        .register(DeviceType::Block, pci::probe_blk)
+        .probe_all(&mut pci_root)
+        .map_err(|e| {
+            error!("Failed to probe VirtIO devices: {e}");
+            RebootReason::InternalError
+        })?;
+    let mut instance_img = find_instance_img(virtio_devices).map_err(|e| {
+        error!("Failed to find instance.img: {e}");
+        RebootReason::InternalError
     })?;
+
+    // Any failure from now on is recorded in instance.img when boot_attempt is dropped.
+    let mut boot_attempt = BootAttemptTracker::start(&mut instance_img);
+
+    let verified_boot_data =
+        with_reduced_privileges(|| verify_payload(signed_kernel, ramdisk, PUBLIC_KEY))?.map_err(
+            |e| {
+                error!("Failed to verify the payload: {e}");
+                RebootReason::PayloadVerificationError
+            },
+        )?;
     let debuggable = verified_boot_data.debug_level != DebugLevel::None;
     if debuggable {
         info!("Successfully verified a debuggable payload.");
         info!("Please disregard any previous libavb ERROR about initrd_normal.");
     }
 
+    let attempts = boot_attempt.attempts();
+    if let Some(failure) = boot_attempt.last_failure() {
+        info!("The previous boot attempt failed: {}", failure.last_error());
+    }
+    let diagnostics = attempts.diagnostics(debuggable);
+    if diagnostics != Diagnostics::None {
+        warn!("The last {} boot attempts failed", attempts.previous_failures());
+        if let Some(failure) = boot_attempt.last_failure() {
+            warn!("End of the log of the last failed boot attempt:");
+            for line in failure.log_tail().lines() {
+                warn!("  {line}");
+            }
+        }
+        warnings.push(PvmfwWarning::RepeatedBootFailures);
+    }
+    if diagnostics == Diagnostics::Verbose {
+        log::set_max_level(LevelFilter::Debug);
+    }
+
     if verified_boot_data.has_capability(Capability::RemoteAttest) {
         info!("Service VM capable of remote attestation detected");
         if service_vm_version::VERSION != verified_boot_data.rollback_index {
@@ -140,23 +203,66 @@ fn main(
         };
     }
 
+    let is_service_vm = verified_boot_data.has_capability(Capability::RemoteAttest)
+        || verified_boot_data.has_capability(Capability::SecretkeeperProtection);
+    if is_service_vm {
+        // The platform may lock down the configuration of service VMs.
+        if let Some(golden_digests) = service_vm_dt_digests {
+            check_golden_dt_digest(fdt, golden_digests)?;
+        }
+    }
+
+    for hook in VERIFICATION_HOOKS {
+        hook.check_payload(&verified_boot_data, fdt).map_err(|e| {
+            error!("Payload rejected by the {} hook: {e}", hook.name());
+            match e {
+                HookError::PolicyViolation(_) => RebootReason::InvalidPayload,
+                HookError::Internal(_) => RebootReason::InternalError,
+            }
+        })?;
+    }
+
     let next_bcc = heap::aligned_boxed_slice(NEXT_BCC_SIZE, GUEST_PAGE_SIZE).ok_or_else(|| {
         error!("Failed to allocate the next-stage BCC");
         RebootReason::InternalError
     })?;
     // By leaking the slice, its content will be left behind for the next stage.
     let next_bcc = Box::leak(next_bcc);
+    let next_bcc_range = {
+        let r = next_bcc.as_ptr_range();
+        (r.start as usize)..(r.end as usize)
+    };
+    claim_memory(next_bcc_range.clone(), MemoryOwner::BccHandover).map_err(|e| {
+        error!("Failed to claim the next-stage BCC: {e}");
+        RebootReason::InternalError
+    })?;
 
-    let dice_inputs = PartialInputs::new(&verified_boot_data).map_err(|e| {
+    let dt_hash = measure_dt(fdt)?;
+    let dice_inputs = PartialInputs::new(&verified_boot_data, Some(dt_hash)).map_err(|e| {
         error!("Failed to compute partial DICE inputs: {e:?}");
         RebootReason::InternalError
     })?;
-    let (new_instance, salt) = get_or_generate_instance_salt(&mut pci_root, &dice_inputs, cdi_seal)
+    debug!("DICE inputs: {dice_inputs:?}");
+    let instance_img = boot_attempt.instance_img();
+    let (new_instance, salt) = get_or_generate_instance_salt(instance_img, &dice_inputs, cdi_seal)
         .map_err(|e| {
             error!("Failed to get instance.img salt: {e}");
             RebootReason::InternalError
         })?;
-    trace!("Got salt from instance.img: {salt:x?}");
+    trace!("Got salt from instance.img: {}", Redacted(&salt));
+
+    let ptr_range = |r: Range<*const u8>| (r.start as usize)..(r.end as usize);
+    let fdt_start = fdt.as_ptr() as usize;
+    let mut reserved = vec![
+        ptr_range(signed_kernel.as_ptr_range()),
+        fdt_start..(fdt_start + FDT_MAX_SIZE),
+        next_bcc_range.clone(),
+    ];
+    reserved.extend(ramdisk.map(|rd| ptr_range(rd.as_ptr_range())));
+    let swiotlb_range = place_swiotlb(fdt, &salt, &reserved)?;
+    if let Some(range) = &swiotlb_range {
+        debug!("Placing swiotlb at {range:#x?}");
+    }
 
     let new_bcc_handover = if cfg!(dice_changes) {
         Cow::Borrowed(current_bcc_handover)
@@ -192,20 +298,40 @@ fn main(
         debug_policy,
         debuggable,
         kaslr_seed,
+        verified_boot_data.kernel_cmdline.as_deref(),
+        verified_boot_data.has_capability(Capability::SecretkeeperProtection),
+        swiotlb_range.as_ref(),
+        bootargs_allow_list.as_ref(),
+        &warnings,
     )
     .map_err(|e| {
         error!("Failed to configure device tree: {e}");
+        if e == FdtError::NoSpace {
+            log_dt_stats(fdt, Level::Error);
+        }
         RebootReason::InternalError
     })?;
+    record_dt_size_and_digest(fdt)?;
+    log_dt_stats(fdt, Level::Debug);
+    if let Err(e) = boot_attempt.succeed() {
+        warn!("Failed to record the successful boot: {e}");
+    }
 
     info!("Starting payload...");
 
-    let bcc_range = {
-        let r = next_bcc.as_ptr_range();
-        (r.start as usize)..(r.end as usize)
-    };
+    Ok(next_bcc_range)
+}
 
-    Ok(bcc_range)
+/// Parses the NUL-terminated list of product-specific bootargs rules from the config data.
+fn parse_bootargs_allow_list(rules: &[u8]) -> Result<BootArgsAllowList, RebootReason> {
+    let rules = CStr::from_bytes_until_nul(rules).map_err(|e| {
+        error!("Bootargs allow-list isn't NUL-terminated: {e}");
+        RebootReason::InvalidConfig
+    })?;
+    BootArgsAllowList::new(rules).map_err(|e| {
+        error!("Invalid bootargs allow-list: {e}");
+        RebootReason::InvalidConfig
+    })
 }
 
 /// Logs the given PCI error and returns the appropriate `RebootReason`.
@@ -14,18 +14,39 @@
 
 //! Low-level allocation and tracking of main memory.
 
+use crate::entry::RebootReason;
 use crate::helpers::PVMFW_PAGE_SIZE;
 use aarch64_paging::paging::VirtualAddress;
 use aarch64_paging::MapError;
+use core::arch::asm;
+use core::mem::align_of;
 use core::ops::Range;
+use core::ptr::{addr_of, addr_of_mut};
 use core::result;
+use core::sync::atomic::{AtomicBool, Ordering};
 use log::error;
 use vmbase::{
     layout,
-    memory::{PageTable, SIZE_2MB, SIZE_4KB},
+    memory::{PageTable, MEMORY, SIZE_2MB, SIZE_4KB},
     util::align_up,
 };
 
+/// Size of the stack used while handling untrusted inputs.
+const RESTRICTED_STACK_SIZE: usize = 8 * PVMFW_PAGE_SIZE;
+
+#[repr(C, align(4096))]
+struct RestrictedStack {
+    /// Unmapped page below the stack, which grows downwards, to catch overflows.
+    guard: [u8; PVMFW_PAGE_SIZE],
+    stack: [u8; RESTRICTED_STACK_SIZE],
+}
+
+const _: () = assert!(align_of::<RestrictedStack>() == PVMFW_PAGE_SIZE);
+
+static mut RESTRICTED_STACK: RestrictedStack =
+    RestrictedStack { guard: [0; PVMFW_PAGE_SIZE], stack: [0; RESTRICTED_STACK_SIZE] };
+static RESTRICTED_STACK_IN_USE: AtomicBool = AtomicBool::new(false);
+
 /// Returns memory range reserved for the appended payload.
 pub fn appended_payload_range() -> Range<VirtualAddress> {
     let start = align_up(layout::binary_end().0, SIZE_4KB).unwrap();
@@ -41,12 +62,22 @@ pub fn stack_range() -> Range<VirtualAddress> {
     layout::stack_range(STACK_PAGES * PVMFW_PAGE_SIZE)
 }
 
+/// Guard page of the stack used while handling untrusted inputs.
+fn restricted_stack_guard_range() -> Range<VirtualAddress> {
+    // SAFETY: Only the address of the static is taken, without creating a reference.
+    let start = unsafe { addr_of!(RESTRICTED_STACK.guard) } as usize;
+    VirtualAddress(start)..VirtualAddress(start + PVMFW_PAGE_SIZE)
+}
+
 pub fn init_page_table() -> result::Result<PageTable, MapError> {
     let mut page_table = PageTable::default();
 
     // Stack and scratch ranges are explicitly zeroed and flushed before jumping to payload,
     // so dirty state management can be omitted.
     page_table.map_data(&layout::scratch_range().into())?;
+    // The guard page is part of .bss, which is only zeroed before jumping to the payload once
+    // this page table has been dropped, so it can be left unmapped here.
+    page_table.map_invalid(&restricted_stack_guard_range().into())?;
     page_table.map_data(&stack_range().into())?;
     page_table.map_code(&layout::text_range().into())?;
     page_table.map_rodata(&layout::rodata_range().into())?;
@@ -57,3 +88,70 @@ pub fn init_page_table() -> result::Result<PageTable, MapError> {
     }
     Ok(page_table)
 }
+
+/// Runs `f`, which handles untrusted inputs, on a separate stack and without access to the
+/// appended payload holding secrets, to contain the effects of bugs in parsers.
+///
+/// Without the `reduced_privileges` feature, simply runs `f`.
+pub fn with_reduced_privileges<T>(f: impl FnOnce() -> T) -> result::Result<T, RebootReason> {
+    if !cfg!(feature = "reduced_privileges") {
+        return Ok(f());
+    }
+    if RESTRICTED_STACK_IN_USE.swap(true, Ordering::Acquire) {
+        error!("Nested calls to with_reduced_privileges() aren't supported");
+        return Err(RebootReason::InternalError);
+    }
+    set_payload_accessible(false)?;
+
+    let mut result = None;
+    let stack_top = {
+        // SAFETY: RESTRICTED_STACK_IN_USE guarantees that nothing else uses the stack.
+        let stack = unsafe { &mut *addr_of_mut!(RESTRICTED_STACK) };
+        stack.stack.as_mut_ptr_range().end as usize
+    };
+    // SAFETY: The stack is aligned, mapped as writable data above an unmapped guard page and
+    // exclusively used by this call.
+    unsafe { run_on_stack(stack_top, || result = Some(f())) };
+
+    set_payload_accessible(true)?;
+    RESTRICTED_STACK_IN_USE.store(false, Ordering::Release);
+    Ok(result.unwrap())
+}
+
+fn set_payload_accessible(accessible: bool) -> result::Result<(), RebootReason> {
+    MEMORY.lock().as_mut().unwrap().set_payload_accessible(accessible).map_err(|e| {
+        error!("Failed to change the accessibility of the appended payload: {e}");
+        RebootReason::InternalError
+    })
+}
+
+/// Calls `f` with the stack pointer set to `stack_top`, then restores the current stack.
+///
+/// # Safety
+///
+/// `stack_top` must be the 16-byte aligned end of a writable region large enough for `f`, which
+/// isn't used for anything else.
+unsafe fn run_on_stack<F: FnOnce()>(stack_top: usize, f: F) {
+    extern "C" fn trampoline<F: FnOnce()>(f: *mut Option<F>) {
+        // SAFETY: The pointer comes from a reference held by run_on_stack() during this call.
+        let f = unsafe { &mut *f };
+        f.take().unwrap()();
+    }
+
+    let mut f = Some(f);
+    // SAFETY: x20 is callee-saved so holds the original stack pointer after the call, and the
+    // caller guarantees that the new stack can be used by `f`.
+    unsafe {
+        asm!(
+            "mov x20, sp",
+            "mov sp, {stack_top}",
+            "blr {trampoline}",
+            "mov sp, x20",
+            stack_top = in(reg) stack_top,
+            trampoline = in(reg) trampoline::<F> as usize,
+            in("x0") &mut f as *mut Option<F>,
+            out("x20") _,
+            clobber_abi("C"),
+        );
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Placement of the swiotlb, when the VMM leaves it to the guest.
+
+use core::ops::Range;
+
+/// Picks, based on `seed`, one of the ranges of `size` bytes aligned to `align` within `window`
+/// which don't overlap with `reserved`. Returns `None` if there is no such range.
+pub fn pick_slot(
+    size: usize,
+    align: usize,
+    window: &Range<usize>,
+    reserved: &[Range<usize>],
+    seed: u64,
+) -> Option<Range<usize>> {
+    let count = slots(size, align, window, reserved).count();
+    if count == 0 {
+        return None;
+    }
+    let index = usize::try_from(seed % u64::try_from(count).unwrap()).unwrap();
+    slots(size, align, window, reserved).nth(index)
+}
+
+/// Returns the ranges of `size` bytes aligned to `align` within `window` which don't overlap with
+/// `reserved`.
+fn slots<'a>(
+    size: usize,
+    align: usize,
+    window: &Range<usize>,
+    reserved: &'a [Range<usize>],
+) -> impl Iterator<Item = Range<usize>> + 'a {
+    let end = window.end;
+    (window.start.next_multiple_of(align)..end)
+        .step_by(align)
+        .map_while(move |start| Some(start..start.checked_add(size).filter(|e| *e <= end)?))
+        .filter(|slot| !reserved.iter().any(|r| slot.start < r.end && r.start < slot.end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: usize = 0x1000;
+
+    #[test]
+    fn slots_are_aligned_within_window() {
+        let window = (PAGE / 2)..(6 * PAGE);
+
+        let all: Vec<_> = slots(2 * PAGE, 2 * PAGE, &window, &[]).collect();
+
+        assert_eq!(all, [(2 * PAGE)..(4 * PAGE), (4 * PAGE)..(6 * PAGE)]);
+    }
+
+    #[test]
+    fn slots_skip_reserved_ranges() {
+        let window = 0..(4 * PAGE);
+        let reserved = [(PAGE + 1)..(PAGE + 2), (3 * PAGE)..(5 * PAGE)];
+
+        let all: Vec<_> = slots(PAGE, PAGE, &window, &reserved).collect();
+
+        assert_eq!(all, [0..PAGE, (2 * PAGE)..(3 * PAGE)]);
+    }
+
+    #[test]
+    fn slots_stop_at_end_of_address_space() {
+        let window = (usize::MAX - 2 * PAGE + 1)..usize::MAX;
+
+        assert_eq!(slots(2 * PAGE, PAGE, &window, &[]).count(), 0);
+    }
+
+    #[test]
+    fn pick_slot_uses_seed() {
+        let window = 0..(4 * PAGE);
+
+        assert_eq!(pick_slot(PAGE, PAGE, &window, &[], 0), Some(0..PAGE));
+        assert_eq!(pick_slot(PAGE, PAGE, &window, &[], 6), Some((2 * PAGE)..(3 * PAGE)));
+    }
+
+    #[test]
+    fn pick_slot_fails_without_room() {
+        let window = 0..(4 * PAGE);
+        let reserved = [0..(4 * PAGE)];
+
+        assert_eq!(pick_slot(PAGE, PAGE, &window, &reserved, 0), None);
+        assert_eq!(pick_slot(8 * PAGE, PAGE, &window, &[], 0), None);
+    }
+}
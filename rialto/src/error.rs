@@ -21,7 +21,10 @@ use fdtpci::PciError;
 use hyp::Error as HypervisorError;
 use libfdt::FdtError;
 use service_vm_comm::RequestProcessingError;
-use vmbase::{memory::MemoryTrackerError, virtio::pci};
+use vmbase::{
+    memory::MemoryTrackerError,
+    virtio::{pci, VirtIOError},
+};
 
 pub type Result<T> = result::Result<T, Error>;
 
@@ -43,11 +46,11 @@ pub enum Error {
     /// Failed to initialize PCI.
     PciInitializationFailed(pci::PciError),
     /// Failed to create VirtIO Socket device.
-    VirtIOSocketCreationFailed(virtio_drivers::Error),
+    VirtIOSocketCreationFailed(VirtIOError),
     /// Missing socket device.
     MissingVirtIOSocketDevice,
     /// Failed VirtIO driver operation.
-    VirtIODriverOperationFailed(virtio_drivers::Error),
+    VirtIODriverOperationFailed(VirtIOError),
     /// Failed to serialize.
     SerializationFailed(CiboriumSerError),
     /// Failed to deserialize.
@@ -116,7 +119,7 @@ impl From<MemoryTrackerError> for Error {
 
 impl From<virtio_drivers::Error> for Error {
     fn from(e: virtio_drivers::Error) -> Self {
-        Self::VirtIODriverOperationFailed(e)
+        Self::VirtIODriverOperationFailed(e.into())
     }
 }
 
@@ -42,7 +42,7 @@ use service_vm_fake_chain::service_vm;
 use service_vm_requests::process_request;
 use virtio_drivers::{
     device::socket::{VsockAddr, VMADDR_CID_HOST},
-    transport::{pci::bus::PciRoot, DeviceType, Transport},
+    transport::{pci::bus::PciRoot, DeviceType},
     Hal,
 };
 use vmbase::{
@@ -53,7 +53,7 @@ use vmbase::{
     memory::{MemoryTracker, PageTable, MEMORY, PAGE_SIZE, SIZE_128KB},
     power::reboot,
     virtio::{
-        pci::{self, PciTransportIterator, VirtIOSocket},
+        pci::{self, VirtIODevice, VirtIODeviceRegistry, VirtIOSocket},
         HalImpl,
     },
 };
@@ -195,11 +195,12 @@ unsafe fn try_main(fdt_addr: usize) -> Result<()> {
 }
 
 fn find_socket_device<T: Hal>(pci_root: &mut PciRoot) -> Result<VirtIOSocket<T>> {
-    PciTransportIterator::<T>::new(pci_root)
-        .find(|t| DeviceType::Socket == t.device_type())
-        .map(VirtIOSocket::<T>::new)
-        .transpose()
+    VirtIODeviceRegistry::<T>::new()
+        .register(DeviceType::Socket, pci::probe_socket)
+        .probe_all(pci_root)
         .map_err(Error::VirtIOSocketCreationFailed)?
+        .into_iter()
+        .find_map(VirtIODevice::into_socket)
         .ok_or(Error::MissingVirtIOSocketDevice)
 }
 
@@ -118,6 +118,8 @@ public class MicrodroidBenchmarks extends MicrodroidDeviceTestBase {
     @Before
     public void setup() throws IOException {
         grantPermission(VirtualMachine.MANAGE_VIRTUAL_MACHINE_PERMISSION);
+        grantPermission(CREATE_PROTECTED_VIRTUAL_MACHINE_PERMISSION);
+        grantPermission(CREATE_NON_PROTECTED_VIRTUAL_MACHINE_PERMISSION);
         grantPermission(VirtualMachine.USE_CUSTOM_VIRTUAL_MACHINE_PERMISSION);
         prepareTestSetup(mProtectedVm);
         setMaxPerformanceTaskProfile();
@@ -56,6 +56,12 @@ import java.util.concurrent.TimeUnit;
 
 public abstract class MicrodroidDeviceTestBase {
     private static final String TAG = "MicrodroidDeviceTestBase";
+
+    protected static final String CREATE_PROTECTED_VIRTUAL_MACHINE_PERMISSION =
+            "android.permission.CREATE_PROTECTED_VIRTUAL_MACHINE";
+    protected static final String CREATE_NON_PROTECTED_VIRTUAL_MACHINE_PERMISSION =
+            "android.permission.CREATE_NON_PROTECTED_VIRTUAL_MACHINE";
+
     private final String MAX_PERFORMANCE_TASK_PROFILE = "CPUSET_SP_TOP_APP";
 
     public static boolean isCuttlefish() {
@@ -90,6 +90,18 @@ public final class Pvmfw {
             header.putInt(0);
         }
 
+        if (hasServiceVmDtDigests(mVersion)) {
+            // Add placeholder entry for the golden DT digests of service VMs.
+            header.putInt(0);
+            header.putInt(0);
+        }
+
+        if (hasBootargsAllowList(mVersion)) {
+            // Add placeholder entry for the product-specific bootargs allow-list.
+            header.putInt(0);
+            header.putInt(0);
+        }
+
         try (FileOutputStream pvmfw = new FileOutputStream(outFile)) {
             appendFile(pvmfw, mPvmfwBinFile);
             padTo(pvmfw, SIZE_4K);
@@ -130,7 +142,13 @@ public final class Pvmfw {
         if (version == getVersion(1, 0)) {
             return Integer.BYTES * 8; // Header has 8 integers.
         }
-        return Integer.BYTES * 10; // Default + VM DTBO (offset, size)
+        if (!hasServiceVmDtDigests(version)) {
+            return Integer.BYTES * 10; // Default + VM DTBO (offset, size)
+        }
+        if (!hasBootargsAllowList(version)) {
+            return Integer.BYTES * 12; // Default + VM DTBO + service VM DT digests (offset, size)
+        }
+        return Integer.BYTES * 14; // Default + VM DTBO + DT digests + bootargs (offset, size)
     }
 
     private static boolean hasVmDtbo(int version) {
@@ -139,6 +157,18 @@ public final class Pvmfw {
         return major > 1 || (major == 1 && minor >= 1);
     }
 
+    private static boolean hasServiceVmDtDigests(int version) {
+        int major = getMajorVersion(version);
+        int minor = getMinorVersion(version);
+        return major > 1 || (major == 1 && minor >= 2);
+    }
+
+    private static boolean hasBootargsAllowList(int version) {
+        int major = getMajorVersion(version);
+        int minor = getMinorVersion(version);
+        return major > 1 || (major == 1 && minor >= 3);
+    }
+
     private static int alignTo(int x, int size) {
         return (x + size - 1) & ~(size - 1);
     }
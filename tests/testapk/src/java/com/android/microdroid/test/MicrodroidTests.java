@@ -127,6 +127,8 @@ public class MicrodroidTests extends MicrodroidDeviceTestBase {
     @Before
     public void setup() {
         grantPermission(VirtualMachine.MANAGE_VIRTUAL_MACHINE_PERMISSION);
+        grantPermission(CREATE_PROTECTED_VIRTUAL_MACHINE_PERMISSION);
+        grantPermission(CREATE_NON_PROTECTED_VIRTUAL_MACHINE_PERMISSION);
         prepareTestSetup(mProtectedVm);
         // USE_CUSTOM_VIRTUAL_MACHINE permission has protection level signature|development, meaning
         // that it will be automatically granted when test apk is installed. We have some tests
@@ -140,6 +142,8 @@ public class MicrodroidTests extends MicrodroidDeviceTestBase {
     @After
     public void tearDown() {
         revokePermission(VirtualMachine.MANAGE_VIRTUAL_MACHINE_PERMISSION);
+        revokePermission(CREATE_PROTECTED_VIRTUAL_MACHINE_PERMISSION);
+        revokePermission(CREATE_NON_PROTECTED_VIRTUAL_MACHINE_PERMISSION);
         revokePermission(VirtualMachine.USE_CUSTOM_VIRTUAL_MACHINE_PERMISSION);
     }
 
@@ -785,6 +789,32 @@ public class MicrodroidTests extends MicrodroidDeviceTestBase {
                 .contains("android.permission.USE_CUSTOM_VIRTUAL_MACHINE permission");
     }
 
+    @Test
+    public void runVmRequiresPermissionForProtectionMode() throws Exception {
+        assumeSupportedDevice();
+        assumeFeatureEnabled(VirtualMachineManager.FEATURE_VM_KIND_PERMISSIONS);
+
+        String permission =
+                mProtectedVm
+                        ? CREATE_PROTECTED_VIRTUAL_MACHINE_PERMISSION
+                        : CREATE_NON_PROTECTED_VIRTUAL_MACHINE_PERMISSION;
+        revokePermission(permission);
+
+        VirtualMachineConfig config =
+                newVmConfigBuilder()
+                        .setPayloadBinaryName("MicrodroidTestNativeLib.so")
+                        .setMemoryBytes(minMemoryRequired())
+                        .build();
+
+        VirtualMachine vm =
+                forceCreateNewVirtualMachine("test_vm_protection_mode_requires_permission", config);
+
+        SecurityException e =
+                assertThrows(
+                        SecurityException.class, () -> runVmTestService(TAG, vm, (ts, tr) -> {}));
+        assertThat(e).hasMessageThat().contains(permission + " permission");
+    }
+
     @Test
     @CddTest(requirements = {
             "9.17/C-1-1",
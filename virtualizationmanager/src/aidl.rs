@@ -20,8 +20,14 @@ use crate::atom::{
 use crate::composite::make_composite_image;
 use crate::crosvm::{CrosvmConfig, DiskFile, PayloadState, VfioDevice, VmContext, VmInstance, VmState};
 use crate::debug_config::DebugConfig;
-use crate::payload::{add_microdroid_payload_images, add_microdroid_system_images, add_microdroid_vendor_image};
+use crate::payload::{
+    add_microdroid_payload_images, add_microdroid_system_images, add_microdroid_vendor_image,
+    get_active_apex_version,
+};
+use crate::payload_output::PayloadOutput;
 use crate::selinux::{getfilecon, SeContext};
+use crate::vm_service_server::VmServiceServer;
+use crate::worker_pool::WorkerPool;
 use android_os_permissions_aidl::aidl::android::os::IPermissionController;
 use android_system_virtualizationcommon::aidl::android::system::virtualizationcommon::{
     Certificate::Certificate,
@@ -30,59 +36,71 @@ use android_system_virtualizationcommon::aidl::android::system::virtualizationco
 };
 use android_system_virtualizationservice::aidl::android::system::virtualizationservice::{
     AssignableDevice::AssignableDevice,
+    ConfigValidationIssue::{Category::Category as IssueCategory, ConfigValidationIssue},
     CpuTopology::CpuTopology,
+    DeviceTreeProperty::DeviceTreeProperty,
     DiskImage::DiskImage,
+    GuestOsInfo::GuestOsInfo,
+    IFileOperationCallback::IFileOperationCallback,
     IVirtualMachine::{BnVirtualMachine, IVirtualMachine},
     IVirtualMachineCallback::IVirtualMachineCallback,
     IVirtualizationService::IVirtualizationService,
     IVirtualizationService::FEATURE_MULTI_TENANT,
     IVirtualizationService::FEATURE_VENDOR_MODULES,
+    IVirtualizationService::FEATURE_VM_KIND_PERMISSIONS,
     IVirtualizationService::FEATURE_DICE_CHANGES,
+    IVirtualizationService::ERROR_CREATION_CANCELED,
     MemoryTrimLevel::MemoryTrimLevel,
     Partition::Partition,
     PartitionType::PartitionType,
     VirtualMachineAppConfig::{DebugLevel::DebugLevel, Payload::Payload, VirtualMachineAppConfig},
     VirtualMachineConfig::VirtualMachineConfig,
     VirtualMachineDebugInfo::VirtualMachineDebugInfo,
+    VirtualMachineInfo::VirtualMachineInfo,
     VirtualMachinePayloadConfig::VirtualMachinePayloadConfig,
     VirtualMachineRawConfig::VirtualMachineRawConfig,
     VirtualMachineState::VirtualMachineState,
+    VmCreationPolicyDecision::{Kind::Kind as VmCreationPolicyKind, VmCreationPolicyDecision},
 };
 use android_system_virtualizationservice_internal::aidl::android::system::virtualizationservice_internal::IVirtualizationServiceInternal::IVirtualizationServiceInternal;
 use android_system_virtualmachineservice::aidl::android::system::virtualmachineservice::IVirtualMachineService::{
         BnVirtualMachineService, IVirtualMachineService,
 };
+use android_system_virtualmachineservice::aidl::android::system::virtualmachineservice::PayloadOutputStream::PayloadOutputStream;
 use anyhow::{anyhow, bail, Context, Result};
 use apkverify::{HashAlgorithm, V4Signature};
 use avflog::LogResult;
 use binder::{
-    self, wait_for_interface, BinderFeatures, ExceptionCode, Interface, ParcelFileDescriptor,
-    Status, StatusCode, Strong,
+    self, wait_for_interface, BinderFeatures, DeathRecipient, ExceptionCode, IBinder, Interface,
+    ParcelFileDescriptor, SpIBinder, Status, StatusCode, Strong,
     IntoBinderResult,
 };
 use disk::QcowFile;
 use lazy_static::lazy_static;
-use libfdt::Fdt;
+use libfdt::{Fdt, OverlayBuilder};
 use log::{debug, error, info, warn};
 use microdroid_payload_config::{OsConfig, Task, TaskType, VmPayloadConfig};
 use nix::unistd::pipe;
 use regex::Regex;
-use rpcbinder::RpcServer;
 use rustutils::system_properties;
 use semver::VersionReq;
 use std::collections::HashSet;
 use std::convert::TryInto;
 use std::ffi::{CStr, CString};
+use std::fmt;
 use std::fs::{canonicalize, read_dir, remove_file, File, OpenOptions};
 use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
 use std::num::{NonZeroU16, NonZeroU32};
 use std::os::unix::io::{FromRawFd, IntoRawFd};
-use std::os::unix::raw::pid_t;
+use std::os::unix::raw::{pid_t, uid_t};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, Weak};
 use vbmeta::VbMetaImage;
 use vmconfig::VmConfig;
 use vsock::VsockStream;
+use vsock_limits::ConnectionTracker;
 use zip::ZipArchive;
 
 /// The unique ID of a VM used (together with a port number) for vsock communication.
@@ -102,27 +120,60 @@ const ANDROID_VM_INSTANCE_VERSION: u16 = 1;
 
 const MICRODROID_OS_NAME: &str = "microdroid";
 
+const MICRODROID_GKI_OS_NAME_PREFIX: &str = "microdroid_gki-";
+
+const VIRT_APEX_NAME: &str = "com.android.virt";
+
+/// Directory of the virt APEX holding the configs and images of the guest OSes.
+const VIRT_APEX_ETC_DIR: &str = "/apex/com.android.virt/etc";
+
 const UNFORMATTED_STORAGE_MAGIC: &str = "UNFORMATTED-STORAGE";
 
 /// Roughly estimated sufficient size for storing vendor public key into DTBO.
 const EMPTY_VENDOR_DT_OVERLAY_BUF_SIZE: usize = 10000;
 
+/// Maximum number of extra DT properties a client can request.
+const MAX_EXTRA_DT_PROPERTIES: usize = 16;
+
+/// Maximum length of the value of an extra DT property, excluding the nul terminator.
+const MAX_EXTRA_DT_PROPERTY_VALUE_LEN: usize = 256;
+
+/// Size of the buffer in which the DTBO holding extra DT properties is built.
+const EXTRA_DT_PROPERTIES_OVERLAY_BUF_SIZE: usize = 16384;
+
+/// Maximum length of the strings reported by the guest about itself, e.g. its kernel release.
+const MAX_GUEST_STRING_LEN: usize = 256;
+
 /// crosvm requires all partitions to be a multiple of 4KiB.
 const PARTITION_GRANULARITY_BYTES: u64 = 4096;
 
+/// Number of threads doing the long-running file I/O (idsig generation, partition initialization)
+/// requested through the asynchronous methods. The synchronous ones run on the binder threads.
+const FILE_IO_WORKERS: usize = 2;
+
+/// Number of asynchronous file operations which can be waiting for a worker. Beyond this, they fail
+/// immediately.
+const FILE_IO_QUEUE_LEN: usize = 16;
+
 lazy_static! {
     pub static ref GLOBAL_SERVICE: Strong<dyn IVirtualizationServiceInternal> =
         wait_for_interface(BINDER_SERVICE_IDENTIFIER)
             .expect("Could not connect to VirtualizationServiceInternal");
     static ref MICRODROID_GKI_OS_NAME_PATTERN: Regex =
         Regex::new(r"^microdroid_gki-android\d+-\d+\.\d+$").expect("Failed to construct Regex");
+    static ref FILE_IO_POOL: WorkerPool =
+        WorkerPool::new("virtmgr_io", FILE_IO_WORKERS, FILE_IO_QUEUE_LEN)
+            .expect("Failed to start the file I/O workers");
 }
 
 fn create_or_update_idsig_file(
     input_fd: &ParcelFileDescriptor,
     idsig_fd: &ParcelFileDescriptor,
 ) -> Result<()> {
-    let mut input = clone_file(input_fd)?;
+    create_or_update_idsig(clone_file(input_fd)?, clone_file(idsig_fd)?)
+}
+
+fn create_or_update_idsig(mut input: File, mut output: File) -> Result<()> {
     let metadata = input.metadata().context("failed to get input metadata")?;
     if !metadata.is_file() {
         bail!("input is not a regular file");
@@ -131,8 +182,6 @@ fn create_or_update_idsig_file(
         V4Signature::create(&mut input, get_current_sdk()?, 4096, &[], HashAlgorithm::SHA256)
             .context("failed to create idsig")?;
 
-    let mut output = clone_file(idsig_fd)?;
-
     // Optimization. We don't have to update idsig file whenever a VM is started. Don't update it,
     // if the idsig file already has the same APK digest.
     if output.metadata()?.len() > 0 {
@@ -168,6 +217,10 @@ pub fn remove_temporary_files(path: &PathBuf) -> Result<()> {
 #[derive(Debug, Default)]
 pub struct VirtualizationService {
     state: Arc<Mutex<State>>,
+    /// Open connections from the VMs to their VirtualMachineService.
+    connections: ConnectionTracker,
+    /// The in-progress cancellable VM creations, with the tokens identifying them.
+    pending_creations: Arc<Mutex<Vec<(SpIBinder, CreationCancellation)>>>,
 }
 
 impl Interface for VirtualizationService {
@@ -183,14 +236,20 @@ impl Interface for VirtualizationService {
             writeln!(writer, "\tPayload state {:?}", vm.payload_state())
                 .or(Err(StatusCode::UNKNOWN_ERROR))?;
             writeln!(writer, "\tProtected: {}", vm.protected).or(Err(StatusCode::UNKNOWN_ERROR))?;
+            writeln!(writer, "\ttags: {:?}", vm.tags).or(Err(StatusCode::UNKNOWN_ERROR))?;
             writeln!(writer, "\ttemporary_directory: {}", vm.temporary_directory.to_string_lossy())
                 .or(Err(StatusCode::UNKNOWN_ERROR))?;
             writeln!(writer, "\trequester_uid: {}", vm.requester_uid)
                 .or(Err(StatusCode::UNKNOWN_ERROR))?;
             writeln!(writer, "\trequester_debug_pid: {}", vm.requester_debug_pid)
                 .or(Err(StatusCode::UNKNOWN_ERROR))?;
+            let versions = vm.component_versions.lock().unwrap().clone();
+            writeln!(writer, "\tguest_kernel_version: {:?}", versions.kernel_release)
+                .or(Err(StatusCode::UNKNOWN_ERROR))?;
+            writeln!(writer, "\tpvmfw_version: {:?}", versions.pvmfw_version)
+                .or(Err(StatusCode::UNKNOWN_ERROR))?;
         }
-        Ok(())
+        self.connections.dump(writer).or(Err(StatusCode::UNKNOWN_ERROR))
     }
 }
 impl IVirtualizationService for VirtualizationService {
@@ -205,18 +264,63 @@ impl IVirtualizationService for VirtualizationService {
         console_in_fd: Option<&ParcelFileDescriptor>,
         log_fd: Option<&ParcelFileDescriptor>,
     ) -> binder::Result<Strong<dyn IVirtualMachine>> {
-        let mut is_protected = false;
-        let ret = self.create_vm_internal(
-            config,
-            console_out_fd,
-            console_in_fd,
-            log_fd,
-            &mut is_protected,
-        );
-        write_vm_creation_stats(config, is_protected, &ret);
+        let cancellation = CreationCancellation::default();
+        self.create_vm(config, console_out_fd, console_in_fd, log_fd, &cancellation)
+    }
+
+    /// Same as `createVm`, but the creation is aborted if `cancelCreate` is called with `token` or
+    /// if `token` dies.
+    fn createVmCancellable(
+        &self,
+        token: &SpIBinder,
+        config: &VirtualMachineConfig,
+        console_out_fd: Option<&ParcelFileDescriptor>,
+        console_in_fd: Option<&ParcelFileDescriptor>,
+        log_fd: Option<&ParcelFileDescriptor>,
+    ) -> binder::Result<Strong<dyn IVirtualMachine>> {
+        let cancellation = CreationCancellation::default();
+        let mut death_recipient = {
+            let cancellation = cancellation.clone();
+            DeathRecipient::new(move || {
+                info!("Client of an in-progress VM creation died, cancelling it");
+                cancellation.cancel();
+            })
+        };
+        let mut token = token.clone();
+        token.link_to_death(&mut death_recipient)?;
+
+        self.pending_creations.lock().unwrap().push((token, cancellation.clone()));
+        let ret = self.create_vm(config, console_out_fd, console_in_fd, log_fd, &cancellation);
+        self.pending_creations.lock().unwrap().retain(|(_, c)| !c.is_same(&cancellation));
         ret
     }
 
+    /// Cancels the in-progress `createVmCancellable` calls made with `token`.
+    fn cancelCreate(&self, token: &SpIBinder) -> binder::Result<()> {
+        check_manage_access()?;
+        for (_, cancellation) in
+            self.pending_creations.lock().unwrap().iter().filter(|(t, _)| t == token)
+        {
+            cancellation.cancel();
+        }
+        Ok(())
+    }
+
+    /// Returns whether the device policies currently allow the creation of VMs.
+    fn checkVmCreationPolicy(&self) -> binder::Result<VmCreationPolicyDecision> {
+        check_manage_access()?;
+        GLOBAL_SERVICE.checkVmCreationPolicy()
+    }
+
+    /// Performs the static checks of `createVm` on the config, without creating the VM.
+    fn validateConfig(
+        &self,
+        config: &VirtualMachineConfig,
+    ) -> binder::Result<Vec<ConfigValidationIssue>> {
+        check_manage_access()?;
+        Ok(validate_config(config))
+    }
+
     /// Initialise an empty partition image of the given size to be used as a writable partition.
     fn initializeWritablePartition(
         &self,
@@ -225,30 +329,27 @@ impl IVirtualizationService for VirtualizationService {
         partition_type: PartitionType,
     ) -> binder::Result<()> {
         check_manage_access()?;
-        let size_bytes = size_bytes
-            .try_into()
-            .with_context(|| format!("Invalid size: {}", size_bytes))
-            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)?;
-        let size_bytes = round_up(size_bytes, PARTITION_GRANULARITY_BYTES);
         let image = clone_file(image_fd)?;
-        // initialize the file. Any data in the file will be erased.
-        image.set_len(0).context("Failed to reset a file").or_service_specific_exception(-1)?;
-        let mut part = QcowFile::new(image, size_bytes)
-            .context("Failed to create QCOW2 image")
-            .or_service_specific_exception(-1)?;
-
-        match partition_type {
-            PartitionType::RAW => Ok(()),
-            PartitionType::ANDROID_VM_INSTANCE => format_as_android_vm_instance(&mut part),
-            PartitionType::ENCRYPTEDSTORE => format_as_encryptedstore(&mut part),
-            _ => Err(Error::new(
-                ErrorKind::Unsupported,
-                format!("Unsupported partition type {:?}", partition_type),
-            )),
-        }
-        .with_context(|| format!("Failed to initialize partition as {:?}", partition_type))
-        .or_service_specific_exception(-1)?;
+        initialize_writable_partition(image, size_bytes, partition_type)
+    }
 
+    /// Same as `initializeWritablePartition`, but reports the outcome to `callback` instead of
+    /// blocking the calling binder thread.
+    fn initializeWritablePartitionAsync(
+        &self,
+        image_fd: &ParcelFileDescriptor,
+        size_bytes: i64,
+        partition_type: PartitionType,
+        callback: &Strong<dyn IFileOperationCallback>,
+    ) -> binder::Result<()> {
+        spawn_file_operation(callback, || {
+            check_manage_access()?;
+            let image = clone_file(image_fd)?;
+            Ok(move || {
+                initialize_writable_partition(image, size_bytes, partition_type)
+                    .map_err(|e| anyhow!("{e}"))
+            })
+        });
         Ok(())
     }
 
@@ -259,8 +360,23 @@ impl IVirtualizationService for VirtualizationService {
         idsig_fd: &ParcelFileDescriptor,
     ) -> binder::Result<()> {
         check_manage_access()?;
+        create_or_update_idsig_file(input_fd, idsig_fd).or_service_specific_exception(-1)
+    }
 
-        create_or_update_idsig_file(input_fd, idsig_fd).or_service_specific_exception(-1)?;
+    /// Same as `createOrUpdateIdsigFile`, but reports the outcome to `callback` instead of
+    /// blocking the calling binder thread.
+    fn createOrUpdateIdsigFileAsync(
+        &self,
+        input_fd: &ParcelFileDescriptor,
+        idsig_fd: &ParcelFileDescriptor,
+        callback: &Strong<dyn IFileOperationCallback>,
+    ) -> binder::Result<()> {
+        spawn_file_operation(callback, || {
+            check_manage_access()?;
+            let input = clone_file(input_fd)?;
+            let output = clone_file(idsig_fd)?;
+            Ok(move || create_or_update_idsig(input, output))
+        });
         Ok(())
     }
 
@@ -277,6 +393,16 @@ impl IVirtualizationService for VirtualizationService {
         GLOBAL_SERVICE.getAssignableDevices()
     }
 
+    /// Get the list of guest OSes which VMs can boot.
+    fn getSupportedGuestOses(&self) -> binder::Result<Vec<GuestOsInfo>> {
+        check_manage_access()?;
+
+        list_guest_oses()
+            .context("Failed to list guest OSes")
+            .with_log()
+            .or_service_specific_exception(-1)
+    }
+
     /// Returns whether given feature is enabled
     fn isFeatureEnabled(&self, feature: &str) -> binder::Result<bool> {
         check_manage_access()?;
@@ -287,6 +413,7 @@ impl IVirtualizationService for VirtualizationService {
             FEATURE_DICE_CHANGES => Ok(cfg!(dice_changes)),
             FEATURE_MULTI_TENANT => Ok(cfg!(multi_tenant)),
             FEATURE_VENDOR_MODULES => Ok(cfg!(vendor_modules)),
+            FEATURE_VM_KIND_PERMISSIONS => Ok(cfg!(vm_kind_permissions)),
             _ => {
                 warn!("unknown feature {feature}");
                 Ok(false)
@@ -300,27 +427,50 @@ impl VirtualizationService {
         VirtualizationService::default()
     }
 
+    fn create_vm(
+        &self,
+        config: &VirtualMachineConfig,
+        console_out_fd: Option<&ParcelFileDescriptor>,
+        console_in_fd: Option<&ParcelFileDescriptor>,
+        log_fd: Option<&ParcelFileDescriptor>,
+        cancellation: &CreationCancellation,
+    ) -> binder::Result<Strong<dyn IVirtualMachine>> {
+        let mut is_protected = false;
+        let ret = self.create_vm_internal(
+            config,
+            console_out_fd,
+            console_in_fd,
+            log_fd,
+            &mut is_protected,
+            cancellation,
+        );
+        write_vm_creation_stats(config, is_protected, &ret);
+        ret
+    }
+
     fn create_vm_context(
         &self,
+        requester_uid: uid_t,
         requester_debug_pid: pid_t,
+        tags: &[String],
     ) -> binder::Result<(VmContext, Cid, PathBuf)> {
         const NUM_ATTEMPTS: usize = 5;
 
         for _ in 0..NUM_ATTEMPTS {
-            let vm_context = GLOBAL_SERVICE.allocateGlobalVmContext(requester_debug_pid)?;
+            let vm_context = GLOBAL_SERVICE.allocateGlobalVmContext(requester_debug_pid, tags)?;
             let cid = vm_context.getCid()? as Cid;
             let temp_dir: PathBuf = vm_context.getTemporaryDirectory()?.into();
             let service = VirtualMachineService::new_binder(self.state.clone(), cid).as_binder();
 
             // Start VM service listening for connections from the new CID on port=CID.
             let port = cid;
-            match RpcServer::new_vsock(service, cid, port) {
+            let connections = self.connections.clone();
+            match VmServiceServer::new(service, cid, port, requester_uid, connections) {
                 Ok(vm_server) => {
-                    vm_server.start();
                     return Ok((VmContext::new(vm_context, vm_server), cid, temp_dir));
                 }
                 Err(err) => {
-                    warn!("Could not start RpcServer on port {}: {}", port, err);
+                    warn!("Could not start RpcServer on port {}: {:?}", port, err);
                 }
             }
         }
@@ -335,30 +485,24 @@ impl VirtualizationService {
         console_in_fd: Option<&ParcelFileDescriptor>,
         log_fd: Option<&ParcelFileDescriptor>,
         is_protected: &mut bool,
+        cancellation: &CreationCancellation,
     ) -> binder::Result<Strong<dyn IVirtualMachine>> {
         let requester_uid = get_calling_uid();
         let requester_debug_pid = get_calling_pid();
 
         check_config_features(config)?;
 
+        let tags = extract_tags(config).to_vec();
+        check_tags(&tags).or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)?;
+
+        // Check this before allocating any resource for the VM.
+        check_vm_kind_allowed(config)?;
+
         // Allocating VM context checks the MANAGE_VIRTUAL_MACHINE permission.
-        let (vm_context, cid, temporary_directory) = self.create_vm_context(requester_debug_pid)?;
+        let (vm_context, cid, temporary_directory) =
+            self.create_vm_context(requester_uid, requester_debug_pid, &tags)?;
 
-        let is_custom = match config {
-            VirtualMachineConfig::RawConfig(_) => true,
-            VirtualMachineConfig::AppConfig(config) => {
-                // Some features are reserved for platform apps only, even when using
-                // VirtualMachineAppConfig. Almost all of these features are grouped in the
-                // CustomConfig struct:
-                // - controlling CPUs;
-                // - specifying a config file in the APK; (this one is not part of CustomConfig)
-                // - gdbPort is set, meaning that crosvm will start a gdb server;
-                // - using anything other than the default kernel;
-                // - specifying devices to be assigned.
-                config.customConfig.is_some() || matches!(config.payload, Payload::ConfigPath(_))
-            }
-        };
-        if is_custom {
+        if is_custom_config(config) {
             check_use_custom_virtual_machine()?;
         }
 
@@ -369,6 +513,12 @@ impl VirtualizationService {
             check_gdb_allowed(config)?;
         }
 
+        let extra_crosvm_args = extract_extra_crosvm_args(config).to_vec();
+        if !extra_crosvm_args.is_empty() {
+            check_extra_crosvm_args_allowed()?;
+            warn!("VM with CID {cid} requested extra crosvm arguments: {extra_crosvm_args:?}");
+        }
+
         let vendor_public_key = extract_vendor_public_key(config)
             .context("Failed to extract vendor public key")
             .or_service_specific_exception(-1)?;
@@ -424,33 +574,12 @@ impl VirtualizationService {
         };
         let config = config.as_ref();
         *is_protected = config.protectedVm;
+        cancellation.check(&temporary_directory)?;
 
-        // Check if partition images are labeled incorrectly. This is to prevent random images
-        // which are not protected by the Android Verified Boot (e.g. bits downloaded by apps) from
-        // being loaded in a pVM. This applies to everything but the instance image in the raw
-        // config, and everything but the non-executable, generated partitions in the app
-        // config.
-        config
-            .disks
-            .iter()
-            .flat_map(|disk| disk.partitions.iter())
-            .filter(|partition| {
-                if is_app_config {
-                    !is_safe_app_partition(&partition.label)
-                } else {
-                    !is_safe_raw_partition(&partition.label)
-                }
-            })
-            .try_for_each(check_label_for_partition)
+        check_partition_labels(config, is_app_config)
+            .try_for_each(|result| result)
             .or_service_specific_exception(-1)?;
-
-        let kernel = maybe_clone_file(&config.kernel)?;
-        let initrd = maybe_clone_file(&config.initrd)?;
-
-        // In a protected VM, we require custom kernels to come from a trusted source (b/237054515).
-        if config.protectedVm {
-            check_label_for_kernel_files(&kernel, &initrd).or_service_specific_exception(-1)?;
-        }
+        let (kernel, initrd) = clone_kernel_files(config)?;
 
         let zero_filler_path = temporary_directory.join("zero.img");
         write_zero_filler(&zero_filler_path)
@@ -472,28 +601,27 @@ impl VirtualizationService {
                 )
             })
             .collect::<Result<Vec<DiskFile>, _>>()?;
+        cancellation.check(&temporary_directory)?;
 
-        let (cpus, host_cpu_topology) = match config.cpuTopology {
-            CpuTopology::MATCH_HOST => (None, true),
-            CpuTopology::ONE_CPU => (NonZeroU32::new(1), false),
-            val => {
-                return Err(anyhow!("Failed to parse CPU topology value {:?}", val))
-                    .with_log()
-                    .or_service_specific_exception(-1);
-            }
+        let (cpus, host_cpu_topology) =
+            parse_cpu_topology(config.cpuTopology).with_log().or_service_specific_exception(-1)?;
+
+        check_config_extra_dt_properties(config)?;
+        let dtbo_host_properties = if !config.extraDtProperties.is_empty() {
+            let dtbo_path = temporary_directory.join("dtbo_host_properties");
+            create_dtbo_for_extra_dt_properties(&config.extraDtProperties, &dtbo_path)
+                .context("Failed to write extra DT properties")
+                .or_service_specific_exception(-1)?;
+            let file = File::open(dtbo_path)
+                .context("Failed to open dtbo_host_properties")
+                .or_service_specific_exception(-1)?;
+            Some(file)
+        } else {
+            None
         };
 
         let (vfio_devices, dtbo) = if !config.devices.is_empty() {
-            let mut set = HashSet::new();
-            for device in config.devices.iter() {
-                let path = canonicalize(device)
-                    .with_context(|| format!("can't canonicalize {device}"))
-                    .or_service_specific_exception(-1)?;
-                if !set.insert(path) {
-                    return Err(anyhow!("duplicated device {device}"))
-                        .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
-                }
-            }
+            canonicalize_devices(&config.devices).try_for_each(|path| path.map(|_| ()))?;
             let devices = GLOBAL_SERVICE
                 .bindDevicesToVfioDriver(&config.devices)?
                 .into_iter()
@@ -515,6 +643,8 @@ impl VirtualizationService {
             (vec![], None)
         };
 
+        cancellation.check(&temporary_directory)?;
+
         // Actually start the VM.
         let crosvm_config = CrosvmConfig {
             cid,
@@ -541,6 +671,9 @@ impl VirtualizationService {
             vfio_devices,
             dtbo,
             dtbo_vendor,
+            dtbo_host_properties,
+            extra_args: extra_crosvm_args,
+            tags,
         };
         let instance = Arc::new(
             VmInstance::new(
@@ -621,6 +754,80 @@ fn create_dtbo_for_vendor_image(vendor_public_key: &[u8], dtbo: &PathBuf) -> Res
     Ok(file.flush()?)
 }
 
+/// Checks that the extra DT properties of the config are supported and valid.
+fn check_config_extra_dt_properties(config: &VirtualMachineRawConfig) -> binder::Result<()> {
+    if config.extraDtProperties.is_empty() {
+        return Ok(());
+    }
+    if config.protectedVm {
+        return Err(anyhow!("Extra DT properties aren't supported for protected VMs"))
+            .or_binder_exception(ExceptionCode::UNSUPPORTED_OPERATION);
+    }
+    check_extra_dt_properties(&config.extraDtProperties)
+        .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)
+}
+
+/// Checks that the extra DT properties requested by a client are valid and reasonably small.
+fn check_extra_dt_properties(properties: &[DeviceTreeProperty]) -> Result<()> {
+    if properties.len() > MAX_EXTRA_DT_PROPERTIES {
+        bail!("Too many extra DT properties: {} > {MAX_EXTRA_DT_PROPERTIES}", properties.len());
+    }
+    let mut names = HashSet::new();
+    for property in properties {
+        let name = &property.name;
+        // Property names are limited to 31 characters by the DT specification (2.2.4.1).
+        let valid_char = |c: char| c.is_ascii_alphanumeric() || ",._+?#-".contains(c);
+        if name.is_empty() || name.len() > 31 || !name.chars().all(valid_char) {
+            bail!("Invalid DT property name {name:?}");
+        }
+        if !names.insert(name) {
+            bail!("Duplicated DT property {name:?}");
+        }
+        let value = &property.value;
+        if value.len() > MAX_EXTRA_DT_PROPERTY_VALUE_LEN || value.contains('\0') {
+            bail!("Invalid value for DT property {name:?}");
+        }
+    }
+    Ok(())
+}
+
+fn create_dtbo_for_extra_dt_properties(
+    properties: &[DeviceTreeProperty],
+    dtbo: &PathBuf,
+) -> Result<()> {
+    if dtbo.exists() {
+        return Err(anyhow!("DTBO file already exists"));
+    }
+
+    let mut buf = vec![0; EXTRA_DT_PROPERTIES_OVERLAY_BUF_SIZE];
+    let mut builder = OverlayBuilder::new(buf.as_mut_slice())
+        .map_err(|e| anyhow!("Failed to create FDT: {:?}", e))?;
+    let target_path = CString::new("/")?;
+    let mut overlay_node = builder
+        .add_fragment(target_path.as_c_str())
+        .map_err(|e| anyhow!("Failed to create fragment: {:?}", e))?;
+    let avf_node_name = CString::new("avf")?;
+    let mut avf_node = overlay_node
+        .add_subnode(avf_node_name.as_c_str())
+        .map_err(|e| anyhow!("Failed to create avf node: {:?}", e))?;
+    let host_properties_node_name = CString::new("host_properties")?;
+    let mut host_properties_node = avf_node
+        .add_subnode(host_properties_node_name.as_c_str())
+        .map_err(|e| anyhow!("Failed to create avf/host_properties node: {:?}", e))?;
+    for property in properties {
+        let name = CString::new(property.name.as_str())?;
+        let value = CString::new(property.value.as_str())?;
+        host_properties_node
+            .setprop(&name, value.as_bytes_with_nul())
+            .map_err(|e| anyhow!("Failed to set avf/host_properties/{name:?}: {:?}", e))?;
+    }
+
+    let fdt = builder.finish().map_err(|e| anyhow!("Failed to pack fdt: {:?}", e))?;
+    let mut file = File::create(dtbo)?;
+    file.write_all(fdt.as_slice())?;
+    Ok(file.flush()?)
+}
+
 fn write_zero_filler(zero_filler_path: &Path) -> Result<()> {
     let file = OpenOptions::new()
         .create_new(true)
@@ -632,6 +839,73 @@ fn write_zero_filler(zero_filler_path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Initializes `image` as an empty partition of the given type. Any data in the file is erased.
+fn initialize_writable_partition(
+    image: File,
+    size_bytes: i64,
+    partition_type: PartitionType,
+) -> binder::Result<()> {
+    let size_bytes = size_bytes
+        .try_into()
+        .with_context(|| format!("Invalid size: {}", size_bytes))
+        .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)?;
+    let size_bytes = round_up(size_bytes, PARTITION_GRANULARITY_BYTES);
+    image.set_len(0).context("Failed to reset a file").or_service_specific_exception(-1)?;
+    let mut part = QcowFile::new(image, size_bytes)
+        .context("Failed to create QCOW2 image")
+        .or_service_specific_exception(-1)?;
+
+    match partition_type {
+        PartitionType::RAW => Ok(()),
+        PartitionType::ANDROID_VM_INSTANCE => format_as_android_vm_instance(&mut part),
+        PartitionType::ENCRYPTEDSTORE => format_as_encryptedstore(&mut part),
+        _ => Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("Unsupported partition type {:?}", partition_type),
+        )),
+    }
+    .with_context(|| format!("Failed to initialize partition as {:?}", partition_type))
+    .or_service_specific_exception(-1)?;
+
+    Ok(())
+}
+
+/// Runs the operation returned by `prepare` on the file I/O workers, and reports its outcome to
+/// `callback`. The errors of `prepare` and of queueing the operation are reported too, as the
+/// caller of a oneway method never sees the errors it returns.
+fn spawn_file_operation<F>(
+    callback: &Strong<dyn IFileOperationCallback>,
+    prepare: impl FnOnce() -> binder::Result<F>,
+) where
+    F: FnOnce() -> Result<()> + Send + 'static,
+{
+    let queued = prepare().map_err(|e| anyhow!("{e}")).and_then(|operation| {
+        let callback = callback.clone();
+        FILE_IO_POOL.spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(operation))
+                .unwrap_or_else(|_| Err(anyhow!("File operation panicked")));
+            report_file_operation(&callback, result);
+        })
+    });
+    if let Err(e) = queued {
+        report_file_operation(callback, Err(e));
+    }
+}
+
+/// Reports the outcome of an asynchronous file operation to the client.
+fn report_file_operation(callback: &Strong<dyn IFileOperationCallback>, result: Result<()>) {
+    let reported = match result {
+        Ok(()) => callback.onCompleted(),
+        Err(e) => {
+            error!("File operation failed: {e:?}");
+            callback.onFailed(&format!("{e:#}"))
+        }
+    };
+    if let Err(e) = reported {
+        warn!("Failed to report the outcome of a file operation: {e:?}");
+    }
+}
+
 fn format_as_android_vm_instance(part: &mut dyn Write) -> std::io::Result<()> {
     part.write_all(ANDROID_VM_INSTANCE_MAGIC.as_bytes())?;
     part.write_all(&ANDROID_VM_INSTANCE_VERSION.to_le_bytes())?;
@@ -663,10 +937,9 @@ fn assemble_disk_image(
     indirect_files: &mut Vec<File>,
 ) -> Result<DiskFile, Status> {
     let image = if !disk.partitions.is_empty() {
-        if disk.image.is_some() {
-            warn!("DiskImage {:?} contains both image and partitions.", disk);
-            return Err(anyhow!("DiskImage contains both image and partitions"))
-                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+        if let Err(e) = check_disk_image(disk) {
+            warn!("DiskImage {:?} is invalid: {e}", disk);
+            return Err(e).or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
         }
 
         let composite_image_filenames =
@@ -717,6 +990,37 @@ fn is_valid_os(os_name: &str) -> bool {
     }
 }
 
+/// Lists the guest OSes which VMs can boot, i.e. the OSes whose config is installed and for which
+/// `is_valid_os()` holds.
+fn list_guest_oses() -> Result<Vec<GuestOsInfo>> {
+    let apex_version = get_active_apex_version(VIRT_APEX_NAME)?;
+    let mut oses = Vec::new();
+    for entry in read_dir(VIRT_APEX_ETC_DIR)? {
+        let path = entry?.path();
+        if path.extension().map_or(true, |ext| ext != "json") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if !is_valid_os(name) {
+            continue;
+        }
+        let config = VmConfig::load(&File::open(&path)?)
+            .with_context(|| format!("Failed to load {}", path.display()))?;
+        let debuggable_initrd = format!("{VIRT_APEX_ETC_DIR}/{name}_initrd_debuggable.img");
+        oses.push(GuestOsInfo {
+            name: name.to_owned(),
+            apexVersion: apex_version.try_into()?,
+            gkiRelease: name.strip_prefix(MICRODROID_GKI_OS_NAME_PREFIX).map(str::to_owned),
+            platformVersion: config.platform_version.to_string(),
+            debuggable: Path::new(&debuggable_initrd).exists(),
+        });
+    }
+    oses.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(oses)
+}
+
 fn load_app_config(
     config: &VirtualMachineAppConfig,
     debug_config: &DebugConfig,
@@ -765,6 +1069,8 @@ fn load_app_config(
         }
 
         vm_config.devices = custom_config.devices.clone();
+        vm_config.extraCrosvmArgs = custom_config.extraCrosvmArgs.clone();
+        vm_config.extraDtProperties = custom_config.extraDtProperties.clone();
     }
 
     if config.memoryMib > 0 {
@@ -876,6 +1182,49 @@ fn check_use_custom_virtual_machine() -> binder::Result<()> {
     check_permission("android.permission.USE_CUSTOM_VIRTUAL_MACHINE")
 }
 
+/// Check whether the caller of the current Binder method is allowed to create the kind of VM
+/// described by `config`. Protected VMs, non-protected VMs and VMs with assigned devices each
+/// require their own permission, on top of MANAGE_VIRTUAL_MACHINE, so that they can be granted
+/// separately.
+///
+/// Native callers are exempt, as their access to VMs is already confined by SELinux. So are all
+/// callers unless the permissions are enabled by the `vm_kind_permissions` build flag, so that apps
+/// which only hold MANAGE_VIRTUAL_MACHINE keep working until then.
+fn check_vm_kind_allowed(config: &VirtualMachineConfig) -> binder::Result<()> {
+    if !cfg!(vm_kind_permissions) || !is_app_uid(get_calling_uid()) {
+        return Ok(());
+    }
+    if is_protected(config) {
+        check_permission("android.permission.CREATE_PROTECTED_VIRTUAL_MACHINE")?;
+    } else {
+        check_permission("android.permission.CREATE_NON_PROTECTED_VIRTUAL_MACHINE")?;
+    }
+    if !extract_devices(config).is_empty() {
+        check_permission("android.permission.USE_VIRTUAL_MACHINE_DEVICE_ASSIGNMENT")?;
+    }
+    Ok(())
+}
+
+/// Returns whether `uid` belongs to an app, in any user, rather than to a native process.
+fn is_app_uid(uid: uid_t) -> bool {
+    const AID_USER_OFFSET: uid_t = 100000;
+    const AID_APP_START: uid_t = 10000;
+    uid % AID_USER_OFFSET >= AID_APP_START
+}
+
+/// Check whether the caller of the current Binder method is allowed to pass extra arguments to
+/// crosvm. This is only ever allowed on debuggable builds.
+fn check_extra_crosvm_args_allowed() -> binder::Result<()> {
+    let debuggable = system_properties::read_bool("ro.debuggable", false)
+        .context("Failed to read ro.debuggable")
+        .or_service_specific_exception(-1)?;
+    if !debuggable {
+        return Err(anyhow!("Extra crosvm arguments are only allowed on debuggable builds"))
+            .or_binder_exception(ExceptionCode::SECURITY);
+    }
+    check_permission("android.permission.USE_CUSTOM_CROSVM_ARGUMENTS")
+}
+
 /// Return whether a partition is exempt from selinux label checks, because we know that it does
 /// not contain code and is likely to be generated in an app-writable directory.
 fn is_safe_app_partition(label: &str) -> bool {
@@ -915,6 +1264,74 @@ fn check_label_is_allowed(context: &SeContext) -> Result<()> {
     }
 }
 
+/// Parses the CPU topology of the config into the number of vCPUs and whether the topology of the
+/// host must be matched.
+fn parse_cpu_topology(topology: CpuTopology) -> Result<(Option<NonZeroU32>, bool)> {
+    match topology {
+        CpuTopology::MATCH_HOST => Ok((None, true)),
+        CpuTopology::ONE_CPU => Ok((NonZeroU32::new(1), false)),
+        val => bail!("Failed to parse CPU topology value {:?}", val),
+    }
+}
+
+/// Canonicalizes the paths of the devices to assign, rejecting duplicates.
+fn canonicalize_devices(devices: &[String]) -> impl Iterator<Item = binder::Result<PathBuf>> + '_ {
+    let mut set = HashSet::new();
+    devices.iter().map(move |device| {
+        let path = canonicalize(device)
+            .with_context(|| format!("can't canonicalize {device}"))
+            .or_service_specific_exception(-1)?;
+        if !set.insert(path.clone()) {
+            return Err(anyhow!("duplicated device {device}"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+        }
+        Ok(path)
+    })
+}
+
+/// Checks that a disk is made of either a single image or a set of partitions.
+fn check_disk_image(disk: &DiskImage) -> Result<()> {
+    if disk.image.is_some() && !disk.partitions.is_empty() {
+        bail!("DiskImage contains both image and partitions");
+    }
+    Ok(())
+}
+
+/// Checks if partition images are labeled incorrectly. This is to prevent random images which are
+/// not protected by the Android Verified Boot (e.g. bits downloaded by apps) from being loaded in
+/// a pVM. This applies to everything but the instance image in the raw config, and everything but
+/// the non-executable, generated partitions in the app config.
+fn check_partition_labels(
+    config: &VirtualMachineRawConfig,
+    is_app_config: bool,
+) -> impl Iterator<Item = Result<()>> + '_ {
+    config
+        .disks
+        .iter()
+        .flat_map(|disk| disk.partitions.iter())
+        .filter(move |partition| {
+            if is_app_config {
+                !is_safe_app_partition(&partition.label)
+            } else {
+                !is_safe_raw_partition(&partition.label)
+            }
+        })
+        .map(check_label_for_partition)
+}
+
+/// Clones the kernel and initrd of the config. In a protected VM, we require custom kernels to
+/// come from a trusted source (b/237054515).
+fn clone_kernel_files(
+    config: &VirtualMachineRawConfig,
+) -> binder::Result<(Option<File>, Option<File>)> {
+    let kernel = maybe_clone_file(&config.kernel)?;
+    let initrd = maybe_clone_file(&config.initrd)?;
+    if config.protectedVm {
+        check_label_for_kernel_files(&kernel, &initrd).or_service_specific_exception(-1)?;
+    }
+    Ok((kernel, initrd))
+}
+
 fn check_label_for_partition(partition: &Partition) -> Result<()> {
     let file = partition.image.as_ref().unwrap().as_ref();
     check_label_is_allowed(&getfilecon(file)?)
@@ -961,6 +1378,16 @@ impl IVirtualMachine for VirtualMachine {
         Ok(get_state(&self.instance))
     }
 
+    fn getVmInfo(&self) -> binder::Result<VirtualMachineInfo> {
+        // Don't check permission. The owner of the VM might have passed this binder object to
+        // others.
+        let versions = self.instance.component_versions.lock().unwrap().clone();
+        Ok(VirtualMachineInfo {
+            guestKernelVersion: versions.kernel_release,
+            pvmfwVersion: versions.pvmfw_version,
+        })
+    }
+
     fn registerCallback(
         &self,
         callback: &Strong<dyn IVirtualMachineCallback>,
@@ -1011,6 +1438,26 @@ impl IVirtualMachine for VirtualMachine {
             .or_service_specific_exception(-1)?;
         Ok(vsock_stream_to_pfd(stream))
     }
+
+    fn setPayloadOutput(
+        &self,
+        stdout: &ParcelFileDescriptor,
+        stderr: &ParcelFileDescriptor,
+        max_bytes: i64,
+    ) -> binder::Result<()> {
+        // Don't check permission. The owner of the VM might have passed this binder object to
+        // others.
+        let max_bytes = u64::try_from(max_bytes)
+            .map_err(|_| anyhow!("Invalid payload output limit {max_bytes}"))
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)?;
+        let stdout = clone_file(stdout)?;
+        let stderr = clone_file(stderr)?;
+        let output = PayloadOutput::new(stdout, stderr, max_bytes)
+            .context("Failed to set up the payload output")
+            .or_service_specific_exception(-1)?;
+        *self.instance.payload_output.lock().unwrap() = Some(output);
+        Ok(())
+    }
 }
 
 impl Drop for VirtualMachine {
@@ -1084,6 +1531,33 @@ impl VirtualMachineCallbacks {
     }
 }
 
+/// Cancellation flag of an in-progress VM creation, shared with whoever can cancel it.
+#[derive(Clone, Debug, Default)]
+struct CreationCancellation(Arc<AtomicBool>);
+
+impl CreationCancellation {
+    fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    fn is_same(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Fails if the creation was cancelled, after deleting the temporary files created so far.
+    fn check(&self, temporary_directory: &PathBuf) -> binder::Result<()> {
+        if !self.0.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        remove_temporary_files(temporary_directory).unwrap_or_else(|e| {
+            error!("Error removing temporary files from {temporary_directory:?}: {e}");
+        });
+        Err(anyhow!("VM creation was cancelled"))
+            .with_log()
+            .or_service_specific_exception(ERROR_CREATION_CANCELED)
+    }
+}
+
 /// The mutable state of the VirtualizationService. There should only be one instance of this
 /// struct.
 #[derive(Debug, Default)]
@@ -1147,6 +1621,16 @@ fn maybe_clone_file(file: &Option<ParcelFileDescriptor>) -> binder::Result<Optio
     file.as_ref().map(clone_file).transpose()
 }
 
+/// Copies a string reported by the (untrusted) guest, truncated to at most
+/// `MAX_GUEST_STRING_LEN` bytes without splitting a character.
+fn truncate_guest_string(s: &str) -> String {
+    let mut end = s.len().min(MAX_GUEST_STRING_LEN);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s[..end].to_owned()
+}
+
 /// Converts a `VsockStream` to a `ParcelFileDescriptor`.
 fn vsock_stream_to_pfd(stream: VsockStream) -> ParcelFileDescriptor {
     // SAFETY: ownership is transferred from stream to f
@@ -1174,6 +1658,24 @@ fn prepare_ramdump_file(temporary_directory: &Path) -> binder::Result<File> {
     Ok(ramdump)
 }
 
+/// Returns whether the config uses features which are reserved to platform apps.
+fn is_custom_config(config: &VirtualMachineConfig) -> bool {
+    match config {
+        VirtualMachineConfig::RawConfig(_) => true,
+        VirtualMachineConfig::AppConfig(config) => {
+            // Some features are reserved for platform apps only, even when using
+            // VirtualMachineAppConfig. Almost all of these features are grouped in the
+            // CustomConfig struct:
+            // - controlling CPUs;
+            // - specifying a config file in the APK; (this one is not part of CustomConfig)
+            // - gdbPort is set, meaning that crosvm will start a gdb server;
+            // - using anything other than the default kernel;
+            // - specifying devices to be assigned.
+            config.customConfig.is_some() || matches!(config.payload, Payload::ConfigPath(_))
+        }
+    }
+}
+
 fn is_protected(config: &VirtualMachineConfig) -> bool {
     match config {
         VirtualMachineConfig::RawConfig(config) => config.protectedVm,
@@ -1209,6 +1711,56 @@ fn extract_gdb_port(config: &VirtualMachineConfig) -> Option<NonZeroU16> {
     }
 }
 
+/// Returns the devices to be assigned to the VM, if any.
+fn extract_devices(config: &VirtualMachineConfig) -> &[String] {
+    match config {
+        VirtualMachineConfig::RawConfig(config) => &config.devices,
+        VirtualMachineConfig::AppConfig(config) => {
+            config.customConfig.as_ref().map(|c| c.devices.as_slice()).unwrap_or_default()
+        }
+    }
+}
+
+/// Returns the extra crosvm arguments requested by the config, if any.
+pub(crate) fn extract_extra_crosvm_args(config: &VirtualMachineConfig) -> &[String] {
+    match config {
+        VirtualMachineConfig::RawConfig(config) => &config.extraCrosvmArgs,
+        VirtualMachineConfig::AppConfig(config) => {
+            config.customConfig.as_ref().map(|c| c.extraCrosvmArgs.as_slice()).unwrap_or_default()
+        }
+    }
+}
+
+/// Returns the tags given in the config.
+pub(crate) fn extract_tags(config: &VirtualMachineConfig) -> &[String] {
+    match config {
+        VirtualMachineConfig::RawConfig(config) => &config.tags,
+        VirtualMachineConfig::AppConfig(config) => &config.tags,
+    }
+}
+
+/// Checks that the tags are few and short enough to be used as metric dimensions.
+fn check_tags(tags: &[String]) -> Result<()> {
+    const MAX_TAGS: usize = 8;
+    const MAX_TAG_LEN: usize = 32;
+
+    if tags.len() > MAX_TAGS {
+        bail!("Too many tags: {} (max {MAX_TAGS})", tags.len());
+    }
+    for tag in tags {
+        if tag.is_empty() || tag.len() > MAX_TAG_LEN {
+            bail!("Tag {tag:?} must have between 1 and {MAX_TAG_LEN} characters");
+        }
+        if !tag.bytes().all(|c| c.is_ascii_alphanumeric() || b"._-".contains(&c)) {
+            bail!("Tag {tag:?} contains invalid characters");
+        }
+        if tags.iter().filter(|t| *t == tag).count() > 1 {
+            bail!("Duplicate tag {tag:?}");
+        }
+    }
+    Ok(())
+}
+
 fn check_no_vendor_modules(config: &VirtualMachineConfig) -> binder::Result<()> {
     let VirtualMachineConfig::AppConfig(config) = config else { return Ok(()) };
     if let Some(custom_config) = &config.customConfig {
@@ -1231,6 +1783,141 @@ fn check_no_devices(config: &VirtualMachineConfig) -> binder::Result<()> {
     Ok(())
 }
 
+/// Problems found by `validate_config`.
+#[derive(Default)]
+struct ConfigIssues(Vec<ConfigValidationIssue>);
+
+impl ConfigIssues {
+    fn add(&mut self, category: IssueCategory, message: impl fmt::Display) {
+        self.0.push(ConfigValidationIssue { category, message: message.to_string() });
+    }
+
+    fn check(&mut self, category: IssueCategory, result: binder::Result<()>) {
+        if let Err(status) = result {
+            self.add(category, status.get_description());
+        }
+    }
+}
+
+/// Runs the checks of `create_vm_internal` which have no side effects, reporting every problem
+/// found instead of failing on the first one.
+fn validate_config(config: &VirtualMachineConfig) -> Vec<ConfigValidationIssue> {
+    let mut issues = ConfigIssues::default();
+
+    match GLOBAL_SERVICE.checkVmCreationPolicy() {
+        Ok(decision) if decision.kind == VmCreationPolicyKind::ALLOW => {}
+        Ok(decision) => issues.add(IssueCategory::QUOTA, decision.reason),
+        Err(status) if status.exception_code() == ExceptionCode::SECURITY => {
+            issues.add(IssueCategory::PERMISSION, status.get_description())
+        }
+        Err(status) => issues.add(IssueCategory::QUOTA, status.get_description()),
+    }
+
+    let supported = if is_protected(config) {
+        hypervisor_props::is_protected_vm_supported()
+    } else {
+        hypervisor_props::is_vm_supported()
+    };
+    match supported {
+        Ok(true) => {}
+        Ok(false) if is_protected(config) => {
+            issues.add(IssueCategory::HYPERVISOR, "Protected VMs aren't supported")
+        }
+        Ok(false) => issues.add(IssueCategory::HYPERVISOR, "Non-protected VMs aren't supported"),
+        Err(e) => issues.add(IssueCategory::HYPERVISOR, format!("{e:#}")),
+    }
+    issues.check(IssueCategory::HYPERVISOR, check_config_features(config));
+
+    if is_custom_config(config) {
+        issues.check(IssueCategory::PERMISSION, check_use_custom_virtual_machine());
+    }
+    if extract_gdb_port(config).is_some() {
+        issues.check(IssueCategory::PERMISSION, check_gdb_allowed(config));
+    }
+    if !extract_extra_crosvm_args(config).is_empty() {
+        issues.check(IssueCategory::PERMISSION, check_extra_crosvm_args_allowed());
+    }
+    issues.check(IssueCategory::PERMISSION, check_vm_kind_allowed(config));
+
+    validate_config_contents(config, &mut issues);
+    let devices = extract_devices(config);
+    if !devices.is_empty() {
+        validate_devices(devices, &mut issues);
+    }
+
+    issues.0
+}
+
+/// Runs the checks of `validate_config` which only depend on the config itself.
+fn validate_config_contents(config: &VirtualMachineConfig, issues: &mut ConfigIssues) {
+    if let Err(e) = check_tags(extract_tags(config)) {
+        issues.add(IssueCategory::INVALID_CONFIG, e);
+    }
+    match config {
+        VirtualMachineConfig::RawConfig(config) => validate_raw_config(config, issues),
+        VirtualMachineConfig::AppConfig(config) => {
+            let images = [
+                ("APK", config.apk.is_some()),
+                ("idsig", config.idsig.is_some()),
+                ("instance image", config.instanceImage.is_some()),
+            ];
+            for (name, _) in images.iter().filter(|(_, present)| !present) {
+                issues.add(IssueCategory::IMAGE, format!("Missing {name}"));
+            }
+        }
+    }
+}
+
+fn validate_raw_config(config: &VirtualMachineRawConfig, issues: &mut ConfigIssues) {
+    for disk in &config.disks {
+        if let Err(e) = check_disk_image(disk) {
+            issues.add(IssueCategory::INVALID_CONFIG, e);
+        }
+    }
+    for result in check_partition_labels(config, false) {
+        if let Err(e) = result {
+            issues.add(IssueCategory::IMAGE, format!("{e:#}"));
+        }
+    }
+    issues.check(IssueCategory::IMAGE, clone_kernel_files(config).map(|_| ()));
+    if let Err(status) = check_config_extra_dt_properties(config) {
+        let category = if status.exception_code() == ExceptionCode::UNSUPPORTED_OPERATION {
+            IssueCategory::HYPERVISOR
+        } else {
+            IssueCategory::INVALID_CONFIG
+        };
+        issues.add(category, status.get_description());
+    }
+    if let Err(e) = parse_cpu_topology(config.cpuTopology) {
+        issues.add(IssueCategory::INVALID_CONFIG, e);
+    }
+}
+
+fn validate_devices(devices: &[String], issues: &mut ConfigIssues) {
+    let assignable = match GLOBAL_SERVICE.getAssignableDevices() {
+        Ok(assignable) => assignable,
+        Err(status) => {
+            issues.add(IssueCategory::DEVICE_ASSIGNMENT, status.get_description());
+            return;
+        }
+    };
+    let assignable: HashSet<_> =
+        assignable.iter().filter_map(|d| canonicalize(&d.node).ok()).collect();
+    for (device, path) in devices.iter().zip(canonicalize_devices(devices)) {
+        match path {
+            Ok(path) if !assignable.contains(&path) => {
+                let message = format!("Device {device} is not assignable");
+                issues.add(IssueCategory::DEVICE_ASSIGNMENT, message);
+            }
+            Ok(_) => {}
+            Err(status) if status.exception_code() == ExceptionCode::ILLEGAL_ARGUMENT => {
+                issues.add(IssueCategory::INVALID_CONFIG, status.get_description());
+            }
+            Err(status) => issues.add(IssueCategory::DEVICE_ASSIGNMENT, status.get_description()),
+        }
+    }
+}
+
 fn check_config_features(config: &VirtualMachineConfig) -> binder::Result<()> {
     if !cfg!(vendor_modules) {
         check_no_vendor_modules(config)?;
@@ -1320,7 +2007,7 @@ impl IVirtualMachineService for VirtualMachineService {
             vm.callbacks.notify_payload_started(cid);
 
             let vm_start_timestamp = vm.vm_metric.lock().unwrap().start_timestamp;
-            write_vm_booted_stats(vm.requester_uid as i32, &vm.name, vm_start_timestamp);
+            write_vm_booted_stats(vm.requester_uid as i32, &vm.name, &vm.tags, vm_start_timestamp);
             Ok(())
         } else {
             error!("notifyPayloadStarted is called from an unknown CID {}", cid);
@@ -1356,6 +2043,29 @@ impl IVirtualMachineService for VirtualMachineService {
         }
     }
 
+    fn notifyComponentVersions(
+        &self,
+        kernel_release: &str,
+        pvmfw_version: Option<&str>,
+    ) -> binder::Result<()> {
+        let cid = self.cid;
+        if let Some(vm) = self.state.lock().unwrap().get_vm(cid) {
+            let kernel_release = truncate_guest_string(kernel_release);
+            let pvmfw_version = pvmfw_version.map(truncate_guest_string);
+            info!(
+                "VM with CID {} runs kernel {} (pvmfw version: {:?})",
+                cid, kernel_release, pvmfw_version
+            );
+            let mut versions = vm.component_versions.lock().unwrap();
+            versions.kernel_release = Some(kernel_release);
+            versions.pvmfw_version = pvmfw_version;
+            Ok(())
+        } else {
+            error!("notifyComponentVersions is called from an unknown CID {}", cid);
+            Err(anyhow!("cannot find a VM with CID {}", cid)).or_service_specific_exception(-1)
+        }
+    }
+
     fn notifyError(&self, error_code: ErrorCode, message: &str) -> binder::Result<()> {
         let cid = self.cid;
         if let Some(vm) = self.state.lock().unwrap().get_vm(cid) {
@@ -1373,6 +2083,30 @@ impl IVirtualMachineService for VirtualMachineService {
     fn requestAttestation(&self, csr: &[u8]) -> binder::Result<Vec<Certificate>> {
         GLOBAL_SERVICE.requestAttestation(csr, get_calling_uid() as i32)
     }
+
+    fn writePayloadOutput(&self, stream: PayloadOutputStream, data: &[u8]) -> binder::Result<i32> {
+        let cid = self.cid;
+        let len = i32::try_from(data.len())
+            .map_err(|_| anyhow!("Payload output chunk of {} bytes is too large", data.len()))
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)?;
+        let vm = self.state.lock().unwrap().get_vm(cid);
+        if let Some(vm) = vm {
+            // This doesn't block: the owner's file descriptors are non-blocking.
+            let Some(output) = &mut *vm.payload_output.lock().unwrap() else {
+                return Ok(len);
+            };
+            let consumed = output
+                .write(stream, data)
+                .with_context(|| format!("Error forwarding payload output of VM {cid}"))
+                .with_log()
+                .or_service_specific_exception(-1)?;
+            // At most `data.len()` bytes are consumed, which was checked to fit.
+            Ok(i32::try_from(consumed).unwrap())
+        } else {
+            error!("writePayloadOutput is called from an unknown CID {}", cid);
+            Err(anyhow!("cannot find a VM with CID {}", cid)).or_service_specific_exception(-1)
+        }
+    }
 }
 
 impl VirtualMachineService {
@@ -1537,6 +2271,164 @@ mod tests {
         Ok(())
     }
 
+    fn dt_property(name: &str, value: &str) -> DeviceTreeProperty {
+        DeviceTreeProperty { name: name.to_owned(), value: value.to_owned() }
+    }
+
+    #[test]
+    fn test_create_dtbo_for_extra_dt_properties() -> Result<()> {
+        let properties = [dt_property("foo", "bar"), dt_property("baz,empty", "")];
+
+        let tmp_dir = tempfile::TempDir::new()?;
+        let dtbo_path = tmp_dir.path().to_path_buf().join("dtbo");
+
+        create_dtbo_for_extra_dt_properties(&properties, &dtbo_path)?;
+
+        let data = std::fs::read(dtbo_path)?;
+        let fdt = Fdt::from_slice(&data).unwrap();
+
+        let node_path = CString::new("/fragment@0/__overlay__/avf/host_properties")?;
+        let Some(node) = fdt.node(node_path.as_c_str()).unwrap() else {
+            bail!("host_properties node shouldn't be None.");
+        };
+        let foo = CString::new("foo")?;
+        assert_eq!(node.getprop(foo.as_c_str()).unwrap(), Some(&b"bar\0"[..]));
+        let empty = CString::new("baz,empty")?;
+        assert_eq!(node.getprop(empty.as_c_str()).unwrap(), Some(&b"\0"[..]));
+
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_extra_dt_properties() {
+        assert!(check_extra_dt_properties(&[dt_property("android,foo", "bar")]).is_ok());
+        assert!(check_extra_dt_properties(&[dt_property("", "bar")]).is_err());
+        assert!(check_extra_dt_properties(&[dt_property("foo/bar", "bar")]).is_err());
+        assert!(check_extra_dt_properties(&[dt_property(&"a".repeat(32), "bar")]).is_err());
+        assert!(check_extra_dt_properties(&[dt_property("foo", "a\0b")]).is_err());
+        let long_value = "a".repeat(MAX_EXTRA_DT_PROPERTY_VALUE_LEN + 1);
+        assert!(check_extra_dt_properties(&[dt_property("foo", &long_value)]).is_err());
+        let duplicated = [dt_property("foo", "a"), dt_property("foo", "b")];
+        assert!(check_extra_dt_properties(&duplicated).is_err());
+        let too_many: Vec<_> =
+            (0..=MAX_EXTRA_DT_PROPERTIES).map(|i| dt_property(&format!("p{i}"), "")).collect();
+        assert!(check_extra_dt_properties(&too_many).is_err());
+    }
+
+    #[test]
+    fn test_check_tags() {
+        let tags = |tags: &[&str]| tags.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        assert!(check_tags(&[]).is_ok());
+        assert!(check_tags(&tags(&["compos", "test-run_1.2"])).is_ok());
+        assert!(check_tags(&tags(&[""])).is_err());
+        assert!(check_tags(&tags(&["a b"])).is_err());
+        assert!(check_tags(&tags(&[&"a".repeat(33)])).is_err());
+        assert!(check_tags(&tags(&["rkp", "rkp"])).is_err());
+        let too_many: Vec<_> = (0..9).map(|i| format!("t{i}")).collect();
+        assert!(check_tags(&too_many).is_err());
+    }
+
+    #[test]
+    fn test_is_app_uid() {
+        assert!(!is_app_uid(0));
+        assert!(!is_app_uid(1000)); // system
+        assert!(is_app_uid(10123));
+        assert!(!is_app_uid(1001000)); // system, in user 10
+        assert!(is_app_uid(1010123));
+    }
+
+    #[test]
+    fn test_validate_raw_config() -> Result<()> {
+        let image = || -> Result<ParcelFileDescriptor> {
+            Ok(ParcelFileDescriptor::new(tempfile::tempfile()?))
+        };
+        let partition = Partition {
+            label: "vm-instance".to_owned(),
+            image: Some(image()?),
+            ..Default::default()
+        };
+        let disk =
+            DiskImage { image: Some(image()?), partitions: vec![partition], ..Default::default() };
+        let config = VirtualMachineRawConfig {
+            disks: vec![disk],
+            cpuTopology: CpuTopology(42),
+            ..Default::default()
+        };
+
+        let mut issues = ConfigIssues::default();
+        validate_raw_config(&config, &mut issues);
+        let categories: Vec<_> = issues.0.iter().map(|issue| issue.category).collect();
+        assert_eq!(categories, [IssueCategory::INVALID_CONFIG, IssueCategory::INVALID_CONFIG]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_app_config() {
+        let config = VirtualMachineAppConfig {
+            tags: vec!["a b".to_owned()],
+            instanceImage: Some(ParcelFileDescriptor::new(tempfile::tempfile().unwrap())),
+            ..Default::default()
+        };
+
+        let mut issues = ConfigIssues::default();
+        validate_config_contents(&VirtualMachineConfig::AppConfig(config), &mut issues);
+        let categories: Vec<_> = issues.0.iter().map(|issue| issue.category).collect();
+        assert_eq!(
+            categories,
+            [IssueCategory::INVALID_CONFIG, IssueCategory::IMAGE, IssueCategory::IMAGE]
+        );
+    }
+
+    #[test]
+    fn test_validate_protected_raw_config() {
+        let config = VirtualMachineRawConfig {
+            protectedVm: true,
+            extraDtProperties: vec![dt_property("foo", "bar")],
+            ..Default::default()
+        };
+
+        let mut issues = ConfigIssues::default();
+        validate_config_contents(&VirtualMachineConfig::RawConfig(config), &mut issues);
+        let categories: Vec<_> = issues.0.iter().map(|issue| issue.category).collect();
+        assert_eq!(categories, [IssueCategory::HYPERVISOR]);
+    }
+
+    #[test]
+    fn test_validate_raw_config_extra_dt_properties() {
+        let config = VirtualMachineRawConfig {
+            extraDtProperties: vec![dt_property("foo", "bar"), dt_property("foo", "baz")],
+            ..Default::default()
+        };
+
+        let mut issues = ConfigIssues::default();
+        validate_raw_config(&config, &mut issues);
+        let categories: Vec<_> = issues.0.iter().map(|issue| issue.category).collect();
+        assert_eq!(categories, [IssueCategory::INVALID_CONFIG]);
+    }
+
+    #[test]
+    fn test_canonicalize_devices_rejects_duplicates() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let device = dir.path().join("device");
+        File::create(&device)?;
+        let device = device.to_str().unwrap().to_owned();
+        let alias = dir.path().join(".").join("device").to_str().unwrap().to_owned();
+
+        let results: Vec<_> = canonicalize_devices(&[device, alias]).collect();
+        assert!(results[0].is_ok());
+        let status = results[1].as_ref().unwrap_err();
+        assert_eq!(status.exception_code(), ExceptionCode::ILLEGAL_ARGUMENT);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cpu_topology() {
+        assert_eq!(parse_cpu_topology(CpuTopology::MATCH_HOST).unwrap(), (None, true));
+        assert_eq!(parse_cpu_topology(CpuTopology::ONE_CPU).unwrap(), (NonZeroU32::new(1), false));
+        assert!(parse_cpu_topology(CpuTopology(42)).is_err());
+    }
+
     #[test]
     fn test_create_dtbo_for_vendor_image_throws_error_if_already_exists() -> Result<()> {
         let vendor_public_key = String::from("foo");
@@ -1553,4 +2445,39 @@ mod tests {
         tmp_dir.close()?;
         Ok(())
     }
+
+    #[test]
+    fn test_creation_cancellation_removes_temporary_files() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let temporary_directory = tmp_dir.path().to_path_buf();
+        File::create(temporary_directory.join("zero.img"))?;
+
+        let cancellation = CreationCancellation::default();
+        let shared = cancellation.clone();
+        assert!(cancellation.check(&temporary_directory).is_ok());
+        assert!(temporary_directory.join("zero.img").exists());
+
+        shared.cancel();
+        let e = cancellation.check(&temporary_directory).unwrap_err();
+        assert_eq!(e.exception_code(), ExceptionCode::SERVICE_SPECIFIC);
+        assert_eq!(e.service_specific_error(), ERROR_CREATION_CANCELED);
+        assert_eq!(read_dir(&temporary_directory)?.count(), 0);
+        assert!(cancellation.is_same(&shared));
+        assert!(!cancellation.is_same(&CreationCancellation::default()));
+
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncate_guest_string() {
+        assert_eq!(truncate_guest_string("6.1.25-android14"), "6.1.25-android14");
+
+        let long = "a".repeat(MAX_GUEST_STRING_LEN + 1);
+        assert_eq!(truncate_guest_string(&long).len(), MAX_GUEST_STRING_LEN);
+
+        // Truncation doesn't split multi-byte characters.
+        let long = format!("{}\u{e9}", "a".repeat(MAX_GUEST_STRING_LEN - 1));
+        assert_eq!(truncate_guest_string(&long), "a".repeat(MAX_GUEST_STRING_LEN - 1));
+    }
 }
@@ -14,13 +14,14 @@
 
 //! Functions for creating and collecting atoms.
 
-use crate::aidl::{clone_file, GLOBAL_SERVICE};
+use crate::aidl::{clone_file, extract_extra_crosvm_args, extract_tags, GLOBAL_SERVICE};
 use crate::crosvm::VmMetric;
 use crate::get_calling_uid;
 use android_system_virtualizationcommon::aidl::android::system::virtualizationcommon::DeathReason::DeathReason;
 use android_system_virtualizationservice::aidl::android::system::virtualizationservice::{
     CpuTopology::CpuTopology,
     IVirtualMachine::IVirtualMachine,
+    IVirtualizationService::ERROR_CREATION_CANCELED,
     VirtualMachineAppConfig::{Payload::Payload, VirtualMachineAppConfig},
     VirtualMachineConfig::VirtualMachineConfig,
 };
@@ -31,7 +32,7 @@ use android_system_virtualizationservice_internal::aidl::android::system::virtua
     AtomVmExited::AtomVmExited,
 };
 use anyhow::{anyhow, Result};
-use binder::ParcelFileDescriptor;
+use binder::{ExceptionCode, ParcelFileDescriptor};
 use log::{info, warn};
 use microdroid_payload_config::VmPayloadConfig;
 use statslog_virtualization_rust::vm_creation_requested;
@@ -101,14 +102,18 @@ pub fn write_vm_creation_stats(
 ) {
     let creation_succeeded;
     let binder_exception_code;
+    let creation_canceled;
     match ret {
         Ok(_) => {
             creation_succeeded = true;
             binder_exception_code = Status::ok().exception_code() as i32;
+            creation_canceled = false;
         }
         Err(ref e) => {
             creation_succeeded = false;
             binder_exception_code = e.exception_code() as i32;
+            creation_canceled = e.exception_code() == ExceptionCode::SERVICE_SPECIFIC
+                && e.service_specific_error() == ERROR_CREATION_CANCELED;
         }
     }
     let (vm_identifier, config_type, cpu_topology, memory_mib, apexes) = match config {
@@ -148,6 +153,9 @@ pub fn write_vm_creation_stats(
         numCpus: num_cpus,
         memoryMib: memory_mib,
         apexes,
+        hasExtraCrosvmArgs: !extract_extra_crosvm_args(config).is_empty(),
+        creationCanceled: creation_canceled,
+        tags: extract_tags(config).to_vec(),
     };
 
     info!("Writing VmCreationRequested atom into statsd.");
@@ -163,6 +171,7 @@ pub fn write_vm_creation_stats(
 pub fn write_vm_booted_stats(
     uid: i32,
     vm_identifier: &str,
+    tags: &[String],
     vm_start_timestamp: Option<SystemTime>,
 ) {
     let vm_identifier = vm_identifier.to_owned();
@@ -172,6 +181,7 @@ pub fn write_vm_booted_stats(
         uid,
         vmIdentifier: vm_identifier,
         elapsedTimeMillis: duration.as_millis() as i64,
+        tags: tags.to_vec(),
     };
 
     info!("Writing VmBooted atom into statsd.");
@@ -186,6 +196,7 @@ pub fn write_vm_booted_stats(
 pub fn write_vm_exited_stats_sync(
     uid: i32,
     vm_identifier: &str,
+    tags: &[String],
     reason: DeathReason,
     exit_signal: Option<i32>,
     vm_metric: &VmMetric,
@@ -204,6 +215,7 @@ pub fn write_vm_exited_stats_sync(
         rssVmKb: rss.vm,
         rssCrosvmKb: rss.crosvm,
         exitSignal: exit_signal.unwrap_or_default(),
+        tags: tags.to_vec(),
     };
 
     info!("Writing VmExited atom into statsd.");
@@ -211,3 +223,11 @@ pub fn write_vm_exited_stats_sync(
         warn!("Failed to write VmExited atom: {e}");
     });
 }
+
+/// Attributes power usage of a VM to the calling UID. Returns whether it was accepted.
+pub fn write_vm_power_usage_sync(cpu_time_millis: i64, wakeups: i64) -> bool {
+    GLOBAL_SERVICE
+        .reportVmPowerUsage(cpu_time_millis, wakeups)
+        .map_err(|e| warn!("Failed to report VM power usage: {e}"))
+        .is_ok()
+}
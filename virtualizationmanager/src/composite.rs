@@ -15,14 +15,19 @@
 //! Functions for creating a composite disk image.
 
 use android_system_virtualizationservice::aidl::android::system::virtualizationservice::Partition::Partition;
-use anyhow::{anyhow, Context, Error};
+use anyhow::{anyhow, ensure, Context, Error};
 use disk::{
     create_composite_disk, create_disk_file, ImagePartitionType, PartitionInfo, MAX_NESTING_DEPTH,
 };
+use dm::util::DeviceInfo;
 use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileTypeExt;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 
+/// Size of the sectors of the composite disk, as exposed to the guest.
+const DISK_SECTOR_SIZE: u64 = 512;
+
 /// Constructs a composite disk image for the given list of partitions, and opens it ready to use.
 ///
 /// Returns the composite disk image file, and a list of files whose file descriptors must be passed
@@ -119,8 +124,18 @@ fn fd_path_for_file(file: &File) -> PathBuf {
 
 /// Find the size of the partition image in the given file by parsing the header.
 ///
-/// This will work for raw, QCOW2, composite and Android sparse images.
+/// This will work for raw, QCOW2, composite and Android sparse images, as well as block devices.
 fn get_partition_size(partition: &File, path: &Path) -> Result<u64, Error> {
+    if partition.metadata()?.file_type().is_block_device() {
+        let info = DeviceInfo::new(path)?;
+        ensure!(
+            u64::from(info.logical_block_size) <= DISK_SECTOR_SIZE,
+            "Logical block size of {:?} is larger than the disk sector size: {}",
+            path,
+            info.logical_block_size
+        );
+        return Ok(info.size);
+    }
     // TODO: Use `context` once disk::Error implements std::error::Error.
     // TODO: Add check for is_sparse_file
     Ok(create_disk_file(
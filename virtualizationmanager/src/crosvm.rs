@@ -15,13 +15,15 @@
 //! Functions for running instances of `crosvm`.
 
 use crate::aidl::{remove_temporary_files, Cid, VirtualMachineCallbacks};
-use crate::atom::{get_num_cpus, write_vm_exited_stats_sync};
+use crate::atom::{get_num_cpus, write_vm_exited_stats_sync, write_vm_power_usage_sync};
 use crate::debug_config::DebugConfig;
+use crate::payload_output::PayloadOutput;
+use crate::vm_service_server::VmServiceServer;
 use anyhow::{anyhow, bail, Context, Error, Result};
 use command_fds::CommandFdExt;
 use lazy_static::lazy_static;
 use libc::{sysconf, _SC_CLK_TCK};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use semver::{Version, VersionReq};
 use nix::{fcntl::OFlag, unistd::pipe2, unistd::Uid, unistd::User};
 use regex::{Captures, Regex};
@@ -50,7 +52,6 @@ use android_system_virtualizationservice_internal::aidl::android::system::virtua
 use binder::Strong;
 use android_system_virtualmachineservice::aidl::android::system::virtualmachineservice::IVirtualMachineService::IVirtualMachineService;
 use tombstoned_client::{TombstonedConnection, DebuggerdDumpType};
-use rpcbinder::RpcServer;
 
 /// external/crosvm
 use base::AsRawDescriptor;
@@ -78,6 +79,9 @@ const RAMDUMP_RESERVED_MIB: u32 = 17;
 
 const MILLIS_PER_SEC: i64 = 1000;
 
+/// How often the power usage of a running VM is attributed to its owner.
+const POWER_USAGE_REPORT_INTERVAL_SECS: u64 = 60;
+
 const SYSPROP_CUSTOM_PVMFW_PATH: &str = "hypervisor.pvmfw.path";
 
 lazy_static! {
@@ -118,6 +122,11 @@ pub struct CrosvmConfig {
     pub vfio_devices: Vec<VfioDevice>,
     pub dtbo: Option<File>,
     pub dtbo_vendor: Option<File>,
+    pub dtbo_host_properties: Option<File>,
+    /// Extra arguments requested by the client, appended to the crosvm command line verbatim.
+    pub extra_args: Vec<String>,
+    /// Tags describing the workload of the VM, reported in dumpsys and metrics.
+    pub tags: Vec<String>,
 }
 
 /// A disk image to pass to crosvm for a VM.
@@ -182,10 +191,54 @@ pub struct VmMetric {
     /// Update most recent guest_time periodically from /proc/[crosvm pid]/stat while VM is
     /// running.
     pub cpu_guest_time: Option<i64>,
+    /// Update most recent number of vCPU wakeups periodically from
+    /// /proc/[crosvm pid]/task/[tid]/status while VM is running.
+    pub vcpu_wakeups: Option<i64>,
     /// Update maximum RSS values periodically from /proc/[crosvm pid]/smaps while VM is running.
     pub rss: Option<Rss>,
 }
 
+/// Tracks the power usage of a VM which hasn't yet been reported to virtualizationservice.
+#[derive(Debug, Default)]
+struct PowerUsageReporter {
+    /// Most recent CPU time and wakeup counters of the VM.
+    current: (i64, i64),
+    /// Counters at the time of the last successful report.
+    reported: (i64, i64),
+}
+
+impl PowerUsageReporter {
+    fn update(&mut self, vm_metric: &VmMetric) {
+        self.current = (
+            vm_metric.cpu_guest_time.unwrap_or(self.current.0),
+            vm_metric.vcpu_wakeups.unwrap_or(self.current.1),
+        );
+    }
+
+    /// Reports the usage since the last report. On failure, the usage is kept and retried with the
+    /// next report.
+    fn report(&mut self) {
+        // Threads may come and go, so clamp the deltas rather than reporting negative usage.
+        let cpu_time_millis = max(self.current.0 - self.reported.0, 0);
+        let wakeups = max(self.current.1 - self.reported.1, 0);
+        if cpu_time_millis == 0 && wakeups == 0 {
+            return;
+        }
+        if write_vm_power_usage_sync(cpu_time_millis, wakeups) {
+            self.reported = self.current;
+        }
+    }
+}
+
+/// Versions of the software components running in the VM, as reported by the guest.
+#[derive(Clone, Debug, Default)]
+pub struct ComponentVersions {
+    /// Release of the guest kernel.
+    pub kernel_release: Option<String>,
+    /// Version of the pvmfw which booted the guest.
+    pub pvmfw_version: Option<String>,
+}
+
 impl VmState {
     /// Tries to start the VM, if it is in the `NotStarted` state.
     ///
@@ -237,12 +290,15 @@ pub struct VmContext {
     #[allow(dead_code)] // Keeps the global context alive
     global_context: Strong<dyn IGlobalVmContext>,
     #[allow(dead_code)] // Keeps the server alive
-    vm_server: RpcServer,
+    vm_server: VmServiceServer,
 }
 
 impl VmContext {
     /// Construct new VmContext.
-    pub fn new(global_context: Strong<dyn IGlobalVmContext>, vm_server: RpcServer) -> VmContext {
+    pub fn new(
+        global_context: Strong<dyn IGlobalVmContext>,
+        vm_server: VmServiceServer,
+    ) -> VmContext {
         VmContext { global_context, vm_server }
     }
 }
@@ -263,6 +319,8 @@ pub struct VmInstance {
     pub name: String,
     /// Whether the VM is a protected VM.
     pub protected: bool,
+    /// Tags describing the workload of the VM.
+    pub tags: Vec<String>,
     /// Directory of temporary files used by the VM while it is running.
     pub temporary_directory: PathBuf,
     /// The UID of the process which requested the VM.
@@ -276,6 +334,10 @@ pub struct VmInstance {
     pub vm_service: Mutex<Option<Strong<dyn IVirtualMachineService>>>,
     /// Recorded metrics of VM such as timestamp or cpu / memory usage.
     pub vm_metric: Mutex<VmMetric>,
+    /// Versions of the components running in the VM.
+    pub component_versions: Mutex<ComponentVersions>,
+    /// Where the owner of the VM asked for the output of the payload to be forwarded, if anywhere.
+    pub payload_output: Mutex<Option<PayloadOutput>>,
     /// The latest lifecycle state which the payload reported itself to be in.
     payload_state: Mutex<PayloadState>,
     /// Represents the condition that payload_state was updated
@@ -289,9 +351,14 @@ impl fmt::Display for VmInstance {
         let adj = if self.protected { "Protected" } else { "Non-protected" };
         write!(
             f,
-            "{} virtual machine \"{}\" (owner: {}, cid: {})",
+            "{} virtual machine \"{}\" (owner: {}, cid: {}",
             adj, self.name, self.requester_uid_name, self.cid
-        )
+        )?;
+        // The tags segment the lifecycle logs of VMs by workload, as they do for metrics.
+        if !self.tags.is_empty() {
+            write!(f, ", tags: {:?}", self.tags)?;
+        }
+        write!(f, ")")
     }
 }
 
@@ -308,6 +375,7 @@ impl VmInstance {
         let cid = config.cid;
         let name = config.name.clone();
         let protected = config.protected;
+        let tags = config.tags.clone();
         let requester_uid_name = User::from_uid(Uid::from_raw(requester_uid))
             .ok()
             .flatten()
@@ -319,12 +387,15 @@ impl VmInstance {
             crosvm_control_socket_path: temporary_directory.join("crosvm.sock"),
             name,
             protected,
+            tags,
             temporary_directory,
             requester_uid,
             requester_debug_pid,
             callbacks: Default::default(),
             vm_service: Mutex::new(None),
             vm_metric: Mutex::new(Default::default()),
+            component_versions: Mutex::new(Default::default()),
+            payload_output: Mutex::new(None),
             payload_state: Mutex::new(PayloadState::Starting),
             payload_state_updated: Condvar::new(),
             requester_uid_name,
@@ -402,6 +473,7 @@ impl VmInstance {
         write_vm_exited_stats_sync(
             self.requester_uid as i32,
             &self.name,
+            &self.tags,
             death_reason,
             exit_signal,
             &vm_metric,
@@ -444,33 +516,49 @@ impl VmInstance {
 
     fn monitor_vm_status(&self, child: Arc<SharedChild>) {
         let pid = child.id();
+        let mut power_usage = PowerUsageReporter::default();
 
-        loop {
-            {
+        for iteration in 1u64.. {
+            let is_dead = {
                 // Check VM state
                 let vm_state = &*self.vm_state.lock().unwrap();
                 if let VmState::Dead = vm_state {
-                    break;
-                }
-
-                let mut vm_metric = self.vm_metric.lock().unwrap();
-
-                // Get CPU Information
-                if let Ok(guest_time) = get_guest_time(pid) {
-                    vm_metric.cpu_guest_time = Some(guest_time);
+                    true
                 } else {
-                    error!("Failed to parse /proc/[pid]/stat");
-                }
+                    let mut vm_metric = self.vm_metric.lock().unwrap();
 
-                // Get Memory Information
-                if let Ok(rss) = get_rss(pid) {
-                    vm_metric.rss = match &vm_metric.rss {
-                        Some(x) => Some(Rss::extract_max(x, &rss)),
-                        None => Some(rss),
+                    // Get CPU Information
+                    if let Ok(guest_time) = get_guest_time(pid) {
+                        vm_metric.cpu_guest_time = Some(guest_time);
+                    } else {
+                        error!("Failed to parse /proc/[pid]/stat");
                     }
-                } else {
-                    error!("Failed to parse /proc/[pid]/smaps");
+                    if let Ok(wakeups) = get_vcpu_wakeups(pid) {
+                        vm_metric.vcpu_wakeups = Some(wakeups);
+                    } else {
+                        error!("Failed to parse /proc/[pid]/task/[tid]/status");
+                    }
+
+                    // Get Memory Information
+                    if let Ok(rss) = get_rss(pid) {
+                        vm_metric.rss = match &vm_metric.rss {
+                            Some(x) => Some(Rss::extract_max(x, &rss)),
+                            None => Some(rss),
+                        }
+                    } else {
+                        error!("Failed to parse /proc/[pid]/smaps");
+                    }
+                    power_usage.update(&vm_metric);
+                    false
                 }
+            };
+
+            // Report the remainder once the VM is dead, so that no usage is left unattributed.
+            if is_dead || iteration % POWER_USAGE_REPORT_INTERVAL_SECS == 0 {
+                power_usage.report();
+            }
+            if is_dead {
+                break;
             }
 
             thread::sleep(Duration::from_secs(1));
@@ -621,6 +709,32 @@ fn get_guest_time(pid: u32) -> Result<i64> {
     Ok(guest_time_ticks * MILLIS_PER_SEC / ticks_per_sec)
 }
 
+// Get the number of vCPU wakeups from /proc/[crosvm pid]/task/[tid]/status
+fn get_vcpu_wakeups(pid: u32) -> Result<i64> {
+    let mut wakeups = 0i64;
+    for task in std::fs::read_dir(format!("/proc/{}/task", pid))? {
+        let task = task?.path();
+        // The thread may have exited since the directory was listed.
+        let Ok(comm) = read_to_string(task.join("comm")) else { continue };
+        if !comm.starts_with("crosvm_vcpu") {
+            continue;
+        }
+        let Ok(status) = read_to_string(task.join("status")) else { continue };
+
+        // A vCPU thread blocks when the guest idles (e.g. WFI) and voluntarily switches out, so
+        // its voluntary context switches count the wakeups of that vCPU. They also include the
+        // rare times the thread blocks in the VMM, so the count is an upper bound.
+        // Example line of /proc/[pid]/task/[tid]/status :
+        // voluntary_ctxt_switches:	1520
+        let switches = status
+            .lines()
+            .find_map(|line| line.strip_prefix("voluntary_ctxt_switches:"))
+            .ok_or_else(|| anyhow!("No voluntary_ctxt_switches in {:?}", task))?;
+        wakeups += switches.trim().parse::<i64>()?;
+    }
+    Ok(wakeups)
+}
+
 // Get rss from /proc/[crosvm pid]/smaps
 fn get_rss(pid: u32) -> Result<Rss> {
     let file = read_to_string(format!("/proc/{}/smaps", pid))?;
@@ -890,6 +1004,11 @@ fn run_vm(
             .arg(add_preserved_fd(&mut preserved_fds, &disk.image));
     }
 
+    if !config.extra_args.is_empty() {
+        warn!("Passing extra arguments to crosvm: {:?}", config.extra_args);
+        command.args(&config.extra_args);
+    }
+
     if let Some(kernel) = &config.kernel {
         command.arg(add_preserved_fd(&mut preserved_fds, kernel));
     }
@@ -902,6 +1021,11 @@ fn run_vm(
 
     // TODO(b/285855436): Pass dtbo_vendor after --device-tree-overlay crosvm option is supported.
 
+    if let Some(dtbo) = &config.dtbo_host_properties {
+        let fd = add_preserved_fd(&mut preserved_fds, dtbo);
+        command.arg(format!("--device-tree-overlay={fd}"));
+    }
+
     append_platform_devices(&mut command, &mut preserved_fds, &config)?;
 
     debug!("Preserving FDs {:?}", preserved_fds);
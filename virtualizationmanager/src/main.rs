@@ -20,7 +20,10 @@ mod composite;
 mod crosvm;
 mod debug_config;
 mod payload;
+mod payload_output;
 mod selinux;
+mod vm_service_server;
+mod worker_pool;
 
 use crate::aidl::{GLOBAL_SERVICE, VirtualizationService};
 use android_system_virtualizationservice::aidl::android::system::virtualizationservice::IVirtualizationService::BnVirtualizationService;
@@ -105,6 +105,11 @@ impl ApexInfoList {
         })
     }
 
+    /// Returns the version of the active APEX with the given name.
+    fn active_version(&self, name: &str) -> Option<u64> {
+        self.list.iter().find(|apex| apex.is_active && apex.name == name).map(|apex| apex.version)
+    }
+
     // Override apex info with the staged one
     fn override_staged_apex(&mut self, staged_apex_info: &StagedApexInfo) -> Result<()> {
         let mut need_to_add: Option<ApexInfo> = None;
@@ -409,6 +414,11 @@ fn collect_apex_infos<'a>(
     Ok(apex_infos)
 }
 
+/// Returns the version code of the active APEX with the given name.
+pub fn get_active_apex_version(name: &str) -> Result<u64> {
+    ApexInfoList::load()?.active_version(name).ok_or_else(|| anyhow!("APEX {name} isn't active"))
+}
+
 pub fn add_microdroid_vendor_image(vendor_image: File, vm_config: &mut VirtualMachineRawConfig) {
     vm_config.disks.push(DiskImage {
         image: None,
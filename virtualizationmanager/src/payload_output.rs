@@ -0,0 +1,161 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Forwarding of the stdout/stderr of the payload to the owner of the VM.
+
+use android_system_virtualmachineservice::aidl::android::system::virtualmachineservice::PayloadOutputStream::PayloadOutputStream;
+use log::{info, warn};
+use nix::fcntl::{fcntl, OFlag, F_GETFL, F_SETFL};
+use std::fs::File;
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::io::AsRawFd;
+
+/// Destination of the output of the payload, set by the owner of the VM.
+#[derive(Debug)]
+pub struct PayloadOutput {
+    stdout: Option<File>,
+    stderr: Option<File>,
+    /// Number of bytes which can still be forwarded.
+    remaining: u64,
+    /// Whether output was dropped because of the limit, to only log it once.
+    truncated: bool,
+}
+
+impl PayloadOutput {
+    /// Forwards the output to `stdout` and `stderr`, which are switched to non-blocking mode so
+    /// that a slow owner never blocks the binder thread forwarding the output.
+    pub fn new(stdout: File, stderr: File, max_bytes: u64) -> io::Result<Self> {
+        set_nonblocking(&stdout)?;
+        set_nonblocking(&stderr)?;
+        Ok(Self {
+            stdout: Some(stdout),
+            stderr: Some(stderr),
+            remaining: max_bytes,
+            truncated: false,
+        })
+    }
+
+    /// Writes as much of `data` as the file of `stream` accepts without blocking, and returns the
+    /// number of bytes consumed. Returns 0 if the owner must first make room for more output.
+    ///
+    /// Data exceeding the limit is dropped, as well as data for a stream that the owner closed,
+    /// and is reported as consumed.
+    pub fn write(&mut self, stream: PayloadOutputStream, data: &[u8]) -> io::Result<usize> {
+        let file = match stream {
+            PayloadOutputStream::STDOUT => &mut self.stdout,
+            PayloadOutputStream::STDERR => &mut self.stderr,
+            _ => return Err(io::Error::new(ErrorKind::InvalidInput, "Unknown output stream")),
+        };
+        let Some(out) = file else {
+            return Ok(data.len());
+        };
+        let len = data.len().min(self.remaining.try_into().unwrap_or(usize::MAX));
+        if len < data.len() && !self.truncated {
+            self.truncated = true;
+            warn!("Payload output exceeds its limit, dropping the rest of it");
+        }
+        if len == 0 {
+            return Ok(data.len());
+        }
+        match out.write(&data[..len]) {
+            Ok(written) => {
+                self.remaining -= written as u64;
+                Ok(if written == len { data.len() } else { written })
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => Ok(0),
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => {
+                info!("Owner closed the payload {stream:?}, no longer forwarding it");
+                *file = None;
+                Ok(data.len())
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn set_nonblocking(file: &File) -> io::Result<()> {
+    let flags = OFlag::from_bits_truncate(fcntl(file.as_raw_fd(), F_GETFL)?);
+    fcntl(file.as_raw_fd(), F_SETFL(flags | OFlag::O_NONBLOCK))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::unistd::pipe2;
+    use std::io::{Read, Seek};
+    use std::os::unix::io::FromRawFd;
+
+    /// Returns a temporary file and a clone of it, to read back what was written to the clone.
+    fn tempfile() -> (File, File) {
+        let file = tempfile::tempfile().unwrap();
+        let clone = file.try_clone().unwrap();
+        (file, clone)
+    }
+
+    fn read_back(file: &mut File) -> Vec<u8> {
+        let mut data = Vec::new();
+        file.rewind().unwrap();
+        file.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    /// Returns the read and write ends of a new pipe.
+    fn pipe() -> (File, File) {
+        let (raw_read, raw_write) = pipe2(OFlag::O_CLOEXEC).unwrap();
+        // SAFETY: We are the sole owner of these FDs as we just created them.
+        unsafe { (File::from_raw_fd(raw_read), File::from_raw_fd(raw_write)) }
+    }
+
+    #[test]
+    fn forwards_to_the_file_of_the_stream() {
+        let (mut stdout, stdout_writer) = tempfile();
+        let (mut stderr, stderr_writer) = tempfile();
+        let mut output = PayloadOutput::new(stdout_writer, stderr_writer, 1024).unwrap();
+        assert_eq!(output.write(PayloadOutputStream::STDOUT, b"out").unwrap(), 3);
+        assert_eq!(output.write(PayloadOutputStream::STDERR, b"err").unwrap(), 3);
+        assert_eq!(read_back(&mut stdout), b"out");
+        assert_eq!(read_back(&mut stderr), b"err");
+    }
+
+    #[test]
+    fn drops_output_over_the_limit() {
+        let (mut stdout, stdout_writer) = tempfile();
+        let (mut stderr, stderr_writer) = tempfile();
+        let mut output = PayloadOutput::new(stdout_writer, stderr_writer, 5).unwrap();
+        assert_eq!(output.write(PayloadOutputStream::STDOUT, b"abc").unwrap(), 3);
+        assert_eq!(output.write(PayloadOutputStream::STDERR, b"defg").unwrap(), 4);
+        assert_eq!(output.write(PayloadOutputStream::STDOUT, b"hij").unwrap(), 3);
+        assert_eq!(read_back(&mut stdout), b"abc");
+        assert_eq!(read_back(&mut stderr), b"de");
+    }
+
+    #[test]
+    fn consumes_nothing_while_the_owner_is_behind() {
+        let (mut stdout, stdout_writer) = pipe();
+        let (_stderr, stderr_writer) = pipe();
+        let mut output = PayloadOutput::new(stdout_writer, stderr_writer, u64::MAX).unwrap();
+        let chunk = [b'x'; 4096];
+        let mut written = 0;
+        loop {
+            match output.write(PayloadOutputStream::STDOUT, &chunk).unwrap() {
+                0 => break,
+                len => written += len,
+            }
+        }
+        let mut buf = vec![0; written];
+        stdout.read_exact(&mut buf).unwrap();
+        assert!(output.write(PayloadOutputStream::STDOUT, &chunk).unwrap() > 0);
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Serves the VirtualMachineService of a VM over vsock, rejecting the connections which exceed
+//! the limits of the VM and of its owner, so that a guest can't exhaust the file descriptors of
+//! virtmgr.
+
+use crate::aidl::Cid;
+use anyhow::{Context, Result};
+use binder::SpIBinder;
+use libc::VMADDR_CID_HOST;
+use log::{error, warn};
+use nix::sys::socket::{sendmsg, ControlMessage, MsgFlags};
+use rpcbinder::RpcServer;
+use std::io::{self, IoSlice};
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::raw::uid_t;
+use std::thread;
+use vsock::{VsockListener, VsockStream};
+use vsock_limits::{ConnectionPermit, ConnectionTracker};
+
+/// RPC Binder server of the VirtualMachineService of a VM, which stops when dropped.
+#[derive(Debug)]
+pub struct VmServiceServer {
+    _server: RpcServer,
+    /// Closing this socket stops the thread accepting the connections.
+    _stop: UnixStream,
+}
+
+impl VmServiceServer {
+    /// Serves `service` on vsock port `port` to the VM with CID `cid`, started by `uid`. The
+    /// connections are counted against the limits of `connections` as long as they are open.
+    pub fn new(
+        service: SpIBinder,
+        cid: Cid,
+        port: u32,
+        uid: uid_t,
+        connections: ConnectionTracker,
+    ) -> Result<Self> {
+        let listener = VsockListener::bind_with_cid_port(VMADDR_CID_HOST, port)
+            .with_context(|| format!("Failed to listen on vsock port {port}"))?;
+        // The server takes its connections from the file descriptors sent over this socket, so
+        // that only the accepted ones reach it.
+        let (bootstrap, server_fd) = UnixStream::pair()?;
+        let server = RpcServer::new_unix_domain_bootstrap(service, OwnedFd::from(server_fd))
+            .context("Failed to create RpcServer")?;
+        server.start();
+        let (stop, stopped) = UnixStream::pair()?;
+        let acceptor =
+            Acceptor { listener, bootstrap, stopped, cid, uid, connections, open: Vec::new() };
+        thread::Builder::new()
+            .name(format!("vm_service_{cid}"))
+            .spawn(move || acceptor.run())
+            .context("Failed to spawn the thread accepting connections")?;
+        Ok(Self { _server: server, _stop: stop })
+    }
+}
+
+/// Accepts the connections to the service of a VM, until `stopped` is closed by the other end.
+struct Acceptor {
+    listener: VsockListener,
+    bootstrap: UnixStream,
+    stopped: UnixStream,
+    cid: Cid,
+    uid: uid_t,
+    connections: ConnectionTracker,
+    /// The connections handed to the server. Their permits are held until the guest closes them.
+    open: Vec<(VsockStream, ConnectionPermit)>,
+}
+
+impl Acceptor {
+    fn run(mut self) {
+        loop {
+            let mut fds = vec![
+                pollfd(self.stopped.as_raw_fd(), libc::POLLIN),
+                pollfd(self.listener.as_raw_fd(), libc::POLLIN),
+            ];
+            fds.extend(
+                self.open.iter().map(|(stream, _)| pollfd(stream.as_raw_fd(), libc::POLLRDHUP)),
+            );
+            // SAFETY: `fds` is an array of `fds.len()` valid pollfd structures, of which poll only
+            // writes the `revents` fields.
+            let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+            if ret < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                error!("Failed to wait for connections to the service of VM {}: {e}", self.cid);
+                return;
+            }
+            if fds[0].revents != 0 {
+                return;
+            }
+            // Forget the connections closed by the guest, which gives their permits back.
+            let mut closed = fds[2..].iter().map(|fd| fd.revents != 0);
+            self.open.retain(|_| !closed.next().unwrap());
+            if fds[1].revents != 0 {
+                match self.listener.accept() {
+                    Ok((stream, _)) => {
+                        if let Err(e) = self.accept(stream) {
+                            warn!("Rejected connection to the service of VM {}: {e:?}", self.cid);
+                        }
+                    }
+                    Err(e) => warn!("Invalid incoming connection: {e:?}"),
+                }
+            }
+        }
+    }
+
+    /// Hands `stream` to the server, or fails with a [`vsock_limits::ConnectionLimitError`] if the
+    /// connection doesn't come from the VM or exceeds its limits. A rejected `stream` is closed.
+    fn accept(&mut self, stream: VsockStream) -> Result<()> {
+        let peer = stream.peer_addr().context("Failed to get the peer address")?.cid();
+        // Other VMs could connect to the port as well.
+        let uid = (peer == self.cid).then_some(self.uid);
+        let permit = self.connections.acquire(peer, uid)?;
+        // Send the file descriptor with a zero int, as RpcSession does for bootstrap clients.
+        let fds = [stream.as_raw_fd()];
+        sendmsg::<()>(
+            self.bootstrap.as_raw_fd(),
+            &[IoSlice::new(&0_i32.to_ne_bytes())],
+            &[ControlMessage::ScmRights(&fds)],
+            MsgFlags::empty(),
+            None,
+        )
+        .context("Failed to hand the connection to the server")?;
+        // Keep the connection open on this side too, to notice when the guest closes it.
+        self.open.push((stream, permit));
+        Ok(())
+    }
+}
+
+fn pollfd(fd: RawFd, events: libc::c_short) -> libc::pollfd {
+    libc::pollfd { fd, events, revents: 0 }
+}
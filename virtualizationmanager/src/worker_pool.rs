@@ -0,0 +1,113 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bounded pools of worker threads, to run long-running file I/O off the binder threads.
+
+use anyhow::{anyhow, Context, Result};
+use log::error;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed number of worker threads, running the jobs queued to them in order.
+pub struct WorkerPool {
+    name: String,
+    sender: Mutex<SyncSender<Job>>,
+}
+
+impl WorkerPool {
+    /// Starts `num_workers` threads named after `name`, with room for `queue_len` jobs waiting for
+    /// a worker to be available.
+    pub fn new(name: &str, num_workers: usize, queue_len: usize) -> Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue_len);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..num_workers {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("{name}_{i}"))
+                .spawn(move || run_worker(&receiver))
+                .with_context(|| format!("Failed to start worker {i} of {name}"))?;
+        }
+        Ok(Self { name: name.to_owned(), sender: Mutex::new(sender) })
+    }
+
+    /// Queues `job` without waiting for it to run. This fails if the queue is full, so that bursts
+    /// of requests are rejected rather than piling up.
+    pub fn spawn<F>(&self, job: F) -> Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        match self.sender.lock().unwrap().try_send(Box::new(job)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(anyhow!("Too many pending jobs in {}", self.name)),
+            Err(TrySendError::Disconnected(_)) => Err(anyhow!("No workers left in {}", self.name)),
+        }
+    }
+}
+
+fn run_worker(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        // Release the lock before running the job, so that other workers can pick the next ones.
+        let job = receiver.lock().unwrap().recv();
+        match job {
+            Ok(job) => job(),
+            Err(_) => {
+                error!("Worker pool was dropped, stopping {:?}", thread::current().name());
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+
+    #[test]
+    fn spawn_runs_job() -> Result<()> {
+        let pool = WorkerPool::new("test", 2, 1)?;
+        let (sender, receiver) = mpsc::channel();
+        pool.spawn(move || sender.send(42).unwrap())?;
+        assert_eq!(receiver.recv()?, 42);
+        Ok(())
+    }
+
+    #[test]
+    fn spawn_rejects_jobs_when_queue_is_full() -> Result<()> {
+        let pool = WorkerPool::new("test", 1, 1)?;
+        let barrier = Arc::new(Barrier::new(2));
+        let started = Arc::new(Barrier::new(2));
+        {
+            let barrier = barrier.clone();
+            let started = started.clone();
+            pool.spawn(move || {
+                started.wait();
+                barrier.wait();
+            })?;
+        }
+        // The worker is busy, so the next job fills the queue.
+        started.wait();
+        pool.spawn(|| {})?;
+        assert!(pool.spawn(|| {}).is_err());
+
+        barrier.wait();
+        let (sender, receiver) = mpsc::channel();
+        pool.spawn(move || sender.send("done").unwrap())?;
+        assert_eq!(receiver.recv()?, "done");
+        Ok(())
+    }
+}
@@ -16,12 +16,14 @@
 
 use crate::{get_calling_pid, get_calling_uid, REMOTELY_PROVISIONED_COMPONENT_SERVICE_NAME};
 use crate::atom::{forward_vm_booted_atom, forward_vm_creation_atom, forward_vm_exited_atom};
+use crate::creation_policy::CreationPolicies;
 use crate::rkpvm::request_attestation;
 use android_os_permissions_aidl::aidl::android::os::IPermissionController;
 use android_system_virtualizationcommon::aidl::android::system::virtualizationcommon::Certificate::Certificate;
 use android_system_virtualizationservice::{
     aidl::android::system::virtualizationservice::AssignableDevice::AssignableDevice,
     aidl::android::system::virtualizationservice::VirtualMachineDebugInfo::VirtualMachineDebugInfo,
+    aidl::android::system::virtualizationservice::VmCreationPolicyDecision::VmCreationPolicyDecision,
     binder::ParcelFileDescriptor,
 };
 use android_system_virtualizationservice_internal::aidl::android::system::virtualizationservice_internal::{
@@ -32,17 +34,19 @@ use android_system_virtualizationservice_internal::aidl::android::system::virtua
     IVirtualizationServiceInternal::BoundDevice::BoundDevice,
     IVirtualizationServiceInternal::IVirtualizationServiceInternal,
     IVfioHandler::{BpVfioHandler, IVfioHandler},
+    VmPowerStats::VmPowerStats,
 };
 use android_system_virtualmachineservice::aidl::android::system::virtualmachineservice::IVirtualMachineService::VM_TOMBSTONES_SERVICE_PORT;
 use anyhow::{anyhow, ensure, Context, Result};
 use avflog::LogResult;
-use binder::{self, wait_for_interface, BinderFeatures, ExceptionCode, Interface, LazyServiceGuard, Status, Strong, IntoBinderResult};
+use binder::{self, wait_for_interface, BinderFeatures, ExceptionCode, Interface, LazyServiceGuard, Status, StatusCode, Strong, IntoBinderResult};
 use libc::VMADDR_CID_HOST;
 use log::{error, info, warn};
 use rkpd_client::get_rkpd_attestation_key;
 use rustutils::system_properties;
 use serde::Deserialize;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::CStr;
 use std::fs::{self, create_dir, remove_dir_all, remove_file, set_permissions, File, Permissions};
 use std::io::{Read, Write};
 use std::os::unix::fs::PermissionsExt;
@@ -51,6 +55,7 @@ use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex, Weak};
 use tombstoned_client::{DebuggerdDumpType, TombstonedConnection};
 use vsock::{VsockListener, VsockStream};
+use vsock_limits::{ConnectionLimitError, ConnectionPermit, ConnectionTracker};
 use nix::unistd::{chown, Uid};
 use x509_parser::{traits::FromDer, certificate::X509Certificate};
 
@@ -80,14 +85,17 @@ fn is_valid_guest_cid(cid: Cid) -> bool {
 #[derive(Debug, Default)]
 pub struct VirtualizationServiceInternal {
     state: Arc<Mutex<GlobalState>>,
+    /// Policies which can defer or deny the creation of new VMs.
+    creation_policies: CreationPolicies,
 }
 
 impl VirtualizationServiceInternal {
     pub fn init() -> VirtualizationServiceInternal {
         let service = VirtualizationServiceInternal::default();
 
-        std::thread::spawn(|| {
-            if let Err(e) = handle_stream_connection_tombstoned() {
+        let state = service.state.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = handle_stream_connection_tombstoned(&state) {
                 warn!("Error receiving tombstone from guest or writing them. Error: {:?}", e);
             }
         });
@@ -96,7 +104,12 @@ impl VirtualizationServiceInternal {
     }
 }
 
-impl Interface for VirtualizationServiceInternal {}
+impl Interface for VirtualizationServiceInternal {
+    fn dump(&self, writer: &mut dyn Write, _args: &[&CStr]) -> Result<(), StatusCode> {
+        check_permission("android.permission.DUMP").or(Err(StatusCode::PERMISSION_DENIED))?;
+        self.state.lock().unwrap().connections.dump(writer).or(Err(StatusCode::UNKNOWN_ERROR))
+    }
+}
 
 impl IVirtualizationServiceInternal for VirtualizationServiceInternal {
     fn removeMemlockRlimit(&self) -> binder::Result<()> {
@@ -117,17 +130,25 @@ impl IVirtualizationServiceInternal for VirtualizationServiceInternal {
     fn allocateGlobalVmContext(
         &self,
         requester_debug_pid: i32,
+        tags: &[String],
     ) -> binder::Result<Strong<dyn IGlobalVmContext>> {
         check_manage_access()?;
 
         let requester_uid = get_calling_uid();
+        self.creation_policies.check(requester_uid).into_binder_result()?;
+
         let requester_debug_pid = requester_debug_pid as pid_t;
         let state = &mut *self.state.lock().unwrap();
         state
-            .allocate_vm_context(requester_uid, requester_debug_pid)
+            .allocate_vm_context(requester_uid, requester_debug_pid, tags.to_vec())
             .or_binder_exception(ExceptionCode::ILLEGAL_STATE)
     }
 
+    fn checkVmCreationPolicy(&self) -> binder::Result<VmCreationPolicyDecision> {
+        check_manage_access()?;
+        Ok(self.creation_policies.check(get_calling_uid()).to_parcelable())
+    }
+
     fn atomVmBooted(&self, atom: &AtomVmBooted) -> Result<(), Status> {
         forward_vm_booted_atom(atom);
         Ok(())
@@ -143,6 +164,27 @@ impl IVirtualizationServiceInternal for VirtualizationServiceInternal {
         Ok(())
     }
 
+    fn reportVmPowerUsage(&self, cpu_time_millis: i64, wakeups: i64) -> binder::Result<()> {
+        check_manage_access()?;
+        if cpu_time_millis < 0 || wakeups < 0 {
+            return Err(anyhow!("Invalid power usage: {cpu_time_millis}ms, {wakeups} wakeups"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+        }
+
+        // virtmgr runs with the UID of the app which owns the VM, so the calling UID is the one to
+        // charge. Don't trust a UID supplied by the caller.
+        let uid = get_calling_uid();
+        self.state.lock().unwrap().record_power_usage(uid, cpu_time_millis, wakeups);
+        Ok(())
+    }
+
+    fn getVmPowerStats(&self) -> binder::Result<Vec<VmPowerStats>> {
+        check_permission("android.permission.BATTERY_STATS")?;
+
+        let state = &*self.state.lock().unwrap();
+        Ok(state.vm_power_stats.values().cloned().collect())
+    }
+
     fn debugListVms(&self) -> binder::Result<Vec<VirtualMachineDebugInfo>> {
         check_debug_access()?;
 
@@ -156,6 +198,7 @@ impl IVirtualizationServiceInternal for VirtualizationServiceInternal {
                 temporaryDirectory: vm.get_temp_dir().to_string_lossy().to_string(),
                 requesterUid: vm.requester_uid as i32,
                 requesterPid: vm.requester_debug_pid,
+                tags: vm.tags.clone(),
             })
             .collect();
         Ok(cids)
@@ -319,6 +362,8 @@ struct GlobalVmInstance {
     requester_uid: uid_t,
     /// PID of the client who requested this VM instance.
     requester_debug_pid: pid_t,
+    /// Tags given in the config of the VM.
+    tags: Vec<String>,
 }
 
 impl GlobalVmInstance {
@@ -338,6 +383,13 @@ struct GlobalState {
 
     /// Cached read-only FD of VM DTBO file. Also serves as a lock for creating the file.
     dtbo_file: Mutex<Option<File>>,
+
+    /// Power usage of VMs, accumulated per owning UID.
+    vm_power_stats: BTreeMap<uid_t, VmPowerStats>,
+
+    /// Open vsock connections accepted from guests. A CID is never recycled as long as there is
+    /// an open connection from it, so that the connection can't be charged to the next VM.
+    connections: ConnectionTracker,
 }
 
 impl GlobalState {
@@ -384,23 +436,36 @@ impl GlobalState {
         Ok(cid)
     }
 
+    /// Adds the given power usage to the counters of the UID, saturating rather than wrapping.
+    fn record_power_usage(&mut self, uid: uid_t, cpu_time_millis: i64, wakeups: i64) {
+        let stats = self
+            .vm_power_stats
+            .entry(uid)
+            .or_insert_with(|| VmPowerStats { uid: uid as i32, ..Default::default() });
+        stats.cpuTimeMillis = stats.cpuTimeMillis.saturating_add(cpu_time_millis);
+        stats.wakeups = stats.wakeups.saturating_add(wakeups);
+    }
+
     fn find_available_cid<I>(&self, mut range: I) -> Option<Cid>
     where
         I: Iterator<Item = Cid>,
     {
-        range.find(|cid| !self.held_contexts.contains_key(cid))
+        range.find(|cid| {
+            !self.held_contexts.contains_key(cid) && !self.connections.is_connected(*cid)
+        })
     }
 
     fn allocate_vm_context(
         &mut self,
         requester_uid: uid_t,
         requester_debug_pid: pid_t,
+        tags: Vec<String>,
     ) -> Result<Strong<dyn IGlobalVmContext>> {
         // Garbage collect unused VM contexts.
         self.held_contexts.retain(|_, instance| instance.strong_count() > 0);
 
         let cid = self.get_next_available_cid()?;
-        let instance = Arc::new(GlobalVmInstance { cid, requester_uid, requester_debug_pid });
+        let instance = Arc::new(GlobalVmInstance { cid, requester_uid, requester_debug_pid, tags });
         create_temporary_directory(&instance.get_temp_dir(), Some(requester_uid))?;
 
         self.held_contexts.insert(cid, Arc::downgrade(&instance));
@@ -408,6 +473,16 @@ impl GlobalState {
         Ok(BnGlobalVmContext::new_binder(binder, BinderFeatures::default()))
     }
 
+    /// Counts a connection from the VM with the given CID against the limits of the VM and of the
+    /// client which started it, failing if either is exceeded or if the VM isn't running.
+    ///
+    /// The VM is looked up and the connection counted under the same lock as CID allocation, so
+    /// the CID can't be reallocated to another VM in between.
+    fn accept_connection(&self, cid: Cid) -> Result<ConnectionPermit, ConnectionLimitError> {
+        let uid = self.held_contexts.get(&cid).and_then(Weak::upgrade).map(|vm| vm.requester_uid);
+        self.connections.acquire(cid, uid)
+    }
+
     fn get_dtbo_file(&mut self) -> Result<File> {
         let mut file = self.dtbo_file.lock().unwrap();
 
@@ -502,7 +577,7 @@ impl IGlobalVmContext for GlobalVmContext {
     }
 }
 
-fn handle_stream_connection_tombstoned() -> Result<()> {
+fn handle_stream_connection_tombstoned(state: &Mutex<GlobalState>) -> Result<()> {
     // Should not listen for tombstones on a guest VM's port.
     assert!(!is_valid_guest_cid(VM_TOMBSTONES_SERVICE_PORT as Cid));
     let listener =
@@ -515,15 +590,30 @@ fn handle_stream_connection_tombstoned() -> Result<()> {
             }
             Ok(s) => s,
         };
+        let permit = match accept_connection(&incoming_stream, state) {
+            Err(e) => {
+                warn!("Rejecting tombstone connection: {:?}", e);
+                continue;
+            }
+            Ok(p) => p,
+        };
         std::thread::spawn(move || {
             if let Err(e) = handle_tombstone(&mut incoming_stream) {
                 error!("Failed to write tombstone- {:?}", e);
             }
+            drop(permit);
         });
     }
     Ok(())
 }
 
+/// Counts a connection from a guest against the limits of its VM and of the client which started
+/// it, failing if either is exceeded.
+fn accept_connection(stream: &VsockStream, state: &Mutex<GlobalState>) -> Result<ConnectionPermit> {
+    let cid = stream.peer_addr().context("Failed to get the peer address")?.cid();
+    Ok(state.lock().unwrap().accept_connection(cid)?)
+}
+
 fn handle_tombstone(stream: &mut VsockStream) -> Result<()> {
     if let Ok(addr) = stream.peer_addr() {
         info!("Vsock Stream connected to cid={} for tombstones", addr.cid());
@@ -604,4 +694,17 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn power_usage_accumulates_per_uid() {
+        let mut state = GlobalState::default();
+        state.record_power_usage(10001, 100, 3);
+        state.record_power_usage(10002, 50, 1);
+        state.record_power_usage(10001, 20, 2);
+        state.record_power_usage(10002, i64::MAX, 0);
+
+        let stats: Vec<_> =
+            state.vm_power_stats.values().map(|s| (s.uid, s.cpuTimeMillis, s.wakeups)).collect();
+        assert_eq!(vec![(10001, 120, 5), (10002, i64::MAX, 1)], stats);
+    }
 }
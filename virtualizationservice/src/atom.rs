@@ -21,11 +21,19 @@ use android_system_virtualizationservice_internal::aidl::android::system::virtua
     AtomVmExited::AtomVmExited,
 };
 use anyhow::Result;
-use log::{trace, warn};
+use log::{info, trace, warn};
 use rustutils::system_properties::PropertyWatcher;
 use statslog_virtualization_rust::{vm_booted, vm_creation_requested, vm_exited};
 
 pub fn forward_vm_creation_atom(atom: &AtomVmCreationRequested) {
+    if atom.hasExtraCrosvmArgs {
+        // Metrics of such VMs aren't representative of the production configuration.
+        info!("VM {} (uid {}) was created with extra crosvm args", atom.vmIdentifier, atom.uid);
+    }
+    if atom.creationCanceled {
+        // The atom only records the service-specific exception, so tell cancellations apart here.
+        info!("Creation of VM {} (uid {}) was cancelled", atom.vmIdentifier, atom.uid);
+    }
     let config_type = match atom.configType {
         x if x == vm_creation_requested::ConfigType::VirtualMachineAppConfig as i32 => {
             vm_creation_requested::ConfigType::VirtualMachineAppConfig
@@ -35,6 +43,7 @@ pub fn forward_vm_creation_atom(atom: &AtomVmCreationRequested) {
         }
         _ => vm_creation_requested::ConfigType::UnknownConfig,
     };
+    log_tags("created", &atom.vmIdentifier, atom.uid, &atom.tags);
     let vm_creation_requested = vm_creation_requested::VmCreationRequested {
         uid: atom.uid,
         vm_identifier: &atom.vmIdentifier,
@@ -59,6 +68,7 @@ pub fn forward_vm_creation_atom(atom: &AtomVmCreationRequested) {
 }
 
 pub fn forward_vm_booted_atom(atom: &AtomVmBooted) {
+    log_tags("booted", &atom.vmIdentifier, atom.uid, &atom.tags);
     let vm_booted = vm_booted::VmBooted {
         uid: atom.uid,
         vm_identifier: &atom.vmIdentifier,
@@ -106,6 +116,7 @@ pub fn forward_vm_exited_atom(atom: &AtomVmExited) {
         _ => vm_exited::DeathReason::Unknown,
     };
 
+    log_tags("exited", &atom.vmIdentifier, atom.uid, &atom.tags);
     let vm_exited = vm_exited::VmExited {
         uid: atom.uid,
         vm_identifier: &atom.vmIdentifier,
@@ -124,6 +135,14 @@ pub fn forward_vm_exited_atom(atom: &AtomVmExited) {
     }
 }
 
+/// Logs the tags of a VM next to the atom reporting `event`, as the VM atoms have no field for
+/// them yet. Tags can't contain commas, see `check_tags` in virtualizationmanager.
+fn log_tags(event: &str, vm_identifier: &str, uid: i32, tags: &[String]) {
+    if !tags.is_empty() {
+        info!("VM {vm_identifier} (uid {uid}) {event} with tags {}", tags.join(","));
+    }
+}
+
 fn wait_for_statsd() -> Result<()> {
     PropertyWatcher::new("init.svc.statsd")?.wait_for_value("running", None)?;
     Ok(())
@@ -0,0 +1,328 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Policies consulted before a VM is created, which can defer or deny the creation, e.g. during a
+//! device maintenance window or because of thermal, battery or enterprise restrictions.
+
+use android_system_virtualizationservice::aidl::android::system::virtualizationservice::{
+    IVirtualizationService::{ERROR_CREATION_DEFERRED, ERROR_CREATION_DENIED},
+    VmCreationPolicyDecision::{Kind::Kind, VmCreationPolicyDecision},
+};
+use anyhow::{anyhow, Context, Result};
+use binder::IntoBinderResult;
+use log::warn;
+use rustutils::system_properties;
+use std::fmt;
+use std::fs;
+use std::os::unix::raw::uid_t;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Device config flag which denies the creation of any VM while set.
+const SYSPROP_CREATION_DENIED: &str =
+    "persist.device_config.virtualization_framework_native.vm_creation_denied";
+
+/// Device config flag holding the end of the current maintenance window, in milliseconds since the
+/// Unix epoch. VM creations are deferred until then.
+const SYSPROP_MAINTENANCE_WINDOW_END: &str =
+    "persist.device_config.virtualization_framework_native.maintenance_window_end_millis";
+
+/// Device config flag holding the battery level, in percent, under which VM creations are
+/// deferred while the device isn't charging. The policy is disabled if it isn't set.
+const SYSPROP_MIN_BATTERY_PERCENT: &str =
+    "persist.device_config.virtualization_framework_native.vm_creation_min_battery_percent";
+
+/// Device config flag holding the temperature, in millidegrees Celsius, above which a thermal
+/// zone defers VM creations. The policy is disabled if it isn't set.
+const SYSPROP_MAX_TEMPERATURE: &str =
+    "persist.device_config.virtualization_framework_native.vm_creation_max_temp_millicelsius";
+
+const BATTERY_PATH: &str = "/sys/class/power_supply/battery";
+const THERMAL_PATH: &str = "/sys/class/thermal";
+
+/// Delay after which a creation deferred because of a low battery may be retried.
+const BATTERY_RETRY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// Delay after which a creation deferred because of the device temperature may be retried.
+const THERMAL_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Outcome of consulting a [`VmCreationPolicy`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PolicyDecision {
+    /// The VM can be created.
+    Allow,
+    /// The VM can't be created now, but the client may retry after the given delay.
+    Defer { reason: String, retry_after: Duration },
+    /// The VM can't be created.
+    Deny { reason: String },
+}
+
+impl PolicyDecision {
+    /// Converts the decision into the error returned to the client, if any. Clients get the delay
+    /// of a deferral from `checkVmCreationPolicy`.
+    pub fn into_binder_result(self) -> binder::Result<()> {
+        match self {
+            Self::Allow => Ok(()),
+            Self::Defer { reason, .. } => {
+                Err(anyhow!("{reason}")).or_service_specific_exception(ERROR_CREATION_DEFERRED)
+            }
+            Self::Deny { reason } => {
+                Err(anyhow!("{reason}")).or_service_specific_exception(ERROR_CREATION_DENIED)
+            }
+        }
+    }
+
+    /// Converts the decision into the parcelable returned by `checkVmCreationPolicy`.
+    pub fn to_parcelable(&self) -> VmCreationPolicyDecision {
+        match self {
+            Self::Allow => VmCreationPolicyDecision::default(),
+            Self::Defer { reason, retry_after } => VmCreationPolicyDecision {
+                kind: Kind::DEFER,
+                retryAfterMillis: retry_after.as_millis().try_into().unwrap_or(i64::MAX),
+                reason: reason.clone(),
+            },
+            Self::Deny { reason } => VmCreationPolicyDecision {
+                kind: Kind::DENY,
+                reason: reason.clone(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// A policy consulted before allocating the resources of a new VM.
+pub trait VmCreationPolicy: fmt::Debug + Send + Sync {
+    /// Decides whether a VM requested by `requester_uid` can be created now.
+    fn check(&self, requester_uid: uid_t) -> Result<PolicyDecision>;
+}
+
+/// Policy driven by device config flags, e.g. pushed by the device owner ahead of maintenance.
+#[derive(Debug, Default)]
+pub struct DeviceConfigPolicy;
+
+impl VmCreationPolicy for DeviceConfigPolicy {
+    fn check(&self, _requester_uid: uid_t) -> Result<PolicyDecision> {
+        let denied = system_properties::read_bool(SYSPROP_CREATION_DENIED, false)?;
+        let window_end = read_sysprop_u64(SYSPROP_MAINTENANCE_WINDOW_END)?
+            .map(|ms| UNIX_EPOCH + Duration::from_millis(ms));
+        Ok(device_config_decision(denied, window_end, SystemTime::now()))
+    }
+}
+
+fn device_config_decision(
+    denied: bool,
+    window_end: Option<SystemTime>,
+    now: SystemTime,
+) -> PolicyDecision {
+    if denied {
+        return PolicyDecision::Deny { reason: "VM creation is disabled on this device".into() };
+    }
+    match window_end.and_then(|end| end.duration_since(now).ok()) {
+        Some(retry_after) if !retry_after.is_zero() => PolicyDecision::Defer {
+            reason: "Device is in a maintenance window".into(),
+            retry_after,
+        },
+        _ => PolicyDecision::Allow,
+    }
+}
+
+/// Policy deferring creations while the battery is low and the device isn't charging.
+#[derive(Debug, Default)]
+pub struct BatteryPolicy;
+
+impl VmCreationPolicy for BatteryPolicy {
+    fn check(&self, _requester_uid: uid_t) -> Result<PolicyDecision> {
+        let Some(min_percent) = read_sysprop_u64(SYSPROP_MIN_BATTERY_PERCENT)? else {
+            return Ok(PolicyDecision::Allow);
+        };
+        let battery = Path::new(BATTERY_PATH);
+        let capacity = read_sysfs_u64(&battery.join("capacity"))?;
+        let status = fs::read_to_string(battery.join("status")).context("Failed to read status")?;
+        let charging = matches!(status.trim(), "Charging" | "Full");
+        Ok(battery_decision(capacity, charging, min_percent))
+    }
+}
+
+fn battery_decision(capacity: u64, charging: bool, min_percent: u64) -> PolicyDecision {
+    if charging || capacity >= min_percent {
+        return PolicyDecision::Allow;
+    }
+    PolicyDecision::Defer {
+        reason: format!("Battery level {capacity}% is below {min_percent}%"),
+        retry_after: BATTERY_RETRY_AFTER,
+    }
+}
+
+/// Policy deferring creations while a thermal zone of the device is too hot.
+#[derive(Debug, Default)]
+pub struct ThermalPolicy;
+
+impl VmCreationPolicy for ThermalPolicy {
+    fn check(&self, _requester_uid: uid_t) -> Result<PolicyDecision> {
+        let Some(max_temperature) = read_sysprop_u64(SYSPROP_MAX_TEMPERATURE)? else {
+            return Ok(PolicyDecision::Allow);
+        };
+        let mut temperatures = vec![];
+        for entry in fs::read_dir(THERMAL_PATH).context("Failed to list thermal zones")? {
+            let path = entry?.path();
+            let is_zone = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("thermal_zone"));
+            if is_zone {
+                // Disabled zones may fail to report their temperature.
+                if let Ok(temperature) = read_sysfs_u64(&path.join("temp")) {
+                    temperatures.push(temperature);
+                }
+            }
+        }
+        Ok(thermal_decision(temperatures.into_iter().max(), max_temperature))
+    }
+}
+
+fn thermal_decision(temperature: Option<u64>, max_temperature: u64) -> PolicyDecision {
+    match temperature {
+        Some(temperature) if temperature > max_temperature => PolicyDecision::Defer {
+            reason: format!(
+                "Device temperature {temperature} exceeds the limit of {max_temperature}"
+            ),
+            retry_after: THERMAL_RETRY_AFTER,
+        },
+        _ => PolicyDecision::Allow,
+    }
+}
+
+fn read_sysprop_u64(name: &str) -> Result<Option<u64>> {
+    system_properties::read(name)?
+        .map(|v| v.parse().with_context(|| format!("Invalid value of {name}: {v:?}")))
+        .transpose()
+}
+
+fn read_sysfs_u64(path: &Path) -> Result<u64> {
+    let value = fs::read_to_string(path).with_context(|| format!("Failed to read {path:?}"))?;
+    value.trim().parse().with_context(|| format!("Invalid value in {path:?}: {value:?}"))
+}
+
+/// The ordered set of policies consulted before creating a VM. The first one which doesn't allow
+/// the creation decides.
+#[derive(Debug)]
+pub struct CreationPolicies(Vec<Box<dyn VmCreationPolicy>>);
+
+impl Default for CreationPolicies {
+    fn default() -> Self {
+        Self(vec![Box::new(DeviceConfigPolicy), Box::new(ThermalPolicy), Box::new(BatteryPolicy)])
+    }
+}
+
+impl CreationPolicies {
+    /// Consults every policy in turn. A policy which fails to decide is ignored, so that a
+    /// misconfigured device doesn't lose the ability to run VMs.
+    pub fn check(&self, requester_uid: uid_t) -> PolicyDecision {
+        for policy in &self.0 {
+            match policy.check(requester_uid) {
+                Ok(PolicyDecision::Allow) => {}
+                Ok(decision) => return decision,
+                Err(e) => warn!("Ignoring VM creation policy {policy:?}: {e:?}"),
+            }
+        }
+        PolicyDecision::Allow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Fixed(Result<PolicyDecision, ()>);
+
+    impl VmCreationPolicy for Fixed {
+        fn check(&self, _requester_uid: uid_t) -> Result<PolicyDecision> {
+            self.0.clone().map_err(|_| anyhow!("broken policy"))
+        }
+    }
+
+    #[test]
+    fn device_config_defers_until_window_end() {
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        let end = now + Duration::from_secs(30);
+        assert_eq!(
+            device_config_decision(false, Some(end), now),
+            PolicyDecision::Defer {
+                reason: "Device is in a maintenance window".into(),
+                retry_after: Duration::from_secs(30),
+            }
+        );
+        assert_eq!(device_config_decision(false, Some(now), now), PolicyDecision::Allow);
+        assert_eq!(device_config_decision(false, Some(UNIX_EPOCH), now), PolicyDecision::Allow);
+        assert_eq!(device_config_decision(false, None, now), PolicyDecision::Allow);
+        assert!(matches!(
+            device_config_decision(true, Some(end), now),
+            PolicyDecision::Deny { .. }
+        ));
+    }
+
+    #[test]
+    fn first_restrictive_policy_decides() {
+        let deny = PolicyDecision::Deny { reason: "enterprise".into() };
+        let policies = CreationPolicies(vec![
+            Box::new(Fixed(Err(()))),
+            Box::new(Fixed(Ok(PolicyDecision::Allow))),
+            Box::new(Fixed(Ok(deny.clone()))),
+            Box::new(Fixed(Ok(PolicyDecision::Defer {
+                reason: "thermal".into(),
+                retry_after: Duration::from_secs(1),
+            }))),
+        ]);
+        assert_eq!(policies.check(1000), deny);
+        assert_eq!(CreationPolicies(vec![]).check(1000), PolicyDecision::Allow);
+    }
+
+    #[test]
+    fn deferral_carries_retry_after() {
+        let decision = PolicyDecision::Defer {
+            reason: "thermal".into(),
+            retry_after: Duration::from_millis(1500),
+        };
+        let parcelable = decision.to_parcelable();
+        assert_eq!(parcelable.kind, Kind::DEFER);
+        assert_eq!(parcelable.retryAfterMillis, 1500);
+        assert_eq!(parcelable.reason, "thermal");
+        let status = decision.into_binder_result().unwrap_err();
+        assert_eq!(status.service_specific_error(), ERROR_CREATION_DEFERRED);
+    }
+
+    #[test]
+    fn battery_defers_when_low_and_discharging() {
+        assert_eq!(battery_decision(50, false, 20), PolicyDecision::Allow);
+        assert_eq!(battery_decision(10, true, 20), PolicyDecision::Allow);
+        assert_eq!(
+            battery_decision(10, false, 20),
+            PolicyDecision::Defer {
+                reason: "Battery level 10% is below 20%".into(),
+                retry_after: BATTERY_RETRY_AFTER,
+            }
+        );
+    }
+
+    #[test]
+    fn thermal_defers_when_too_hot() {
+        assert_eq!(thermal_decision(None, 45000), PolicyDecision::Allow);
+        assert_eq!(thermal_decision(Some(45000), 45000), PolicyDecision::Allow);
+        assert!(matches!(
+            thermal_decision(Some(45001), 45000),
+            PolicyDecision::Defer { retry_after: THERMAL_RETRY_AFTER, .. }
+        ));
+    }
+}
@@ -16,6 +16,7 @@
 
 mod aidl;
 mod atom;
+mod creation_policy;
 mod remote_provisioning;
 mod rkpvm;
 
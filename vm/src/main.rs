@@ -83,6 +83,11 @@ pub struct DebugConfig {
     /// Note: this is only supported on Android kernels android14-5.15 and higher.
     #[arg(long)]
     gdb: Option<NonZeroU16>,
+
+    /// Extra argument to append to the crosvm command line. Can be repeated. Only allowed on
+    /// debuggable builds.
+    #[arg(long = "extra-crosvm-arg", allow_hyphen_values = true)]
+    extra_crosvm_args: Vec<String>,
 }
 
 #[derive(Args)]
@@ -367,6 +372,10 @@ fn command_info() -> Result<(), Error> {
         gki_configs.iter().filter_map(|x| extract_gki_version(x)).collect::<Vec<_>>();
     println!("Available gki versions: {}", serde_json::to_string(&gki_versions)?);
 
+    let guest_oses = get_service()?.getSupportedGuestOses()?;
+    let guest_oses = guest_oses.into_iter().map(|x| x.name).collect::<Vec<_>>();
+    println!("Available guest OSes: {}", serde_json::to_string(&guest_oses)?);
+
     Ok(())
 }
 
@@ -17,7 +17,9 @@
 use crate::create_partition::command_create_partition;
 use crate::{get_service, RunAppConfig, RunCustomVmConfig, RunMicrodroidConfig};
 use android_system_virtualizationservice::aidl::android::system::virtualizationservice::{
-    IVirtualizationService::IVirtualizationService,
+    IVirtualizationService::{
+        IVirtualizationService, ERROR_CREATION_DEFERRED, ERROR_CREATION_DENIED,
+    },
     PartitionType::PartitionType,
     VirtualMachineAppConfig::{
         CustomConfig::CustomConfig, DebugLevel::DebugLevel, Payload::Payload,
@@ -26,9 +28,10 @@ use android_system_virtualizationservice::aidl::android::system::virtualizations
     VirtualMachineConfig::VirtualMachineConfig,
     VirtualMachinePayloadConfig::VirtualMachinePayloadConfig,
     VirtualMachineState::VirtualMachineState,
+    VmCreationPolicyDecision::Kind::Kind as VmCreationPolicyKind,
 };
 use anyhow::{anyhow, bail, Context, Error};
-use binder::ParcelFileDescriptor;
+use binder::{ParcelFileDescriptor, Status};
 use glob::glob;
 use microdroid_payload_config::VmPayloadConfig;
 use rand::{distributions::Alphanumeric, Rng};
@@ -139,6 +142,7 @@ pub fn command_run_app(config: RunAppConfig) -> Result<(), Error> {
                 x.to_str().map(String::from).ok_or(anyhow!("Failed to convert {x:?} to String"))
             })
             .collect::<Result<_, _>>()?,
+        extraCrosvmArgs: config.debug.extra_crosvm_args,
     };
 
     let vm_config = VirtualMachineConfig::AppConfig(VirtualMachineAppConfig {
@@ -154,6 +158,7 @@ pub fn command_run_app(config: RunAppConfig) -> Result<(), Error> {
         memoryMib: config.common.mem.unwrap_or(0) as i32, // 0 means use the VM default
         cpuTopology: config.common.cpu_topology,
         customConfig: Some(custom_config),
+        tags: vec![],
     });
     run(
         service.as_ref(),
@@ -230,6 +235,7 @@ pub fn command_run(config: RunCustomVmConfig) -> Result<(), Error> {
     }
     vm_config.cpuTopology = config.common.cpu_topology;
     vm_config.taskProfiles = config.common.task_profiles;
+    vm_config.extraCrosvmArgs = config.debug.extra_crosvm_args;
     run(
         get_service()?.as_ref(),
         &VirtualMachineConfig::RawConfig(vm_config),
@@ -240,6 +246,30 @@ pub fn command_run(config: RunCustomVmConfig) -> Result<(), Error> {
     )
 }
 
+/// Explains why the VM couldn't be created, including when to retry if a device policy deferred
+/// the creation.
+fn creation_error(service: &dyn IVirtualizationService, status: Status) -> Error {
+    let code = status.service_specific_error();
+    if code == ERROR_CREATION_DEFERRED || code == ERROR_CREATION_DENIED {
+        if let Ok(decision) = service.checkVmCreationPolicy() {
+            match decision.kind {
+                VmCreationPolicyKind::DEFER => {
+                    return anyhow!(
+                        "VM creation deferred by a device policy: {}. Retry in {} ms.",
+                        decision.reason,
+                        decision.retryAfterMillis
+                    );
+                }
+                VmCreationPolicyKind::DENY => {
+                    return anyhow!("VM creation denied by a device policy: {}", decision.reason);
+                }
+                _ => {}
+            }
+        }
+    }
+    Error::new(status).context("Failed to create VM")
+}
+
 fn state_to_str(vm_state: VirtualMachineState) -> &'static str {
     match vm_state {
         VirtualMachineState::NOT_STARTED => "NOT_STARTED",
@@ -285,7 +315,7 @@ fn run(
     };
     let callback = Box::new(Callback {});
     let vm = VmInstance::create(service, config, console_out, console_in, log, Some(callback))
-        .context("Failed to create VM")?;
+        .map_err(|e| creation_error(service, e))?;
     vm.start().context("Failed to start VM")?;
 
     let debug_level = match config {
@@ -260,4 +260,26 @@ size_t AVmAttestationResult_getCertificateAt(const struct AVmAttestationResult*
                                              size_t index, void* _Nullable data, size_t size)
         __INTRODUCED_IN(__ANDROID_API_V__);
 
+/**
+ * Lets the payload migrate the state it persisted with a previous version, if
+ * it was updated since the VM instance last ran it.
+ *
+ * Updates of the payload signed with the same key are accepted, keeping the VM
+ * instance secrets unchanged. If there is an update to migrate, `callback` is
+ * called with the version code of the payload APK before the update. Once it
+ * returns true, the VM instance is bound to the updated payload and the
+ * callback isn't called again on later boots. Until then, e.g. if the callback
+ * returns false or the VM stops first, it is called again on the next boot.
+ *
+ * \param callback the function migrating the state of the payload, which is
+ * called at most once, while this function is executing.
+ * \param param parameter to be passed to the `callback`.
+ *
+ * \return true if the payload was updated and `callback` succeeded, false
+ * otherwise.
+ */
+bool AVmPayload_migratePayloadUpdate(
+        bool (*_Nonnull callback)(int64_t previous_version_code, void* _Nullable param),
+        void* _Nullable param) __INTRODUCED_IN(__ANDROID_API_V__);
+
 __END_DECLS
@@ -15,7 +15,7 @@
 //! This module handles the interaction with virtual machine payload service.
 
 use android_system_virtualization_payload::aidl::android::system::virtualization::payload:: IVmPayloadService::{
-    IVmPayloadService, ENCRYPTEDSTORE_MOUNTPOINT, VM_APK_CONTENTS_PATH,
+    IVmPayloadService, ENCRYPTEDSTORE_MOUNTPOINT, NO_PAYLOAD_UPDATE, VM_APK_CONTENTS_PATH,
     VM_PAYLOAD_SERVICE_SOCKET_NAME, AttestationResult::AttestationResult,
 };
 use anyhow::{bail, ensure, Context, Result};
@@ -492,6 +492,47 @@ pub unsafe extern "C" fn AVmAttestationResult_free(res: *mut AttestationResult)
     }
 }
 
+/// Calls `callback` to migrate the state of the payload, if it was updated since the previous
+/// boot, then binds the VM instance to the updated payload if the callback succeeded.
+/// Panics on failure.
+///
+/// # Safety
+///
+/// The `callback` must be a valid function pointer, which will be called at most once, while this
+/// function is executing, with the `param` parameter.
+#[no_mangle]
+pub unsafe extern "C" fn AVmPayload_migratePayloadUpdate(
+    callback: unsafe extern "C" fn(previous_version_code: i64, param: *mut c_void) -> bool,
+    param: *mut c_void,
+) -> bool {
+    initialize_logging();
+
+    // SAFETY: See the requirements on `callback` above.
+    unwrap_or_abort(unsafe { try_migrate_payload_update(callback, param) })
+}
+
+/// # Safety: Same as `AVmPayload_migratePayloadUpdate`.
+unsafe fn try_migrate_payload_update(
+    callback: unsafe extern "C" fn(previous_version_code: i64, param: *mut c_void) -> bool,
+    param: *mut c_void,
+) -> Result<bool> {
+    let service = get_vm_payload_service()?;
+    let version_code = service
+        .getPreviousPayloadVersionCode()
+        .context("Cannot get previous payload version code")?;
+    if version_code == NO_PAYLOAD_UPDATE {
+        return Ok(false);
+    }
+    // SAFETY: We're calling the callback with the parameter specified within the allowed
+    // lifetime.
+    if !unsafe { callback(version_code, param) } {
+        info!("Payload failed to migrate from version {version_code}");
+        return Ok(false);
+    }
+    service.notifyPayloadMigrated().context("Cannot notify payload migrated")?;
+    Ok(true)
+}
+
 /// Gets the path to the APK contents.
 #[no_mangle]
 pub extern "C" fn AVmPayload_getApkContentsPath() -> *const c_char {
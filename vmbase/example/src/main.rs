@@ -33,7 +33,7 @@ use fdtpci::PciInfo;
 use libfdt::Fdt;
 use log::{debug, error, info, trace, warn, LevelFilter};
 use vmbase::{
-    bionic, configure_heap,
+    bionic, configure_heap, heap,
     layout::{dtb_range, rodata_range, scratch_range, text_range},
     linker, logger, main,
     memory::{PageTable, SIZE_64KB},
@@ -54,7 +54,7 @@ fn init_page_table(pci_bar_range: &MemoryRegion) -> Result<(), MapError> {
     page_table.map_rodata(&rodata_range().into())?;
     page_table.map_data(&scratch_range().into())?;
     page_table.map_data(&boot_stack_range().into())?;
-    page_table.map_rodata(&dtb_range().into())?;
+    page_table.map_data(&dtb_range().into())?;
     page_table.map_device(pci_bar_range)?;
 
     info!("Activating IdMap...");
@@ -98,6 +98,7 @@ pub fn main(arg0: u64, arg1: u64, arg2: u64, arg3: u64) {
     init_page_table(&get_bar_region(&pci_info)).unwrap();
 
     check_data();
+    check_reclaimed_dtb();
     check_dice();
 
     // SAFETY: This is the only place where `make_pci_root` is called.
@@ -215,6 +216,19 @@ fn check_alloc() {
     info!("Vec seems to work.");
 }
 
+fn check_reclaimed_dtb() {
+    info!("Handing the DTB range over to the heap...");
+    let dtb = dtb_range();
+    // SAFETY: The DT isn't accessed past this point and the range is mapped as writable data.
+    unsafe { heap::add_region(dtb.start.0..dtb.end.0) }.unwrap();
+
+    // This wouldn't fit in the heap configured with configure_heap!().
+    let vector = vec![0xa5u8; 4 * SIZE_64KB];
+    assert!(vector.iter().all(|b| *b == 0xa5));
+    assert!(dtb.start.0 <= vector.as_ptr() as usize && (vector.as_ptr() as usize) < dtb.end.0);
+    info!("Reclaimed DTB range seems to work.");
+}
+
 fn check_dice() {
     info!("Testing DICE integration...");
     let hash = diced_open_dice::hash("hello world".as_bytes()).expect("DiceHash failed");
@@ -27,7 +27,10 @@ use virtio_drivers::{
     },
     BufferDirection, Error, Hal, PhysAddr, PAGE_SIZE,
 };
-use vmbase::virtio::pci::{self, PciTransportIterator};
+use vmbase::virtio::{
+    pci::{self, PciTransportIterator},
+    TrackedTransport,
+};
 
 /// The standard sector size of a VirtIO block device, in bytes.
 const SECTOR_SIZE_BYTES: usize = 512;
@@ -71,7 +74,8 @@ pub fn check_pci(pci_root: &mut PciRoot) {
 
 /// Checks the given VirtIO block device.
 fn check_virtio_block_device(transport: PciTransport, index: usize) {
-    let mut blk = pci::VirtIOBlk::<HalImpl>::new(transport).expect("failed to create blk driver");
+    let mut blk = pci::VirtIOBlk::<HalImpl>::new(TrackedTransport::new(transport))
+        .expect("failed to create blk driver");
     info!("Found {} KiB block device.", blk.capacity() * SECTOR_SIZE_BYTES as u64 / 1024);
     match index {
         0 => {
@@ -97,7 +101,7 @@ fn check_virtio_block_device(transport: PciTransport, index: usize) {
 
 /// Checks the given VirtIO socket device.
 fn check_virtio_socket_device(transport: PciTransport) {
-    let socket = pci::VirtIOSocket::<HalImpl>::new(transport)
+    let socket = pci::VirtIOSocket::<HalImpl>::new(TrackedTransport::new(transport))
         .expect("Failed to create VirtIO socket driver");
     info!("Found socket device: guest_cid={}", socket.guest_cid());
 }
@@ -15,7 +15,8 @@
 //! Rust entry point.
 
 use crate::{
-    bionic, console, heap, logger,
+    bionic, console, heap, layout, logger,
+    memory::{claim_memory, MemoryOwner},
     power::{reboot, shutdown},
     rand,
 };
@@ -48,6 +49,13 @@ extern "C" fn rust_entry(x0: u64, x1: u64, x2: u64, x3: u64) -> ! {
     logger::init().expect("Failed to initialize the logger");
     // We initialize the logger to Off (like the log crate) and clients should log::set_max_level.
 
+    if let Err(e) = layout::check::validate() {
+        panic!("Invalid memory layout: {e}");
+    }
+
+    let image = layout::text_range().start.0..layout::binary_end().0;
+    claim_memory(image, MemoryOwner::Image).expect("Failed to claim the image memory");
+
     const SIZE_OF_STACK_GUARD: usize = size_of::<u64>();
     let mut stack_guard = [0u8; SIZE_OF_STACK_GUARD];
     // We keep a null byte at the top of the stack guard to act as a string terminator.
@@ -16,14 +16,29 @@
 
 use crate::{
     console, eprintln,
+    layout::LayoutRegion,
     memory::{page_4kb_of, MemoryTrackerError},
     read_sysreg,
 };
 use aarch64_paging::paging::VirtualAddress;
 use core::fmt;
+use spin::mutex::SpinMutex;
 
 const UART_PAGE: usize = page_4kb_of(console::BASE_ADDRESS);
 
+/// Handler called with the details of the exceptions which are reported as fatal.
+pub type FaultHandler = fn(&ArmException, u64);
+
+static FAULT_HANDLER: SpinMutex<Option<FaultHandler>> = SpinMutex::new(None);
+
+/// Registers `handler` to be called with the exception and the exception link register whenever
+/// an exception is reported with [`ArmException::print`], e.g. to add client-specific context.
+///
+/// Replaces any previously registered handler.
+pub fn register_fault_handler(handler: FaultHandler) {
+    *FAULT_HANDLER.lock() = Some(handler);
+}
+
 /// Represents an error that can occur while handling an exception.
 #[derive(Debug)]
 pub enum HandleExceptionError {
@@ -99,17 +114,139 @@ impl fmt::Display for Esr {
         }
     }
 }
+
+/// Type of the fault reported by the fault status code (FSC) of an abort.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum FaultKind {
+    /// Address size fault at the given translation table level.
+    AddressSize { level: u8 },
+    /// Translation fault at the given translation table level.
+    Translation { level: u8 },
+    /// Access flag fault at the given translation table level.
+    AccessFlag { level: u8 },
+    /// Permission fault at the given translation table level.
+    Permission { level: u8 },
+    /// Synchronous external abort, not on a translation table walk.
+    SyncExternal,
+    /// Alignment fault.
+    Alignment,
+    /// Any other fault status code.
+    Other(u8),
+}
+
+impl FaultKind {
+    fn from_fsc(fsc: u8) -> Self {
+        let level = fsc & 0b11;
+        match fsc {
+            0x00..=0x03 => Self::AddressSize { level },
+            0x04..=0x07 => Self::Translation { level },
+            0x08..=0x0b => Self::AccessFlag { level },
+            0x0c..=0x0f => Self::Permission { level },
+            0x10 => Self::SyncExternal,
+            0x21 => Self::Alignment,
+            _ => Self::Other(fsc),
+        }
+    }
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::AddressSize { level } => write!(f, "address size fault (level {level})"),
+            Self::Translation { level } => write!(f, "translation fault (level {level})"),
+            Self::AccessFlag { level } => write!(f, "access flag fault (level {level})"),
+            Self::Permission { level } => write!(f, "permission fault (level {level})"),
+            Self::SyncExternal => write!(f, "synchronous external abort"),
+            Self::Alignment => write!(f, "alignment fault"),
+            Self::Other(fsc) => write!(f, "fault status code {fsc:#04x}"),
+        }
+    }
+}
+
+/// Syndrome of a data or instruction abort, decoded from the value of ESR_ELx.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct AbortSyndrome {
+    /// Whether the abort was caused by an instruction fetch, rather than a data access.
+    pub instruction: bool,
+    /// The type of the fault.
+    pub kind: FaultKind,
+    /// Whether the abort was caused by a write, rather than a read.
+    pub write: bool,
+    /// The size of the faulting access in bytes, if the syndrome holds it.
+    pub access_size: Option<usize>,
+    /// Whether the fault address register holds the faulting address.
+    pub far_valid: bool,
+}
+
+impl AbortSyndrome {
+    const EC_SHIFT: usize = 26;
+    const EC_MASK: usize = 0x3f;
+    const EC_IABT_LOWER: usize = 0x20;
+    const EC_IABT_CURRENT: usize = 0x21;
+    const EC_DABT_LOWER: usize = 0x24;
+    const EC_DABT_CURRENT: usize = 0x25;
+    const ISV: usize = 1 << 24;
+    const SAS_SHIFT: usize = 22;
+    const SAS_MASK: usize = 0b11;
+    const FNV: usize = 1 << 10;
+    const WNR: usize = 1 << 6;
+    const FSC_MASK: usize = 0x3f;
+
+    /// Decodes `esr`, if it reports a data or instruction abort.
+    pub fn decode(esr: usize) -> Option<Self> {
+        let instruction = match (esr >> Self::EC_SHIFT) & Self::EC_MASK {
+            Self::EC_IABT_LOWER | Self::EC_IABT_CURRENT => true,
+            Self::EC_DABT_LOWER | Self::EC_DABT_CURRENT => false,
+            _ => return None,
+        };
+        let access_size = if !instruction && esr & Self::ISV != 0 {
+            Some(1 << ((esr >> Self::SAS_SHIFT) & Self::SAS_MASK))
+        } else {
+            None
+        };
+        Some(Self {
+            instruction,
+            kind: FaultKind::from_fsc((esr & Self::FSC_MASK) as u8),
+            write: !instruction && esr & Self::WNR != 0,
+            access_size,
+            far_valid: esr & Self::FNV == 0,
+        })
+    }
+}
+
+impl fmt::Display for AbortSyndrome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.instruction {
+            return write!(f, "instruction abort: {}", self.kind);
+        }
+        write!(f, "data abort: {} on ", self.kind)?;
+        if let Some(size) = self.access_size {
+            write!(f, "{size}-byte ")?;
+        }
+        write!(f, "{}", if self.write { "write" } else { "read" })
+    }
+}
+
 /// A struct representing an Armv8 exception.
 pub struct ArmException {
     /// The value of the exception syndrome register.
     pub esr: Esr,
+    /// The decoded exception syndrome register, if the exception is an abort.
+    pub syndrome: Option<AbortSyndrome>,
     /// The faulting virtual address read from the fault address register.
     pub far: VirtualAddress,
 }
 
 impl fmt::Display for ArmException {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "ArmException: esr={}, far={}", self.esr, self.far)
+        write!(f, "ArmException: esr={}, far={}", self.esr, self.far)?;
+        match self.syndrome {
+            Some(syndrome) if syndrome.far_valid => {
+                write!(f, " ({syndrome}, {})", LayoutRegion::nearest(self.far))
+            }
+            Some(syndrome) => write!(f, " ({syndrome}, far not valid)"),
+            None => Ok(()),
+        }
     }
 }
 
@@ -118,18 +255,26 @@ impl ArmException {
     /// and fault address register (`far_el1`) and returns a new instance of
     /// `ArmException` with these values.
     pub fn from_el1_regs() -> Self {
-        let esr: Esr = read_sysreg!("esr_el1").into();
+        let esr = read_sysreg!("esr_el1");
         let far = read_sysreg!("far_el1");
-        Self { esr, far: VirtualAddress(far) }
+        Self { esr: esr.into(), syndrome: AbortSyndrome::decode(esr), far: VirtualAddress(far) }
     }
 
-    /// Prints the details of an obj and the exception, excluding UART exceptions.
+    /// Prints the details of an obj and the exception, excluding UART exceptions, then calls the
+    /// handler registered with [`register_fault_handler`], if any.
     pub fn print<T: fmt::Display>(&self, exception_name: &str, obj: T, elr: u64) {
         // Don't print to the UART if we are handling an exception it could raise.
-        if !self.is_uart_exception() {
-            eprintln!("{exception_name}");
-            eprintln!("{obj}");
-            eprintln!("{}, elr={:#08x}", self, elr);
+        if self.is_uart_exception() {
+            return;
+        }
+        eprintln!("{exception_name}");
+        eprintln!("{obj}");
+        let elr_region = LayoutRegion::nearest(VirtualAddress(elr as usize));
+        eprintln!("{}, elr={:#08x} ({elr_region})", self, elr);
+        // The exception may have been raised while the handler was being registered.
+        let handler = FAULT_HANDLER.try_lock().and_then(|h| *h);
+        if let Some(handler) = handler {
+            handler(self, elr);
         }
     }
 
@@ -36,13 +36,13 @@ impl SwiotlbInfo {
             fdt.compatible_nodes(cstr!("restricted-dma-pool"))?.next().ok_or(FdtError::NotFound)?;
 
         let (addr, size, align) = if let Some(mut reg) = node.reg()? {
-            let reg = reg.next().ok_or(FdtError::NotFound)?;
-            let size = reg.size.ok_or(FdtError::NotFound)?;
-            (Some(reg.addr.try_into().unwrap()), size.try_into().unwrap(), None)
+            let range = reg.next().ok_or(FdtError::NotFound)?.try_into_range(usize::MAX)?;
+            (Some(range.start), range.len(), None)
         } else {
             let size = node.getprop_u64(cstr!("size"))?.ok_or(FdtError::NotFound)?;
             let align = node.getprop_u64(cstr!("alignment"))?.ok_or(FdtError::NotFound)?;
-            (None, size.try_into().unwrap(), Some(align.try_into().unwrap()))
+            let to_usize = |n: u64| usize::try_from(n).map_err(|_| FdtError::BadValue);
+            (None, to_usize(size)?, Some(to_usize(align)?))
         };
         Ok(Self { addr, size, align })
     }
@@ -52,3 +52,42 @@ impl SwiotlbInfo {
         self.addr.map(|addr| addr..addr + self.size)
     }
 }
+
+/// A DT paired with a scratch buffer, to apply modifications which must either succeed as a whole
+/// or leave the DT untouched.
+pub struct DtWorkspace<'a> {
+    fdt: &'a mut Fdt,
+    scratch: &'a mut [u8],
+}
+
+impl<'a> DtWorkspace<'a> {
+    /// Creates a workspace for `fdt`, where `scratch` must be large enough to hold a copy of the
+    /// DT (up to its totalsize) at the time of each modification.
+    pub fn new(fdt: &'a mut Fdt, scratch: &'a mut [u8]) -> Self {
+        Self { fdt, scratch }
+    }
+
+    /// Returns the DT.
+    pub fn fdt(&mut self) -> &mut Fdt {
+        self.fdt
+    }
+
+    /// Runs `f` on the DT and keeps its changes if it succeeds, or rolls them back if it fails.
+    ///
+    /// Returns the result of `f`, or an error if the DT couldn't be backed up (in which case `f`
+    /// isn't run) or restored (in which case the DT must be discarded).
+    pub fn try_modify<T, E>(
+        &mut self,
+        f: impl FnOnce(&mut Fdt) -> Result<T, E>,
+    ) -> libfdt::Result<Result<T, E>> {
+        let backup = self.fdt.as_slice();
+        let len = backup.len();
+        self.scratch.get_mut(..len).ok_or(FdtError::NoSpace)?.copy_from_slice(backup);
+
+        let result = f(self.fdt);
+        if result.is_err() {
+            self.fdt.copy_from_slice(&self.scratch[..len])?;
+        }
+        Ok(result)
+    }
+}
@@ -26,6 +26,10 @@ use core::ptr;
 use core::ptr::NonNull;
 
 use buddy_system_allocator::LockedHeap;
+use spin::mutex::SpinMutex;
+use tinyvec::ArrayVec;
+
+use crate::memory::{claim_memory, MemoryOwner, MemoryRange, MemoryTrackerError};
 
 /// Configures the size of the global allocator.
 #[macro_export]
@@ -46,6 +50,13 @@ extern "Rust" {
 #[global_allocator]
 static HEAP_ALLOCATOR: LockedHeap<32> = LockedHeap::<32>::new();
 
+/// Maximum number of regions that can be handed over to the heap after [`init`].
+const MAX_EXTRA_REGIONS: usize = 4;
+
+/// Regions handed over to the heap after [`init`], e.g. one-shot regions no longer needed.
+static EXTRA_REGIONS: SpinMutex<ArrayVec<[MemoryRange; MAX_EXTRA_REGIONS]>> =
+    SpinMutex::new(ArrayVec::from_array_empty([0..0, 0..0, 0..0, 0..0]));
+
 /// Initialize the global allocator.
 ///
 /// # Safety
@@ -56,11 +67,53 @@ pub(crate) unsafe fn init() {
     // never touch it again. The heap is locked, so there cannot be any races.
     let (start, size) = unsafe { (HEAP.as_mut_ptr() as usize, HEAP.len()) };
 
+    // This is the first claim so it can't conflict with any other.
+    claim_memory(start..(start + size), MemoryOwner::Heap).unwrap();
+
     let mut heap = HEAP_ALLOCATOR.lock();
     // SAFETY: We are supplying a valid memory range, and we only do this once.
     unsafe { heap.init(start, size) };
 }
 
+/// Hands `range` over to the global allocator, which uses it for future allocations.
+///
+/// This is meant for regions only needed during early boot (e.g. the DT received from the VMM)
+/// which would otherwise stay reserved forever. The region can't be taken back from the heap.
+///
+/// # Safety
+///
+/// `range` must be mapped as writable normal memory for as long as the heap is in use and must
+/// not be accessed through any other means (e.g. references derived from its previous use).
+pub unsafe fn add_region(range: MemoryRange) -> Result<(), MemoryTrackerError> {
+    let mut regions = EXTRA_REGIONS.lock();
+    if regions.len() == regions.capacity() {
+        return Err(MemoryTrackerError::Full);
+    }
+    claim_memory(range.clone(), MemoryOwner::Heap)?;
+
+    let mut heap = HEAP_ALLOCATOR.lock();
+    // SAFETY: The caller guarantees that the range is valid, mapped and unused.
+    unsafe { heap.add_to_heap(range.start, range.end) };
+    regions.push(range);
+    Ok(())
+}
+
+/// Returns whether [`add_region`] has room for another region.
+pub(crate) fn has_room_for_region() -> bool {
+    let regions = EXTRA_REGIONS.lock();
+    regions.len() < regions.capacity()
+}
+
+/// Returns whether `addr` lies in memory managed by the global allocator.
+fn is_heap_address(addr: usize) -> bool {
+    // SAFETY: The contents of the HEAP slice may change, but the address range never does.
+    let heap_range = unsafe { HEAP.as_ptr_range() };
+    if heap_range.contains(&(addr as *const u8)) {
+        return true;
+    }
+    EXTRA_REGIONS.lock().iter().any(|r| r.contains(&addr))
+}
+
 /// Allocate an aligned but uninitialized slice of heap.
 pub fn aligned_boxed_slice(size: usize, align: usize) -> Option<Box<[u8]>> {
     let size = NonZeroUsize::new(size)?.get();
@@ -107,10 +160,8 @@ unsafe extern "C" fn __memset_chk(
 /// errors.
 unsafe extern "C" fn free(ptr: *mut c_void) {
     let Some(ptr) = NonNull::new(ptr) else { return };
-    // SAFETY: The contents of the HEAP slice may change, but the address range never does.
-    let heap_range = unsafe { HEAP.as_ptr_range() };
     assert!(
-        heap_range.contains(&(ptr.as_ptr() as *const u8)),
+        is_heap_address(ptr.as_ptr() as usize),
         "free() called on a pointer that is not part of the HEAP: {ptr:?}"
     );
     // SAFETY: ptr is non-null and was allocated by allocate, which prepends a correctly aligned
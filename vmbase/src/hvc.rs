@@ -16,10 +16,9 @@
 
 pub mod trng;
 use self::trng::Error;
-use smccc::{
-    error::{positive_or_error_64, success_or_error_64},
-    hvc64,
-};
+use crate::eprintln;
+use hyp::trace::{for_each_traced_call, hvc64};
+use smccc::error::{positive_or_error_64, success_or_error_64};
 
 const ARM_SMCCC_TRNG_VERSION: u32 = 0x8400_0050;
 const ARM_SMCCC_TRNG_FEATURES: u32 = 0x8400_0051;
@@ -55,3 +54,18 @@ pub fn trng_features(fid: u32) -> trng::Result<u64> {
 
     positive_or_error_64::<Error>(hvc64(ARM_SMCCC_TRNG_FEATURES, args)[0])
 }
+
+/// Prints the most recent calls to the hypervisor, if they have been traced.
+pub fn dump_hypervisor_calls() {
+    let mut empty = true;
+    let traced = for_each_traced_call(|call| {
+        if empty {
+            eprintln!("Recent hypervisor calls:");
+            empty = false;
+        }
+        eprintln!("  {call}");
+    });
+    if !traced {
+        eprintln!("Hypervisor calls unavailable: trace locked");
+    }
+}
@@ -14,13 +14,16 @@
 
 //! Memory layout.
 
+pub mod check;
 pub mod crosvm;
 
 use crate::console::BASE_ADDRESS;
 use crate::linker::__stack_chk_guard;
 use aarch64_paging::paging::VirtualAddress;
+use core::fmt;
 use core::ops::Range;
 use core::ptr::addr_of;
+use core::slice;
 
 /// First address that can't be translated by a level 1 TTBR0_EL1.
 pub const MAX_VIRT_ADDR: usize = 1 << 40;
@@ -103,6 +106,77 @@ pub fn binary_end() -> VirtualAddress {
     linker_addr!(bin_end)
 }
 
+/// Unique identifier of the build of the image, from its GNU build ID note, if it has one.
+pub fn build_id() -> Option<&'static [u8]> {
+    let range = linker_region!(build_id_begin, build_id_end);
+    let len = range.end.0.checked_sub(range.start.0)?;
+    // SAFETY: The linker script places the note in .rodata, which is never written to.
+    let note = unsafe { slice::from_raw_parts(range.start.0 as *const u8, len) };
+    let word = |i: usize| {
+        let bytes = note.get((i * 4)..((i + 1) * 4))?;
+        usize::try_from(u32::from_ne_bytes(bytes.try_into().unwrap())).ok()
+    };
+    // The note header is followed by its (padded) name, "GNU", then by the ID itself.
+    let (name_size, desc_size) = (word(0)?, word(1)?);
+    let desc_start = 12usize.checked_add(name_size.checked_next_multiple_of(4)?)?;
+    note.get(desc_start..desc_start.checked_add(desc_size)?)
+}
+
+/// Named regions of the memory layout of the image.
+fn regions() -> [(&'static str, Range<VirtualAddress>); 8] {
+    [
+        (".text", text_range()),
+        (".rodata", rodata_range()),
+        (".data", data_range()),
+        (".bss", bss_range()),
+        (".dtb", dtb_range()),
+        ("stack", linker_region!(stack_limit, init_stack_pointer)),
+        ("exception stack", linker_region!(eh_stack_limit, init_eh_stack_pointer)),
+        ("console", console_uart_range()),
+    ]
+}
+
+/// A region of the memory layout of the image, used to describe where an address lies.
+#[derive(Clone, Debug)]
+pub struct LayoutRegion {
+    /// Name of the region.
+    pub name: &'static str,
+    /// Range of virtual addresses covered by the region.
+    pub range: Range<VirtualAddress>,
+    /// The address being described.
+    pub addr: VirtualAddress,
+}
+
+impl LayoutRegion {
+    /// Returns the region of the layout containing `addr` or, if there is none, the closest one.
+    pub fn nearest(addr: VirtualAddress) -> Self {
+        let distance = |range: &Range<VirtualAddress>| {
+            if addr < range.start {
+                range.start.0 - addr.0
+            } else if addr >= range.end {
+                addr.0 - range.end.0
+            } else {
+                0
+            }
+        };
+        let (name, range) = regions().into_iter().min_by_key(|(_, range)| distance(range)).unwrap();
+        Self { name, range, addr }
+    }
+}
+
+impl fmt::Display for LayoutRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Self { name, range, addr } = self;
+        if *addr < range.start {
+            write!(f, "{:#x} bytes before {name}", range.start.0 - addr.0)
+        } else if *addr >= range.end {
+            write!(f, "{:#x} bytes past {name}", addr.0 - range.end.0)
+        } else {
+            write!(f, "{name}+{:#x}", addr.0 - range.start.0)
+        }
+    }
+}
+
 /// Value of __stack_chk_guard.
 pub fn stack_chk_guard() -> u64 {
     // SAFETY: __stack_chk_guard shouldn't have any mutable aliases unless the stack overflows. If
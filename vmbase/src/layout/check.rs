@@ -0,0 +1,146 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks of the assumptions made by vmbase about the memory layout.
+//!
+//! Constants are checked at build time while the regions placed by the linker script, which are
+//! only known once linked, are checked by [`validate`] when the image starts.
+
+use super::crosvm::{FDT_MAX_SIZE, MEM_START, MMIO_END};
+use super::regions;
+use crate::memory::PAGE_SIZE;
+use aarch64_paging::paging::VirtualAddress;
+use core::fmt;
+use core::ops::Range;
+
+const _: () = assert!(PAGE_SIZE.is_power_of_two());
+const _: () = assert!(MMIO_END <= MEM_START, "MMIO overlaps with main memory");
+const _: () = assert!(MEM_START % PAGE_SIZE == 0, "Main memory isn't page-aligned");
+const _: () = assert!(FDT_MAX_SIZE % PAGE_SIZE == 0, "FDT region isn't a whole number of pages");
+
+/// The entry code copies .data in chunks of 32 bytes.
+const DATA_ALIGN: usize = 32;
+/// The entry code zeroes .bss in chunks of 16 bytes.
+const BSS_ALIGN: usize = 16;
+
+/// A violation of the assumptions made about the memory layout.
+#[derive(Clone, Debug)]
+pub enum LayoutError {
+    /// A boundary of a region isn't at the required alignment.
+    Misaligned {
+        /// Name of the region.
+        name: &'static str,
+        /// Address of the boundary.
+        addr: VirtualAddress,
+        /// Required alignment.
+        align: usize,
+    },
+    /// A region ends before it starts.
+    Inverted {
+        /// Name of the region.
+        name: &'static str,
+        /// Range covered by the region.
+        range: Range<VirtualAddress>,
+    },
+    /// A region is smaller than its minimum size.
+    TooSmall {
+        /// Name of the region.
+        name: &'static str,
+        /// Range covered by the region.
+        range: Range<VirtualAddress>,
+        /// Minimum size of the region.
+        min_size: usize,
+    },
+    /// Two regions overlap.
+    Overlap {
+        /// Name of the first region.
+        first: &'static str,
+        /// Name of the second region.
+        second: &'static str,
+    },
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Misaligned { name, addr, align } => {
+                write!(f, "{name} boundary {:#x} isn't aligned to {align:#x} bytes", addr.0)
+            }
+            Self::Inverted { name, range } => {
+                write!(f, "{name} ({range:#x?}) ends before it starts")
+            }
+            Self::TooSmall { name, range, min_size } => {
+                write!(f, "{name} ({range:#x?}) is smaller than {min_size:#x} bytes")
+            }
+            Self::Overlap { first, second } => write!(f, "{first} overlaps with {second}"),
+        }
+    }
+}
+
+/// Checks that the regions placed by the linker script are consistent, so that layout regressions
+/// are reported clearly instead of silently corrupting memory.
+pub fn validate() -> Result<(), LayoutError> {
+    let regions = regions();
+    for (name, range) in &regions {
+        if range.end < range.start {
+            return Err(LayoutError::Inverted { name: *name, range: range.clone() });
+        }
+    }
+
+    let [text, rodata, data, bss, _, stack, eh_stack, _] = regions.clone();
+    // Sections are page-aligned so that they can be mapped with different attributes.
+    check_aligned(".text", text.start, PAGE_SIZE)?;
+    check_aligned(".rodata", rodata.start, PAGE_SIZE)?;
+    check_aligned(".data", data.start, PAGE_SIZE)?;
+    check_aligned(".data", data.end, DATA_ALIGN)?;
+    check_aligned(".bss", bss.start, BSS_ALIGN)?;
+    check_aligned(".bss", bss.end, BSS_ALIGN)?;
+    for (name, range) in [("stack", &stack), ("exception stack", &eh_stack)] {
+        check_aligned(name, range.start, PAGE_SIZE)?;
+        check_aligned(name, range.end, PAGE_SIZE)?;
+        check_size(name, range, PAGE_SIZE)?;
+    }
+
+    for (i, (first, a)) in regions.iter().enumerate() {
+        for (second, b) in &regions[i + 1..] {
+            if a.start < b.end && b.start < a.end {
+                return Err(LayoutError::Overlap { first: *first, second: *second });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn check_aligned(
+    name: &'static str,
+    addr: VirtualAddress,
+    align: usize,
+) -> Result<(), LayoutError> {
+    if addr.0 % align != 0 {
+        return Err(LayoutError::Misaligned { name, addr, align });
+    }
+    Ok(())
+}
+
+fn check_size(
+    name: &'static str,
+    range: &Range<VirtualAddress>,
+    min_size: usize,
+) -> Result<(), LayoutError> {
+    if range.end.0 - range.start.0 < min_size {
+        return Err(LayoutError::TooSmall { name, range: range.clone(), min_size });
+    }
+    Ok(())
+}
@@ -36,11 +36,16 @@ pub mod uart;
 pub mod util;
 pub mod virtio;
 
+pub use hvc::dump_hypervisor_calls;
+
 use core::panic::PanicInfo;
+use memory::dump_memory_owners;
 use power::reboot;
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     eprintln!("{}", info);
+    dump_memory_owners();
+    dump_hypervisor_calls();
     reboot()
 }
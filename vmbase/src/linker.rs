@@ -23,6 +23,10 @@ extern "C" {
     pub static bss_begin: u8;
     /// First byte beyond the `.bss` section.
     pub static bss_end: u8;
+    /// First byte of the GNU build ID note.
+    pub static build_id_begin: u8;
+    /// First byte beyond the GNU build ID note.
+    pub static build_id_end: u8;
     /// First byte of the (loaded) `.data` section.
     pub static data_begin: u8;
     /// First byte beyond the (loaded) `.data` section.
@@ -35,6 +39,8 @@ extern "C" {
     pub static dtb_end: u8;
     /// First byte of the region available for the exception handler stack.
     pub static eh_stack_limit: u8;
+    /// First byte past the region available for the exception handler stack.
+    pub static init_eh_stack_pointer: u8;
     /// First byte past the region available for the stack.
     pub static init_stack_pointer: u8;
     /// First byte of the `.rodata` section.
@@ -19,8 +19,17 @@
 //! should avoid using this logger and instead print with eprintln!.
 
 use crate::console::println;
+use core::fmt::{self, Write};
 use core::sync::atomic::{AtomicBool, Ordering};
-use log::{Log, Metadata, Record, SetLoggerError};
+use hexfmt::Hex;
+use log::{log, Level, Log, Metadata, Record, SetLoggerError};
+use spin::mutex::SpinMutex;
+
+/// Size of the most recent log output kept in memory, see [`tail`].
+pub const TAIL_SIZE: usize = 256;
+
+/// Size of the most recent error message kept in memory, see [`last_error`].
+pub const LAST_ERROR_SIZE: usize = 128;
 
 struct Logger {
     is_enabled: AtomicBool,
@@ -28,6 +37,8 @@ struct Logger {
 
 static LOGGER: Logger = Logger::new();
 
+static HISTORY: SpinMutex<History> = SpinMutex::new(History::new());
+
 impl Logger {
     const fn new() -> Self {
         Self { is_enabled: AtomicBool::new(true) }
@@ -46,12 +57,98 @@ impl Log for Logger {
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
             println!("[{}] {}", record.level(), record.args());
+            HISTORY.lock().record(record);
         }
     }
 
     fn flush(&self) {}
 }
 
+/// The end of the log output, kept so that it can be reported after a failure.
+struct History {
+    /// Circular buffer of the last bytes logged, the oldest of which is at `tail_end`.
+    tail: [u8; TAIL_SIZE],
+    tail_end: usize,
+    tail_wrapped: bool,
+    last_error: [u8; LAST_ERROR_SIZE],
+    last_error_len: usize,
+}
+
+impl History {
+    const fn new() -> Self {
+        Self {
+            tail: [0; TAIL_SIZE],
+            tail_end: 0,
+            tail_wrapped: false,
+            last_error: [0; LAST_ERROR_SIZE],
+            last_error_len: 0,
+        }
+    }
+
+    fn record(&mut self, record: &Record) {
+        // Neither writer fails: they drop the bytes which don't fit instead.
+        let _ = writeln!(TailWriter(self), "[{}] {}", record.level(), record.args());
+        if record.level() == Level::Error {
+            self.last_error_len = 0;
+            let _ = write!(LastErrorWriter(self), "{}", record.args());
+        }
+    }
+}
+
+/// Appends to the tail of the log, overwriting its oldest bytes.
+struct TailWriter<'a>(&'a mut History);
+
+impl Write for TailWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &b in s.as_bytes() {
+            self.0.tail[self.0.tail_end] = b;
+            self.0.tail_end = (self.0.tail_end + 1) % TAIL_SIZE;
+            self.0.tail_wrapped |= self.0.tail_end == 0;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the last error message, truncating it if it doesn't fit.
+struct LastErrorWriter<'a>(&'a mut History);
+
+impl Write for LastErrorWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let history = &mut *self.0;
+        let n = s.len().min(LAST_ERROR_SIZE - history.last_error_len);
+        let start = history.last_error_len;
+        history.last_error[start..(start + n)].copy_from_slice(&s.as_bytes()[..n]);
+        history.last_error_len += n;
+        Ok(())
+    }
+}
+
+/// Copies the last [`TAIL_SIZE`] bytes of log output, oldest first, and returns their count.
+///
+/// The copy may start or end in the middle of a UTF-8 character.
+pub fn tail(buf: &mut [u8; TAIL_SIZE]) -> usize {
+    let history = HISTORY.lock();
+    let (newest, oldest) = history.tail.split_at(history.tail_end);
+    if history.tail_wrapped {
+        buf[..oldest.len()].copy_from_slice(oldest);
+        buf[oldest.len()..].copy_from_slice(newest);
+        TAIL_SIZE
+    } else {
+        buf[..newest.len()].copy_from_slice(newest);
+        newest.len()
+    }
+}
+
+/// Copies the last message logged at [`Level::Error`], truncated to [`LAST_ERROR_SIZE`] bytes,
+/// and returns its length.
+///
+/// The copy may end in the middle of a UTF-8 character.
+pub fn last_error(buf: &mut [u8; LAST_ERROR_SIZE]) -> usize {
+    let history = HISTORY.lock();
+    buf[..history.last_error_len].copy_from_slice(&history.last_error[..history.last_error_len]);
+    history.last_error_len
+}
+
 /// An RAII implementation of a log suppressor. When the instance is dropped, logging is re-enabled.
 pub struct SuppressGuard {
     old_enabled: bool,
@@ -79,3 +176,13 @@ pub(crate) fn init() -> Result<(), SetLoggerError> {
 pub fn suppress() -> SuppressGuard {
     SuppressGuard::new()
 }
+
+/// Logs `data` at the given level, as rows of hex digits prefixed by their offset.
+pub fn hexdump(level: Level, label: &str, data: &[u8]) {
+    const ROW_SIZE: usize = 16;
+
+    log!(level, "{label} ({} bytes):", data.len());
+    for (i, row) in data.chunks(ROW_SIZE).enumerate() {
+        log!(level, "  {:08x}: {}", i * ROW_SIZE, Hex(row));
+    }
+}
@@ -16,11 +16,16 @@
 
 mod dbm;
 mod error;
+mod ownership;
 mod page_table;
 mod shared;
 mod util;
 
 pub use error::MemoryTrackerError;
+pub use ownership::{
+    claim_memory, dump_memory_owners, memory_owner_of, release_memory, release_memory_tail,
+    MemoryOwner,
+};
 pub use page_table::PageTable;
 pub use shared::{
     handle_permission_fault, handle_translation_fault, MemoryRange, MemoryTracker, MEMORY,
@@ -0,0 +1,177 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracking of the subsystems owning physical memory ranges.
+//!
+//! This is a debugging aid: it doesn't control any mapping but records which module uses which
+//! range, to catch code writing into memory that another module still relies on.
+
+use super::error::MemoryTrackerError;
+use super::shared::MemoryRange;
+use crate::eprintln;
+use crate::util::RangeExt as _;
+use core::fmt;
+use core::result;
+use spin::mutex::SpinMutex;
+use tinyvec::ArrayVec;
+
+type Result<T> = result::Result<T, MemoryTrackerError>;
+
+static MEMORY_OWNERS: SpinMutex<MemoryOwners> = SpinMutex::new(MemoryOwners::new());
+
+/// Subsystem owning a range of physical memory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemoryOwner {
+    /// The binary image being executed.
+    #[default]
+    Image,
+    /// The global allocator.
+    Heap,
+    /// The device tree received from the VMM and passed to the next stage.
+    DeviceTree,
+    /// The BCC handed over to the next stage.
+    BccHandover,
+    /// The pool of buffers used for DMA with the host.
+    DmaPool,
+    /// Memory shared with the host.
+    Shared,
+    /// The payload loaded for the next stage.
+    Payload,
+}
+
+impl fmt::Display for MemoryOwner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Image => "image",
+            Self::Heap => "heap",
+            Self::DeviceTree => "device tree",
+            Self::BccHandover => "BCC handover",
+            Self::DmaPool => "DMA pool",
+            Self::Shared => "shared",
+            Self::Payload => "payload",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct OwnedRange {
+    range: MemoryRange,
+    owner: MemoryOwner,
+}
+
+impl OwnedRange {
+    const EMPTY: Self = Self { range: 0..0, owner: MemoryOwner::Image };
+
+    /// Returns whether `range` can't be claimed while this range is owned. Heap allocations can be
+    /// handed over to other subsystems so claiming them isn't a conflict, unless they straddle the
+    /// bounds of the heap.
+    fn conflicts_with(&self, range: &MemoryRange) -> bool {
+        if self.owner == MemoryOwner::Heap && range.is_within(&self.range) {
+            return false;
+        }
+        self.range.overlaps(range)
+    }
+}
+
+struct MemoryOwners {
+    ranges: ArrayVec<[OwnedRange; MemoryOwners::CAPACITY]>,
+}
+
+impl MemoryOwners {
+    const CAPACITY: usize = 16;
+
+    const fn new() -> Self {
+        Self { ranges: ArrayVec::from_array_empty([OwnedRange::EMPTY; Self::CAPACITY]) }
+    }
+
+    fn claim(&mut self, range: MemoryRange, owner: MemoryOwner) -> Result<()> {
+        if self.ranges.iter().any(|r| r.conflicts_with(&range)) {
+            return Err(MemoryTrackerError::Overlaps);
+        }
+        if self.ranges.try_push(OwnedRange { range, owner }).is_some() {
+            return Err(MemoryTrackerError::Full);
+        }
+        Ok(())
+    }
+
+    fn release(&mut self, range: &MemoryRange, owner: MemoryOwner) -> Result<()> {
+        let i = self
+            .ranges
+            .iter()
+            .position(|r| r.range == *range && r.owner == owner)
+            .ok_or(MemoryTrackerError::OutOfRange)?;
+        self.ranges.remove(i);
+        Ok(())
+    }
+
+    fn release_tail(&mut self, range: &MemoryRange, owner: MemoryOwner) -> Result<()> {
+        let i = self
+            .ranges
+            .iter()
+            .position(|r| r.owner == owner && range.is_within(&r.range) && r.range.end == range.end)
+            .ok_or(MemoryTrackerError::OutOfRange)?;
+        if self.ranges[i].range.start == range.start {
+            self.ranges.remove(i);
+        } else {
+            self.ranges[i].range.end = range.start;
+        }
+        Ok(())
+    }
+}
+
+/// Records that `owner` uses `range` until it is released with [`release_memory`].
+///
+/// Claiming a range that overlaps with a range already claimed panics in debug builds.
+pub fn claim_memory(range: MemoryRange, owner: MemoryOwner) -> Result<()> {
+    let result = MEMORY_OWNERS.lock().claim(range.clone(), owner);
+    // Panic once the registry is unlocked, so that the panic handler can dump it.
+    if cfg!(debug_assertions) && matches!(result, Err(MemoryTrackerError::Overlaps)) {
+        panic!("{owner} claimed {range:#x?}, which overlaps with claimed memory");
+    }
+    result
+}
+
+/// Records that `owner` no longer uses `range`, which must have been claimed by `owner`.
+pub fn release_memory(range: &MemoryRange, owner: MemoryOwner) -> Result<()> {
+    MEMORY_OWNERS.lock().release(range, owner)
+}
+
+/// Records that `owner` no longer uses `range`, which must be the end of a range claimed by
+/// `owner`, possibly all of it.
+pub fn release_memory_tail(range: &MemoryRange, owner: MemoryOwner) -> Result<()> {
+    MEMORY_OWNERS.lock().release_tail(range, owner)
+}
+
+/// Returns the subsystem owning the physical address `addr`, if any.
+///
+/// This is meant to be called from exception handlers so returns None if the registry is locked.
+pub fn memory_owner_of(addr: usize) -> Option<MemoryOwner> {
+    let owners = MEMORY_OWNERS.try_lock()?;
+    owners.ranges.iter().find(|r| r.range.contains(&addr)).map(|r| r.owner)
+}
+
+/// Prints the claimed memory ranges and their owners to the console.
+///
+/// This is meant to be called while panicking so doesn't wait for the registry if it is locked.
+pub fn dump_memory_owners() {
+    let Some(owners) = MEMORY_OWNERS.try_lock() else {
+        eprintln!("Memory owners unavailable: registry locked");
+        return;
+    };
+    eprintln!("Memory owners:");
+    for r in owners.ranges.iter() {
+        eprintln!("  {:#x?}: {}", r.range, r.owner);
+    }
+}
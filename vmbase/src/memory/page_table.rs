@@ -132,6 +132,11 @@ impl PageTable {
         self.idmap.map_range(range, RODATA)
     }
 
+    /// Maps the given range of virtual addresses as invalid, so that any access to it faults.
+    pub fn map_invalid(&mut self, range: &MemoryRegion) -> Result<()> {
+        self.idmap.map_range(range, Attributes::empty())
+    }
+
     /// Applies the provided updater function to a number of PTEs corresponding to a given memory
     /// range.
     pub fn modify_range<F>(&mut self, range: &MemoryRegion, f: &F) -> Result<()>
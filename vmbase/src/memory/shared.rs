@@ -16,11 +16,13 @@
 
 use super::dbm::{flush_dirty_range, mark_dirty_block, set_dbm_enabled};
 use super::error::MemoryTrackerError;
+use super::ownership::{claim_memory, release_memory_tail, MemoryOwner};
 use super::page_table::{PageTable, MMIO_LAZY_MAP_FLAG};
 use super::util::{page_4kb_of, virt_to_phys};
-use crate::dsb;
 use crate::exceptions::HandleExceptionError;
+use crate::heap;
 use crate::util::RangeExt as _;
+use crate::{dsb, isb, tlbi};
 use aarch64_paging::paging::{
     Attributes, Descriptor, MemoryRegion as VaRange, VirtualAddress, BITS_PER_LEVEL, PAGE_SIZE,
 };
@@ -185,6 +187,71 @@ impl MemoryTracker {
         self.alloc_range_mut(&(base..(base + size.get())))
     }
 
+    /// Hands the end of a tracked region, claimed by `owner`, over to the global allocator.
+    ///
+    /// This is meant for one-shot regions, such as the DT received from the VMM once the final DT
+    /// has been produced, which would otherwise stay mapped and reserved until the tracker is
+    /// dropped. `range` may cover the whole region, which is then no longer tracked. Note that
+    /// the heap isn't zeroed when the tracker is dropped so, if the next stage can read `range`,
+    /// nothing secret should be allocated once it has been handed over.
+    ///
+    /// # Safety
+    ///
+    /// The caller must not access `range` through any reference derived from its previous use.
+    pub unsafe fn reclaim_range(&mut self, range: &MemoryRange, owner: MemoryOwner) -> Result<()> {
+        if range.is_empty() || range.start % PAGE_SIZE != 0 || range.end % PAGE_SIZE != 0 {
+            return Err(MemoryTrackerError::OutOfRange);
+        }
+        let i = self
+            .regions
+            .iter()
+            .position(|r| range.is_within(&r.range) && r.range.end == range.end)
+            .ok_or(MemoryTrackerError::OutOfRange)?;
+        let mem_type = self.regions[i].mem_type;
+
+        // The heap can't take the range back once it has been released and remapped, so check
+        // that it has room for it first. Otherwise, the steps below are undone on failure.
+        if !heap::has_room_for_region() {
+            return Err(MemoryTrackerError::Full);
+        }
+
+        release_memory_tail(range, owner)?;
+        // Make sure that the range is writable and that no stale dirty state gets flushed later.
+        if let Err(e) = self.page_table.map_data(&get_va_range(range)) {
+            error!("Error while remapping the range for the heap: {e}");
+            self.remap(range, mem_type)?;
+            claim_memory(range.clone(), owner)?;
+            return Err(MemoryTrackerError::FailedToMap);
+        }
+        // SAFETY: The range is now mapped as writable and is untracked below, so nothing else in
+        // vmbase accesses it, and the caller guarantees that it isn't accessed otherwise.
+        if let Err(e) = unsafe { heap::add_region(range.clone()) } {
+            self.remap(range, mem_type)?;
+            claim_memory(range.clone(), owner)?;
+            return Err(e);
+        }
+
+        if self.regions[i].range.start == range.start {
+            self.regions.remove(i);
+        } else {
+            self.regions[i].range.end = range.start;
+        }
+        Ok(())
+    }
+
+    /// Maps `range` back as its tracked region of type `mem_type` was.
+    fn remap(&mut self, range: &MemoryRange, mem_type: MemoryType) -> Result<()> {
+        let va_range = get_va_range(range);
+        let result = match mem_type {
+            MemoryType::ReadOnly => self.page_table.map_rodata(&va_range),
+            MemoryType::ReadWrite => self.page_table.map_data_dbm(&va_range),
+        };
+        result.map_err(|e| {
+            error!("Error while remapping the range: {e}");
+            MemoryTrackerError::FailedToMap
+        })
+    }
+
     /// Checks that the given range of addresses is within the MMIO region, and then maps it
     /// appropriately.
     pub fn map_mmio_range(&mut self, range: MemoryRange) -> Result<()> {
@@ -262,6 +329,26 @@ impl MemoryTracker {
         Ok(())
     }
 
+    /// Makes the appended payload range inaccessible, or accessible again, through the page table.
+    ///
+    /// This allows code handling untrusted inputs to run without access to the secrets that the
+    /// appended payload may hold.
+    pub fn set_payload_accessible(&mut self, accessible: bool) -> Result<()> {
+        let Some(range) = &self.payload_range else {
+            return Ok(());
+        };
+        let (set, clear) = if accessible {
+            (Attributes::VALID, Attributes::empty())
+        } else {
+            (Attributes::empty(), Attributes::VALID)
+        };
+        self.page_table
+            .modify_range(&get_va_range(range), &|va_range, desc, level| {
+                set_pte_flags(va_range, desc, level, set, clear)
+            })
+            .map_err(|_| MemoryTrackerError::FailedToMap)
+    }
+
     /// Initialize the shared heap to dynamically share memory from the global allocator.
     pub fn init_dynamic_shared_pool(&mut self, granule: usize) -> Result<()> {
         const INIT_CAP: usize = 10;
@@ -288,6 +375,7 @@ impl MemoryTracker {
     pub fn init_static_shared_pool(&mut self, range: Range<usize>) -> Result<()> {
         let size = NonZeroUsize::new(range.len()).unwrap();
         let range = self.alloc_mut(range.start, size)?;
+        claim_memory(range.clone(), MemoryOwner::DmaPool)?;
         let shared_pool = LockedFrameAllocator::<32>::new();
 
         shared_pool.lock().insert(range);
@@ -514,6 +602,32 @@ fn mmio_guard_unmap_page(
     Ok(())
 }
 
+/// Sets then clears the given flags of a page PTE, invalidating its TLB entry.
+fn set_pte_flags(
+    va_range: &VaRange,
+    desc: &mut Descriptor,
+    level: usize,
+    set: Attributes,
+    clear: Attributes,
+) -> result::Result<(), ()> {
+    // The payload range is mapped down to pages so only the last level holds its PTEs, and
+    // toggling their VALID flag doesn't require break-before-make. Upper levels may only hold
+    // the table descriptors leading to those pages.
+    if level != 3 {
+        let flags = desc.flags().ok_or(())?;
+        if flags.contains(Attributes::TABLE_OR_PAGE) {
+            return Ok(());
+        }
+        error!("Can't change the flags of a block mapping at level {level}");
+        return Err(());
+    }
+    desc.modify_flags(set, clear);
+    tlbi!("vale1", PageTable::ASID, va_range.start().0);
+    dsb!("ish");
+    isb!();
+    Ok(())
+}
+
 /// Handles a translation fault with the given fault address register (FAR).
 #[inline]
 pub fn handle_translation_fault(far: VirtualAddress) -> result::Result<(), HandleExceptionError> {
@@ -14,7 +14,51 @@
 
 //! Modules for working with VirtIO devices.
 
+mod diagnostics;
 mod hal;
 pub mod pci;
+mod transport;
 
+pub use diagnostics::{QueueDiagnostics, QueueUsage, SharedBuffers, Submission};
 pub use hal::HalImpl;
+pub use transport::TrackedTransport;
+
+use crate::memory::phys_to_virt;
+use core::fmt;
+use diagnostics::Usage;
+use spin::mutex::SpinMutex;
+
+static USAGE: SpinMutex<Usage> = SpinMutex::new(Usage::new());
+
+/// Returns a snapshot of the current usage of the VirtIO queues.
+///
+/// Only the queues of devices driven through a [`TrackedTransport`] are reported individually.
+pub fn diagnostics() -> QueueDiagnostics {
+    USAGE.lock().snapshot(|paddr| {
+        // SAFETY: The tracked rings are forgotten before the DMA regions holding them are freed
+        // and their indices are naturally aligned.
+        let idx = unsafe { phys_to_virt(paddr).cast::<u16>().as_ptr().read_volatile() };
+        u16::from_le(idx)
+    })
+}
+
+/// Error of a VirtIO driver, with the usage of the queues when it occurred.
+#[derive(Clone, Debug)]
+pub struct VirtIOError {
+    /// The error reported by the driver.
+    pub error: virtio_drivers::Error,
+    /// Usage of the queues when the error was reported.
+    pub diagnostics: QueueDiagnostics,
+}
+
+impl From<virtio_drivers::Error> for VirtIOError {
+    fn from(error: virtio_drivers::Error) -> Self {
+        Self { error, diagnostics: diagnostics() }
+    }
+}
+
+impl fmt::Display for VirtIOError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.error, self.diagnostics)
+    }
+}
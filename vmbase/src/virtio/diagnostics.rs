@@ -0,0 +1,390 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bookkeeping of the usage of the VirtIO queues, to explain driver failures.
+//!
+//! This module only keeps track of what the transport and HAL report; it never accesses the
+//! queues itself so that it can be tested in isolation.
+
+use core::fmt;
+use core::ops::Range;
+use virtio_drivers::{transport::DeviceType, BufferDirection, PhysAddr};
+
+/// Maximum number of virtqueues individually reported.
+const MAX_TRACKED_QUEUES: usize = 16;
+
+/// Offset of the `idx` field in both the available and used rings.
+const RING_IDX_OFFSET: usize = 2;
+
+/// Buffer shared with a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Submission {
+    /// Guest physical address of the bounce buffer.
+    pub paddr: PhysAddr,
+    /// Length of the buffer, in bytes.
+    pub len: usize,
+    /// Direction of the transfer.
+    pub direction: BufferDirection,
+}
+
+/// Usage of the buffers shared with devices, across all queues.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SharedBuffers {
+    /// Number of buffers shared with devices which haven't been returned yet.
+    pub outstanding: usize,
+    /// Total size of the outstanding buffers, in bytes.
+    pub outstanding_bytes: usize,
+    /// The last buffer shared with a device, if any.
+    pub last: Option<Submission>,
+}
+
+/// Usage of a virtqueue, when the diagnostics were collected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueUsage {
+    /// Identifier of the device, in the order in which their transports were tracked.
+    pub device: usize,
+    /// Type of the device.
+    pub device_type: DeviceType,
+    /// Index of the queue within the device.
+    pub index: u16,
+    /// Number of descriptors in the queue.
+    pub depth: u32,
+    /// Number of descriptor chains made available to the device which it hasn't used yet.
+    pub outstanding: u16,
+    /// Number of times the device was notified of new descriptors in the queue.
+    pub notifications: u64,
+    /// The last buffer shared before the device was notified of new descriptors in the queue.
+    pub last_submission: Option<Submission>,
+}
+
+/// Snapshot of the usage of the VirtIO queues, attached to driver errors.
+#[derive(Clone, Debug)]
+pub struct QueueDiagnostics {
+    queues: [Option<QueueUsage>; MAX_TRACKED_QUEUES],
+    untracked_queues: usize,
+    /// Usage of the buffers shared with devices.
+    pub shared: SharedBuffers,
+}
+
+impl QueueDiagnostics {
+    /// Returns the usage of the individually tracked queues.
+    pub fn queues(&self) -> impl Iterator<Item = &QueueUsage> {
+        self.queues.iter().flatten()
+    }
+
+    /// Returns the number of virtqueues, including those which couldn't be tracked individually.
+    pub fn queue_count(&self) -> usize {
+        self.queues().count() + self.untracked_queues
+    }
+}
+
+impl fmt::Display for QueueDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} queue(s) [", self.queue_count())?;
+        for (i, q) in self.queues().enumerate() {
+            let sep = if i == 0 { "" } else { "; " };
+            write!(
+                f,
+                "{sep}{:?}#{} queue {}: {}/{} outstanding, {} notification(s)",
+                q.device_type, q.device, q.index, q.outstanding, q.depth, q.notifications
+            )?;
+            if let Some(s) = q.last_submission {
+                write!(f, ", last {} bytes {:?} at {:#x}", s.len, s.direction, s.paddr)?;
+            }
+        }
+        write!(
+            f,
+            "], {} shared buffer(s) of {} bytes",
+            self.shared.outstanding, self.shared.outstanding_bytes
+        )
+    }
+}
+
+/// Virtqueue configured by a driver through a tracked transport.
+#[derive(Clone, Copy, Debug)]
+struct Queue {
+    device: usize,
+    device_type: DeviceType,
+    index: u16,
+    size: u32,
+    driver_area: PhysAddr,
+    device_area: PhysAddr,
+    notifications: u64,
+    last_submission: Option<Submission>,
+}
+
+impl Queue {
+    fn is(&self, device: usize, index: u16) -> bool {
+        self.device == device && self.index == index
+    }
+}
+
+/// Usage of the VirtIO queues, as reported by the transports and HAL.
+pub(crate) struct Usage {
+    queues: [Option<Queue>; MAX_TRACKED_QUEUES],
+    untracked_queues: usize,
+    devices: usize,
+    shared: SharedBuffers,
+}
+
+impl Usage {
+    pub(crate) const fn new() -> Self {
+        Self {
+            queues: [None; MAX_TRACKED_QUEUES],
+            untracked_queues: 0,
+            devices: 0,
+            shared: SharedBuffers { outstanding: 0, outstanding_bytes: 0, last: None },
+        }
+    }
+
+    /// Returns a new identifier for a device whose queues are going to be tracked.
+    pub(crate) fn add_device(&mut self) -> usize {
+        let device = self.devices;
+        self.devices += 1;
+        device
+    }
+
+    /// Records that the queue `index` of `device` was configured with the given rings.
+    pub(crate) fn queue_set(
+        &mut self,
+        device: usize,
+        device_type: DeviceType,
+        index: u16,
+        size: u32,
+        driver_area: PhysAddr,
+        device_area: PhysAddr,
+    ) {
+        let queue = Queue {
+            device,
+            device_type,
+            index,
+            size,
+            driver_area,
+            device_area,
+            notifications: 0,
+            last_submission: None,
+        };
+        let existing = self.queues.iter().position(|q| matches!(q, Some(q) if q.is(device, index)));
+        match existing.or_else(|| self.queues.iter().position(Option::is_none)) {
+            Some(i) => self.queues[i] = Some(queue),
+            None => self.untracked_queues += 1,
+        }
+    }
+
+    /// Records that the queue `index` of `device` was reset.
+    pub(crate) fn queue_unset(&mut self, device: usize, index: u16) {
+        match self.queues.iter_mut().find(|q| matches!(q, Some(q) if q.is(device, index))) {
+            Some(slot) => *slot = None,
+            None => self.untracked_queues = self.untracked_queues.saturating_sub(1),
+        }
+    }
+
+    /// Stops tracking the queues whose rings are within `region`, as it is being freed.
+    ///
+    /// Drivers may free their queues without resetting them, e.g. when failing to initialize.
+    pub(crate) fn forget_rings_in(&mut self, region: &Range<PhysAddr>) {
+        for slot in &mut self.queues {
+            if matches!(slot, Some(q) if region.contains(&q.driver_area)
+                || region.contains(&q.device_area))
+            {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Records that `device` was notified of new descriptors in its queue `index`.
+    ///
+    /// The buffers of a request are shared right before the device is notified so the last one
+    /// is attributed to the queue.
+    pub(crate) fn notify(&mut self, device: usize, index: u16) {
+        let last = self.shared.last;
+        if let Some(q) = self.queues.iter_mut().flatten().find(|q| q.is(device, index)) {
+            q.notifications += 1;
+            q.last_submission = last;
+        }
+    }
+
+    /// Records that a buffer was shared with a device.
+    pub(crate) fn share(&mut self, paddr: PhysAddr, len: usize, direction: BufferDirection) {
+        self.shared.outstanding += 1;
+        self.shared.outstanding_bytes += len;
+        self.shared.last = Some(Submission { paddr, len, direction });
+    }
+
+    /// Records that a buffer was returned by a device.
+    pub(crate) fn unshare(&mut self, len: usize) {
+        self.shared.outstanding = self.shared.outstanding.saturating_sub(1);
+        self.shared.outstanding_bytes = self.shared.outstanding_bytes.saturating_sub(len);
+    }
+
+    /// Returns a snapshot of the usage, reading the ring indices of the tracked queues with
+    /// `read_u16`.
+    pub(crate) fn snapshot(&self, read_u16: impl Fn(PhysAddr) -> u16) -> QueueDiagnostics {
+        let mut queues = [None; MAX_TRACKED_QUEUES];
+        for (usage, queue) in queues.iter_mut().zip(self.queues.iter().flatten()) {
+            let available = read_u16(queue.driver_area + RING_IDX_OFFSET);
+            let used = read_u16(queue.device_area + RING_IDX_OFFSET);
+            *usage = Some(QueueUsage {
+                device: queue.device,
+                device_type: queue.device_type,
+                index: queue.index,
+                depth: queue.size,
+                // The indices are free-running counters, which wrap around.
+                outstanding: available.wrapping_sub(used),
+                notifications: queue.notifications,
+                last_submission: queue.last_submission,
+            });
+        }
+        QueueDiagnostics { queues, untracked_queues: self.untracked_queues, shared: self.shared }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DRIVER_AREA: PhysAddr = 0x1000;
+    const DEVICE_AREA: PhysAddr = 0x2000;
+
+    fn rings(available: u16, used: u16) -> impl Fn(PhysAddr) -> u16 {
+        move |paddr| match paddr {
+            p if p == DRIVER_AREA + RING_IDX_OFFSET => available,
+            p if p == DEVICE_AREA + RING_IDX_OFFSET => used,
+            p => panic!("Unexpected read at {p:#x}"),
+        }
+    }
+
+    fn usage_with_queue() -> (Usage, usize) {
+        let mut usage = Usage::new();
+        let device = usage.add_device();
+        usage.queue_set(device, DeviceType::Block, 0, 16, DRIVER_AREA, DEVICE_AREA);
+        (usage, device)
+    }
+
+    #[test]
+    fn devices_get_distinct_ids() {
+        let mut usage = Usage::new();
+
+        assert_ne!(usage.add_device(), usage.add_device());
+    }
+
+    #[test]
+    fn queue_reports_depth_and_outstanding_descriptors() {
+        let (usage, device) = usage_with_queue();
+
+        let diagnostics = usage.snapshot(rings(5, 2));
+        let queues: Vec<_> = diagnostics.queues().collect();
+
+        assert_eq!(queues.len(), 1);
+        assert_eq!(queues[0].device, device);
+        assert_eq!(queues[0].device_type, DeviceType::Block);
+        assert_eq!(queues[0].depth, 16);
+        assert_eq!(queues[0].outstanding, 3);
+    }
+
+    #[test]
+    fn outstanding_descriptors_handle_wrapping_indices() {
+        let (usage, _) = usage_with_queue();
+
+        let diagnostics = usage.snapshot(rings(1, u16::MAX));
+
+        assert_eq!(diagnostics.queues().next().unwrap().outstanding, 2);
+    }
+
+    #[test]
+    fn notifications_are_counted_per_queue() {
+        let (mut usage, device) = usage_with_queue();
+        usage.queue_set(device, DeviceType::Block, 1, 16, 0x3000, 0x4000);
+
+        usage.share(0x8000, 512, BufferDirection::DriverToDevice);
+        usage.notify(device, 0);
+        usage.notify(device, 0);
+        usage.share(0x9000, 64, BufferDirection::DeviceToDriver);
+        usage.notify(device, 1);
+        let diagnostics = usage.snapshot(|_| 0);
+        let queues: Vec<_> = diagnostics.queues().collect();
+
+        assert_eq!(queues[0].notifications, 2);
+        assert_eq!(
+            queues[0].last_submission,
+            Some(Submission {
+                paddr: 0x8000,
+                len: 512,
+                direction: BufferDirection::DriverToDevice
+            })
+        );
+        assert_eq!(queues[1].notifications, 1);
+        assert_eq!(queues[1].last_submission.unwrap().paddr, 0x9000);
+    }
+
+    #[test]
+    fn reconfigured_queue_is_tracked_once() {
+        let (mut usage, device) = usage_with_queue();
+
+        usage.notify(device, 0);
+        usage.queue_set(device, DeviceType::Block, 0, 32, DRIVER_AREA, DEVICE_AREA);
+        let diagnostics = usage.snapshot(|_| 0);
+
+        assert_eq!(diagnostics.queue_count(), 1);
+        assert_eq!(diagnostics.queues().next().unwrap().depth, 32);
+        assert_eq!(diagnostics.queues().next().unwrap().notifications, 0);
+    }
+
+    #[test]
+    fn unset_queue_is_no_longer_reported() {
+        let (mut usage, device) = usage_with_queue();
+
+        usage.queue_unset(device, 0);
+
+        assert_eq!(usage.snapshot(|_| panic!("Unexpected read")).queue_count(), 0);
+    }
+
+    #[test]
+    fn freed_rings_are_no_longer_read() {
+        let (mut usage, _) = usage_with_queue();
+
+        usage.forget_rings_in(&(DRIVER_AREA..DEVICE_AREA));
+
+        assert_eq!(usage.snapshot(|_| panic!("Unexpected read")).queue_count(), 0);
+    }
+
+    #[test]
+    fn queues_beyond_limit_are_counted() {
+        let mut usage = Usage::new();
+        let device = usage.add_device();
+        for i in 0..=MAX_TRACKED_QUEUES {
+            let index = i.try_into().unwrap();
+            usage.queue_set(device, DeviceType::Socket, index, 8, DRIVER_AREA, DEVICE_AREA);
+        }
+
+        let diagnostics = usage.snapshot(|_| 0);
+
+        assert_eq!(diagnostics.queues().count(), MAX_TRACKED_QUEUES);
+        assert_eq!(diagnostics.queue_count(), MAX_TRACKED_QUEUES + 1);
+    }
+
+    #[test]
+    fn shared_buffers_are_accounted() {
+        let mut usage = Usage::new();
+
+        usage.share(0x8000, 512, BufferDirection::DriverToDevice);
+        usage.share(0x9000, 64, BufferDirection::DeviceToDriver);
+        usage.unshare(512);
+        let shared = usage.snapshot(|_| 0).shared;
+
+        assert_eq!(shared.outstanding, 1);
+        assert_eq!(shared.outstanding_bytes, 64);
+        assert_eq!(shared.last.unwrap().paddr, 0x9000);
+    }
+}
@@ -15,6 +15,7 @@
 //! HAL for the virtio_drivers crate.
 
 use super::pci::PCI_INFO;
+use super::{diagnostics, USAGE};
 use crate::memory::{alloc_shared, dealloc_shared, phys_to_virt, virt_to_phys};
 use crate::util::RangeExt as _;
 use core::alloc::Layout;
@@ -44,15 +45,18 @@ unsafe impl Hal for HalImpl {
     /// buffer before returning it.
     fn dma_alloc(pages: usize, _direction: BufferDirection) -> (PhysAddr, NonNull<u8>) {
         let layout = dma_layout(pages);
-        let vaddr =
-            alloc_shared(layout).expect("Failed to allocate and share VirtIO DMA range with host");
+        let vaddr = alloc_shared(layout).unwrap_or_else(|e| {
+            let diagnostics = diagnostics();
+            panic!("Failed to allocate and share VirtIO DMA range with host: {e} ({diagnostics})")
+        });
         // SAFETY: vaddr points to a region allocated for the caller so is safe to access.
         unsafe { core::ptr::write_bytes(vaddr.as_ptr(), 0, layout.size()) };
         let paddr = virt_to_phys(vaddr);
         (paddr, vaddr)
     }
 
-    unsafe fn dma_dealloc(_paddr: PhysAddr, vaddr: NonNull<u8>, pages: usize) -> i32 {
+    unsafe fn dma_dealloc(paddr: PhysAddr, vaddr: NonNull<u8>, pages: usize) -> i32 {
+        USAGE.lock().forget_rings_in(&(paddr..paddr + dma_layout(pages).size()));
         // SAFETY: Memory was allocated by `dma_alloc` using `alloc_shared` with the same layout.
         unsafe { dealloc_shared(vaddr, dma_layout(pages)) }
             .expect("Failed to unshare VirtIO DMA range with host");
@@ -88,9 +92,12 @@ unsafe impl Hal for HalImpl {
     unsafe fn share(buffer: NonNull<[u8]>, direction: BufferDirection) -> PhysAddr {
         let size = buffer.len();
 
-        let bounce = alloc_shared(bb_layout(size))
-            .expect("Failed to allocate and share VirtIO bounce buffer with host");
+        let bounce = alloc_shared(bb_layout(size)).unwrap_or_else(|e| {
+            let diagnostics = diagnostics();
+            panic!("Failed to allocate and share VirtIO bounce buffer: {e} ({diagnostics})")
+        });
         let paddr = virt_to_phys(bounce);
+        USAGE.lock().share(paddr, size, direction);
         if direction == BufferDirection::DriverToDevice {
             let src = buffer.cast::<u8>().as_ptr().cast_const();
             trace!("VirtIO bounce buffer at {bounce:?} (PA:{paddr:#x}) initialized from {src:?}");
@@ -111,6 +118,7 @@ unsafe impl Hal for HalImpl {
             unsafe { copy_nonoverlapping(bounce.as_ptr(), dest, size) };
         }
 
+        USAGE.lock().unshare(size);
         // SAFETY: Memory was allocated by `share` using `alloc_shared` with the same layout.
         unsafe { dealloc_shared(bounce, bb_layout(size)) }
             .expect("Failed to unshare and deallocate VirtIO bounce buffer");
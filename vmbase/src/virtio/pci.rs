@@ -14,8 +14,10 @@
 
 //! Functions to scan the PCI bus for VirtIO devices.
 
+use super::{TrackedTransport, VirtIOError};
 use crate::memory::{MemoryTracker, MemoryTrackerError};
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::fmt;
 use core::marker::PhantomData;
 use fdtpci::PciInfo;
@@ -27,6 +29,7 @@ use virtio_drivers::{
         bus::{BusDeviceIterator, PciRoot},
         virtio_device_type, PciTransport,
     },
+    transport::{DeviceType, Transport},
     Hal,
 };
 
@@ -77,12 +80,26 @@ pub fn initialize(pci_info: PciInfo, memory: &mut MemoryTracker) -> Result<PciRo
 }
 
 /// Virtio Block device.
-pub type VirtIOBlk<T> = blk::VirtIOBlk<T, PciTransport>;
+pub type VirtIOBlk<T> = blk::VirtIOBlk<T, TrackedTransport<PciTransport>>;
+
+/// Feature bit advertised by Virtio Block devices supporting the flush command.
+///
+/// Spec: https://docs.oasis-open.org/virtio/virtio/v1.2/csd01/virtio-v1.2-csd01.html 5.2.3
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+
+/// Virtio Block device, as instantiated by [`probe_blk`].
+pub struct BlkDevice<T: Hal> {
+    /// Driver of the device.
+    pub driver: VirtIOBlk<T>,
+    /// Whether the device supports the flush command, without which completed writes might still
+    /// be lost if the host crashes.
+    pub supports_flush: bool,
+}
 
 /// Virtio Socket device.
 ///
 /// Spec: https://docs.oasis-open.org/virtio/virtio/v1.2/csd01/virtio-v1.2-csd01.html 5.10
-pub type VirtIOSocket<T> = socket::VirtIOSocket<T, PciTransport>;
+pub type VirtIOSocket<T> = socket::VirtIOSocket<T, TrackedTransport<PciTransport>>;
 
 /// An iterator that iterates over the PCI transport for each device.
 pub struct PciTransportIterator<'a, T: Hal> {
@@ -120,3 +137,85 @@ impl<'a, T: Hal> Iterator for PciTransportIterator<'a, T> {
         }
     }
 }
+
+/// A VirtIO device driver instantiated by a probe function.
+pub enum VirtIODevice<T: Hal> {
+    /// Block device.
+    Blk(BlkDevice<T>),
+    /// Socket device.
+    Socket(VirtIOSocket<T>),
+}
+
+impl<T: Hal> VirtIODevice<T> {
+    /// Returns the block device driver, if this is one.
+    pub fn into_blk(self) -> Option<BlkDevice<T>> {
+        match self {
+            Self::Blk(blk) => Some(blk),
+            _ => None,
+        }
+    }
+
+    /// Returns the socket device driver, if this is one.
+    pub fn into_socket(self) -> Option<VirtIOSocket<T>> {
+        match self {
+            Self::Socket(socket) => Some(socket),
+            _ => None,
+        }
+    }
+}
+
+/// Function instantiating a driver for a transport of the device type it was registered for.
+pub type ProbeFn<T> = fn(PciTransport) -> virtio_drivers::Result<VirtIODevice<T>>;
+
+/// Probes a VirtIO block device.
+pub fn probe_blk<T: Hal>(mut transport: PciTransport) -> virtio_drivers::Result<VirtIODevice<T>> {
+    let supports_flush = transport.read_device_features() & VIRTIO_BLK_F_FLUSH != 0;
+    let driver = VirtIOBlk::<T>::new(TrackedTransport::new(transport))?;
+    Ok(VirtIODevice::Blk(BlkDevice { driver, supports_flush }))
+}
+
+/// Probes a VirtIO socket device.
+pub fn probe_socket<T: Hal>(transport: PciTransport) -> virtio_drivers::Result<VirtIODevice<T>> {
+    Ok(VirtIODevice::Socket(VirtIOSocket::<T>::new(TrackedTransport::new(transport))?))
+}
+
+/// Set of probe functions, keyed by VirtIO device type, used to scan the PCI bus in a single pass.
+pub struct VirtIODeviceRegistry<T: Hal> {
+    probes: Vec<(DeviceType, ProbeFn<T>)>,
+}
+
+impl<T: Hal> VirtIODeviceRegistry<T> {
+    /// Creates a registry without any probe function.
+    pub fn new() -> Self {
+        Self { probes: Vec::new() }
+    }
+
+    /// Registers `probe` for devices of type `device_type`, replacing any previous one.
+    pub fn register(mut self, device_type: DeviceType, probe: ProbeFn<T>) -> Self {
+        self.probes.retain(|(t, _)| *t != device_type);
+        self.probes.push((device_type, probe));
+        self
+    }
+
+    /// Enumerates the PCI bus and instantiates a driver for each device with a registered type.
+    ///
+    /// Devices of other types are ignored.
+    pub fn probe_all(&self, pci_root: &mut PciRoot) -> Result<Vec<VirtIODevice<T>>, VirtIOError> {
+        let mut devices = Vec::new();
+        for transport in PciTransportIterator::<T>::new(pci_root) {
+            let device_type = transport.device_type();
+            let Some((_, probe)) = self.probes.iter().find(|(t, _)| *t == device_type) else {
+                debug!("No driver registered for VirtIO {:?}", device_type);
+                continue;
+            };
+            devices.push(probe(transport)?);
+        }
+        Ok(devices)
+    }
+}
+
+impl<T: Hal> Default for VirtIODeviceRegistry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
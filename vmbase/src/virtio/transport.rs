@@ -0,0 +1,106 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! VirtIO transport recording the usage of the queues of its device.
+
+use super::USAGE;
+use core::ptr::NonNull;
+use virtio_drivers::{
+    transport::{DeviceStatus, DeviceType, Transport},
+    PhysAddr, Result,
+};
+
+/// Transport forwarding to `T` while recording the configuration of the queues of the device and
+/// the notifications sent to it, for [`diagnostics`](super::diagnostics).
+pub struct TrackedTransport<T: Transport> {
+    inner: T,
+    device: usize,
+}
+
+impl<T: Transport> TrackedTransport<T> {
+    /// Starts tracking the queues of the device behind `inner`.
+    pub fn new(inner: T) -> Self {
+        let device = USAGE.lock().add_device();
+        Self { inner, device }
+    }
+}
+
+impl<T: Transport> Transport for TrackedTransport<T> {
+    fn device_type(&self) -> DeviceType {
+        self.inner.device_type()
+    }
+
+    fn read_device_features(&mut self) -> u64 {
+        self.inner.read_device_features()
+    }
+
+    fn write_driver_features(&mut self, driver_features: u64) {
+        self.inner.write_driver_features(driver_features)
+    }
+
+    fn max_queue_size(&mut self, queue: u16) -> u32 {
+        self.inner.max_queue_size(queue)
+    }
+
+    fn notify(&mut self, queue: u16) {
+        USAGE.lock().notify(self.device, queue);
+        self.inner.notify(queue)
+    }
+
+    fn get_status(&self) -> DeviceStatus {
+        self.inner.get_status()
+    }
+
+    fn set_status(&mut self, status: DeviceStatus) {
+        self.inner.set_status(status)
+    }
+
+    fn set_guest_page_size(&mut self, guest_page_size: u32) {
+        self.inner.set_guest_page_size(guest_page_size)
+    }
+
+    fn requires_legacy_layout(&self) -> bool {
+        self.inner.requires_legacy_layout()
+    }
+
+    fn queue_set(
+        &mut self,
+        queue: u16,
+        size: u32,
+        descriptors: PhysAddr,
+        driver_area: PhysAddr,
+        device_area: PhysAddr,
+    ) {
+        self.inner.queue_set(queue, size, descriptors, driver_area, device_area);
+        let device_type = self.inner.device_type();
+        USAGE.lock().queue_set(self.device, device_type, queue, size, driver_area, device_area);
+    }
+
+    fn queue_unset(&mut self, queue: u16) {
+        USAGE.lock().queue_unset(self.device, queue);
+        self.inner.queue_unset(queue)
+    }
+
+    fn queue_used(&mut self, queue: u16) -> bool {
+        self.inner.queue_used(queue)
+    }
+
+    fn ack_interrupt(&mut self) -> bool {
+        self.inner.ack_interrupt()
+    }
+
+    fn config_space<C: 'static>(&self) -> Result<NonNull<C>> {
+        self.inner.config_space()
+    }
+}
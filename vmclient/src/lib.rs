@@ -40,16 +40,17 @@ use android_system_virtualizationservice::{
     },
 };
 use command_fds::CommandFdExt;
-use log::warn;
+use log::{info, warn};
 use rpcbinder::{FileDescriptorTransportMode, RpcSession};
 use shared_child::SharedChild;
-use std::io::{self, Read};
+use std::io::{self, BufRead, BufReader, Read};
 use std::process::Command;
 use std::{
     fmt::{self, Debug, Formatter},
     fs::File,
     os::unix::io::{AsFd, AsRawFd, FromRawFd, IntoRawFd, OwnedFd},
     sync::Arc,
+    thread,
     time::Duration,
 };
 
@@ -240,6 +241,22 @@ impl VmInstance {
         }
     }
 
+    /// Streams the stdout and stderr of the payload through pipes, whose read ends are returned.
+    ///
+    /// The payload blocks when the pipes are full, so the caller must keep reading them. At most
+    /// `max_bytes` bytes of output are forwarded in total, the rest is dropped.
+    pub fn stream_payload_output(&self, max_bytes: u64) -> BinderResult<PayloadOutput> {
+        let (stdout, stdout_writer) = posix_pipe().map_err(|_| StatusCode::UNKNOWN_ERROR)?;
+        let (stderr, stderr_writer) = posix_pipe().map_err(|_| StatusCode::UNKNOWN_ERROR)?;
+        let max_bytes = max_bytes.try_into().map_err(|_| StatusCode::BAD_VALUE)?;
+        self.vm.setPayloadOutput(
+            &ParcelFileDescriptor::new(stdout_writer),
+            &ParcelFileDescriptor::new(stderr_writer),
+            max_bytes,
+        )?;
+        Ok(PayloadOutput { stdout: stdout.into(), stderr: stderr.into() })
+    }
+
     /// Tries to connect to an RPC Binder service provided by the VM on the given vsock port.
     pub fn connect_service<T: FromIBinder + ?Sized>(
         &self,
@@ -260,6 +277,45 @@ impl VmInstance {
     }
 }
 
+/// The read ends of the pipes carrying the output of the payload of a VM, see
+/// [`VmInstance::stream_payload_output`].
+#[derive(Debug)]
+pub struct PayloadOutput {
+    /// What the payload writes to its stdout.
+    pub stdout: File,
+    /// What the payload writes to its stderr.
+    pub stderr: File,
+}
+
+impl PayloadOutput {
+    /// Logs each line of the output of the payload, prefixed with `name`, from background threads
+    /// which exit once the payload closes its output.
+    pub fn forward_to_log(self, name: &str) -> io::Result<()> {
+        for (file, stream) in [(self.stdout, "stdout"), (self.stderr, "stderr")] {
+            let prefix = format!("{name} ({stream})");
+            thread::Builder::new().name(format!("{name}_{stream}")).spawn(move || {
+                let mut reader = BufReader::new(file);
+                let mut line = Vec::new();
+                loop {
+                    line.clear();
+                    match reader.read_until(b'\n', &mut line) {
+                        Ok(0) => return,
+                        Ok(_) => {
+                            let line = line.strip_suffix(b"\n").unwrap_or(&line);
+                            info!("{prefix}: {}", String::from_utf8_lossy(line));
+                        }
+                        Err(e) => {
+                            warn!("Failed to read payload output of {prefix}: {e}");
+                            return;
+                        }
+                    }
+                }
+            })?;
+        }
+        Ok(())
+    }
+}
+
 impl Debug for VmInstance {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.debug_struct("VmInstance").field("cid", &self.cid).field("state", &self.state).finish()
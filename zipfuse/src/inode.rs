@@ -16,8 +16,10 @@
 use anyhow::{anyhow, bail, Result};
 use std::collections::HashMap;
 use std::ffi::{CStr, CString};
-use std::io;
+use std::io::{self, Read, Write};
+use std::ops::Range;
 use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
 
 /// `InodeTable` is a table of `InodeData` indexed by `Inode`.
 #[derive(Debug)]
@@ -47,6 +49,14 @@ const DEFAULT_DIR_MODE: u32 = READ_MODE | EXECUTE_MODE;
 const DEFAULT_FILE_MODE: u32 = READ_MODE;
 const EXECUTABLE_FILE_MODE: u32 = DEFAULT_FILE_MODE | EXECUTE_MODE;
 
+/// Header ID of the zip extra field that lists the zero-filled regions of a file entry. The payload
+/// is a sequence of (offset, length) pairs, each encoded as a little-endian u64.
+pub const ZERO_RUNS_EXTRA_FIELD_ID: u16 = 0x525a; // "ZR"
+
+// Deflated zeros shrink by roughly 1000x. Entries that don't compress at least this well can't be
+// entirely zero, so we don't bother checksumming zeros for them.
+const MIN_ZERO_COMPRESSION_RATIO: u64 = 512;
+
 /// `InodeData` represents an inode which has metadata about a file or a directory
 #[derive(Debug)]
 pub struct InodeData {
@@ -57,21 +67,72 @@ pub struct InodeData {
     /// doesn't have the information in the external_attributes fields. To test if this inode
     /// is for a regular file or a directory, use `is_dir`.
     pub mode: u32,
+    /// Sorted, non-overlapping ranges of the file that are known to be entirely zero. These come
+    /// from the `ZERO_RUNS_EXTRA_FIELD_ID` extra field, or cover the whole file if the checksum
+    /// of the entry matches that of a zero-filled file of the same size.
+    zero_runs: Box<[Range<u64>]>,
     data: InodeDataData,
 }
 
 type ZipIndex = usize;
 
 /// `InodeDataData` is the actual data (or a means to access the data) of the file or the directory
-/// that an inode is representing. In case of a directory, this data is the hash table of the
+/// that an inode is representing. In case of a directory, this data is the sorted list of the
 /// directory entries. In case of a file, this data is the index of the file in `ZipArchive` which
 /// can be used to retrieve `ZipFile` that provides access to the content of the file.
 #[derive(Debug)]
 enum InodeDataData {
-    Directory(HashMap<CString, DirectoryEntry>),
+    Directory(Directory),
     File(ZipIndex),
 }
 
+/// Entries of a directory, sorted by name once the inode table is built. The order is then fixed,
+/// so the position of an entry can be used as a stable offset when reading the directory, no matter
+/// how many times it is opened.
+#[derive(Debug, Default)]
+pub struct Directory {
+    entries: Vec<(CString, DirectoryEntry)>,
+}
+
+impl Directory {
+    /// Returns the entry named `name`, if any.
+    pub fn get(&self, name: &CStr) -> Option<&DirectoryEntry> {
+        let index = self.entries.binary_search_by(|(n, _)| n.as_c_str().cmp(name)).ok()?;
+        Some(&self.entries[index].1)
+    }
+
+    /// Returns all the entries, in their stable order.
+    pub fn entries(&self) -> &[(CString, DirectoryEntry)] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Only used by tests for now, but goes with `len`.
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds an entry. `sort` must be called once all the entries are added, before the directory
+    /// is looked up.
+    fn push(&mut self, name: CString, entry: DirectoryEntry) {
+        self.entries.push((name, entry));
+    }
+
+    /// Sorts the entries by name. Returns false if two entries have the same name.
+    fn sort(&mut self) -> bool {
+        self.entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        self.entries.windows(2).all(|pair| pair[0].0 != pair[1].0)
+    }
+
+    fn entries_mut(&mut self) -> impl Iterator<Item = &mut DirectoryEntry> {
+        self.entries.iter_mut().map(|(_, entry)| entry)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DirectoryEntry {
     pub inode: Inode,
@@ -89,9 +150,9 @@ impl InodeData {
         matches!(&self.data, InodeDataData::Directory(_))
     }
 
-    pub fn get_directory(&self) -> Option<&HashMap<CString, DirectoryEntry>> {
+    pub fn get_directory(&self) -> Option<&Directory> {
         match &self.data {
-            InodeDataData::Directory(hash) => Some(hash),
+            InodeDataData::Directory(directory) => Some(directory),
             _ => None,
         }
     }
@@ -103,22 +164,52 @@ impl InodeData {
         }
     }
 
+    /// Returns true if some part of the file is known to be zero-filled.
+    pub fn is_sparse(&self) -> bool {
+        !self.zero_runs.is_empty()
+    }
+
+    /// Returns the number of bytes of the file that are not covered by a zero run, i.e. the number
+    /// of bytes that would have to be stored in order to serve the file.
+    pub fn allocated_size(&self) -> u64 {
+        self.size - self.zero_runs.iter().map(|r| r.end - r.start).sum::<u64>()
+    }
+
     // Below methods are used to construct the inode table when initializing the filesystem. Once
     // the initialization is done, these are not used because this is a read-only filesystem.
 
     fn new_dir(mode: u32) -> InodeData {
-        InodeData { mode, size: 0, data: InodeDataData::Directory(HashMap::new()) }
+        InodeData {
+            mode,
+            size: 0,
+            zero_runs: Box::new([]),
+            data: InodeDataData::Directory(Directory::default()),
+        }
     }
 
-    fn new_file(zip_index: ZipIndex, mode: u32, zip_file: &zip::read::ZipFile) -> InodeData {
-        InodeData { mode, size: zip_file.size(), data: InodeDataData::File(zip_index) }
+    fn new_file(
+        zip_index: ZipIndex,
+        mode: u32,
+        zip_file: &zip::read::ZipFile,
+    ) -> Result<InodeData> {
+        let size = zip_file.size();
+        let zero_runs = if is_zero_filled(zip_file) {
+            std::iter::once(0..size).collect()
+        } else {
+            parse_zero_runs(zip_file.extra_data(), size)?
+        };
+        Ok(InodeData {
+            mode,
+            size,
+            zero_runs: zero_runs.into_boxed_slice(),
+            data: InodeDataData::File(zip_index),
+        })
     }
 
     fn add_to_directory(&mut self, name: CString, entry: DirectoryEntry) {
         match &mut self.data {
-            InodeDataData::Directory(hashtable) => {
-                let existing = hashtable.insert(name, entry);
-                assert!(existing.is_none());
+            InodeDataData::Directory(directory) => {
+                directory.push(name, entry);
             }
             _ => {
                 panic!("can't add a directory entry to a file inode");
@@ -127,6 +218,74 @@ impl InodeData {
     }
 }
 
+/// Returns true if `zip_file` is a non-empty file whose content is entirely zero. This is decided
+/// without decompressing the file, by comparing its CRC against that of zeros of the same size.
+fn is_zero_filled(zip_file: &zip::read::ZipFile) -> bool {
+    let size = zip_file.size();
+    if size == 0 || zip_file.compressed_size() > size / MIN_ZERO_COMPRESSION_RATIO {
+        return false;
+    }
+    zeros_crc32(size) == zip_file.crc32()
+}
+
+/// Returns the CRC32 of `size` zero bytes. As `size` comes from the archive, the zeros aren't
+/// hashed one by one: the CRC32 of 2^n zeros is combined with itself to get that of 2^(n+1) zeros,
+/// so this takes O(log(size)) steps.
+fn zeros_crc32(size: u64) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut zeros = crc32fast::Hasher::new(); // 2^n zeros, for the n-th bit of `size`
+    zeros.update(&[0]);
+    let mut remaining = size;
+    while remaining > 0 {
+        if remaining & 1 != 0 {
+            hasher.combine(&zeros);
+        }
+        remaining >>= 1;
+        if remaining > 0 {
+            let half = zeros.clone();
+            zeros.combine(&half);
+        }
+    }
+    hasher.finalize()
+}
+
+/// Parses the zero runs out of the raw `extra` field of a zip entry whose uncompressed size is
+/// `size`. Extra fields other than `ZERO_RUNS_EXTRA_FIELD_ID` are ignored.
+fn parse_zero_runs(mut extra: &[u8], size: u64) -> Result<Vec<Range<u64>>> {
+    let mut runs: Vec<Range<u64>> = Vec::new();
+    while !extra.is_empty() {
+        if extra.len() < 4 {
+            bail!("truncated extra field header");
+        }
+        let id = u16::from_le_bytes([extra[0], extra[1]]);
+        let len = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        if extra.len() < 4 + len {
+            bail!("truncated extra field");
+        }
+        let (data, rest) = extra[4..].split_at(len);
+        extra = rest;
+        if id != ZERO_RUNS_EXTRA_FIELD_ID {
+            continue;
+        }
+        if data.len() % 16 != 0 {
+            bail!("invalid zero runs extra field length {}", data.len());
+        }
+        for pair in data.chunks_exact(16) {
+            let offset = u64::from_le_bytes(pair[..8].try_into().unwrap());
+            let length = u64::from_le_bytes(pair[8..].try_into().unwrap());
+            let end = offset.checked_add(length).filter(|end| *end <= size);
+            let end = end.ok_or_else(|| anyhow!("zero run {offset}+{length} is out of bounds"))?;
+            if runs.last().is_some_and(|last| last.end > offset) {
+                bail!("zero runs are not sorted or overlap at {offset}");
+            }
+            if length > 0 {
+                runs.push(offset..end);
+            }
+        }
+    }
+    Ok(runs)
+}
+
 impl InodeTable {
     /// Gets `InodeData` at a specific index.
     pub fn get(&self, inode: Inode) -> Option<&InodeData> {
@@ -159,11 +318,17 @@ impl InodeTable {
         }
     }
 
+    /// Finds the inode number of the file or directory at `path`, relative to the root.
+    pub fn find_path(&self, path: &Path) -> Option<Inode> {
+        path.iter().try_fold(ROOT, |parent, name| {
+            self.get(parent)?.get_directory()?;
+            self.find(parent, &CString::new(name.as_bytes()).ok()?)
+        })
+    }
+
     // Adds the inode `data` to the inode table and also links it to the `parent` inode as a file
-    // named `name`. The `parent` inode must exist and be a directory.
+    // named `name`. The `parent` inode must exist and be a directory, with no entry named `name`.
     fn add(&mut self, parent: Inode, name: CString, data: InodeData) -> Inode {
-        assert!(self.find(parent, &name).is_none());
-
         let kind = if data.is_dir() { InodeKind::Directory } else { InodeKind::File };
         // Add the inode to the table
         let inode = self.put(data);
@@ -183,6 +348,10 @@ impl InodeTable {
         assert_eq!(INVALID, table.put(InodeData::new_dir(0)));
         assert_eq!(ROOT, table.put(InodeData::new_dir(DEFAULT_DIR_MODE)));
 
+        // The directories are only sorted once complete, so look up their entries in a separate
+        // index while building the table.
+        let mut children: HashMap<Inode, HashMap<CString, Inode>> = HashMap::new();
+
         // For each zip file in the archive, create an inode and add it to the table. If the file's
         // parent directories don't have corresponding inodes in the table, handle them too.
         for i in 0..archive.len() {
@@ -217,7 +386,7 @@ impl InodeTable {
                 // The happy path; the inode for `name` is already in the `parent` inode. Move on
                 // to the next path element.
                 let name = CString::new(name.as_bytes()).unwrap();
-                if let Some(found) = table.find(parent, &name) {
+                if let Some(&found) = children.get(&parent).and_then(|c| c.get(name.as_c_str())) {
                     parent = found;
                     // Update the mode if this is a directory leaf.
                     if !is_file && is_leaf {
@@ -232,18 +401,173 @@ impl InodeTable {
                 // permissions (apart from the ones on lib/), but it might change in the future.
                 // TODO(b/270955654): should we control the file permissions ourselves?
                 let inode = if is_file {
-                    InodeData::new_file(i, file.unix_mode().unwrap_or(file_mode), &file)
+                    InodeData::new_file(i, file.unix_mode().unwrap_or(file_mode), &file)?
                 } else if is_leaf {
                     InodeData::new_dir(file.unix_mode().unwrap_or(DEFAULT_DIR_MODE))
                 } else {
                     InodeData::new_dir(DEFAULT_DIR_MODE)
                 };
-                let new = table.add(parent, name, inode);
+                let new = table.add(parent, name.clone(), inode);
+                children.entry(parent).or_default().insert(name, new);
                 parent = new;
             }
         }
+        for data in table.table.iter_mut() {
+            if let InodeDataData::Directory(entries) = &mut data.data {
+                assert!(entries.sort());
+            }
+        }
         Ok(table)
     }
+
+    /// Writes the table to `w`, so that it can be loaded with `deserialize` instead of being
+    /// rebuilt from the same zip archive.
+    pub fn serialize<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&(self.table.len() as u64).to_le_bytes())?;
+        for data in &self.table {
+            w.write_all(&data.mode.to_le_bytes())?;
+            w.write_all(&data.size.to_le_bytes())?;
+            w.write_all(&(data.zero_runs.len() as u64).to_le_bytes())?;
+            for run in data.zero_runs.iter() {
+                w.write_all(&run.start.to_le_bytes())?;
+                w.write_all(&run.end.to_le_bytes())?;
+            }
+            match &data.data {
+                InodeDataData::File(zip_index) => {
+                    w.write_all(&[SERIALIZED_FILE])?;
+                    w.write_all(&(*zip_index as u64).to_le_bytes())?;
+                }
+                InodeDataData::Directory(entries) => {
+                    w.write_all(&[SERIALIZED_DIRECTORY])?;
+                    w.write_all(&(entries.len() as u64).to_le_bytes())?;
+                    for (name, entry) in entries.entries() {
+                        let name = name.as_bytes();
+                        w.write_all(&(name.len() as u64).to_le_bytes())?;
+                        w.write_all(name)?;
+                        w.write_all(&entry.inode.to_le_bytes())?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a table written by `serialize`, for a zip archive with `num_zip_entries` entries.
+    ///
+    /// The table is checked to be consistent, but not to match the content of the archive.
+    pub fn deserialize<R: Read>(r: &mut R, num_zip_entries: usize) -> Result<InodeTable> {
+        let len = read_len(r)?;
+        if len < 2 {
+            bail!("missing root inode");
+        }
+        let mut table = Vec::new();
+        for _ in 0..len {
+            let mode = u32::from_le_bytes(read_array(r)?);
+            let size = read_u64(r)?;
+            let mut zero_runs = Vec::new();
+            for _ in 0..read_len(r)? {
+                let start = read_u64(r)?;
+                let end = read_u64(r)?;
+                let overlaps = zero_runs.last().is_some_and(|last: &Range<u64>| last.end > start);
+                if start >= end || end > size || overlaps {
+                    bail!("invalid zero run {start}..{end}");
+                }
+                zero_runs.push(start..end);
+            }
+            let data = match read_array(r)? {
+                [SERIALIZED_FILE] => {
+                    let zip_index = read_len(r)?;
+                    if zip_index >= num_zip_entries {
+                        bail!("invalid zip index {zip_index}");
+                    }
+                    InodeDataData::File(zip_index)
+                }
+                [SERIALIZED_DIRECTORY] => {
+                    let mut entries = Directory::default();
+                    for _ in 0..read_len(r)? {
+                        let name_len = read_len(r)?;
+                        if name_len > u16::MAX.into() {
+                            bail!("directory entry name is too long");
+                        }
+                        let mut name = vec![0; name_len];
+                        r.read_exact(&mut name)?;
+                        let name = CString::new(name)?;
+                        if name.as_bytes().is_empty() || name.as_bytes().contains(&b'/') {
+                            bail!("invalid directory entry name {name:?}");
+                        }
+                        // The kind is fixed up below, once all the inodes are known.
+                        let entry = DirectoryEntry { inode: read_u64(r)?, kind: InodeKind::File };
+                        entries.push(name, entry);
+                    }
+                    if !entries.sort() {
+                        bail!("duplicated directory entry");
+                    }
+                    InodeDataData::Directory(entries)
+                }
+                [kind] => bail!("invalid inode kind {kind}"),
+            };
+            table.push(InodeData { mode, size, zero_runs: zero_runs.into_boxed_slice(), data });
+        }
+
+        let kinds: Vec<_> = table
+            .iter()
+            .map(|data| if data.is_dir() { InodeKind::Directory } else { InodeKind::File })
+            .collect();
+        if kinds[ROOT as usize] != InodeKind::Directory {
+            bail!("root inode is not a directory");
+        }
+        // As in a table built from a zip archive, each inode but the root one must be linked from
+        // exactly one directory. This alone still allows detached cycles of directories, so the
+        // inodes must also all be reachable from the root one for the hierarchy to be a tree.
+        let mut linked = vec![false; table.len()];
+        for data in table.iter_mut() {
+            if let InodeDataData::Directory(entries) = &mut data.data {
+                for entry in entries.entries_mut() {
+                    let inode = entry.inode as usize;
+                    if entry.inode == INVALID || entry.inode == ROOT || inode >= kinds.len() {
+                        bail!("invalid directory entry inode {inode}");
+                    }
+                    if std::mem::replace(&mut linked[inode], true) {
+                        bail!("inode {inode} is linked more than once");
+                    }
+                    entry.kind = kinds[inode];
+                }
+            }
+        }
+        if linked.iter().skip(ROOT as usize + 1).any(|linked| !linked) {
+            bail!("some inodes aren't linked to any directory");
+        }
+        let mut reachable = 0;
+        let mut pending = vec![ROOT];
+        while let Some(inode) = pending.pop() {
+            reachable += 1;
+            if let InodeDataData::Directory(entries) = &table[inode as usize].data {
+                pending.extend(entries.entries().iter().map(|(_, entry)| entry.inode));
+            }
+        }
+        if reachable != table.len() - ROOT as usize {
+            bail!("some inodes aren't reachable from the root directory");
+        }
+        Ok(InodeTable { table })
+    }
+}
+
+/// Values identifying the kind of a serialized inode.
+const SERIALIZED_FILE: u8 = 0;
+const SERIALIZED_DIRECTORY: u8 = 1;
+
+fn read_array<R: Read, const N: usize>(r: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    Ok(u64::from_le_bytes(read_array(r)?))
+}
+
+fn read_len<R: Read>(r: &mut R) -> Result<usize> {
+    Ok(read_u64(r)?.try_into()?)
 }
 
 #[cfg(test)]
@@ -317,7 +641,7 @@ mod tests {
         });
         let inode = check_dir(&it, ROOT, "foo");
         // The directory doesn't have any entries
-        assert_eq!(0, it.get(inode).unwrap().get_directory().unwrap().len());
+        assert!(it.get(inode).unwrap().get_directory().unwrap().is_empty());
     }
 
     #[test]
@@ -420,6 +744,76 @@ mod tests {
         assert_eq!(2 << 20, f.size);
     }
 
+    #[test]
+    fn zero_filled_file_is_sparse() {
+        let it = setup(|zip| {
+            let opt = FileOptions::default();
+            zip.start_file("zeros", opt).unwrap();
+            zip.write_all(&[0; 1 << 20]).unwrap();
+
+            zip.start_file("ones", opt).unwrap();
+            zip.write_all(&[1; 1 << 20]).unwrap();
+
+            zip.start_file("empty", opt).unwrap();
+        });
+
+        let f = check_file(&it, ROOT, "zeros");
+        assert!(f.is_sparse());
+        assert_eq!(0, f.allocated_size());
+
+        let f = check_file(&it, ROOT, "ones");
+        assert!(!f.is_sparse());
+        assert_eq!(1 << 20, f.allocated_size());
+
+        let f = check_file(&it, ROOT, "empty");
+        assert!(!f.is_sparse());
+    }
+
+    #[test]
+    fn zeros_crc32_matches_hashing_zeros() {
+        for size in [0, 1, 2, 3, 4095, 4096, 4097, 1 << 20, (1 << 20) + 12345] {
+            let mut hasher = crc32fast::Hasher::new();
+            hasher.update(&vec![0; size]);
+            assert_eq!(hasher.finalize(), zeros_crc32(size as u64), "size {size}");
+        }
+        // Sizes which would take forever to hash are handled too.
+        zeros_crc32(u64::MAX);
+    }
+
+    fn zero_runs_extra_field(runs: &[(u64, u64)]) -> Vec<u8> {
+        let mut extra = Vec::new();
+        extra.extend_from_slice(&ZERO_RUNS_EXTRA_FIELD_ID.to_le_bytes());
+        extra.extend_from_slice(&((runs.len() * 16) as u16).to_le_bytes());
+        for (offset, length) in runs {
+            extra.extend_from_slice(&offset.to_le_bytes());
+            extra.extend_from_slice(&length.to_le_bytes());
+        }
+        extra
+    }
+
+    #[test]
+    fn parses_zero_runs() {
+        let mut extra = vec![0x01, 0x00, 0x02, 0x00, 0xaa, 0xbb]; // unrelated extra field
+        extra.extend(zero_runs_extra_field(&[(0, 10), (20, 0), (100, 28)]));
+        let runs = parse_zero_runs(&extra, 128).unwrap();
+        assert_eq!(vec![0..10, 100..128], runs);
+
+        assert!(parse_zero_runs(&[], 128).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_zero_runs() {
+        // out of bounds
+        assert!(parse_zero_runs(&zero_runs_extra_field(&[(100, 29)]), 128).is_err());
+        assert!(parse_zero_runs(&zero_runs_extra_field(&[(u64::MAX, 2)]), 128).is_err());
+        // overlapping and unsorted
+        assert!(parse_zero_runs(&zero_runs_extra_field(&[(0, 10), (5, 10)]), 128).is_err());
+        assert!(parse_zero_runs(&zero_runs_extra_field(&[(20, 10), (0, 10)]), 128).is_err());
+        // truncated
+        let extra = zero_runs_extra_field(&[(0, 10)]);
+        assert!(parse_zero_runs(&extra[..extra.len() - 1], 128).is_err());
+    }
+
     #[test]
     fn rejects_invalid_paths() {
         let invalid_paths = [
@@ -441,4 +835,102 @@ mod tests {
             assert!(it.is_err());
         }
     }
+
+    #[test]
+    fn directory_entries_are_sorted() {
+        let it = setup(|zip| {
+            let opt = FileOptions::default();
+            zip.start_file("dir/b", opt).unwrap();
+            zip.add_directory("dir/c", opt).unwrap();
+            zip.start_file("dir/a", opt).unwrap();
+            zip.start_file("dir/ab", opt).unwrap();
+        });
+        let names = |it: &InodeTable| {
+            let dir = check_dir(it, ROOT, "dir");
+            let entries = it.get(dir).unwrap().get_directory().unwrap().entries();
+            entries.iter().map(|(name, _)| name.to_str().unwrap().to_owned()).collect::<Vec<_>>()
+        };
+        assert_eq!(names(&it), ["a", "ab", "b", "c"]);
+
+        // The order, on which directory offsets rely, is preserved by serialization.
+        let buf = serialize(&it);
+        let it = InodeTable::deserialize(&mut buf.as_slice(), 4).unwrap();
+        assert_eq!(names(&it), ["a", "ab", "b", "c"]);
+    }
+
+    fn serialize(it: &InodeTable) -> Vec<u8> {
+        let mut buf = Vec::new();
+        it.serialize(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn serialization_round_trip() {
+        let it = setup(|zip| {
+            zip.start_file("a/b/foo", FileOptions::default()).unwrap();
+            zip.write_all(b"foo").unwrap();
+            zip.add_directory("a/c", FileOptions::default()).unwrap();
+            zip.start_file("bar", FileOptions::default()).unwrap();
+            zip.write_all(b"barbar").unwrap();
+        });
+        let buf = serialize(&it);
+        let it = InodeTable::deserialize(&mut buf.as_slice(), 3).unwrap();
+
+        let a = check_dir(&it, ROOT, "a");
+        let b = check_dir(&it, a, "b");
+        check_dir(&it, a, "c");
+        assert_eq!(3, check_file(&it, b, "foo").size);
+        assert_eq!(6, check_file(&it, ROOT, "bar").size);
+        assert_eq!(buf, serialize(&it));
+    }
+
+    #[test]
+    fn rejects_invalid_serialized_table() {
+        let it = setup(|zip| {
+            zip.start_file("a/foo", FileOptions::default()).unwrap();
+            zip.write_all(b"foo").unwrap();
+        });
+        let buf = serialize(&it);
+        assert!(InodeTable::deserialize(&mut buf.as_slice(), 1).is_ok());
+
+        // zip index out of bounds
+        assert!(InodeTable::deserialize(&mut buf.as_slice(), 0).is_err());
+        // truncated
+        for len in 0..buf.len() {
+            assert!(InodeTable::deserialize(&mut &buf[..len], 1).is_err());
+        }
+        // missing inodes
+        let mut missing = buf.clone();
+        missing[..8].copy_from_slice(&1u64.to_le_bytes());
+        assert!(InodeTable::deserialize(&mut missing.as_slice(), 1).is_err());
+    }
+
+    #[test]
+    fn rejects_serialized_table_with_detached_cycle() {
+        let mut table = vec![InodeData::new_dir(0), InodeData::new_dir(0)];
+        for (inode, next) in [(2, 3), (3, 2)] {
+            let mut dir = InodeData::new_dir(0);
+            let entry = DirectoryEntry { inode: next, kind: InodeKind::Directory };
+            if let InodeDataData::Directory(entries) = &mut dir.data {
+                entries.push(CString::new(format!("d{inode}")).unwrap(), entry);
+            }
+            table.push(dir);
+        }
+        let buf = serialize(&InodeTable { table });
+        assert!(InodeTable::deserialize(&mut buf.as_slice(), 0).is_err());
+    }
+
+    #[test]
+    fn rejects_serialized_table_with_duplicated_entries() {
+        let mut root = InodeData::new_dir(0);
+        for inode in [2, 3] {
+            let entry = DirectoryEntry { inode, kind: InodeKind::Directory };
+            if let InodeDataData::Directory(entries) = &mut root.data {
+                entries.push(CString::new("a").unwrap(), entry);
+            }
+        }
+        let table = vec![InodeData::new_dir(0), root, InodeData::new_dir(0), InodeData::new_dir(0)];
+        let buf = serialize(&InodeTable { table });
+        assert!(InodeTable::deserialize(&mut buf.as_slice(), 0).is_err());
+    }
 }
@@ -20,7 +20,7 @@
 
 mod inode;
 
-use anyhow::{Context as AnyhowContext, Result};
+use anyhow::{bail, Context as AnyhowContext, Result};
 use clap::{builder::ValueParser, Arg, ArgAction, Command};
 use fuse::filesystem::*;
 use fuse::mount::*;
@@ -30,12 +30,14 @@ use std::convert::TryFrom;
 use std::ffi::{CStr, CString};
 use std::fs::{File, OpenOptions};
 use std::io;
-use std::io::Read;
+use std::io::{Read, Seek, Write};
 use std::mem::{size_of, MaybeUninit};
+use std::os::unix::fs::{FileExt, MetadataExt};
 use std::os::unix::io::AsRawFd;
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::Mutex;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::Duration;
 
 use crate::inode::{DirectoryEntry, Inode, InodeData, InodeKind, InodeTable};
 
@@ -44,12 +46,23 @@ fn main() -> Result<()> {
 
     let zip_file = matches.get_one::<PathBuf>("ZIPFILE").unwrap();
     let mount_point = matches.get_one::<PathBuf>("MOUNTPOINT").unwrap();
-    let options = matches.get_one::<String>("options");
-    let noexec = matches.get_flag("noexec");
-    let ready_prop = matches.get_one::<String>("readyprop");
-    let uid: u32 = matches.get_one::<String>("uid").map_or(0, |s| s.parse().unwrap());
-    let gid: u32 = matches.get_one::<String>("gid").map_or(0, |s| s.parse().unwrap());
-    run_fuse(zip_file, mount_point, options, noexec, ready_prop, uid, gid)?;
+    let options = RunOptions {
+        extra_options: matches.get_one::<String>("options").cloned(),
+        noexec: matches.get_flag("noexec"),
+        ready_prop: matches.get_one::<String>("readyprop").cloned(),
+        uid: matches.get_one::<String>("uid").map_or(0, |s| s.parse().unwrap()),
+        gid: matches.get_one::<String>("gid").map_or(0, |s| s.parse().unwrap()),
+        strict: matches.get_flag("strict"),
+        cache: CacheOptions {
+            attr_timeout: matches.get_one::<u64>("attr_timeout").map_or(timeout_max(), secs),
+            entry_timeout: matches.get_one::<u64>("entry_timeout").map_or(timeout_max(), secs),
+            keep_dir_cache: !matches.get_flag("purge_cache"),
+        },
+        inode_cache: matches.get_one::<PathBuf>("inode_cache").cloned(),
+        verify_digests: matches.get_one::<PathBuf>("verify_digests").cloned(),
+        max_inflate_bytes: matches.get_one::<u64>("max_inflate_bytes").copied(),
+    };
+    run_fuse(zip_file, mount_point, &options)?;
 
     Ok(())
 }
@@ -73,25 +86,137 @@ fn clap_command() -> Command {
                 .short('p')
                 .help("Specify a property to be set when mount is ready"),
         )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .action(ArgAction::SetTrue)
+                .help("Refuse to mount archives using zip features that are not supported"),
+        )
+        .arg(
+            Arg::new("attr_timeout")
+                .long("attr-timeout")
+                .value_parser(clap::value_parser!(u64))
+                .help("Seconds for which the kernel may cache file attributes (default: forever)"),
+        )
+        .arg(
+            Arg::new("entry_timeout")
+                .long("entry-timeout")
+                .value_parser(clap::value_parser!(u64))
+                .help("Seconds for which the kernel may cache name lookups (default: forever)"),
+        )
+        .arg(
+            Arg::new("purge_cache")
+                .long("purge-cache")
+                .action(ArgAction::SetTrue)
+                .help("Don't keep directory contents cached by the kernel across opens"),
+        )
+        .arg(
+            Arg::new("inode_cache").long("inode-cache").value_parser(ValueParser::path_buf()).help(
+                "File in which the inode table is cached across mounts of the same archive, \
+                    e.g. next to it. It must be as trusted as the archive itself",
+            ),
+        )
+        .arg(
+            Arg::new("verify_digests")
+                .long("verify-digests")
+                .value_parser(ValueParser::path_buf())
+                .help(
+                    "File listing the SHA-256 digests of entries, in the format of sha256sum. \
+                    Listed entries fail to open with EIO if their content doesn't match",
+                ),
+        )
+        .arg(
+            Arg::new("max_inflate_bytes")
+                .long("max-inflate-bytes")
+                .value_parser(clap::value_parser!(u64))
+                .help(
+                    "Maximum number of bytes held by the decompressed content of open files. \
+                    Opening compressed files waits for memory to be freed while they are \
+                    exhausted, and fails with ENOMEM if none is freed in time",
+                ),
+        )
         .arg(Arg::new("uid").short('u').help("numeric UID who's the owner of the files"))
         .arg(Arg::new("gid").short('g').help("numeric GID who's the group of the files"))
         .arg(Arg::new("ZIPFILE").value_parser(ValueParser::path_buf()).required(true))
         .arg(Arg::new("MOUNTPOINT").value_parser(ValueParser::path_buf()).required(true))
 }
 
+/// How long the kernel may cache what zipfuse returns.
+///
+/// The defaults assume that the archive never changes. Callers that may swap the backing zip, e.g.
+/// when an APK is updated, should use shorter timeouts so that the kernel caches are invalidated.
+/// zipfuse then reloads the archive when it notices the change.
+#[derive(Clone, Copy, Debug)]
+pub struct CacheOptions {
+    /// Duration for which the attributes of files and directories are valid.
+    pub attr_timeout: Duration,
+    /// Duration for which name lookups are valid.
+    pub entry_timeout: Duration,
+    /// Whether the kernel keeps the contents of directories cached when they are opened again.
+    pub keep_dir_cache: bool,
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        Self { attr_timeout: timeout_max(), entry_timeout: timeout_max(), keep_dir_cache: true }
+    }
+}
+
+impl CacheOptions {
+    /// Whether the kernel caches expire, so that a change to the archive can be served.
+    fn expires(&self) -> bool {
+        self.attr_timeout != timeout_max()
+            || self.entry_timeout != timeout_max()
+            || !self.keep_dir_cache
+    }
+}
+
+/// How `run_fuse` mounts and serves a zip archive.
+#[derive(Clone, Debug, Default)]
+pub struct RunOptions {
+    /// Comma separated list of additional mount options.
+    pub extra_options: Option<String>,
+    /// Whether the execution of binary files is disallowed.
+    pub noexec: bool,
+    /// Property set to "1" once the filesystem is mounted.
+    pub ready_prop: Option<String>,
+    /// Owner of the files.
+    pub uid: u32,
+    /// Group of the files.
+    pub gid: u32,
+    /// Whether the mount fails when the archive uses zip features that zipfuse can't serve.
+    pub strict: bool,
+    /// How long the kernel may cache what zipfuse returns.
+    pub cache: CacheOptions,
+    /// File from which the inode table is loaded when it matches the archive, and to which it is
+    /// saved otherwise.
+    pub inode_cache: Option<PathBuf>,
+    /// File listing the digests that entries are checked against when they are opened.
+    pub verify_digests: Option<PathBuf>,
+    /// Bound on the memory used by the content of compressed files.
+    pub max_inflate_bytes: Option<u64>,
+}
+
 /// Runs a fuse filesystem by mounting `zip_file` on `mount_point`.
-pub fn run_fuse(
-    zip_file: &Path,
-    mount_point: &Path,
-    extra_options: Option<&String>,
-    noexec: bool,
-    ready_prop: Option<&String>,
-    uid: u32,
-    gid: u32,
-) -> Result<()> {
+pub fn run_fuse(zip_file: &Path, mount_point: &Path, options: &RunOptions) -> Result<()> {
     const MAX_READ: u32 = 1 << 20; // TODO(jiyong): tune this
     const MAX_WRITE: u32 = 1 << 13; // This is a read-only filesystem
 
+    // Open the archive before mounting so that unsupported archives are rejected up front.
+    let mut zipfuse = ZipFuse::new(
+        zip_file,
+        options.uid,
+        options.gid,
+        options.strict,
+        options.inode_cache.as_deref(),
+    )?
+    .with_cache_options(options.cache);
+    if let Some(digests_file) = &options.verify_digests {
+        zipfuse = zipfuse.with_digests(digests_file)?;
+    }
+    if let Some(max_inflate_bytes) = options.max_inflate_bytes {
+        zipfuse = zipfuse.with_inflate_budget(max_inflate_bytes);
+    }
     let dev_fuse = OpenOptions::new().read(true).write(true).open("/dev/fuse")?;
 
     let mut mount_options = vec![
@@ -103,53 +228,327 @@ pub fn run_fuse(
         MountOption::GroupId(0),
         MountOption::MaxRead(MAX_READ),
     ];
-    if let Some(value) = extra_options {
+    if let Some(value) = &options.extra_options {
         mount_options.push(MountOption::Extra(value));
     }
 
     let mut mount_flags = libc::MS_NOSUID | libc::MS_NODEV | libc::MS_RDONLY;
-    if noexec {
+    if options.noexec {
         mount_flags |= libc::MS_NOEXEC;
     }
 
     fuse::mount(mount_point, "zipfuse", mount_flags, &mount_options)?;
 
-    if let Some(property_name) = ready_prop {
+    if let Some(property_name) = &options.ready_prop {
         system_properties::write(property_name, "1").context("Failed to set readyprop")?;
     }
 
     let mut config = fuse::FuseConfig::new();
     config.dev_fuse(dev_fuse).max_write(MAX_WRITE).max_read(MAX_READ);
-    Ok(config.enter_message_loop(ZipFuse::new(zip_file, uid, gid)?)?)
+    if max_inflate_bytes.is_some() {
+        // Opens waiting for the inflate budget must not block the releases that free it.
+        config.num_threads(INFLATE_BUDGET_THREADS);
+    }
+    Ok(config.enter_message_loop(zipfuse)?)
 }
 
 struct ZipFuse {
-    zip_archive: Mutex<zip::ZipArchive<File>>,
-    raw_file: Mutex<File>,
-    inode_table: InodeTable,
+    zip_file: PathBuf,
+    strict: bool,
+    inode_cache: Option<PathBuf>,
+    digests_file: Option<PathBuf>,
+    /// The archive being served. It is replaced when `zip_file` changes, if the kernel caches
+    /// expire.
+    archive: RwLock<Arc<Archive>>,
+    /// Identity of the last version of `zip_file` that failed to load, so that it isn't retried
+    /// on each lookup.
+    rejected: Mutex<Option<FileIdentity>>,
     open_files: Mutex<HashMap<Handle, OpenFile>>,
-    open_dirs: Mutex<HashMap<Handle, OpenDirBuf>>,
+    open_dirs: Mutex<HashMap<Handle, OpenDir>>,
     uid: u32,
     gid: u32,
+    cache: CacheOptions,
+    inflate_budget: Option<Arc<InflateBudget>>,
+}
+
+/// A zip archive opened by zipfuse, with what is derived from its content.
+struct Archive {
+    zip_archive: Mutex<zip::ZipArchive<File>>,
+    raw_file: Mutex<File>,
+    inode_table: InodeTable,
+    digests: Option<DigestVerifier>,
+    identity: FileIdentity,
+}
+
+/// Identifies a version of a file, cheaply enough to be checked on each lookup.
+#[derive(Debug, PartialEq, Eq)]
+struct FileIdentity {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl FileIdentity {
+    fn new(metadata: &std::fs::Metadata) -> Self {
+        Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.size(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        }
+    }
+}
+
+/// Expected SHA-256 digests of the content of some entries, checked when they are first loaded.
+struct DigestVerifier {
+    expected: HashMap<Inode, [u8; SHA256_DIGEST_SIZE]>,
+    /// Whether the content of the entries that were loaded so far matched their digest.
+    verdicts: Mutex<HashMap<Inode, bool>>,
+}
+
+const SHA256_DIGEST_SIZE: usize = 32;
+
+impl DigestVerifier {
+    /// Loads the digests from `digests_file`, where each line holds the hex-encoded digest of an
+    /// entry followed by its path, as printed by `sha256sum`.
+    fn load(digests_file: &Path, inode_table: &InodeTable) -> Result<Self> {
+        let content = std::fs::read_to_string(digests_file)?;
+        let mut expected = HashMap::new();
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let (digest, path) = line
+                .split_once(char::is_whitespace)
+                .with_context(|| format!("Malformed line: {line:?}"))?;
+            // sha256sum marks files hashed in binary mode with a '*'.
+            let path = path.trim_start();
+            let path = path.strip_prefix('*').unwrap_or(path);
+            let mut bytes = [0; SHA256_DIGEST_SIZE];
+            hex::decode_to_slice(digest, &mut bytes)
+                .with_context(|| format!("Invalid digest for {path}"))?;
+            let inode = inode_table
+                .find_path(Path::new(path))
+                .filter(|inode| inode_table.get(*inode).is_some_and(|i| !i.is_dir()))
+                .with_context(|| format!("No file {path} in the archive"))?;
+            expected.insert(inode, bytes);
+        }
+        Ok(Self { expected, verdicts: Mutex::new(HashMap::new()) })
+    }
+
+    /// Returns whether the content of `inode` has yet to be checked against an expected digest.
+    /// Fails with EIO if it was already found not to match.
+    fn is_pending(&self, inode: Inode) -> io::Result<bool> {
+        if !self.expected.contains_key(&inode) {
+            return Ok(false);
+        }
+        match self.verdicts.lock().unwrap().get(&inode) {
+            Some(true) => Ok(false),
+            Some(false) => Err(io::Error::from_raw_os_error(libc::EIO)),
+            None => Ok(true),
+        }
+    }
+
+    /// Checks `digest`, computed over the content of `inode`, against the expected digest of
+    /// `inode`, and records the verdict. Fails with EIO if it doesn't match.
+    fn check(&self, inode: Inode, digest: [u8; SHA256_DIGEST_SIZE]) -> io::Result<()> {
+        let matches = self.expected.get(&inode) == Some(&digest);
+        self.verdicts.lock().unwrap().insert(inode, matches);
+        if matches {
+            Ok(())
+        } else {
+            log::error!("Digest of inode {inode} doesn't match");
+            Err(io::Error::from_raw_os_error(libc::EIO))
+        }
+    }
+}
+
+/// Feeds what is read from `reader` to a SHA-256 hasher, if any.
+struct Sha256Reader<R> {
+    reader: R,
+    hasher: Option<openssl::sha::Sha256>,
+}
+
+impl<R: Read> Read for Sha256Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.reader.read(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..len]);
+        }
+        Ok(len)
+    }
 }
 
 /// Represents a [`ZipFile`] that is opened.
 struct OpenFile {
     open_count: u32, // multiple opens share the buf because this is a read-only filesystem
     content: OpenFileContent,
+    /// Memory charged to the inflate budget for `content`, given back when the file is released.
+    _reservation: Option<InflateReservation>,
+}
+
+/// How long decompressing a file waits for the inflate budget before failing with ENOMEM.
+const INFLATE_BUDGET_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of threads serving FUSE requests when the inflate budget is enabled.
+const INFLATE_BUDGET_THREADS: usize = 4;
+
+/// Bounds the memory held by the decompressed content of the open files, so that archives with
+/// many large compressed entries can't exhaust the memory of the VM.
+struct InflateBudget {
+    limit: u64,
+    timeout: Duration,
+    used: Mutex<u64>,
+    freed: Condvar,
+}
+
+impl InflateBudget {
+    fn new(limit: u64, timeout: Duration) -> Self {
+        Self { limit, timeout, used: Mutex::new(0), freed: Condvar::new() }
+    }
+
+    /// Starts an empty reservation, to be resized as the memory is actually used.
+    fn reservation(self: &Arc<Self>) -> InflateReservation {
+        InflateReservation { budget: self.clone(), size: 0 }
+    }
+}
+
+/// Memory reserved from an [`InflateBudget`], until dropped.
+struct InflateReservation {
+    budget: Arc<InflateBudget>,
+    size: u64,
+}
+
+impl InflateReservation {
+    /// Resizes the reservation to `size` bytes, waiting for other reservations to be given back
+    /// if that exceeds what is left of the budget. Fails with ENOMEM, leaving the reservation as
+    /// is, if `size` exceeds the whole budget or not enough is given back before the timeout.
+    fn resize(&mut self, size: u64) -> io::Result<()> {
+        let enomem = || io::Error::from_raw_os_error(libc::ENOMEM);
+        let budget = &self.budget;
+        if size > budget.limit {
+            return Err(enomem());
+        }
+        let used = budget.used.lock().unwrap();
+        let exceeds = |used: &mut u64| size > budget.limit - (*used - self.size);
+        let (mut used, result) =
+            budget.freed.wait_timeout_while(used, budget.timeout, exceeds).unwrap();
+        if result.timed_out() {
+            return Err(enomem());
+        }
+        *used = *used - self.size + size;
+        if size < self.size {
+            budget.freed.notify_all();
+        }
+        self.size = size;
+        Ok(())
+    }
+}
+
+impl Drop for InflateReservation {
+    fn drop(&mut self) {
+        *self.budget.used.lock().unwrap() -= self.size;
+        self.budget.freed.notify_all();
+    }
 }
 
 /// Holds the content of a [`ZipFile`]. Depending on whether it is compressed or not, the
-/// entire content is stored, or only the zip index is stored.
+/// entire content is stored, or only the zip index is stored. Compressed files having zero-filled
+/// chunks are stored sparsely.
 enum OpenFileContent {
     Compressed(Box<[u8]>),
+    Sparse(SparseContent),
     Uncompressed(usize), // zip index
 }
 
-/// Holds the directory entries in a directory opened by [`opendir`].
-struct OpenDirBuf {
+/// Granularity at which zero-filled regions of a compressed file are detected and dropped.
+const SPARSE_CHUNK_SIZE: usize = 4096;
+
+/// Decompressed content of a file where only the chunks that are not entirely zero are kept.
+struct SparseContent {
+    size: u64,
+    /// For each `SPARSE_CHUNK_SIZE` chunk of the file, the offset of its content in `data`, or
+    /// `None` if the chunk is zero-filled.
+    chunks: Box<[Option<usize>]>,
+    data: Box<[u8]>,
+}
+
+impl SparseContent {
+    /// Writes up to `size` bytes of the content starting at `offset` to `w`.
+    fn write_to<W: io::Write>(&self, w: &mut W, size: u64, offset: u64) -> io::Result<usize> {
+        const ZEROS: [u8; SPARSE_CHUNK_SIZE] = [0; SPARSE_CHUNK_SIZE];
+        let end = std::cmp::min(self.size, offset.saturating_add(size));
+        let mut pos = offset;
+        let mut written = 0;
+        while pos < end {
+            let chunk = (pos / SPARSE_CHUNK_SIZE as u64) as usize;
+            let in_chunk = (pos % SPARSE_CHUNK_SIZE as u64) as usize;
+            let len = std::cmp::min(SPARSE_CHUNK_SIZE - in_chunk, (end - pos) as usize);
+            let buf = match self.chunks[chunk] {
+                Some(start) => &self.data[start + in_chunk..start + in_chunk + len],
+                None => &ZEROS[..len],
+            };
+            w.write_all(buf)?;
+            written += len;
+            pos += len as u64;
+        }
+        Ok(written)
+    }
+}
+
+/// Decompresses `size` bytes from `reader`. If `sparse` is set, the chunks that are entirely zero
+/// are dropped. If there are no such chunks, the content is returned as is. The memory holding the
+/// content is charged to `reservation` as it is filled, and the content must end after `size`
+/// bytes.
+fn decompress(
+    reader: &mut impl Read,
+    size: u64,
+    sparse: bool,
+    mut reservation: Option<&mut InflateReservation>,
+) -> io::Result<OpenFileContent> {
+    // The declared size isn't trusted to allocate anything upfront.
+    let mut chunks = Vec::new();
+    let mut data = Vec::new();
+    let mut remaining = size;
+    while remaining > 0 {
+        let len = std::cmp::min(remaining, SPARSE_CHUNK_SIZE as u64) as usize;
+        let start = data.len();
+        if let Some(reservation) = reservation.as_mut() {
+            reservation.resize((start + len) as u64)?;
+        }
+        data.resize(start + len, 0);
+        reader.read_exact(&mut data[start..])?;
+        if sparse && data[start..].iter().all(|b| *b == 0) {
+            data.truncate(start);
+            chunks.push(None);
+        } else {
+            chunks.push(Some(start));
+        }
+        remaining -= len as u64;
+    }
+    // Reaching the end of a zip entry also checks its CRC32.
+    if reader.read(&mut [0])? != 0 {
+        return Err(io::Error::from_raw_os_error(libc::EIO));
+    }
+    if let Some(reservation) = reservation {
+        reservation.resize(data.len() as u64)?;
+    }
+    if chunks.iter().all(Option::is_some) {
+        Ok(OpenFileContent::Compressed(data.into_boxed_slice()))
+    } else {
+        data.shrink_to_fit();
+        Ok(OpenFileContent::Sparse(SparseContent {
+            size,
+            chunks: chunks.into_boxed_slice(),
+            data: data.into_boxed_slice(),
+        }))
+    }
+}
+
+/// Tracks a directory opened by [`opendir`]. The entries are read from the inode table, whose
+/// order is stable, so offsets returned by `readdir` remain valid across `opendir` calls.
+struct OpenDir {
     open_count: u32,
-    buf: Box<[(CString, DirectoryEntry)]>,
 }
 
 type Handle = u64;
@@ -158,40 +557,392 @@ fn ebadf() -> io::Error {
     io::Error::from_raw_os_error(libc::EBADF)
 }
 
-fn timeout_max() -> std::time::Duration {
-    std::time::Duration::new(u64::MAX, 1_000_000_000 - 1)
+fn timeout_max() -> Duration {
+    Duration::new(u64::MAX, 1_000_000_000 - 1)
 }
 
-impl ZipFuse {
-    fn new(zip_file: &Path, uid: u32, gid: u32) -> Result<ZipFuse> {
+fn secs(secs: &u64) -> Duration {
+    Duration::from_secs(*secs)
+}
+
+/// Bits of the general purpose flags of a local file header.
+const FLAG_ENCRYPTED: u16 = 1 << 0;
+const FLAG_PATCHED_DATA: u16 = 1 << 5;
+const FLAG_STRONG_ENCRYPTION: u16 = 1 << 6;
+
+/// Offset of the general purpose flags in a local file header.
+const LOCAL_HEADER_FLAGS_OFFSET: u64 = 6;
+
+/// Checks that none of the entries of `archive` use zip features that zipfuse doesn't support.
+/// Multi-disk archives are already rejected when `archive` is opened.
+fn check_supported_features(archive: &mut zip::ZipArchive<File>, raw_file: &File) -> Result<()> {
+    for i in 0..archive.len() {
+        // Raw access doesn't fail on encrypted entries, so that they can be reported here.
+        let file = archive.by_index_raw(i)?;
+        let name = file.name();
+        let mut flags = [0u8; 2];
+        raw_file
+            .read_exact_at(&mut flags, file.header_start() + LOCAL_HEADER_FLAGS_OFFSET)
+            .with_context(|| format!("Failed to read the local header of {name}"))?;
+        let flags = u16::from_le_bytes(flags);
+        if flags & (FLAG_ENCRYPTED | FLAG_STRONG_ENCRYPTION) != 0 {
+            bail!("{name} is encrypted");
+        }
+        if flags & FLAG_PATCHED_DATA != 0 {
+            bail!("{name} is stored as patched data");
+        }
+        match file.compression() {
+            zip::CompressionMethod::Stored | zip::CompressionMethod::Deflated => {}
+            method => bail!("{name} uses unsupported compression method {method}"),
+        }
+    }
+    Ok(())
+}
+
+/// Header of inode cache files, followed by a `ZipFingerprint` and the serialized `InodeTable`.
+const INODE_CACHE_MAGIC: &[u8; 8] = b"ZFINODE1";
+
+/// Identifies the content of a zip archive, to tell whether a cached inode table was built from it.
+#[derive(Debug, PartialEq, Eq)]
+struct ZipFingerprint {
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+    /// CRC32 of everything from the start of the central directory to the end of the archive.
+    digest: u32,
+}
+
+impl ZipFingerprint {
+    const SIZE: usize = 28;
+
+    fn new(zip: &File) -> Result<Self> {
+        let metadata = zip.metadata()?;
+        // The archive may be a block device, whose metadata doesn't have the size.
+        let size = (&*zip).seek(io::SeekFrom::End(0))?;
+        let digest = central_directory_digest(zip, size)?;
+        Ok(Self { size, mtime: metadata.mtime(), mtime_nsec: metadata.mtime_nsec(), digest })
+    }
+
+    fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..8].copy_from_slice(&self.size.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.mtime.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.mtime_nsec.to_le_bytes());
+        bytes[24..].copy_from_slice(&self.digest.to_le_bytes());
+        bytes
+    }
+}
+
+/// Offset of the central directory offset in the end of central directory record.
+const EOCD_CD_OFFSET_OFFSET: usize = 16;
+/// Size of the end of central directory record, excluding the trailing comment.
+const EOCD_SIZE: usize = 22;
+const EOCD_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x05, 0x06];
+
+/// Computes the CRC32 of the central directory of the zip archive `zip`, of `size` bytes, and of
+/// the records following it. This covers the names, attributes, offsets, and CRC32 of all entries.
+fn central_directory_digest(zip: &File, size: u64) -> Result<u32> {
+    // The end of central directory record is followed by a comment of at most 64KiB.
+    let tail_len = std::cmp::min(size, (EOCD_SIZE + usize::from(u16::MAX)) as u64);
+    let tail_start = size - tail_len;
+    let mut tail = vec![0; tail_len as usize];
+    zip.read_exact_at(&mut tail, tail_start)?;
+    let eocd = (0..tail.len().saturating_sub(EOCD_SIZE - 1))
+        .rev()
+        .find(|i| tail[*i..].starts_with(&EOCD_SIGNATURE))
+        .context("Missing end of central directory record")?;
+    let offset = &tail[eocd + EOCD_CD_OFFSET_OFFSET..eocd + EOCD_CD_OFFSET_OFFSET + 4];
+    let offset = u32::from_le_bytes(offset.try_into().unwrap());
+    if offset == u32::MAX {
+        bail!("Zip64 archives are not supported");
+    }
+
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0; 1 << 16];
+    let mut pos = u64::from(offset);
+    while pos < size {
+        let len = std::cmp::min(buf.len() as u64, size - pos) as usize;
+        zip.read_exact_at(&mut buf[..len], pos)?;
+        hasher.update(&buf[..len]);
+        pos += len as u64;
+    }
+    Ok(hasher.finalize())
+}
+
+/// Loads the inode table of `archive` from `inode_cache` if it was built from the same archive.
+/// Otherwise, builds the table and tries to save it to `inode_cache` for subsequent mounts.
+fn load_or_build_inode_table(
+    inode_cache: &Path,
+    raw_file: &File,
+    archive: &mut zip::ZipArchive<File>,
+) -> Result<InodeTable> {
+    let fingerprint = match ZipFingerprint::new(raw_file) {
+        Ok(fingerprint) => fingerprint,
+        Err(e) => {
+            log::warn!("Can't fingerprint the archive, ignoring the inode cache: {e:?}");
+            return InodeTable::from_zip(archive);
+        }
+    };
+    match load_inode_table(inode_cache, &fingerprint, archive.len()) {
+        Ok(table) => return Ok(table),
+        Err(e) => log::info!("Not using the inode cache {}: {e:?}", inode_cache.display()),
+    }
+    let table = InodeTable::from_zip(archive)?;
+    if let Err(e) = save_inode_table(inode_cache, &fingerprint, &table) {
+        log::warn!("Failed to save the inode cache {}: {e:?}", inode_cache.display());
+    }
+    Ok(table)
+}
+
+fn load_inode_table(
+    inode_cache: &Path,
+    fingerprint: &ZipFingerprint,
+    num_zip_entries: usize,
+) -> Result<InodeTable> {
+    let mut r = io::BufReader::new(File::open(inode_cache)?);
+    let mut header = [0; INODE_CACHE_MAGIC.len() + ZipFingerprint::SIZE];
+    r.read_exact(&mut header)?;
+    let (magic, cached_fingerprint) = header.split_at(INODE_CACHE_MAGIC.len());
+    if magic != INODE_CACHE_MAGIC {
+        bail!("Unknown format");
+    }
+    if cached_fingerprint != fingerprint.to_bytes() {
+        bail!("Built from a different archive");
+    }
+    let table = InodeTable::deserialize(&mut r, num_zip_entries)?;
+    if r.read(&mut [0])? != 0 {
+        bail!("Trailing data");
+    }
+    Ok(table)
+}
+
+fn save_inode_table(
+    inode_cache: &Path,
+    fingerprint: &ZipFingerprint,
+    table: &InodeTable,
+) -> Result<()> {
+    // Write to a temporary file first, so that concurrent mounts never see a partial cache.
+    let mut tmp_path = inode_cache.as_os_str().to_owned();
+    tmp_path.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = PathBuf::from(tmp_path);
+    let tmp_file = File::create(&tmp_path)?;
+    let tmp_path = scopeguard::guard(tmp_path, |path| {
+        let _ = std::fs::remove_file(path);
+    });
+    let mut w = io::BufWriter::new(tmp_file);
+    w.write_all(INODE_CACHE_MAGIC)?;
+    w.write_all(&fingerprint.to_bytes())?;
+    table.serialize(&mut w)?;
+    w.into_inner()?.sync_all()?;
+    std::fs::rename(&*tmp_path, inode_cache)?;
+    // The temporary file doesn't exist anymore.
+    scopeguard::ScopeGuard::into_inner(tmp_path);
+    Ok(())
+}
+
+impl Archive {
+    fn open(
+        zip_file: &Path,
+        strict: bool,
+        inode_cache: Option<&Path>,
+        digests_file: Option<&Path>,
+    ) -> Result<Archive> {
         // TODO(jiyong): Use O_DIRECT to avoid double caching.
         // `.custom_flags(nix::fcntl::OFlag::O_DIRECT.bits())` currently doesn't work.
         let f = File::open(zip_file)?;
+        let identity = FileIdentity::new(&f.metadata()?);
         let mut z = zip::ZipArchive::new(f)?;
         // Open the same file again so that we can directly access it when accessing
         // uncompressed zip_file entries in it. `ZipFile` doesn't implement `Seek`.
         let raw_file = File::open(zip_file)?;
-        let it = InodeTable::from_zip(&mut z)?;
-        Ok(ZipFuse {
+        if strict {
+            check_supported_features(&mut z, &raw_file)
+                .with_context(|| format!("{} uses unsupported zip features", zip_file.display()))?;
+        }
+        let it = match inode_cache {
+            Some(inode_cache) => load_or_build_inode_table(inode_cache, &raw_file, &mut z)?,
+            None => InodeTable::from_zip(&mut z)?,
+        };
+        let mut archive = Archive {
             zip_archive: Mutex::new(z),
             raw_file: Mutex::new(raw_file),
             inode_table: it,
+            digests: None,
+            identity,
+        };
+        if let Some(digests_file) = digests_file {
+            archive.load_digests(digests_file)?;
+        }
+        Ok(archive)
+    }
+
+    fn load_digests(&mut self, digests_file: &Path) -> Result<()> {
+        let digests = DigestVerifier::load(digests_file, &self.inode_table)
+            .with_context(|| format!("Failed to load digests from {}", digests_file.display()))?;
+        self.digests = Some(digests);
+        Ok(())
+    }
+
+    fn find_inode(&self, inode: Inode) -> io::Result<&InodeData> {
+        self.inode_table.get(inode).ok_or_else(ebadf)
+    }
+}
+
+impl ZipFuse {
+    fn new(
+        zip_file: &Path,
+        uid: u32,
+        gid: u32,
+        strict: bool,
+        inode_cache: Option<&Path>,
+    ) -> Result<ZipFuse> {
+        let archive = Archive::open(zip_file, strict, inode_cache, None)?;
+        Ok(ZipFuse {
+            zip_file: zip_file.to_owned(),
+            strict,
+            inode_cache: inode_cache.map(Path::to_owned),
+            digests_file: None,
+            archive: RwLock::new(Arc::new(archive)),
+            rejected: Mutex::new(None),
             open_files: Mutex::new(HashMap::new()),
             open_dirs: Mutex::new(HashMap::new()),
             uid,
             gid,
+            cache: CacheOptions::default(),
+            inflate_budget: None,
         })
     }
 
-    fn find_inode(&self, inode: Inode) -> io::Result<&InodeData> {
-        self.inode_table.get(inode).ok_or_else(ebadf)
+    fn with_inflate_budget(mut self, max_inflate_bytes: u64) -> Self {
+        self.inflate_budget =
+            Some(Arc::new(InflateBudget::new(max_inflate_bytes, INFLATE_BUDGET_TIMEOUT)));
+        self
+    }
+
+    fn with_cache_options(mut self, cache: CacheOptions) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    fn with_digests(mut self, digests_file: &Path) -> Result<Self> {
+        // The archive isn't shared until the filesystem is served.
+        Arc::get_mut(self.archive.get_mut().unwrap()).unwrap().load_digests(digests_file)?;
+        self.digests_file = Some(digests_file.to_owned());
+        Ok(self)
+    }
+
+    /// Returns the archive being served.
+    fn current_archive(&self) -> Arc<Archive> {
+        self.archive.read().unwrap().clone()
+    }
+
+    /// Returns the archive being served, after reloading it if the kernel caches expire and the
+    /// backing file has changed. The archive is only reloaded once no file or directory of the
+    /// previous one is open, as they are identified by inode numbers that may change meaning.
+    fn archive(&self) -> Arc<Archive> {
+        let archive = self.current_archive();
+        if !self.cache.expires() {
+            return archive;
+        }
+        let identity = match std::fs::metadata(&self.zip_file) {
+            Ok(metadata) => FileIdentity::new(&metadata),
+            Err(e) => {
+                log::warn!("Failed to check {} for changes: {e}", self.zip_file.display());
+                return archive;
+            }
+        };
+        let mut rejected = self.rejected.lock().unwrap();
+        if identity == archive.identity || rejected.as_ref() == Some(&identity) {
+            return archive;
+        }
+        // Holding the locks prevents files and directories from being opened during the reload.
+        let open_files = self.open_files.lock().unwrap();
+        let open_dirs = self.open_dirs.lock().unwrap();
+        if !open_files.is_empty() || !open_dirs.is_empty() {
+            return archive;
+        }
+        let reloaded = Archive::open(
+            &self.zip_file,
+            self.strict,
+            self.inode_cache.as_deref(),
+            self.digests_file.as_deref(),
+        );
+        match reloaded {
+            // The archive may have changed again while being opened, which is noticed next time.
+            Ok(reloaded) => {
+                log::info!("Reloaded {}", self.zip_file.display());
+                let reloaded = Arc::new(reloaded);
+                *self.archive.write().unwrap() = reloaded.clone();
+                reloaded
+            }
+            Err(e) => {
+                log::error!("Failed to reload {}: {e:?}", self.zip_file.display());
+                *rejected = Some(identity);
+                archive
+            }
+        }
+    }
+
+    /// Loads the content of the file `inode` of `archive`. The first time it is loaded, it is
+    /// checked against its expected digest if any. The memory holding it is charged to the
+    /// inflate budget, if any.
+    fn load_content(
+        &self,
+        archive: &Archive,
+        inode: Inode,
+        inode_data: &InodeData,
+    ) -> io::Result<(OpenFileContent, Option<InflateReservation>)> {
+        let zip_index = inode_data.get_zip_index().ok_or_else(ebadf)?;
+        let digests = archive.digests.as_ref();
+        let check_digest = match digests {
+            Some(digests) => digests.is_pending(inode)?,
+            None => false,
+        };
+        let mut zip_archive = archive.zip_archive.lock().unwrap();
+        let mut zip_file = zip_archive.by_index(zip_index)?;
+        let is_compressed = zip_file.compression() != zip::CompressionMethod::Stored;
+        if let Some(mode) = zip_file.unix_mode().filter(|_| is_compressed) {
+            let is_reg_file = zip_file.is_file();
+            let is_executable = mode & (libc::S_IXUSR | libc::S_IXGRP | libc::S_IXOTH) != 0;
+            if is_reg_file && is_executable {
+                log::warn!(
+                    "Executable file {:?} is stored compressed. Consider \
+                    storing it uncompressed to save memory",
+                    zip_file.mangled_name()
+                );
+            }
+        }
+        // The digest is computed as the content is read, which also checks its CRC32.
+        let hasher = check_digest.then(openssl::sha::Sha256::new);
+        let mut reader = Sha256Reader { reader: &mut zip_file, hasher };
+        let (content, reservation) = if is_compressed {
+            // The size declared by the archive isn't trusted to be that of the content, so the
+            // memory is charged as it is filled rather than upfront.
+            let mut reservation = self.inflate_budget.as_ref().map(InflateBudget::reservation);
+            let content = decompress(
+                &mut reader,
+                inode_data.size,
+                inode_data.is_sparse(),
+                reservation.as_mut(),
+            )?;
+            (content, reservation)
+        } else {
+            if check_digest {
+                io::copy(&mut reader, &mut io::sink())?;
+            }
+            (OpenFileContent::Uncompressed(zip_index), None)
+        };
+        if let (Some(digests), Some(hasher)) = (digests, reader.hasher) {
+            digests.check(inode, hasher.finish())?;
+        }
+        Ok((content, reservation))
     }
 
     // TODO(jiyong) remove this. Right now this is needed to do the nlink_t to u64 conversion below
     // on aosp_x86_64 target. That however is a useless conversion on other targets.
     #[allow(clippy::useless_conversion)]
-    fn stat_from(&self, inode: Inode) -> io::Result<libc::stat64> {
-        let inode_data = self.find_inode(inode)?;
+    fn stat_from(&self, archive: &Archive, inode: Inode) -> io::Result<libc::stat64> {
+        let inode_data = archive.find_inode(inode)?;
         // SAFETY: All fields of stat64 are valid for zero byte patterns.
         let mut st = unsafe { MaybeUninit::<libc::stat64>::zeroed().assume_init() };
         st.st_dev = 0;
@@ -206,6 +957,9 @@ impl ZipFuse {
         st.st_uid = self.uid;
         st.st_gid = self.gid;
         st.st_size = i64::try_from(inode_data.size).unwrap_or(i64::MAX);
+        // Report zero-filled regions as holes, like a sparse file on a regular filesystem would.
+        st.st_blksize = SPARSE_CHUNK_SIZE as _;
+        st.st_blocks = inode_data.allocated_size().div_ceil(512) as _;
         Ok(st)
     }
 }
@@ -221,16 +975,17 @@ impl fuse::filesystem::FileSystem for ZipFuse {
     }
 
     fn lookup(&self, _ctx: Context, parent: Self::Inode, name: &CStr) -> io::Result<Entry> {
-        let inode = self.find_inode(parent)?;
+        let archive = self.archive();
+        let inode = archive.find_inode(parent)?;
         let directory = inode.get_directory().ok_or_else(ebadf)?;
         let entry = directory.get(name);
         match entry {
             Some(e) => Ok(Entry {
                 inode: e.inode,
                 generation: 0,
-                attr: self.stat_from(e.inode)?,
-                attr_timeout: timeout_max(), // this is a read-only fs
-                entry_timeout: timeout_max(),
+                attr: self.stat_from(&archive, e.inode)?,
+                attr_timeout: self.cache.attr_timeout,
+                entry_timeout: self.cache.entry_timeout,
             }),
             _ => Err(io::Error::from_raw_os_error(libc::ENOENT)),
         }
@@ -242,8 +997,8 @@ impl fuse::filesystem::FileSystem for ZipFuse {
         inode: Self::Inode,
         _handle: Option<Self::Handle>,
     ) -> io::Result<(libc::stat64, std::time::Duration)> {
-        let st = self.stat_from(inode)?;
-        Ok((st, timeout_max()))
+        let st = self.stat_from(&self.archive(), inode)?;
+        Ok((st, self.cache.attr_timeout))
     }
 
     fn open(
@@ -264,31 +1019,18 @@ impl fuse::filesystem::FileSystem for ZipFuse {
             }
             file.open_count += 1;
         } else {
-            let inode_data = self.find_inode(inode)?;
-            let zip_index = inode_data.get_zip_index().ok_or_else(ebadf)?;
-            let mut zip_archive = self.zip_archive.lock().unwrap();
-            let mut zip_file = zip_archive.by_index(zip_index)?;
-            let content = match zip_file.compression() {
-                zip::CompressionMethod::Stored => OpenFileContent::Uncompressed(zip_index),
-                _ => {
-                    if let Some(mode) = zip_file.unix_mode() {
-                        let is_reg_file = zip_file.is_file();
-                        let is_executable =
-                            mode & (libc::S_IXUSR | libc::S_IXGRP | libc::S_IXOTH) != 0;
-                        if is_reg_file && is_executable {
-                            log::warn!(
-                                "Executable file {:?} is stored compressed. Consider \
-                                storing it uncompressed to save memory",
-                                zip_file.mangled_name()
-                            );
-                        }
-                    }
-                    let mut buf = Vec::with_capacity(inode_data.size as usize);
-                    zip_file.read_to_end(&mut buf)?;
-                    OpenFileContent::Compressed(buf.into_boxed_slice())
-                }
-            };
-            open_files.insert(handle, OpenFile { open_count: 1, content });
+            drop(open_files);
+            let archive = self.current_archive();
+            let inode_data = archive.find_inode(inode)?;
+            let (content, reservation) = self.load_content(&archive, inode, inode_data)?;
+            let mut open_files = self.open_files.lock().unwrap();
+            // The file may have been opened by another thread in the meantime.
+            let file = open_files.entry(handle).or_insert(OpenFile {
+                open_count: 0,
+                content,
+                _reservation: reservation,
+            });
+            file.open_count += 1;
         }
         // Note: we don't return `DIRECT_IO` here, because then applications wouldn't be able to
         // mmap the files.
@@ -338,13 +1080,15 @@ impl fuse::filesystem::FileSystem for ZipFuse {
         }
         Ok(match &file.content {
             OpenFileContent::Uncompressed(zip_index) => {
-                let mut zip_archive = self.zip_archive.lock().unwrap();
+                // The archive isn't reloaded while files are open.
+                let archive = self.current_archive();
+                let mut zip_archive = archive.zip_archive.lock().unwrap();
                 let zip_file = zip_archive.by_index(*zip_index)?;
                 let start = zip_file.data_start() + offset;
                 let remaining_size = zip_file.size() - offset;
                 let size = std::cmp::min(remaining_size, size.into());
 
-                let mut raw_file = self.raw_file.lock().unwrap();
+                let mut raw_file = archive.raw_file.lock().unwrap();
                 w.write_from(&mut raw_file, size as usize, start)?
             }
             OpenFileContent::Compressed(buf) => {
@@ -353,6 +1097,7 @@ impl fuse::filesystem::FileSystem for ZipFuse {
                 let end = std::cmp::min(end, buf.len());
                 w.write(&buf[start..end])?
             }
+            OpenFileContent::Sparse(content) => content.write_to(&mut w, size.into(), offset)?,
         })
     }
 
@@ -370,16 +1115,15 @@ impl fuse::filesystem::FileSystem for ZipFuse {
             }
             odb.open_count += 1;
         } else {
-            let inode_data = self.find_inode(inode)?;
-            let directory = inode_data.get_directory().ok_or_else(ebadf)?;
-            let mut buf: Vec<(CString, DirectoryEntry)> = Vec::with_capacity(directory.len());
-            for (name, dir_entry) in directory.iter() {
-                let name = CString::new(name.as_bytes()).unwrap();
-                buf.push((name, dir_entry.clone()));
-            }
-            open_dirs.insert(handle, OpenDirBuf { open_count: 1, buf: buf.into_boxed_slice() });
+            self.current_archive().find_inode(inode)?.get_directory().ok_or_else(ebadf)?;
+            open_dirs.insert(handle, OpenDir { open_count: 1 });
         }
-        Ok((Some(handle), fuse::filesystem::OpenOptions::CACHE_DIR))
+        let options = if self.cache.keep_dir_cache {
+            fuse::filesystem::OpenOptions::CACHE_DIR
+        } else {
+            fuse::filesystem::OpenOptions::empty()
+        };
+        Ok((Some(handle), options))
     }
 
     fn releasedir(
@@ -415,8 +1159,11 @@ impl fuse::filesystem::FileSystem for ZipFuse {
         if odb.open_count == 0 {
             return Err(ebadf());
         }
-        let buf = &odb.buf;
-        let start = offset as usize;
+        let archive = self.current_archive();
+        let buf = archive.find_inode(inode)?.get_directory().ok_or_else(ebadf)?.entries();
+        // The offset of an entry is one past its index in the sorted entries of the directory, so
+        // an offset past the end (e.g. from a stale `telldir`) just yields no more entries.
+        let start = usize::try_from(offset).map_or(buf.len(), |offset| offset.min(buf.len()));
 
         // Estimate the size of each entry will take space in the buffer. See
         // external/crosvm/fuse/src/server.rs#add_dirent
@@ -471,29 +1218,23 @@ mod tests {
     use std::collections::BTreeSet;
     use std::fs;
     use std::io::Write;
+    use std::os::unix::ffi::OsStrExt;
     use std::os::unix::fs::MetadataExt;
     use std::path::{Path, PathBuf};
     use std::time::{Duration, Instant};
     use zip::write::FileOptions;
 
-    #[derive(Default)]
-    struct Options {
-        noexec: bool,
-        uid: u32,
-        gid: u32,
-    }
-
     #[cfg(not(target_os = "android"))]
-    fn start_fuse(zip_path: &Path, mnt_path: &Path, opt: Options) {
+    fn start_fuse(zip_path: &Path, mnt_path: &Path, opt: RunOptions) {
         let zip_path = PathBuf::from(zip_path);
         let mnt_path = PathBuf::from(mnt_path);
         std::thread::spawn(move || {
-            crate::run_fuse(&zip_path, &mnt_path, None, opt.noexec, opt.uid, opt.gid).unwrap();
+            crate::run_fuse(&zip_path, &mnt_path, &opt).unwrap();
         });
     }
 
     #[cfg(target_os = "android")]
-    fn start_fuse(zip_path: &Path, mnt_path: &Path, opt: Options) {
+    fn start_fuse(zip_path: &Path, mnt_path: &Path, opt: RunOptions) {
         // Note: for some unknown reason, running a thread to serve fuse doesn't work on Android.
         // Explicitly spawn a zipfuse process instead.
         // TODO(jiyong): fix this
@@ -537,7 +1278,7 @@ mod tests {
     }
 
     fn run_test_with_options(
-        opt: Options,
+        opt: RunOptions,
         add: fn(&mut zip::ZipWriter<File>),
         check: fn(&std::path::Path),
     ) {
@@ -654,7 +1395,7 @@ mod tests {
         });
 
         // Mounting with noexec results in permissions denial when running an executable.
-        let opt = Options { noexec: true, ..Default::default() };
+        let opt = RunOptions { noexec: true, ..Default::default() };
         run_test_with_options(opt, add_executable, |root| {
             let res = std::process::Command::new(root.join("executable")).status();
             assert!(matches!(res.unwrap_err().kind(), std::io::ErrorKind::PermissionDenied));
@@ -666,7 +1407,7 @@ mod tests {
         const UID: u32 = 100;
         const GID: u32 = 200;
         run_test_with_options(
-            Options { noexec: true, uid: UID, gid: GID },
+            RunOptions { noexec: true, uid: UID, gid: GID, ..Default::default() },
             |zip| {
                 zip.start_file("foo", FileOptions::default()).unwrap();
                 zip.write_all(b"0123456789").unwrap();
@@ -768,6 +1509,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn zero_filled_file() {
+        run_test(
+            |zip| {
+                zip.start_file("zeros", FileOptions::default()).unwrap();
+                zip.write_all(&[0; (1 << 20) + 3]).unwrap();
+            },
+            |root| {
+                check_file(root, "zeros", &[0; (1 << 20) + 3]);
+                // The content is entirely zero, so no blocks are allocated for it.
+                assert_eq!(0, fs::metadata(root.join("zeros")).unwrap().blocks());
+            },
+        );
+    }
+
     #[test]
     fn large_dir() {
         const NUM_FILES: usize = 1 << 10;
@@ -792,11 +1548,72 @@ mod tests {
         );
     }
 
+    // Reads the remaining entries of `dir`, along with the position of each one as reported by
+    // `telldir` right before reading it.
+    fn read_dir_stream(dir: *mut libc::DIR) -> Vec<(libc::c_long, String)> {
+        let mut entries = Vec::new();
+        loop {
+            // SAFETY: `dir` is a valid directory stream, and the entry returned by `readdir` is
+            // valid until the next call on the stream.
+            let (pos, name) = unsafe {
+                let pos = libc::telldir(dir);
+                let entry = libc::readdir(dir);
+                if entry.is_null() {
+                    break;
+                }
+                (pos, CStr::from_ptr((*entry).d_name.as_ptr()).to_str().unwrap().to_owned())
+            };
+            entries.push((pos, name));
+        }
+        entries
+    }
+
+    #[test]
+    fn stable_dir_offsets() {
+        run_test(
+            |zip| {
+                for name in ["c", "a", "e", "b", "d"] {
+                    zip.start_file(format!("dir/{name}"), FileOptions::default()).unwrap();
+                }
+            },
+            |root| {
+                let path = CString::new(root.join("dir").as_os_str().as_bytes()).unwrap();
+                // SAFETY: `path` is a valid C string.
+                let open = || unsafe { libc::opendir(path.as_ptr()) };
+
+                let dir = open();
+                assert!(!dir.is_null());
+                let entries = read_dir_stream(dir);
+                let names: Vec<_> = entries.iter().map(|(_, name)| name.as_str()).collect();
+                assert_eq!(names, ["a", "b", "c", "d", "e"]);
+
+                // Seeking back within the same stream resumes at the same entry.
+                let (pos, _) = entries[2];
+                // SAFETY: `dir` is a valid directory stream and `pos` came from `telldir` on it.
+                unsafe { libc::seekdir(dir, pos) };
+                assert_eq!(read_dir_stream(dir), entries[2..]);
+                // SAFETY: `dir` is a valid directory stream, which isn't used afterwards.
+                unsafe { libc::closedir(dir) };
+
+                // The positions remain valid in streams of later opendir calls.
+                for (pos, name) in &entries {
+                    let dir = open();
+                    assert!(!dir.is_null());
+                    // SAFETY: `dir` is a valid directory stream and `pos` came from `telldir`.
+                    unsafe { libc::seekdir(dir, *pos) };
+                    assert_eq!(&read_dir_stream(dir)[0].1, name);
+                    // SAFETY: `dir` is a valid directory stream, which isn't used afterwards.
+                    unsafe { libc::closedir(dir) };
+                }
+            },
+        );
+    }
+
     fn run_fuse_and_check_test_zip(test_dir: &Path, zip_path: &Path) {
         let mnt_path = test_dir.join("mnt");
         assert!(fs::create_dir(&mnt_path).is_ok());
 
-        let opt = Options { noexec: false, ..Default::default() };
+        let opt = RunOptions { noexec: false, ..Default::default() };
         start_fuse(zip_path, &mnt_path, opt);
 
         // Give some time for the fuse to boot up
@@ -838,7 +1655,233 @@ mod tests {
         );
     }
 
-    #[cfg(not(target_os = "android"))] // Android doesn't have the loopdev crate
+    #[test]
+    fn strict_mode_rejects_patched_data() {
+        let test_dir = tempfile::TempDir::new().unwrap();
+        let zip_path = test_dir.path().join("test.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        zip.start_file("foo", FileOptions::default()).unwrap();
+        zip.write_all(b"foo").unwrap();
+        zip.finish().unwrap();
+        drop(zip);
+        assert!(ZipFuse::new(&zip_path, 0, 0, true, None).is_ok());
+
+        // Mark the only entry as patched data in its local header.
+        let file = OpenOptions::new().read(true).write(true).open(&zip_path).unwrap();
+        let mut flags = [0u8; 2];
+        file.read_exact_at(&mut flags, LOCAL_HEADER_FLAGS_OFFSET).unwrap();
+        let flags = u16::from_le_bytes(flags) | FLAG_PATCHED_DATA;
+        file.write_all_at(&flags.to_le_bytes(), LOCAL_HEADER_FLAGS_OFFSET).unwrap();
+        drop(file);
+
+        assert!(ZipFuse::new(&zip_path, 0, 0, false, None).is_ok());
+        assert!(ZipFuse::new(&zip_path, 0, 0, true, None).is_err());
+    }
+
+    #[test]
+    fn inode_cache() {
+        let test_dir = tempfile::TempDir::new().unwrap();
+        let zip_path = test_dir.path().join("test.zip");
+        let cache_path = test_dir.path().join("test.zip.inodes");
+        let create_zip = |content: &[u8]| {
+            let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+            zip.start_file("dir/foo", FileOptions::default()).unwrap();
+            zip.write_all(content).unwrap();
+            zip.finish().unwrap();
+        };
+        let foo_size = |zipfuse: &ZipFuse| {
+            let archive = zipfuse.current_archive();
+            let it = &archive.inode_table;
+            let lookup = |parent, name: &str| {
+                let dir = it.get(parent).unwrap().get_directory().unwrap();
+                dir.get(&CString::new(name).unwrap()).unwrap().inode
+            };
+            it.get(lookup(lookup(1, "dir"), "foo")).unwrap().size
+        };
+
+        create_zip(b"foo");
+        let zipfuse = ZipFuse::new(&zip_path, 0, 0, false, Some(&cache_path)).unwrap();
+        assert_eq!(foo_size(&zipfuse), 3);
+        let cache = fs::read(&cache_path).unwrap();
+
+        // The cache is used as is for the same archive.
+        let zipfuse = ZipFuse::new(&zip_path, 0, 0, false, Some(&cache_path)).unwrap();
+        assert_eq!(foo_size(&zipfuse), 3);
+        assert_eq!(fs::read(&cache_path).unwrap(), cache);
+
+        // The cache is rebuilt when the archive changes.
+        create_zip(b"foobar");
+        let zipfuse = ZipFuse::new(&zip_path, 0, 0, false, Some(&cache_path)).unwrap();
+        assert_eq!(foo_size(&zipfuse), 6);
+        assert_ne!(fs::read(&cache_path).unwrap(), cache);
+
+        // A corrupted cache is ignored.
+        fs::write(&cache_path, b"garbage").unwrap();
+        let zipfuse = ZipFuse::new(&zip_path, 0, 0, false, Some(&cache_path)).unwrap();
+        assert_eq!(foo_size(&zipfuse), 6);
+    }
+
+    #[test]
+    fn reloads_changed_archive() {
+        let test_dir = tempfile::TempDir::new().unwrap();
+        let zip_path = test_dir.path().join("test.zip");
+        // Replace the archive like an update would, rather than modifying it in place.
+        let create_zip = |name: &str| {
+            let tmp_path = test_dir.path().join("tmp.zip");
+            let mut zip = zip::ZipWriter::new(File::create(&tmp_path).unwrap());
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.finish().unwrap();
+            fs::rename(&tmp_path, &zip_path).unwrap();
+        };
+        let has_file =
+            |zipfuse: &ZipFuse, name| zipfuse.archive().inode_table.find_path(name).is_some();
+
+        create_zip("foo");
+        let cache = CacheOptions {
+            attr_timeout: Duration::ZERO,
+            entry_timeout: Duration::ZERO,
+            keep_dir_cache: false,
+        };
+        let zipfuse = ZipFuse::new(&zip_path, 0, 0, false, None).unwrap().with_cache_options(cache);
+        let never_reloaded = ZipFuse::new(&zip_path, 0, 0, false, None).unwrap();
+        assert!(has_file(&zipfuse, Path::new("foo")));
+
+        // The archive isn't reloaded while a directory of the previous one is open.
+        create_zip("bar");
+        zipfuse.open_dirs.lock().unwrap().insert(1, OpenDir { open_count: 1 });
+        assert!(has_file(&zipfuse, Path::new("foo")));
+        zipfuse.open_dirs.lock().unwrap().clear();
+        assert!(has_file(&zipfuse, Path::new("bar")));
+        assert!(!has_file(&zipfuse, Path::new("foo")));
+
+        // An archive that fails to load is ignored.
+        fs::write(&zip_path, b"garbage").unwrap();
+        assert!(has_file(&zipfuse, Path::new("bar")));
+
+        // The archive is never reloaded if the kernel caches don't expire.
+        assert!(has_file(&never_reloaded, Path::new("foo")));
+    }
+
+    #[test]
+    fn verify_digests() {
+        let test_dir = tempfile::TempDir::new().unwrap();
+        let zip_path = test_dir.path().join("test.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        zip.start_file("dir/good", FileOptions::default()).unwrap();
+        zip.write_all(b"good").unwrap();
+        zip.start_file("bad", FileOptions::default()).unwrap();
+        zip.write_all(b"bad").unwrap();
+        zip.start_file("unlisted", FileOptions::default()).unwrap();
+        zip.write_all(b"unlisted").unwrap();
+        let stored = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("stored", stored).unwrap();
+        zip.write_all(b"stored").unwrap();
+        zip.start_file("bad_stored", stored).unwrap();
+        zip.write_all(b"bad").unwrap();
+        zip.finish().unwrap();
+
+        let digests_path = test_dir.path().join("digests");
+        let digest = |content: &[u8]| hex::encode(openssl::sha::sha256(content));
+        let digests = format!(
+            "{}  dir/good\n{} *bad\n{}  stored\n{}  bad_stored\n",
+            digest(b"good"),
+            digest(b"other"),
+            digest(b"stored"),
+            digest(b"other")
+        );
+        fs::write(&digests_path, digests).unwrap();
+
+        let zipfuse = ZipFuse::new(&zip_path, 0, 0, false, None)
+            .unwrap()
+            .with_digests(&digests_path)
+            .unwrap();
+        let archive = zipfuse.current_archive();
+        let it = &archive.inode_table;
+        for (path, expect_ok) in [
+            ("dir/good", true),
+            ("stored", true),
+            ("bad", false),
+            ("bad_stored", false),
+            ("unlisted", true),
+        ] {
+            let inode = it.find_path(Path::new(path)).unwrap();
+            let inode_data = it.get(inode).unwrap();
+            // The verdict of the first load is recorded, and later loads aren't checked again.
+            for _ in 0..2 {
+                let result = zipfuse.load_content(&archive, inode, inode_data);
+                assert_eq!(result.is_ok(), expect_ok, "{path}");
+            }
+            let verdicts = archive.digests.as_ref().unwrap().verdicts.lock().unwrap();
+            let expected_verdict = (path != "unlisted").then_some(expect_ok);
+            assert_eq!(verdicts.get(&inode).copied(), expected_verdict, "{path}");
+        }
+        // Entries stored uncompressed are still served from the archive once checked.
+        let inode = it.find_path(Path::new("stored")).unwrap();
+        let (content, _) = zipfuse.load_content(&archive, inode, it.get(inode).unwrap()).unwrap();
+        assert!(matches!(content, OpenFileContent::Uncompressed(_)));
+
+        fs::write(&digests_path, format!("{}  missing\n", digest(b"good"))).unwrap();
+        let zipfuse = ZipFuse::new(&zip_path, 0, 0, false, None).unwrap();
+        assert!(zipfuse.with_digests(&digests_path).is_err());
+    }
+
+    #[test]
+    fn inflate_budget() {
+        let is_enomem = |e: io::Error| e.raw_os_error() == Some(libc::ENOMEM);
+        let budget = Arc::new(InflateBudget::new(10, Duration::ZERO));
+        let mut first = budget.reservation();
+        first.resize(6).unwrap();
+        let mut second = budget.reservation();
+        assert!(second.resize(6).is_err_and(is_enomem));
+        assert!(second.resize(u64::MAX).is_err_and(is_enomem));
+        second.resize(4).unwrap();
+
+        // Memory is given back as soon as a reservation shrinks or is dropped.
+        first.resize(1).unwrap();
+        second.resize(9).unwrap();
+        drop(first);
+        drop(second);
+        assert_eq!(*budget.used.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn inflate_budget_waits_for_memory() {
+        let budget = Arc::new(InflateBudget::new(10, Duration::from_secs(60)));
+        let mut first = budget.reservation();
+        first.resize(6).unwrap();
+        let mut second = budget.reservation();
+        let waiter = std::thread::spawn(move || second.resize(6).map(|_| second.size));
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!waiter.is_finished());
+
+        // The waiting reservation goes through once enough memory is given back.
+        first.resize(4).unwrap();
+        assert_eq!(waiter.join().unwrap().unwrap(), 6);
+        assert_eq!(*budget.used.lock().unwrap(), 4);
+    }
+
+    #[test]
+    fn decompress_charges_the_content() {
+        let is_enomem = |e: io::Error| e.raw_os_error() == Some(libc::ENOMEM);
+        let budget = Arc::new(InflateBudget::new(2 * SPARSE_CHUNK_SIZE as u64, Duration::ZERO));
+        let mut content = vec![1; SPARSE_CHUNK_SIZE];
+        content.extend_from_slice(&[0; 2 * SPARSE_CHUNK_SIZE]);
+        content.extend_from_slice(&[1; SPARSE_CHUNK_SIZE]);
+
+        // Only the chunks that aren't zero-filled are charged.
+        let mut reservation = budget.reservation();
+        let size = content.len() as u64;
+        decompress(&mut content.as_slice(), size, true, Some(&mut reservation)).unwrap();
+        assert_eq!(reservation.size, 2 * SPARSE_CHUNK_SIZE as u64);
+
+        // Running out of budget, or content longer than declared, fails the decompression.
+        let mut other = budget.reservation();
+        let result = decompress(&mut content.as_slice(), size, true, Some(&mut other));
+        assert!(result.is_err_and(is_enomem));
+        drop(reservation);
+        assert!(decompress(&mut content.as_slice(), size - 1, true, Some(&mut other)).is_err());
+    }
+
     #[test]
     fn supports_zip_on_block_device() {
         // Write test.zip to the test directory
@@ -858,14 +1901,16 @@ mod tests {
         drop(zip_file);
 
         // Attach test.zip to a loop device
-        let lc = loopdev::LoopControl::open().unwrap();
-        let ld = scopeguard::guard(lc.next_free().unwrap(), |ld| {
-            ld.detach().unwrap();
-        });
-        ld.attach_file(&zip_path).unwrap();
+        let dev = dm::loopdevice::attach(
+            &zip_path, 0, 0, /*direct_io*/ false, /*writable*/ false,
+        )
+        .unwrap();
+        scopeguard::defer! {
+            dm::loopdevice::detach(&dev).unwrap();
+        }
 
         // Start zipfuse over to the loop device (not the zip file)
-        run_fuse_and_check_test_zip(&test_dir.path(), &ld.path().unwrap());
+        run_fuse_and_check_test_zip(&test_dir.path(), &dev);
     }
 
     #[test]